@@ -8,7 +8,7 @@ use crate::countertimer;
 use alsa::ctl::{ElemId, ElemIface};
 use alsa::ctl::{ElemType, ElemValue};
 use alsa::hctl::{Elem, HCtl};
-use alsa::pcm::{Access, Format, Frames, HwParams};
+use alsa::pcm::{Access, Format, Frames, HwParams, State};
 use alsa::{Direction, ValueOr, PCM};
 use alsa_sys;
 use parking_lot::{Mutex, RwLock, RwLockUpgradableReadGuard};
@@ -39,6 +39,9 @@ lazy_static! {
     static ref ALSA_MUTEX: Mutex<()> = Mutex::new(());
 }
 
+// Step size of the hardware rate adjust when it's combined with the resampler, 100 ppm.
+const COMBINED_RATE_ADJUST_STEP: f64 = 0.0001;
+
 pub struct AlsaPlaybackDevice {
     pub devname: String,
     pub samplerate: usize,
@@ -48,6 +51,7 @@ pub struct AlsaPlaybackDevice {
     pub target_level: usize,
     pub adjust_period: f32,
     pub enable_rate_adjust: bool,
+    pub open_retry: OpenRetry,
 }
 
 pub struct AlsaCaptureDevice {
@@ -62,6 +66,24 @@ pub struct AlsaCaptureDevice {
     pub silence_timeout: PrcFmt,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
+    pub combined_rate_adjust: bool,
+    pub open_retry: OpenRetry,
+}
+
+/// Captures from several Alsa devices in lockstep, and merges their channels.
+pub struct AlsaMultiCaptureDevice {
+    /// The name and number of channels of each device, in the order the channels are merged.
+    pub devices: Vec<(String, usize)>,
+    pub samplerate: usize,
+    pub capture_samplerate: usize,
+    pub resampler_config: Option<config::Resampler>,
+    pub chunksize: usize,
+    pub sample_format: SampleFormat,
+    pub silence_threshold: PrcFmt,
+    pub silence_timeout: PrcFmt,
+    pub stop_on_rate_change: bool,
+    pub rate_measure_interval: f32,
+    pub open_retry: OpenRetry,
 }
 
 struct CaptureChannels {
@@ -89,6 +111,7 @@ struct CaptureParams {
     capture_status: Arc<RwLock<CaptureStatus>>,
     stop_on_rate_change: bool,
     rate_measure_interval: f32,
+    combined_rate_adjust: bool,
 }
 
 struct PlaybackParams {
@@ -105,25 +128,87 @@ struct PlaybackParams {
 
 enum CaptureResult {
     Normal,
+    Overrun,
     Stalled,
 }
 
 #[derive(Debug)]
 enum PlaybackResult {
     Normal,
+    Underrun,
     Stalled,
 }
 
+/// Reads and writes interleaved frames, with plain read/write calls
+/// or via mmap, depending on the access mode of the device.
+struct PcmIo<'a> {
+    pcmdevice: &'a alsa::PCM,
+    io: alsa::pcm::IO<'a, u8>,
+    mmap: bool,
+    bytes_per_frame: usize,
+}
+
+impl<'a> PcmIo<'a> {
+    fn new(pcmdevice: &'a alsa::PCM, bytes_per_frame: usize) -> Self {
+        let mmap = pcmdevice
+            .hw_params_current()
+            .and_then(|hwp| hwp.get_access())
+            .map(|access| access == Access::MMapInterleaved)
+            .unwrap_or(false);
+        if mmap {
+            debug!("Device uses mmap access");
+        }
+        PcmIo {
+            pcmdevice,
+            io: pcmdevice.io_bytes(),
+            mmap,
+            bytes_per_frame,
+        }
+    }
+
+    /// Write frames, returns the number of frames written.
+    fn writei(&self, buffer: &[u8]) -> alsa::Result<usize> {
+        if !self.mmap {
+            return self.io.writei(buffer);
+        }
+        self.pcmdevice.avail_update()?;
+        let frames = self.io.mmap(buffer.len() / self.bytes_per_frame, |dest| {
+            let nbr_bytes = dest.len().min(buffer.len());
+            dest[..nbr_bytes].copy_from_slice(&buffer[..nbr_bytes]);
+            nbr_bytes / self.bytes_per_frame
+        })?;
+        // Writing via mmap doesn't start the device, start it here like writei does.
+        if frames > 0 && self.pcmdevice.state() == State::Prepared {
+            self.pcmdevice.start()?;
+        }
+        Ok(frames)
+    }
+
+    /// Read frames, returns the number of frames read.
+    fn readi(&self, buffer: &mut [u8]) -> alsa::Result<usize> {
+        if !self.mmap {
+            return self.io.readi(buffer);
+        }
+        self.pcmdevice.avail_update()?;
+        self.io.mmap(buffer.len() / self.bytes_per_frame, |src| {
+            let nbr_bytes = src.len().min(buffer.len());
+            buffer[..nbr_bytes].copy_from_slice(&src[..nbr_bytes]);
+            nbr_bytes / self.bytes_per_frame
+        })
+    }
+}
+
 /// Play a buffer.
 fn play_buffer(
     mut buffer: &[u8],
     pcmdevice: &alsa::PCM,
-    io: &alsa::pcm::IO<u8>,
+    io: &PcmIo,
     millis_per_frame: f32,
     bytes_per_frame: usize,
     buf_manager: &mut PlaybackBufferManager,
 ) -> Res<PlaybackResult> {
     let playback_state = pcmdevice.state_raw();
+    let mut result = PlaybackResult::Normal;
     //trace!("Playback state {:?}", playback_state);
     if playback_state < 0 {
         // This should never happen but sometimes does anyway,
@@ -137,6 +222,7 @@ fn play_buffer(
     } else if playback_state == alsa_sys::SND_PCM_STATE_XRUN as i32 {
         warn!("PB: Prepare playback after buffer underrun");
         pcmdevice.prepare()?;
+        result = PlaybackResult::Underrun;
         buf_manager.sleep_for_target_delay(millis_per_frame);
     } else if playback_state == alsa_sys::SND_PCM_STATE_PREPARED as i32 {
         info!("PB: Starting playback from Prepared state");
@@ -224,22 +310,24 @@ fn play_buffer(
             }
         };
     }
-    Ok(PlaybackResult::Normal)
+    Ok(result)
 }
 
 /// Capture a buffer.
 fn capture_buffer(
     mut buffer: &mut [u8],
     pcmdevice: &alsa::PCM,
-    io: &alsa::pcm::IO<u8>,
+    io: &PcmIo,
     samplerate: usize,
     frames_to_read: usize,
     bytes_per_frame: usize,
 ) -> Res<CaptureResult> {
     let capture_state = pcmdevice.state_raw();
+    let mut result = CaptureResult::Normal;
     if capture_state == alsa_sys::SND_PCM_STATE_XRUN as i32 {
         warn!("Prepare capture device");
         pcmdevice.prepare()?;
+        result = CaptureResult::Overrun;
     } else if capture_state < 0 {
         // This should never happen but sometimes does anyway,
         // for example if a USB device is unplugged.
@@ -290,7 +378,7 @@ fn capture_buffer(
                 let frames_req = buffer.len() / bytes_per_frame;
                 if frames_read == frames_req {
                     trace!("Capture read {} frames as requested", frames_read);
-                    return Ok(CaptureResult::Normal);
+                    return Ok(result);
                 } else {
                     warn!(
                         "Capture read {} frames instead of the requested {}",
@@ -370,8 +458,21 @@ fn open_pcm(
             SampleFormat::FLOAT64LE => hwp.set_format(Format::float64())?,
         }
 
-        // Set access mode, buffersize and periods
-        hwp.set_access(Access::RWInterleaved)?;
+        // Set access mode, buffersize and periods.
+        // Some pro audio cards only support mmap, use that if plain read/write isn't available.
+        if let Err(rw_err) = hwp.set_access(Access::RWInterleaved) {
+            debug!(
+                "{}: RWInterleaved access not supported, trying MMapInterleaved",
+                direction
+            );
+            if let Err(mmap_err) = hwp.set_access(Access::MMapInterleaved) {
+                let msg = format!(
+                    "{} device \"{}\" supports neither RWInterleaved ({}) nor MMapInterleaved ({}) access",
+                    direction, devname, rw_err, mmap_err
+                );
+                return Err(DeviceError::new(&msg).into());
+            }
+        }
         buf_manager.apply_buffer_size(&hwp)?;
         buf_manager.apply_period_size(&hwp)?;
 
@@ -405,13 +506,18 @@ fn playback_loop_bytes(
         rms: vec![0.0; params.channels],
         peak: vec![0.0; params.channels],
     };
+    let mut level_meter = LevelMeter::new(
+        params.playback_status.read().meter_ballistics,
+        params.samplerate,
+    );
+    let mut true_peak_meter = countertimer::TruePeakMeter::default();
     let mut buffer_avg = countertimer::Averager::new();
     let mut conversion_result;
     let adjust = params.adjust_period > 0.0 && params.adjust_enabled;
     let millis_per_frame: f32 = 1000.0 / params.samplerate as f32;
     let mut device_stalled = false;
 
-    let io = pcmdevice.io_bytes();
+    let io = PcmIo::new(pcmdevice, params.bytes_per_frame);
     debug!("Playback loop uses a buffer of {} frames", params.chunksize);
     let mut buffer = vec![0u8; params.chunksize * params.bytes_per_frame];
     let pcminfo = pcmdevice.info().unwrap();
@@ -473,8 +579,14 @@ fn playback_loop_bytes(
                     params.bytes_per_frame,
                     buf_manager,
                 );
+                if let Ok(PlaybackResult::Underrun) = playback_res {
+                    channels
+                        .status
+                        .send(StatusMessage::PlaybackUnderrun)
+                        .unwrap_or(());
+                }
                 device_stalled = match playback_res {
-                    Ok(PlaybackResult::Normal) => {
+                    Ok(PlaybackResult::Normal) | Ok(PlaybackResult::Underrun) => {
                         if device_stalled {
                             info!("PB: device resumed normal operation");
                             timer.restart();
@@ -520,7 +632,8 @@ fn playback_loop_bytes(
                 };
                 if !device_stalled {
                     // updates only for non-stalled device
-                    chunk.update_stats(&mut chunk_stats);
+                    level_meter.update(&chunk, &mut chunk_stats);
+                    true_peak_meter.update(&chunk);
                     {
                         let mut playback_status = params.playback_status.write();
                         if conversion_result.1 > 0 {
@@ -532,6 +645,9 @@ fn playback_loop_bytes(
                         playback_status
                             .signal_peak
                             .add_record(chunk_stats.peak_linear());
+                        playback_status.signal_minmax.update(&chunk);
+                        playback_status.true_peak.add_record(&mut true_peak_meter);
+                        playback_status.frames_played += chunk.valid_frames as u64;
                     }
                     if let Some(delay) = delay_at_chunk_recvd {
                         if delay != 0 {
@@ -611,6 +727,155 @@ fn drain_check_eos(audio: &Receiver<AudioMessage>) -> Option<AudioMessage> {
     eos
 }
 
+/// The per-chunk work of a capture loop, shared by the single and multi device loops.
+/// Updates the capture status, detects silence, resamples and sends the captured chunks.
+struct CaptureLoopState {
+    averager: countertimer::TimeAverage,
+    silence_counter: countertimer::SilenceCounter,
+    state: ProcessingState,
+    value_range: PrcFmt,
+    device_stalled: bool,
+    rate_adjust: f64,
+    chunk_stats: ChunkStats,
+    level_meter: LevelMeter,
+    true_peak_meter: countertimer::TruePeakMeter,
+    channel_mask: Vec<bool>,
+}
+
+impl CaptureLoopState {
+    fn new(params: &CaptureParams) -> Self {
+        CaptureLoopState {
+            averager: countertimer::TimeAverage::new(),
+            silence_counter: countertimer::SilenceCounter::new(
+                params.silence_threshold,
+                params.silence_timeout,
+                params.capture_samplerate,
+                params.chunksize,
+            ),
+            state: ProcessingState::Running,
+            value_range: 0.0,
+            device_stalled: false,
+            rate_adjust: 0.0,
+            chunk_stats: ChunkStats {
+                rms: vec![0.0; params.channels],
+                peak: vec![0.0; params.channels],
+            },
+            level_meter: LevelMeter::new(
+                params.capture_status.read().meter_ballistics,
+                params.capture_samplerate,
+            ),
+            true_peak_meter: countertimer::TruePeakMeter::default(),
+            channel_mask: vec![true; params.channels],
+        }
+    }
+
+    /// Measure the sample rate, and update the capture status at the configured interval.
+    fn update_status(&mut self, params: &CaptureParams, capture_bytes: usize) {
+        self.averager.add_value(capture_bytes);
+        let capture_status = params.capture_status.upgradable_read();
+        if self
+            .averager
+            .larger_than_millis(capture_status.update_interval as u64)
+        {
+            self.device_stalled = false;
+            let bytes_per_sec = self.averager.average();
+            self.averager.restart();
+            let measured_rate_f =
+                bytes_per_sec / (params.channels * params.store_bytes_per_sample) as f64;
+            trace!("Measured sample rate is {:.1} Hz", measured_rate_f);
+            let mut capture_status = RwLockUpgradableReadGuard::upgrade(capture_status); // to write lock
+            capture_status.measured_samplerate = measured_rate_f as usize;
+            capture_status.signal_range = self.value_range as f32;
+            capture_status.rate_adjust = self.rate_adjust as f32;
+            capture_status.state = self.state;
+        }
+    }
+
+    /// Mark the capture as stalled.
+    /// Returns true only the first time, when the devices should be restarted to drop outdated samples.
+    fn set_stalled(&mut self, params: &CaptureParams) -> bool {
+        if self.device_stalled {
+            return false;
+        }
+        info!("Capture device is stalled, processing is stalled");
+        self.device_stalled = true;
+        params.capture_status.write().state = ProcessingState::Stalled;
+        true
+    }
+
+    /// Convert the captured bytes to a chunk, update the signal levels, and send it for processing.
+    /// Returns false if the processing thread has stopped.
+    fn send_chunk(
+        &mut self,
+        params: &CaptureParams,
+        buffer: &[u8],
+        resampler: &mut Option<Box<dyn VecResampler<PrcFmt>>>,
+        audio: &mpsc::SyncSender<AudioMessage>,
+    ) -> bool {
+        let mut chunk = buffer_to_chunk_rawbytes(
+            buffer,
+            params.channels,
+            &params.sample_format,
+            buffer.len(),
+            &params.capture_status.read().used_channels,
+        );
+        self.level_meter.update(&chunk, &mut self.chunk_stats);
+        self.true_peak_meter.update(&chunk);
+        {
+            let mut capture_status = params.capture_status.write();
+            capture_status
+                .signal_rms
+                .add_record_squared(self.chunk_stats.rms_linear());
+            capture_status
+                .signal_peak
+                .add_record(self.chunk_stats.peak_linear());
+            capture_status.signal_minmax.update(&chunk);
+            capture_status
+                .true_peak
+                .add_record(&mut self.true_peak_meter);
+            capture_status.recent_samples.update(&chunk);
+            capture_status.signal_duty.update(
+                self.silence_counter.is_signal(chunk.maxval - chunk.minval),
+                chunk.valid_frames,
+            );
+            capture_status.frames_captured += chunk.valid_frames as u64;
+        }
+        self.value_range = chunk.maxval - chunk.minval;
+        if self.device_stalled {
+            self.state = ProcessingState::Stalled;
+        } else {
+            self.state = self.silence_counter.update(self.value_range);
+        }
+        if self.state == ProcessingState::Running {
+            if let Some(resampl) = resampler {
+                chunk.update_channel_mask(&mut self.channel_mask);
+                let new_waves = resampl
+                    .process(&chunk.waveforms, Some(&self.channel_mask))
+                    .unwrap();
+                let mut chunk_frames = new_waves.iter().map(|w| w.len()).max().unwrap();
+                if chunk_frames == 0 {
+                    chunk_frames = params.chunksize;
+                }
+                chunk.frames = chunk_frames;
+                chunk.valid_frames = chunk.frames;
+                chunk.waveforms = new_waves;
+            }
+            let msg = AudioMessage::Audio(chunk);
+            if audio.send(msg).is_err() {
+                info!("Processing thread has already stopped.");
+                return false;
+            }
+        } else if self.state == ProcessingState::Paused || self.state == ProcessingState::Stalled {
+            let msg = AudioMessage::Pause;
+            if audio.send(msg).is_err() {
+                info!("Processing thread has already stopped.");
+                return false;
+            }
+        }
+        true
+    }
+}
+
 fn capture_loop_bytes(
     channels: CaptureChannels,
     pcmdevice: &alsa::PCM,
@@ -618,7 +883,7 @@ fn capture_loop_bytes(
     mut resampler: Option<Box<dyn VecResampler<PrcFmt>>>,
     buf_manager: &mut CaptureBufferManager,
 ) {
-    let io = pcmdevice.io_bytes();
+    let io = PcmIo::new(pcmdevice, params.bytes_per_frame);
     let pcminfo = pcmdevice.info().unwrap();
     let card = pcminfo.get_card();
     let device = pcminfo.get_device();
@@ -644,7 +909,15 @@ fn capture_loop_bytes(
         element_uac2_gadget = h.find_elem(&elid_uac2_gadget);
     }
 
-    if element_loopback.is_some() || element_uac2_gadget.is_some() {
+    let hw_rate_adjust = element_loopback.is_some() || element_uac2_gadget.is_some();
+    let combined_rate_adjust = params.combined_rate_adjust && params.async_src;
+    if combined_rate_adjust {
+        if hw_rate_adjust {
+            info!("Capture device supports rate adjust, combining it with the resampler");
+        } else {
+            warn!("Combined rate adjust requested, but the capture device does not support rate adjust. Using only the resampler.");
+        }
+    } else if hw_rate_adjust {
         info!("Capture device supports rate adjust");
         if params.samplerate == params.capture_samplerate && resampler.is_some() {
             warn!("Needless 1:1 sample rate conversion active. Not needed since capture device supports rate adjust");
@@ -659,7 +932,6 @@ fn capture_loop_bytes(
 
     let mut capture_bytes = params.chunksize * params.channels * params.store_bytes_per_sample;
     let mut capture_frames = params.chunksize as Frames;
-    let mut averager = countertimer::TimeAverage::new();
     let mut watcher_averager = countertimer::TimeAverage::new();
     let mut valuewatcher = countertimer::ValueWatcher::new(
         params.capture_samplerate as f32,
@@ -667,21 +939,8 @@ fn capture_loop_bytes(
         RATE_CHANGE_THRESHOLD_COUNT,
     );
     let rate_measure_interval_ms = (1000.0 * params.rate_measure_interval) as u64;
-    let mut rate_adjust = 0.0;
-    let mut silence_counter = countertimer::SilenceCounter::new(
-        params.silence_threshold,
-        params.silence_timeout,
-        params.capture_samplerate,
-        params.chunksize,
-    );
-    let mut state = ProcessingState::Running;
-    let mut value_range = 0.0;
-    let mut device_stalled = false;
-    let mut chunk_stats = ChunkStats {
-        rms: vec![0.0; params.channels],
-        peak: vec![0.0; params.channels],
-    };
-    let mut channel_mask = vec![true; params.channels];
+    let mut hw_speed = 1.0;
+    let mut loop_state = CaptureLoopState::new(&params);
     loop {
         match channels.command.try_recv() {
             Ok(CommandMessage::Exit) => {
@@ -696,8 +955,34 @@ fn capture_loop_bytes(
             }
             Ok(CommandMessage::SetSpeed { speed }) => {
                 let mut elval = ElemValue::new(ElemType::Integer).unwrap();
-                rate_adjust = speed;
-                if let Some(elem_loopback) = &element_loopback {
+                loop_state.rate_adjust = speed;
+                if combined_rate_adjust {
+                    // The hardware takes the coarse part of the correction, and is only updated
+                    // when the speed has moved more than one step away from the current setting.
+                    // The resampler takes care of the remaining fine correction.
+                    if hw_rate_adjust && (speed - hw_speed).abs() > COMBINED_RATE_ADJUST_STEP {
+                        hw_speed =
+                            (speed / COMBINED_RATE_ADJUST_STEP).round() * COMBINED_RATE_ADJUST_STEP;
+                        debug!("Setting hardware rate adjust to {}", hw_speed);
+                        if let Some(elem_loopback) = &element_loopback {
+                            elval.set_integer(0, (100_000.0 / hw_speed) as i32).unwrap();
+                            elem_loopback.write(&elval).unwrap();
+                        } else if let Some(elem_uac2_gadget) = &element_uac2_gadget {
+                            elval
+                                .set_integer(0, (hw_speed * 1_000_000.0) as i32)
+                                .unwrap();
+                            elem_uac2_gadget.write(&elval).unwrap();
+                        }
+                    }
+                    if let Some(resampl) = &mut resampler {
+                        if resampl
+                            .set_resample_ratio_relative(speed / hw_speed, true)
+                            .is_err()
+                        {
+                            debug!("Failed to set resampling speed to {}", speed / hw_speed);
+                        }
+                    }
+                } else if let Some(elem_loopback) = &element_loopback {
                     elval.set_integer(0, (100_000.0 / speed) as i32).unwrap();
                     elem_loopback.write(&elval).unwrap();
                 } else if let Some(elem_uac2_gadget) = &element_uac2_gadget {
@@ -748,26 +1033,16 @@ fn capture_loop_bytes(
             capture_frames as usize,
             params.bytes_per_frame,
         );
+        if let Ok(CaptureResult::Overrun) = capture_res {
+            channels
+                .status
+                .send(StatusMessage::CaptureOverrun)
+                .unwrap_or(());
+        }
         match capture_res {
-            Ok(CaptureResult::Normal) => {
+            Ok(CaptureResult::Normal) | Ok(CaptureResult::Overrun) => {
                 //trace!("Captured {} bytes", capture_bytes);
-                averager.add_value(capture_bytes);
-                {
-                    let capture_status = params.capture_status.upgradable_read();
-                    if averager.larger_than_millis(capture_status.update_interval as u64) {
-                        device_stalled = false;
-                        let bytes_per_sec = averager.average();
-                        averager.restart();
-                        let measured_rate_f = bytes_per_sec
-                            / (params.channels * params.store_bytes_per_sample) as f64;
-                        trace!("Measured sample rate is {:.1} Hz", measured_rate_f);
-                        let mut capture_status = RwLockUpgradableReadGuard::upgrade(capture_status); // to write lock
-                        capture_status.measured_samplerate = measured_rate_f as usize;
-                        capture_status.signal_range = value_range as f32;
-                        capture_status.rate_adjust = rate_adjust as f32;
-                        capture_status.state = state;
-                    }
-                }
+                loop_state.update_status(&params, capture_bytes);
                 watcher_averager.add_value(capture_bytes);
                 if watcher_averager.larger_than_millis(rate_measure_interval_ms) {
                     let bytes_per_sec = watcher_averager.average();
@@ -794,10 +1069,7 @@ fn capture_loop_bytes(
                 }
             }
             Ok(CaptureResult::Stalled) => {
-                // only the first time
-                if !device_stalled {
-                    info!("Capture device is stalled, processing is stalled");
-                    device_stalled = true;
+                if loop_state.set_stalled(&params) {
                     // restarting the device to drop outdated samples
                     pcmdevice
                         .drop()
@@ -805,7 +1077,6 @@ fn capture_loop_bytes(
                     pcmdevice
                         .prepare()
                         .unwrap_or_else(|err| warn!("Capture error {:?}", err));
-                    params.capture_status.write().state = ProcessingState::Stalled;
                 }
             }
             Err(msg) => {
@@ -818,55 +1089,273 @@ fn capture_loop_bytes(
                 return;
             }
         };
-        let mut chunk = buffer_to_chunk_rawbytes(
+        if !loop_state.send_chunk(
+            &params,
             &buffer[0..capture_bytes],
-            params.channels,
-            &params.sample_format,
-            capture_bytes,
-            &params.capture_status.read().used_channels,
-        );
-        chunk.update_stats(&mut chunk_stats);
-        {
-            let mut capture_status = params.capture_status.write();
-            capture_status
-                .signal_rms
-                .add_record_squared(chunk_stats.rms_linear());
-            capture_status
-                .signal_peak
-                .add_record(chunk_stats.peak_linear());
+            &mut resampler,
+            &channels.audio,
+        ) {
+            break;
         }
-        value_range = chunk.maxval - chunk.minval;
-        if device_stalled {
-            state = ProcessingState::Stalled;
-        } else {
-            state = silence_counter.update(value_range);
+    }
+    params.capture_status.write().state = ProcessingState::Inactive;
+}
+
+/// Copy the frames of each device into a single buffer, with the channels of each device following the previous.
+fn merge_device_buffers(
+    merged: &mut [u8],
+    buffers: &[Vec<u8>],
+    bytes_per_frame: &[usize],
+    frames: usize,
+) {
+    let mut offset = 0;
+    for frame in 0..frames {
+        for (buffer, bpf) in buffers.iter().zip(bytes_per_frame.iter()) {
+            merged[offset..offset + bpf].copy_from_slice(&buffer[frame * bpf..(frame + 1) * bpf]);
+            offset += bpf;
         }
-        if state == ProcessingState::Running {
-            if let Some(resampl) = &mut resampler {
-                chunk.update_channel_mask(&mut channel_mask);
-                let new_waves = resampl
-                    .process(&chunk.waveforms, Some(&channel_mask))
-                    .unwrap();
-                let mut chunk_frames = new_waves.iter().map(|w| w.len()).max().unwrap();
-                if chunk_frames == 0 {
-                    chunk_frames = params.chunksize;
+    }
+}
+
+fn capture_loop_multi(
+    channels: CaptureChannels,
+    pcmdevices: &[(String, alsa::PCM)],
+    device_channels: &[usize],
+    params: CaptureParams,
+    mut resampler: Option<Box<dyn VecResampler<PrcFmt>>>,
+    buf_managers: &mut [CaptureBufferManager],
+) {
+    let ios: Vec<PcmIo> = pcmdevices
+        .iter()
+        .zip(device_channels.iter())
+        .map(|((_, pcmdevice), chans)| PcmIo::new(pcmdevice, chans * params.store_bytes_per_sample))
+        .collect();
+    let device_bytes_per_frame: Vec<usize> = device_channels
+        .iter()
+        .map(|chans| chans * params.store_bytes_per_sample)
+        .collect();
+    let mut device_buffers: Vec<Vec<u8>> = buf_managers
+        .iter()
+        .zip(device_bytes_per_frame.iter())
+        .map(|(manager, bpf)| vec![0u8; manager.data().buffersize() as usize * bpf])
+        .collect();
+    let buffer_frames = buf_managers[0].data().buffersize() as usize;
+    debug!(
+        "Multi device capture loop uses buffers of {} frames, for {} devices",
+        buffer_frames,
+        pcmdevices.len()
+    );
+    let mut buffer = vec![0u8; buffer_frames * params.bytes_per_frame];
+
+    let mut capture_bytes = params.chunksize * params.channels * params.store_bytes_per_sample;
+    let mut capture_frames = params.chunksize as Frames;
+    let mut watcher_averager = countertimer::TimeAverage::new();
+    // Each device gets its own rate watcher, since the devices may run on separate clocks.
+    let mut valuewatchers: Vec<countertimer::ValueWatcher> = pcmdevices
+        .iter()
+        .map(|_| {
+            countertimer::ValueWatcher::new(
+                params.capture_samplerate as f32,
+                RATE_CHANGE_THRESHOLD_VALUE,
+                RATE_CHANGE_THRESHOLD_COUNT,
+            )
+        })
+        .collect();
+    // Frames left in the buffer of each device at the start of the rate measurement.
+    let mut start_avail: Vec<i64> = vec![0; pcmdevices.len()];
+    let mut frames_since_measure: i64 = 0;
+    let rate_measure_interval_ms = (1000.0 * params.rate_measure_interval) as u64;
+    let mut loop_state = CaptureLoopState::new(&params);
+    'capture: loop {
+        match channels.command.try_recv() {
+            Ok(CommandMessage::Exit) => {
+                debug!("Exit message received, sending EndOfStream");
+                let msg = AudioMessage::EndOfStream;
+                channels.audio.send(msg).unwrap_or(());
+                channels
+                    .status
+                    .send(StatusMessage::CaptureDone)
+                    .unwrap_or(());
+                break;
+            }
+            Ok(CommandMessage::SetSpeed { speed }) => {
+                loop_state.rate_adjust = speed;
+                // Rate adjust of the hardware isn't used, since it would only affect one of the devices.
+                if let Some(resampl) = &mut resampler {
+                    if params.async_src {
+                        if resampl.set_resample_ratio_relative(speed, true).is_err() {
+                            debug!("Failed to set resampling speed to {}", speed);
+                        }
+                    } else {
+                        warn!("Requested rate adjust of synchronous resampler. Ignoring request.");
+                    }
                 }
-                chunk.frames = chunk_frames;
-                chunk.valid_frames = chunk.frames;
-                chunk.waveforms = new_waves;
             }
-            let msg = AudioMessage::Audio(chunk);
-            if channels.audio.send(msg).is_err() {
-                info!("Processing thread has already stopped.");
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(mpsc::TryRecvError::Disconnected) => {
+                error!("Command channel was closed");
                 break;
             }
-        } else if state == ProcessingState::Paused || state == ProcessingState::Stalled {
-            let msg = AudioMessage::Pause;
-            if channels.audio.send(msg).is_err() {
-                info!("Processing thread has already stopped.");
-                break;
+        };
+        let (new_capture_bytes, new_capture_frames) = nbr_capture_bytes_and_frames(
+            capture_bytes,
+            capture_frames,
+            &resampler,
+            &params,
+            &mut buffer,
+        );
+        if new_capture_bytes != capture_bytes {
+            trace!(
+                "Updating capture bytes from {} to {}, and frames from {} to {}",
+                capture_bytes,
+                new_capture_bytes,
+                capture_frames,
+                new_capture_frames
+            );
+            capture_bytes = new_capture_bytes;
+            capture_frames = new_capture_frames;
+            for (((_, pcmdevice), manager), (device_buffer, bpf)) in pcmdevices
+                .iter()
+                .zip(buf_managers.iter_mut())
+                .zip(device_buffers.iter_mut().zip(device_bytes_per_frame.iter()))
+            {
+                let device_bytes = capture_frames as usize * bpf;
+                if device_bytes > device_buffer.len() {
+                    device_buffer.resize(device_bytes, 0);
+                }
+                update_avail_min(pcmdevice, new_capture_frames, manager).unwrap_or(());
+            }
+        }
+        let mut result = CaptureResult::Normal;
+        for ((((devname, pcmdevice), io), device_buffer), bpf) in pcmdevices
+            .iter()
+            .zip(ios.iter())
+            .zip(device_buffers.iter_mut())
+            .zip(device_bytes_per_frame.iter())
+        {
+            let capture_res = capture_buffer(
+                &mut device_buffer[0..capture_frames as usize * bpf],
+                pcmdevice,
+                io,
+                params.capture_samplerate,
+                capture_frames as usize,
+                *bpf,
+            );
+            match capture_res {
+                Ok(CaptureResult::Normal) => {}
+                Ok(CaptureResult::Overrun) => {
+                    warn!("Capture device \"{}\" had an overrun", devname);
+                    channels
+                        .status
+                        .send(StatusMessage::CaptureOverrun)
+                        .unwrap_or(());
+                }
+                Ok(CaptureResult::Stalled) => {
+                    result = CaptureResult::Stalled;
+                    break;
+                }
+                Err(err) => {
+                    // An error on any of the devices stops the capture.
+                    let msg = format!("Capture device \"{}\" failed: {}", devname, err);
+                    channels
+                        .status
+                        .send(StatusMessage::CaptureError(msg))
+                        .unwrap_or(());
+                    let msg = AudioMessage::EndOfStream;
+                    channels.audio.send(msg).unwrap_or(());
+                    break 'capture;
+                }
+            }
+        }
+        match result {
+            CaptureResult::Normal | CaptureResult::Overrun => {
+                loop_state.update_status(&params, capture_bytes);
+                watcher_averager.add_value(capture_bytes);
+                frames_since_measure += capture_frames;
+                if watcher_averager.larger_than_millis(rate_measure_interval_ms) {
+                    let bytes_per_sec = watcher_averager.average();
+                    watcher_averager.restart();
+                    // The devices are read in lockstep, so a device with a faster clock
+                    // accumulates frames in its buffer. Include these in the rate of each device.
+                    let seconds =
+                        frames_since_measure as f64 * params.bytes_per_frame as f64 / bytes_per_sec;
+                    let frames_read = frames_since_measure;
+                    frames_since_measure = 0;
+                    let mut device_rates = Vec::with_capacity(pcmdevices.len());
+                    for (((devname, pcmdevice), watcher), avail_at_start) in pcmdevices
+                        .iter()
+                        .zip(valuewatchers.iter_mut())
+                        .zip(start_avail.iter_mut())
+                    {
+                        let avail = pcmdevice.avail().unwrap_or(*avail_at_start);
+                        let measured_rate_f =
+                            (frames_read + avail - *avail_at_start) as f64 / seconds;
+                        *avail_at_start = avail;
+                        device_rates.push(measured_rate_f);
+                        trace!(
+                            "Measured sample rate of device \"{}\" is {:.1} Hz",
+                            devname,
+                            measured_rate_f
+                        );
+                        if watcher.check_value(measured_rate_f as f32) {
+                            warn!(
+                                "sample rate change detected on device \"{}\", last rate was {} Hz",
+                                devname, measured_rate_f
+                            );
+                            if params.stop_on_rate_change {
+                                let msg = AudioMessage::EndOfStream;
+                                channels.audio.send(msg).unwrap_or(());
+                                channels
+                                    .status
+                                    .send(StatusMessage::CaptureFormatChange(
+                                        measured_rate_f as usize,
+                                    ))
+                                    .unwrap_or(());
+                                break 'capture;
+                            }
+                        }
+                    }
+                    let spread = start_avail.iter().max().unwrap_or(&0)
+                        - start_avail.iter().min().unwrap_or(&0);
+                    if spread > capture_frames {
+                        let fastest = device_rates.iter().cloned().fold(f64::MIN, f64::max);
+                        let slowest = device_rates.iter().cloned().fold(f64::MAX, f64::min);
+                        warn!(
+                            "Capture devices are drifting apart, the difference is now {} frames, about {:.0} ppm",
+                            spread,
+                            1.0e6 * (fastest - slowest) / params.capture_samplerate as f64
+                        );
+                    }
+                }
+            }
+            CaptureResult::Stalled => {
+                if loop_state.set_stalled(&params) {
+                    // restarting all devices to drop outdated samples and keep them in sync
+                    for (_, pcmdevice) in pcmdevices.iter() {
+                        pcmdevice
+                            .drop()
+                            .unwrap_or_else(|err| warn!("Capture error {:?}", err));
+                        pcmdevice
+                            .prepare()
+                            .unwrap_or_else(|err| warn!("Capture error {:?}", err));
+                    }
+                }
             }
         }
+        merge_device_buffers(
+            &mut buffer[0..capture_bytes],
+            &device_buffers,
+            &device_bytes_per_frame,
+            capture_frames as usize,
+        );
+        if !loop_state.send_chunk(
+            &params,
+            &buffer[0..capture_bytes],
+            &mut resampler,
+            &channels.audio,
+        ) {
+            break;
+        }
     }
     params.capture_status.write().state = ProcessingState::Inactive;
 }
@@ -916,6 +1405,7 @@ impl PlaybackDevice for AlsaPlaybackDevice {
         playback_status: Arc<RwLock<PlaybackStatus>>,
     ) -> Res<Box<thread::JoinHandle<()>>> {
         let devname = self.devname.clone();
+        let open_retry = self.open_retry;
         let target_level = if self.target_level > 0 {
             self.target_level
         } else {
@@ -933,14 +1423,16 @@ impl PlaybackDevice for AlsaPlaybackDevice {
         let handle = thread::Builder::new()
             .name("AlsaPlayback".to_string())
             .spawn(move || {
-                match open_pcm(
-                    devname,
-                    samplerate as u32,
-                    channels as u32,
-                    &sample_format,
-                    &mut buf_manager,
-                    false,
-                ) {
+                match open_with_retry(open_retry, &devname, || {
+                    open_pcm(
+                        devname.clone(),
+                        samplerate as u32,
+                        channels as u32,
+                        &sample_format,
+                        &mut buf_manager,
+                        false,
+                    )
+                }) {
                     Ok(pcmdevice) => {
                         match status_channel.send(StatusMessage::PlaybackReady) {
                             Ok(()) => {}
@@ -992,6 +1484,7 @@ impl CaptureDevice for AlsaCaptureDevice {
         capture_status: Arc<RwLock<CaptureStatus>>,
     ) -> Res<Box<thread::JoinHandle<()>>> {
         let devname = self.devname.clone();
+        let open_retry = self.open_retry;
         let samplerate = self.samplerate;
         let capture_samplerate = self.capture_samplerate;
         let chunksize = self.chunksize;
@@ -1005,6 +1498,7 @@ impl CaptureDevice for AlsaCaptureDevice {
         let async_src = resampler_is_async(&resampler_config);
         let stop_on_rate_change = self.stop_on_rate_change;
         let rate_measure_interval = self.rate_measure_interval;
+        let combined_rate_adjust = self.combined_rate_adjust;
         let mut buf_manager = CaptureBufferManager::new(
             chunksize as Frames,
             samplerate as f32 / capture_samplerate as f32,
@@ -1020,14 +1514,16 @@ impl CaptureDevice for AlsaCaptureDevice {
                     capture_samplerate,
                     chunksize,
                 );
-                match open_pcm(
-                    devname,
-                    capture_samplerate as u32,
-                    channels as u32,
-                    &sample_format,
-                    &mut buf_manager,
-                    true,
-                ) {
+                match open_with_retry(open_retry, &devname, || {
+                    open_pcm(
+                        devname.clone(),
+                        capture_samplerate as u32,
+                        channels as u32,
+                        &sample_format,
+                        &mut buf_manager,
+                        true,
+                    )
+                }) {
                     Ok(pcmdevice) => {
                         match status_channel.send(StatusMessage::CaptureReady) {
                             Ok(()) => {}
@@ -1049,6 +1545,7 @@ impl CaptureDevice for AlsaCaptureDevice {
                             capture_status,
                             stop_on_rate_change,
                             rate_measure_interval,
+                            combined_rate_adjust,
                         };
                         let cap_channels = CaptureChannels {
                             audio: channel,
@@ -1077,3 +1574,139 @@ impl CaptureDevice for AlsaCaptureDevice {
         Ok(Box::new(handle))
     }
 }
+
+/// Start a capture thread reading from all the devices, and providing AudioMessages via a channel
+impl CaptureDevice for AlsaMultiCaptureDevice {
+    fn start(
+        &mut self,
+        channel: mpsc::SyncSender<AudioMessage>,
+        barrier: Arc<Barrier>,
+        status_channel: crossbeam_channel::Sender<StatusMessage>,
+        command_channel: mpsc::Receiver<CommandMessage>,
+        capture_status: Arc<RwLock<CaptureStatus>>,
+    ) -> Res<Box<thread::JoinHandle<()>>> {
+        let devices = self.devices.clone();
+        let open_retry = self.open_retry;
+        let samplerate = self.samplerate;
+        let capture_samplerate = self.capture_samplerate;
+        let chunksize = self.chunksize;
+
+        let device_channels: Vec<usize> = devices.iter().map(|(_, chans)| *chans).collect();
+        let channels = device_channels.iter().sum();
+        let store_bytes_per_sample = self.sample_format.bytes_per_sample();
+        let silence_timeout = self.silence_timeout;
+        let silence_threshold = self.silence_threshold;
+        let sample_format = self.sample_format;
+        let resampler_config = self.resampler_config;
+        let async_src = resampler_is_async(&resampler_config);
+        let stop_on_rate_change = self.stop_on_rate_change;
+        let rate_measure_interval = self.rate_measure_interval;
+        let mut buf_managers: Vec<CaptureBufferManager> = devices
+            .iter()
+            .map(|_| {
+                CaptureBufferManager::new(
+                    chunksize as Frames,
+                    samplerate as f32 / capture_samplerate as f32,
+                )
+            })
+            .collect();
+
+        let handle = thread::Builder::new()
+            .name("AlsaMultiCapture".to_string())
+            .spawn(move || {
+                let resampler = new_resampler(
+                    &resampler_config,
+                    channels,
+                    samplerate,
+                    capture_samplerate,
+                    chunksize,
+                );
+                let pcm_res: Res<Vec<(String, alsa::PCM)>> = devices
+                    .iter()
+                    .zip(buf_managers.iter_mut())
+                    .map(|((devname, chans), manager)| {
+                        open_with_retry(open_retry, devname, || {
+                            open_pcm(
+                                devname.clone(),
+                                capture_samplerate as u32,
+                                *chans as u32,
+                                &sample_format,
+                                manager,
+                                true,
+                            )
+                        })
+                        .map(|pcm| (devname.clone(), pcm))
+                    })
+                    .collect();
+                match pcm_res {
+                    Ok(pcmdevices) => {
+                        match status_channel.send(StatusMessage::CaptureReady) {
+                            Ok(()) => {}
+                            Err(_err) => {}
+                        }
+                        barrier.wait();
+                        debug!("Starting multi device captureloop");
+                        let cap_params = CaptureParams {
+                            channels,
+                            sample_format,
+                            silence_timeout,
+                            silence_threshold,
+                            chunksize,
+                            store_bytes_per_sample,
+                            bytes_per_frame: channels * store_bytes_per_sample,
+                            samplerate,
+                            capture_samplerate,
+                            async_src,
+                            capture_status,
+                            stop_on_rate_change,
+                            rate_measure_interval,
+                            combined_rate_adjust: false,
+                        };
+                        let cap_channels = CaptureChannels {
+                            audio: channel,
+                            status: status_channel,
+                            command: command_channel,
+                        };
+                        capture_loop_multi(
+                            cap_channels,
+                            &pcmdevices,
+                            &device_channels,
+                            cap_params,
+                            resampler,
+                            &mut buf_managers,
+                        );
+                    }
+                    Err(err) => {
+                        let send_result =
+                            status_channel.send(StatusMessage::CaptureError(err.to_string()));
+                        if send_result.is_err() {
+                            error!("Capture error: {}", err);
+                        }
+                        barrier.wait();
+                    }
+                }
+            })
+            .unwrap();
+        Ok(Box::new(handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alsadevice::merge_device_buffers;
+
+    #[test]
+    fn merge_buffers_of_different_widths() {
+        // Two frames from a stereo device and a 3-channel device, 2 bytes per sample.
+        // Each byte pair is (device, frame * 10 + channel).
+        let first = vec![1, 0, 1, 1, 1, 10, 1, 11];
+        let second = vec![2, 0, 2, 1, 2, 2, 2, 10, 2, 11, 2, 12];
+        let mut merged = vec![0u8; 20];
+        merge_device_buffers(&mut merged, &[first, second], &[4, 6], 2);
+        let expected = vec![
+            1, 0, 1, 1, 2, 0, 2, 1, 2, 2, // frame 0
+            1, 10, 1, 11, 2, 10, 2, 11, 2, 12, // frame 1
+        ];
+        assert_eq!(merged, expected);
+    }
+}
@@ -6,6 +6,7 @@ use alsa::device_name::HintIter;
 use alsa::pcm::{Format, HwParams};
 use alsa::Card;
 use alsa::Direction;
+use alsa::PCM;
 use alsa_sys;
 
 const STANDARD_RATES: [u32; 17] = [
@@ -175,6 +176,20 @@ pub fn list_nbr_channels(hwp: &HwParams) -> Res<(u32, u32, Vec<u32>)> {
     Ok((min_channels, max_channels, channels))
 }
 
+/// Get the number of channels that a capture device is currently locked to.
+/// A device like the Loopback follows the format of the stream that is played into it,
+/// and then supports only that number of channels.
+/// Returns None if the device can't be opened, or if it supports a range of channel counts.
+pub fn fixed_capture_channels(devname: &str) -> Option<usize> {
+    let pcm = PCM::new(devname, Direction::Capture, true).ok()?;
+    let hwp = HwParams::any(&pcm).ok()?;
+    let (min_channels, max_channels, _) = list_nbr_channels(&hwp).ok()?;
+    if min_channels == max_channels {
+        return Some(min_channels as usize);
+    }
+    None
+}
+
 pub fn list_channels_as_text(hwp: &HwParams) -> String {
     let supported_channels_res = list_nbr_channels(hwp);
     if let Ok((min_ch, max_ch, ch_list)) = supported_channels_res {
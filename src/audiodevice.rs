@@ -1,6 +1,8 @@
 // Traits for audio devices
 #[cfg(target_os = "linux")]
 use crate::alsadevice;
+#[cfg(target_os = "linux")]
+use crate::alsadevice_utils;
 use crate::config;
 #[cfg(target_os = "macos")]
 use crate::coreaudiodevice;
@@ -21,6 +23,7 @@ use crate::pulsedevice;
 #[cfg(target_os = "windows")]
 use crate::wasapidevice;
 use parking_lot::RwLock;
+use realfft::RealFftPlanner;
 use rubato::{
     calculate_cutoff, FastFixedOut, FftFixedOut, PolynomialDegree, SincFixedOut,
     SincInterpolationParameters, SincInterpolationType, VecResampler, WindowFunction,
@@ -30,8 +33,9 @@ use std::fmt;
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use crate::helpers::linear_to_db;
 use crate::CommandMessage;
 use crate::PrcFmt;
 use crate::Res;
@@ -40,6 +44,14 @@ use crate::{CaptureStatus, PlaybackStatus};
 
 pub const RATE_CHANGE_THRESHOLD_COUNT: usize = 3;
 pub const RATE_CHANGE_THRESHOLD_VALUE: f32 = 0.04;
+pub const SPECTRUM_MIN_FFT_SIZE: usize = 256;
+pub const SPECTRUM_MAX_FFT_SIZE: usize = 16384;
+// Spacing of the spectrum bins, in fractions of an octave.
+const SPECTRUM_BINS_PER_OCTAVE: PrcFmt = 6.0;
+const SPECTRUM_LOWEST_FREQ: PrcFmt = 20.0;
+const DOMINANT_FREQ_FFT_SIZE: usize = 8192;
+// Peaks below -80 dBFS are not reported as a dominant frequency.
+const DOMINANT_FREQ_THRESHOLD: PrcFmt = 1.0e-4;
 
 #[derive(Debug)]
 pub struct DeviceError {
@@ -195,6 +207,345 @@ impl AudioChunk {
     }
 }
 
+/// Smallest and largest sample values per channel since the last reset.
+#[derive(Clone, Debug, Default)]
+pub struct SignalMinMax {
+    min: Vec<PrcFmt>,
+    max: Vec<PrcFmt>,
+}
+
+impl SignalMinMax {
+    pub fn update(&mut self, chunk: &AudioChunk) {
+        if self.min.len() != chunk.channels {
+            self.min.resize(chunk.channels, PrcFmt::MAX);
+            self.max.resize(chunk.channels, PrcFmt::MIN);
+        }
+        for (wf, (minval, maxval)) in chunk
+            .waveforms
+            .iter()
+            .zip(self.min.iter_mut().zip(self.max.iter_mut()))
+        {
+            for sample in wf.iter().take(chunk.valid_frames) {
+                *minval = minval.min(*sample);
+                *maxval = maxval.max(*sample);
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.min.clear();
+        self.max.clear();
+    }
+
+    /// Get the minimum values, channels that have not seen any samples report zero.
+    pub fn min_linear(&self) -> Vec<f32> {
+        self.min
+            .iter()
+            .map(|val| {
+                if *val == PrcFmt::MAX {
+                    0.0
+                } else {
+                    *val as f32
+                }
+            })
+            .collect()
+    }
+
+    /// Get the maximum values, channels that have not seen any samples report zero.
+    pub fn max_linear(&self) -> Vec<f32> {
+        self.max
+            .iter()
+            .map(|val| {
+                if *val == PrcFmt::MIN {
+                    0.0
+                } else {
+                    *val as f32
+                }
+            })
+            .collect()
+    }
+}
+
+/// The most recent captured samples of each channel.
+/// Used for calculating a spectrum or the dominant frequencies on request.
+#[derive(Clone, Debug, Default)]
+pub struct RecentSamples {
+    buffers: Vec<Vec<PrcFmt>>,
+    write_pos: usize,
+}
+
+impl RecentSamples {
+    pub fn update(&mut self, chunk: &AudioChunk) {
+        if chunk.channels == 0 {
+            return;
+        }
+        if self.buffers.len() != chunk.channels {
+            self.buffers = vec![vec![0.0; SPECTRUM_MAX_FFT_SIZE]; chunk.channels];
+            self.write_pos = 0;
+        }
+        let mut write_pos = self.write_pos;
+        for (buffer, waveform) in self.buffers.iter_mut().zip(chunk.waveforms.iter()) {
+            write_pos = self.write_pos;
+            for frame in 0..chunk.valid_frames {
+                // Unused channels have empty waveforms, store silence for them
+                buffer[write_pos] = waveform.get(frame).copied().unwrap_or(0.0);
+                write_pos = (write_pos + 1) % SPECTRUM_MAX_FFT_SIZE;
+            }
+        }
+        self.write_pos = write_pos;
+    }
+
+    /// Get the latest samples of one channel, oldest first.
+    fn latest_channel(&self, channel: usize, nbr_samples: usize) -> Vec<PrcFmt> {
+        let buffer = &self.buffers[channel];
+        let len = buffer.len();
+        let start = (self.write_pos + len - nbr_samples) % len;
+        (0..nbr_samples)
+            .map(|n| buffer[(start + n) % len])
+            .collect()
+    }
+
+    /// Get the latest samples of all channels mixed to mono, oldest first.
+    fn latest(&self, nbr_samples: usize) -> Vec<PrcFmt> {
+        let mut mono = vec![0.0; nbr_samples];
+        if self.buffers.is_empty() {
+            return mono;
+        }
+        let scale = 1.0 / self.buffers.len() as PrcFmt;
+        for channel in 0..self.buffers.len() {
+            for (sum, value) in mono
+                .iter_mut()
+                .zip(self.latest_channel(channel, nbr_samples))
+            {
+                *sum += scale * value;
+            }
+        }
+        mono
+    }
+
+    /// Copy the latest samples for a spectrum with the given FFT size, mixed to mono.
+    pub fn spectrum_samples(&self, fft_size: usize) -> Res<Vec<PrcFmt>> {
+        if !fft_size.is_power_of_two()
+            || !(SPECTRUM_MIN_FFT_SIZE..=SPECTRUM_MAX_FFT_SIZE).contains(&fft_size)
+        {
+            let msg = format!(
+                "FFT size must be a power of two between {} and {}, got {}",
+                SPECTRUM_MIN_FFT_SIZE, SPECTRUM_MAX_FFT_SIZE, fft_size
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+        Ok(self.latest(fft_size))
+    }
+
+    /// Copy the latest samples of each channel, for estimating the dominant frequencies.
+    pub fn dominant_frequency_samples(&self) -> Vec<Vec<PrcFmt>> {
+        (0..self.buffers.len())
+            .map(|channel| self.latest_channel(channel, DOMINANT_FREQ_FFT_SIZE))
+            .collect()
+    }
+}
+
+/// Calculates spectra of captured samples.
+/// The FFT planner is kept, so that the FFTs can be reused between requests.
+/// The samples are copied out of the capture status first,
+/// so that the capture thread isn't blocked while the FFTs are calculated.
+#[derive(Default)]
+pub struct SpectrumAnalyzer {
+    planner: RealFftPlanner<PrcFmt>,
+}
+
+impl fmt::Debug for SpectrumAnalyzer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpectrumAnalyzer").finish_non_exhaustive()
+    }
+}
+
+impl SpectrumAnalyzer {
+    /// Apply a Hann window to the samples and calculate the magnitudes of the FFT.
+    /// The magnitudes are scaled so that a full scale sine gives a peak of 1.0.
+    fn windowed_magnitudes(&mut self, mut samples: Vec<PrcFmt>) -> Res<Vec<PrcFmt>> {
+        let fft_size = samples.len();
+        let pi = std::f64::consts::PI as PrcFmt;
+        let mut window_sum = 0.0;
+        for (n, sample) in samples.iter_mut().enumerate() {
+            let window = 0.5 - 0.5 * (2.0 * pi * n as PrcFmt / fft_size as PrcFmt).cos();
+            window_sum += window;
+            *sample *= window;
+        }
+        let fft = self.planner.plan_fft_forward(fft_size);
+        let mut spectrum = fft.make_output_vec();
+        fft.process(&mut samples, &mut spectrum)?;
+        Ok(spectrum
+            .iter()
+            .map(|val| 2.0 * val.norm() / window_sum)
+            .collect())
+    }
+
+    /// Estimate the frequency of the strongest tone in each channel,
+    /// from the samples given by `RecentSamples::dominant_frequency_samples`.
+    /// The peak of the magnitude spectrum is located with parabolic interpolation
+    /// of the log magnitudes around the highest FFT bin.
+    /// Channels where the peak is below the threshold give None.
+    pub fn dominant_frequencies(
+        &mut self,
+        channels: Vec<Vec<PrcFmt>>,
+        samplerate: usize,
+    ) -> Res<Vec<Option<f32>>> {
+        let bin_width = samplerate as PrcFmt / DOMINANT_FREQ_FFT_SIZE as PrcFmt;
+        let first_bin = ((SPECTRUM_LOWEST_FREQ / bin_width).ceil() as usize).max(1);
+        let mut result = Vec::with_capacity(channels.len());
+        for samples in channels {
+            let magnitudes = self.windowed_magnitudes(samples)?;
+            let last_bin = magnitudes.len() - 2;
+            let peak = (first_bin..=last_bin).fold(first_bin, |best, idx| {
+                if magnitudes[idx] > magnitudes[best] {
+                    idx
+                } else {
+                    best
+                }
+            });
+            if magnitudes[peak] < DOMINANT_FREQ_THRESHOLD {
+                result.push(None);
+                continue;
+            }
+            let ln_mag = |idx: usize| magnitudes[idx].max(1.0e-12).ln();
+            let (left, center, right) = (ln_mag(peak - 1), ln_mag(peak), ln_mag(peak + 1));
+            let denominator = left - 2.0 * center + right;
+            let offset = if denominator.abs() > 1.0e-12 {
+                (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+            } else {
+                0.0
+            };
+            result.push(Some(((peak as PrcFmt + offset) * bin_width) as f32));
+        }
+        Ok(result)
+    }
+
+    /// Calculate the magnitude spectrum of samples given by `RecentSamples::spectrum_samples`,
+    /// using a Hann window.
+    /// Returns the center frequencies of log-spaced bins, and the magnitudes in dB.
+    /// A full scale sine gives a peak of 0 dB.
+    pub fn spectrum(
+        &mut self,
+        samples: Vec<PrcFmt>,
+        samplerate: usize,
+    ) -> Res<(Vec<f32>, Vec<f32>)> {
+        let fft_size = samples.len();
+        let magnitudes = self.windowed_magnitudes(samples)?;
+
+        let bin_width = samplerate as PrcFmt / fft_size as PrcFmt;
+        let nyquist = samplerate as PrcFmt / 2.0;
+        let half_step = (2.0 as PrcFmt).powf(0.5 / SPECTRUM_BINS_PER_OCTAVE);
+        let mut freqs = Vec::new();
+        let mut levels = Vec::new();
+        let mut freq = SPECTRUM_LOWEST_FREQ;
+        while freq < nyquist {
+            let first = ((freq / half_step / bin_width).ceil() as usize).min(magnitudes.len() - 1);
+            let last = ((freq * half_step / bin_width).floor() as usize).min(magnitudes.len() - 1);
+            let peak = if first <= last {
+                magnitudes[first..=last]
+                    .iter()
+                    .fold(0.0 as PrcFmt, |acc, val| acc.max(*val))
+            } else {
+                // The bin is narrower than the FFT resolution, use the nearest value
+                magnitudes[((freq / bin_width).round() as usize).min(magnitudes.len() - 1)]
+            };
+            freqs.push(freq as f32);
+            levels.push(peak as f32);
+            freq *= half_step * half_step;
+        }
+        linear_to_db(&mut levels);
+        Ok((freqs, levels))
+    }
+}
+
+/// Level meter that optionally applies standardized ballistics to the chunk stats.
+/// The PPM types replace the peak values, and the VU type replaces the RMS values.
+pub struct LevelMeter {
+    ballistics: Option<config::MeterBallistics>,
+    rise: PrcFmt,
+    fall: PrcFmt,
+    levels: Vec<PrcFmt>,
+}
+
+// Scale the rectified average of a VU meter to read the RMS value of a sine.
+const VU_SINE_SCALE: PrcFmt = 1.110_720_7;
+
+impl LevelMeter {
+    pub fn new(ballistics: Option<config::MeterBallistics>, samplerate: usize) -> Self {
+        let fs = samplerate as PrcFmt;
+        // The integration time of a PPM is the duration of a tone burst
+        // that reads 2 dB below the steady state value.
+        let ppm_rise = |integration_time: PrcFmt| {
+            let tau = integration_time / (1.0 / (1.0 - (10.0 as PrcFmt).powf(-2.0 / 20.0))).ln();
+            1.0 - (-1.0 / (tau * fs)).exp()
+        };
+        let ppm_fall = |db_per_sec: PrcFmt| (10.0 as PrcFmt).powf(-db_per_sec / (20.0 * fs));
+        let (rise, fall) = match ballistics {
+            Some(config::MeterBallistics::PpmType1) => (ppm_rise(0.005), ppm_fall(20.0 / 1.5)),
+            Some(config::MeterBallistics::PpmType2) => (ppm_rise(0.010), ppm_fall(24.0 / 2.8)),
+            Some(config::MeterBallistics::Vu) => {
+                // A VU meter reaches 99% of the steady state value in 300 ms.
+                let tau = 0.3 / (100.0 as PrcFmt).ln();
+                let coeff = 1.0 - (-1.0 / (tau * fs)).exp();
+                (coeff, coeff)
+            }
+            None => (1.0, 0.0),
+        };
+        LevelMeter {
+            ballistics,
+            rise,
+            fall,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Update the stats with the levels of a new chunk.
+    pub fn update(&mut self, chunk: &AudioChunk, stats: &mut ChunkStats) {
+        chunk.update_stats(stats);
+        let ballistics = match self.ballistics {
+            Some(ballistics) => ballistics,
+            None => return,
+        };
+        self.levels.resize(chunk.channels, 0.0);
+        let values = match ballistics {
+            config::MeterBallistics::Vu => &mut stats.rms,
+            _ => &mut stats.peak,
+        };
+        for (wf, (level, value)) in chunk
+            .waveforms
+            .iter()
+            .zip(self.levels.iter_mut().zip(values.iter_mut()))
+        {
+            if wf.is_empty() {
+                *level = 0.0;
+                continue;
+            }
+            match ballistics {
+                config::MeterBallistics::Vu => {
+                    for sample in wf.iter() {
+                        *level += self.rise * (VU_SINE_SCALE * sample.abs() - *level);
+                    }
+                    *value = *level;
+                }
+                _ => {
+                    let mut max_level: PrcFmt = 0.0;
+                    for sample in wf.iter() {
+                        let rectified = sample.abs();
+                        if rectified > *level {
+                            *level += self.rise * (rectified - *level);
+                        } else {
+                            *level *= self.fall;
+                        }
+                        max_level = max_level.max(*level);
+                    }
+                    *value = max_level;
+                }
+            }
+        }
+    }
+}
+
 /// Get RMS and peak value of a vector
 pub fn rms_and_peak(data: &[PrcFmt]) -> (PrcFmt, PrcFmt) {
     if !data.is_empty() {
@@ -235,6 +586,51 @@ pub trait CaptureDevice {
     ) -> Res<Box<thread::JoinHandle<()>>>;
 }
 
+/// How many more times to try opening a device that fails to open, and how long to wait between the attempts.
+#[derive(Clone, Copy, Debug)]
+pub struct OpenRetry {
+    pub count: usize,
+    pub interval: Duration,
+}
+
+impl OpenRetry {
+    pub fn from_config(conf: &config::Devices) -> Self {
+        OpenRetry {
+            count: conf.open_retry_count(),
+            interval: Duration::from_secs_f32(conf.open_retry_interval_ms() / 1000.0),
+        }
+    }
+}
+
+/// Open a device, and retry if it fails.
+/// This is useful for devices that appear some time after CamillaDSP has started, for example USB DACs at boot.
+/// The error of the last attempt is returned if none of the attempts succeed.
+pub fn open_with_retry<T>(
+    retry: OpenRetry,
+    devname: &str,
+    mut open: impl FnMut() -> Res<T>,
+) -> Res<T> {
+    let mut attempt = 0;
+    loop {
+        match open() {
+            Ok(device) => return Ok(device),
+            Err(err) if attempt < retry.count => {
+                attempt += 1;
+                warn!(
+                    "Failed to open device '{}': {}. Retrying in {} ms, attempt {} of {}",
+                    devname,
+                    err,
+                    retry.interval.as_millis(),
+                    attempt,
+                    retry.count
+                );
+                thread::sleep(retry.interval);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
 /// Create a playback device.
 pub fn new_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
     match conf.playback {
@@ -243,6 +639,7 @@ pub fn new_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             channels,
             ref device,
             format,
+            ..
         } => Box::new(alsadevice::AlsaPlaybackDevice {
             devname: device.clone(),
             samplerate: conf.samplerate,
@@ -252,12 +649,14 @@ pub fn new_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             target_level: conf.target_level(),
             adjust_period: conf.adjust_period(),
             enable_rate_adjust: conf.rate_adjust(),
+            open_retry: OpenRetry::from_config(&conf),
         }),
         #[cfg(feature = "pulse-backend")]
         config::PlaybackDevice::Pulse {
             channels,
             device,
             format,
+            ..
         } => Box::new(pulsedevice::PulsePlaybackDevice {
             devname: device,
             samplerate: conf.samplerate,
@@ -269,6 +668,7 @@ pub fn new_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             channels,
             filename,
             format,
+            wav_header_format,
             ..
         } => Box::new(filedevice::FilePlaybackDevice {
             destination: filedevice::PlaybackDest::Filename(filename),
@@ -276,15 +676,20 @@ pub fn new_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             chunksize: conf.chunksize,
             channels,
             sample_format: format,
+            wav_header_format: wav_header_format.unwrap_or(config::WavHeaderFormat::None),
         }),
         config::PlaybackDevice::Stdout {
-            channels, format, ..
+            channels,
+            format,
+            wav_header_format,
+            ..
         } => Box::new(filedevice::FilePlaybackDevice {
             destination: filedevice::PlaybackDest::Stdout,
             samplerate: conf.samplerate,
             chunksize: conf.chunksize,
             channels,
             sample_format: format,
+            wav_header_format: wav_header_format.unwrap_or(config::WavHeaderFormat::None),
         }),
         #[cfg(target_os = "macos")]
         config::PlaybackDevice::CoreAudio(ref dev) => {
@@ -298,6 +703,8 @@ pub fn new_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
                 adjust_period: conf.adjust_period(),
                 enable_rate_adjust: conf.rate_adjust(),
                 exclusive: dev.is_exclusive(),
+                aggregate: dev.aggregate.clone(),
+                open_retry: OpenRetry::from_config(&conf),
             })
         }
         #[cfg(target_os = "windows")]
@@ -311,6 +718,7 @@ pub fn new_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
             target_level: conf.target_level(),
             adjust_period: conf.adjust_period(),
             enable_rate_adjust: conf.rate_adjust(),
+            open_retry: OpenRetry::from_config(&conf),
         }),
         #[cfg(all(
             feature = "cpal-backend",
@@ -325,6 +733,7 @@ pub fn new_playback_device(conf: config::Devices) -> Box<dyn PlaybackDevice> {
         config::PlaybackDevice::Jack {
             channels,
             ref device,
+            ..
         } => Box::new(cpaldevice::CpalPlaybackDevice {
             devname: device.clone(),
             host: cpaldevice::CpalHost::Jack,
@@ -503,6 +912,43 @@ pub fn new_resampler(
     }
 }
 
+/// Get the delay of the resampler in frames at the output samplerate, zero if no resampler is used.
+pub fn resampler_delay(conf: &config::Devices) -> usize {
+    let capture_samplerate = if conf.resampler.is_some() {
+        conf.capture_samplerate()
+    } else {
+        conf.samplerate
+    };
+    new_resampler(
+        &conf.resampler,
+        1,
+        conf.samplerate,
+        capture_samplerate,
+        conf.chunksize,
+    )
+    .map(|resampler| resampler.output_delay())
+    .unwrap_or(0)
+}
+
+/// Estimate the total capture to playback latency in milliseconds.
+/// This is the sum of one chunk collected by the capture device,
+/// the latency of the pipeline including the resampler, and the playback buffer level.
+/// If the playback device does not report a buffer level, the target level is used instead.
+/// The pipeline latency in frames is given by the caller, see `filters::pipeline_latency`.
+pub fn estimate_total_latency(
+    devices: &config::Devices,
+    pipeline_latency: PrcFmt,
+    buffer_level: usize,
+) -> f32 {
+    let playback_buffer = if buffer_level > 0 {
+        buffer_level
+    } else {
+        devices.target_level()
+    };
+    let frames = (devices.chunksize + playback_buffer) as PrcFmt + pipeline_latency;
+    1000.0 * frames as f32 / devices.samplerate as f32
+}
+
 /// Create a capture device.
 pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
     // Use `capture_samplerate` from config if given, and resampling is enabled.
@@ -542,6 +988,7 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             channels,
             ref device,
             format,
+            combined_rate_adjust,
         } => Box::new(alsadevice::AlsaCaptureDevice {
             devname: device.clone(),
             samplerate: conf.samplerate,
@@ -554,6 +1001,26 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             silence_timeout: conf.silence_timeout(),
             stop_on_rate_change: conf.stop_on_rate_change(),
             rate_measure_interval: conf.rate_measure_interval(),
+            combined_rate_adjust: combined_rate_adjust.unwrap_or(false),
+            open_retry: OpenRetry::from_config(&conf),
+        }),
+        #[cfg(target_os = "linux")]
+        config::CaptureDevice::AlsaMulti(ref dev) => Box::new(alsadevice::AlsaMultiCaptureDevice {
+            devices: dev
+                .devices
+                .iter()
+                .map(|member| (member.device.clone(), member.channels))
+                .collect(),
+            samplerate: conf.samplerate,
+            capture_samplerate,
+            resampler_config: conf.resampler,
+            chunksize: conf.chunksize,
+            sample_format: dev.format,
+            silence_threshold: conf.silence_threshold(),
+            silence_timeout: conf.silence_timeout(),
+            stop_on_rate_change: conf.stop_on_rate_change(),
+            rate_measure_interval: conf.rate_measure_interval(),
+            open_retry: OpenRetry::from_config(&conf),
         }),
         #[cfg(feature = "pulse-backend")]
         config::CaptureDevice::Pulse {
@@ -572,7 +1039,12 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             silence_timeout: conf.silence_timeout(),
         }),
         config::CaptureDevice::File(ref dev) => Box::new(filedevice::FileCaptureDevice {
-            source: filedevice::CaptureSource::Filename(dev.filename.clone()),
+            source: match &dev.filenames {
+                Some(filenames) => filedevice::CaptureSource::Playlist(filenames.clone()),
+                None => {
+                    filedevice::CaptureSource::Filename(dev.filename.clone().unwrap_or_default())
+                }
+            },
             samplerate: conf.samplerate,
             capture_samplerate,
             resampler_config: conf.resampler,
@@ -584,6 +1056,24 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             silence_timeout: conf.silence_timeout(),
             skip_bytes: dev.skip_bytes(),
             read_bytes: dev.read_bytes(),
+            channel_select: dev.channel_select.clone(),
+            stop_on_rate_change: conf.stop_on_rate_change(),
+            rate_measure_interval: conf.rate_measure_interval(),
+        }),
+        config::CaptureDevice::FlacFile(ref dev) => Box::new(filedevice::FileCaptureDevice {
+            source: filedevice::CaptureSource::Flac(dev.filename.clone()),
+            samplerate: conf.samplerate,
+            capture_samplerate,
+            resampler_config: conf.resampler,
+            chunksize: conf.chunksize,
+            channels: conf.capture.channels(),
+            sample_format: config::SampleFormat::S32LE,
+            extra_samples: dev.extra_samples(),
+            silence_threshold: conf.silence_threshold(),
+            silence_timeout: conf.silence_timeout(),
+            skip_bytes: 0,
+            read_bytes: 0,
+            channel_select: None,
             stop_on_rate_change: conf.stop_on_rate_change(),
             rate_measure_interval: conf.rate_measure_interval(),
         }),
@@ -600,12 +1090,34 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             silence_timeout: conf.silence_timeout(),
             skip_bytes: dev.skip_bytes(),
             read_bytes: dev.read_bytes(),
+            channel_select: dev.channel_select.clone(),
+            stop_on_rate_change: conf.stop_on_rate_change(),
+            rate_measure_interval: conf.rate_measure_interval(),
+        }),
+        config::CaptureDevice::TcpStream(ref dev) => Box::new(filedevice::FileCaptureDevice {
+            source: filedevice::CaptureSource::Tcp(dev.address.clone(), dev.port, dev.listen()),
+            samplerate: conf.samplerate,
+            capture_samplerate,
+            resampler_config: conf.resampler,
+            chunksize: conf.chunksize,
+            channels: dev.channels,
+            sample_format: dev.format,
+            extra_samples: 0,
+            silence_threshold: conf.silence_threshold(),
+            silence_timeout: conf.silence_timeout(),
+            skip_bytes: 0,
+            read_bytes: 0,
+            channel_select: None,
             stop_on_rate_change: conf.stop_on_rate_change(),
             rate_measure_interval: conf.rate_measure_interval(),
         }),
         #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
         config::CaptureDevice::Bluez(ref dev) => Box::new(filedevice::FileCaptureDevice {
-            source: filedevice::CaptureSource::BluezDBus(dev.service(), dev.dbus_path.clone()),
+            source: filedevice::CaptureSource::BluezDBus(
+                dev.service(),
+                dev.dbus_path.clone(),
+                dev.reconnect(),
+            ),
             samplerate: conf.samplerate,
             capture_samplerate,
             resampler_config: conf.resampler,
@@ -617,6 +1129,7 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             silence_timeout: conf.silence_timeout(),
             skip_bytes: 0,
             read_bytes: 0,
+            channel_select: None,
             stop_on_rate_change: conf.stop_on_rate_change(),
             rate_measure_interval: conf.rate_measure_interval(),
         }),
@@ -634,6 +1147,7 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
                 silence_timeout: conf.silence_timeout(),
                 stop_on_rate_change: conf.stop_on_rate_change(),
                 rate_measure_interval: conf.rate_measure_interval(),
+                open_retry: OpenRetry::from_config(&conf),
             })
         }
         #[cfg(target_os = "windows")]
@@ -651,6 +1165,7 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
             silence_timeout: conf.silence_timeout(),
             stop_on_rate_change: conf.stop_on_rate_change(),
             rate_measure_interval: conf.rate_measure_interval(),
+            open_retry: OpenRetry::from_config(&conf),
         }),
         #[cfg(all(
             feature = "cpal-backend",
@@ -682,6 +1197,18 @@ pub fn new_capture_device(conf: config::Devices) -> Box<dyn CaptureDevice> {
     }
 }
 
+/// Get the number of channels that the capture device currently delivers.
+/// This is only available for backends where the device follows the format of the source.
+pub fn current_capture_channels(conf: &config::CaptureDevice) -> Option<usize> {
+    match conf {
+        #[cfg(target_os = "linux")]
+        config::CaptureDevice::Alsa { device, .. } => {
+            alsadevice_utils::fixed_capture_channels(device)
+        }
+        _ => None,
+    }
+}
+
 pub fn calculate_speed(avg_level: f64, target_level: usize, adjust_period: f32, srate: u32) -> f64 {
     let diff = avg_level as isize - target_level as isize;
     let rel_diff = (diff as f64) / (srate as f64);
@@ -699,7 +1226,90 @@ pub fn calculate_speed(avg_level: f64, target_level: usize, adjust_period: f32,
 
 #[cfg(test)]
 mod tests {
-    use crate::audiodevice::{rms_and_peak, AudioChunk, ChunkStats};
+    use crate::audiodevice::{
+        open_with_retry, rms_and_peak, AudioChunk, ChunkStats, LevelMeter, OpenRetry,
+        RecentSamples, SignalMinMax, SpectrumAnalyzer,
+    };
+    use crate::config::{self, MeterBallistics};
+    use crate::PrcFmt;
+    use std::time::Duration;
+
+    #[test]
+    fn retry_open() {
+        let retry = OpenRetry {
+            count: 2,
+            interval: Duration::from_millis(1),
+        };
+        let mut attempts = 0;
+        let result = open_with_retry(retry, "test", || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(config::ConfigError::new("not yet").into())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+        let mut attempts = 0;
+        let result: crate::Res<()> = open_with_retry(retry, "test", || {
+            attempts += 1;
+            Err(config::ConfigError::new("never").into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn spectrum_of_sine() {
+        let fs = 48000;
+        let pi = std::f64::consts::PI as PrcFmt;
+        let wave: Vec<PrcFmt> = (0..4096)
+            .map(|n| (2.0 * pi * 1000.0 * n as PrcFmt / fs as PrcFmt).sin())
+            .collect();
+        let chunk = AudioChunk::new(vec![wave], 1.0, -1.0, 4096, 4096);
+        let mut recent = RecentSamples::default();
+        recent.update(&chunk);
+        let mut analyzer = SpectrumAnalyzer::default();
+        let samples = recent.spectrum_samples(4096).unwrap();
+        let (freqs, levels) = analyzer.spectrum(samples, fs).unwrap();
+        assert_eq!(freqs.len(), levels.len());
+        let (idx_1k, _) = freqs
+            .iter()
+            .enumerate()
+            .min_by(|a, b| {
+                (a.1 - 1000.0)
+                    .abs()
+                    .partial_cmp(&(b.1 - 1000.0).abs())
+                    .unwrap()
+            })
+            .unwrap();
+        assert!(levels[idx_1k].abs() < 1.5);
+        let idx_10k = freqs.iter().position(|f| *f > 10000.0).unwrap();
+        assert!(levels[idx_10k] < -60.0);
+        assert!(recent.spectrum_samples(1000).is_err());
+        assert!(recent.spectrum_samples(65536).is_err());
+    }
+
+    #[test]
+    fn dominant_frequency_of_sine() {
+        let fs = 48000;
+        let pi = std::f64::consts::PI as PrcFmt;
+        let wave: Vec<PrcFmt> = (0..8192)
+            .map(|n| 0.5 * (2.0 * pi * 441.3 * n as PrcFmt / fs as PrcFmt).sin())
+            .collect();
+        let silent = vec![0.0; 8192];
+        let chunk = AudioChunk::new(vec![wave, silent, Vec::new()], 1.0, -1.0, 8192, 8192);
+        let mut recent = RecentSamples::default();
+        recent.update(&chunk);
+        let mut analyzer = SpectrumAnalyzer::default();
+        let freqs = analyzer
+            .dominant_frequencies(recent.dominant_frequency_samples(), fs)
+            .unwrap();
+        assert_eq!(freqs.len(), 3);
+        assert!((freqs[0].unwrap() - 441.3).abs() < 0.5);
+        assert_eq!(freqs[1], None);
+        assert_eq!(freqs[2], None);
+    }
 
     #[test]
     fn vec_rms_and_peak() {
@@ -732,4 +1342,45 @@ mod tests {
         assert_eq!(0.0, stats.peak_db()[0]);
         assert!(stats.rms_db()[1] > -6.1 && stats.rms_db()[1] < -5.9);
     }
+
+    #[test]
+    fn ppm_tone_burst() {
+        // A tone burst as long as the integration time should read 2 dB below full scale.
+        let mut meter = LevelMeter::new(Some(MeterBallistics::PpmType1), 48000);
+        let mut waveform = vec![0.0; 4800];
+        for (n, sample) in waveform.iter_mut().take(240).enumerate() {
+            *sample = if n % 2 == 0 { 1.0 } else { -1.0 };
+        }
+        let chunk = AudioChunk::new(vec![waveform], 1.0, -1.0, 4800, 4800);
+        let mut stats = chunk.stats();
+        meter.update(&chunk, &mut stats);
+        let peak = stats.peak_db()[0];
+        assert!(peak > -2.1 && peak < -1.9, "got {peak}");
+    }
+
+    #[test]
+    fn vu_steady_sine() {
+        let mut meter = LevelMeter::new(Some(MeterBallistics::Vu), 48000);
+        let waveform: Vec<PrcFmt> = (0..48000)
+            .map(|n| (2.0 * std::f64::consts::PI * 1000.0 * n as f64 / 48000.0).sin() as PrcFmt)
+            .collect();
+        let chunk = AudioChunk::new(vec![waveform], 1.0, -1.0, 48000, 48000);
+        let mut stats = chunk.stats();
+        meter.update(&chunk, &mut stats);
+        let rms = stats.rms_db()[0];
+        assert!(rms > -3.1 && rms < -2.9, "got {rms}");
+    }
+
+    #[test]
+    fn minmax_accumulates_and_resets() {
+        let mut minmax = SignalMinMax::default();
+        let chunk = AudioChunk::new(vec![vec![0.1, -0.3, 0.2], vec![]], 0.2, -0.3, 3, 3);
+        minmax.update(&chunk);
+        let chunk = AudioChunk::new(vec![vec![0.5, -0.1, 0.0], vec![]], 0.5, -0.1, 3, 3);
+        minmax.update(&chunk);
+        assert_eq!(minmax.min_linear(), vec![-0.3, 0.0]);
+        assert_eq!(minmax.max_linear(), vec![0.5, 0.0]);
+        minmax.reset();
+        assert!(minmax.min_linear().is_empty());
+    }
 }
@@ -12,12 +12,19 @@ use crate::PrcFmt;
 use crate::ProcessingParameters;
 use crate::Res;
 
+// Speed of sound in m/s, used for delays given as distances.
+const SPEED_OF_SOUND: PrcFmt = 343.0;
+
 #[derive(Clone, Debug)]
 pub struct Gain {
     pub name: String,
     pub gain: PrcFmt,
 }
 
+pub struct Polarity {
+    pub name: String,
+}
+
 pub struct Delay {
     pub name: String,
     samplerate: usize,
@@ -87,7 +94,7 @@ impl Volume {
     ) -> Self {
         let fader = conf.fader as usize;
         let current_volume = processing_params.current_volume(fader);
-        let mute = processing_params.is_mute(fader);
+        let mute = processing_params.is_output_muted(fader);
         Self::new(
             name,
             conf.ramp_time(),
@@ -123,8 +130,8 @@ impl Volume {
     }
 
     fn prepare_processing(&mut self) {
-        let shared_vol = self.processing_params.target_volume(self.fader);
-        let shared_mute = self.processing_params.is_mute(self.fader);
+        let shared_vol = self.processing_params.output_volume(self.fader);
+        let shared_mute = self.processing_params.is_output_muted(self.fader);
 
         // Volume setting changed
         if (shared_vol - self.target_volume).abs() > 0.01 || self.mute != shared_mute {
@@ -309,6 +316,48 @@ impl Filter for Gain {
     }
 }
 
+impl Polarity {
+    /// A filter that inverts the polarity of the signal.
+    pub fn new(name: &str) -> Self {
+        Polarity {
+            name: name.to_string(),
+        }
+    }
+}
+
+impl Filter for Polarity {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        for item in waveform.iter_mut() {
+            *item = -*item;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        if !matches!(conf, config::Filter::Polarity { .. }) {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Convert the configured delay to a number of samples.
+pub fn delay_in_samples(samplerate: usize, conf: &config::DelayParameters) -> PrcFmt {
+    match conf.unit() {
+        config::TimeUnit::Milliseconds => conf.delay / 1000.0 * (samplerate as PrcFmt),
+        config::TimeUnit::Millimetres => {
+            conf.delay / 1000.0 * (samplerate as PrcFmt) / SPEED_OF_SOUND
+        }
+        config::TimeUnit::Feet => conf.delay * 0.3048 * (samplerate as PrcFmt) / SPEED_OF_SOUND,
+        config::TimeUnit::Inches => conf.delay * 0.0254 * (samplerate as PrcFmt) / SPEED_OF_SOUND,
+        config::TimeUnit::Samples => conf.delay,
+    }
+}
+
 impl Delay {
     /// Creates a delay filter with delay in samples
     pub fn new(name: &str, samplerate: usize, delay: PrcFmt, subsample: bool) -> Self {
@@ -349,12 +398,7 @@ impl Delay {
     }
 
     pub fn from_config(name: &str, samplerate: usize, conf: config::DelayParameters) -> Self {
-        let delay_samples = match conf.unit() {
-            config::TimeUnit::Milliseconds => conf.delay / 1000.0 * (samplerate as PrcFmt),
-            config::TimeUnit::Millimetres => conf.delay / 1000.0 * (samplerate as PrcFmt) / 343.0,
-            config::TimeUnit::Samples => conf.delay,
-        };
-
+        let delay_samples = delay_in_samples(samplerate, &conf);
         Self::new(name, samplerate, delay_samples, conf.subsample())
     }
 }
@@ -419,9 +463,12 @@ pub fn validate_gain_config(conf: &config::GainParameters) -> Res<()> {
 
 #[cfg(test)]
 mod tests {
-    use crate::basicfilters::{Delay, Gain};
+    use crate::basicfilters::{Delay, Gain, Polarity, Volume};
+    use crate::config;
     use crate::filters::Filter;
     use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::sync::Arc;
 
     fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
         println!("{left} - {right}");
@@ -446,6 +493,15 @@ mod tests {
         assert_eq!(waveform, waveform_inv);
     }
 
+    #[test]
+    fn polarity_invert() {
+        let mut waveform = vec![-0.5, 0.0, 0.5];
+        let waveform_inv = vec![0.5, 0.0, -0.5];
+        let mut polarity = Polarity::new("test");
+        polarity.process_waveform(&mut waveform).unwrap();
+        assert_eq!(waveform, waveform_inv);
+    }
+
     #[test]
     fn gain_ampl() {
         let mut waveform = vec![-0.5, 0.0, 0.5];
@@ -455,6 +511,40 @@ mod tests {
         assert_eq!(waveform, waveform_ampl);
     }
 
+    #[test]
+    fn delay_distance_units() {
+        let delay_mm = Delay::from_config(
+            "test",
+            96000,
+            config::DelayParameters {
+                delay: 3048.0,
+                unit: Some(config::TimeUnit::Millimetres),
+                subsample: None,
+            },
+        );
+        let delay_ft = Delay::from_config(
+            "test",
+            96000,
+            config::DelayParameters {
+                delay: 10.0,
+                unit: Some(config::TimeUnit::Feet),
+                subsample: None,
+            },
+        );
+        let delay_in = Delay::from_config(
+            "test",
+            96000,
+            config::DelayParameters {
+                delay: 120.0,
+                unit: Some(config::TimeUnit::Inches),
+                subsample: None,
+            },
+        );
+        assert_eq!(delay_mm.queue.capacity(), 853);
+        assert_eq!(delay_ft.queue.capacity(), delay_mm.queue.capacity());
+        assert_eq!(delay_in.queue.capacity(), delay_mm.queue.capacity());
+    }
+
     #[test]
     fn delay_small() {
         let mut waveform = vec![0.0, -0.5, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
@@ -504,4 +594,38 @@ mod tests {
         delay.process_waveform(&mut waveform).unwrap();
         assert!(compare_waveforms(waveform, waveform_delayed, 1.0e-6));
     }
+
+    #[test]
+    fn volume_persists_across_reload() {
+        let params = Arc::new(ProcessingParameters::default());
+        params.set_target_volume(1, -20.0);
+        let conf = config::VolumeParameters {
+            ramp_time: Some(0.0),
+            fader: config::VolumeFader::Aux1,
+        };
+        let mut vol = Volume::from_config("vol", conf, 4, 44100, params.clone());
+        let mut waveform = vec![1.0; 4];
+        vol.process_waveform(&mut waveform).unwrap();
+        assert!(compare_waveforms(waveform, vec![0.1; 4], 1.0e-6));
+
+        // Reloading the config with changed parameters must keep the fader volume.
+        let new_conf = config::VolumeParameters {
+            ramp_time: Some(200.0),
+            fader: config::VolumeFader::Aux1,
+        };
+        vol.update_parameters(config::Filter::Volume {
+            description: None,
+            parameters: new_conf.clone(),
+        });
+        let mut waveform = vec![1.0; 4];
+        vol.process_waveform(&mut waveform).unwrap();
+        assert!(compare_waveforms(waveform, vec![0.1; 4], 1.0e-6));
+
+        // A rebuilt pipeline creates a new filter, that must start at the same volume.
+        let mut new_vol = Volume::from_config("vol", new_conf, 4, 44100, params.clone());
+        let mut waveform = vec![1.0; 4];
+        new_vol.process_waveform(&mut waveform).unwrap();
+        assert!(compare_waveforms(waveform, vec![0.1; 4], 1.0e-6));
+        assert_eq!(params.target_volume(1), -20.0);
+    }
 }
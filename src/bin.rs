@@ -35,7 +35,7 @@ use std::sync::atomic::AtomicBool;
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use flexi_logger::DeferredNow;
 use log::Record;
@@ -58,11 +58,13 @@ use camillalib::statefile;
 use camillalib::ControllerMessage;
 #[cfg(feature = "websocket")]
 use std::net::IpAddr;
+#[cfg(feature = "websocket")]
+use std::sync::atomic::AtomicUsize;
 
 use camillalib::{
     list_supported_devices, CaptureStatus, CommandMessage, ExitState, PlaybackStatus,
-    ProcessingParameters, ProcessingState, ProcessingStatus, SharedConfigs, StatusMessage,
-    StatusStructs, StopReason,
+    ProcessingParameters, ProcessingState, ProcessingStatus, RecentErrors, SharedConfigs,
+    StatusMessage, StatusStructs, StopReason,
 };
 
 const EXIT_BAD_CONFIG: i32 = 101; // Error in config file
@@ -105,6 +107,44 @@ pub fn custom_logger_format(
     )
 }
 
+fn new_playback_status() -> PlaybackStatus {
+    PlaybackStatus {
+        buffer_level: 0,
+        clipped_samples: 0,
+        update_interval: 1000,
+        signal_rms: countertimer::ValueHistory::new(1024, 2),
+        signal_peak: countertimer::ValueHistory::new(1024, 2),
+        true_peak: countertimer::TruePeakRecord::default(),
+        signal_minmax: audiodevice::SignalMinMax::default(),
+        frames_played: 0,
+        meter_ballistics: None,
+    }
+}
+
+fn join_playback_threads(handles: Vec<thread::JoinHandle<()>>) {
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+/// After a capture format change, get a config using the pipeline variant
+/// for the new number of capture channels, or the default pipeline if there is none, to restart processing with.
+/// Returns None if the number of channels is unchanged or unknown.
+fn config_for_new_capture_channels(
+    active_config: &config::Configuration,
+) -> Option<config::Configuration> {
+    active_config.pipeline_by_channels.as_ref()?;
+    let channels = audiodevice::current_capture_channels(&active_config.devices.capture)?;
+    if channels == active_config.devices.capture.channels() {
+        return None;
+    }
+    info!(
+        "Capture device changed to {} channels, restarting with the pipeline for this number of channels",
+        channels
+    );
+    config::config_for_capture_channels(active_config, channels)
+}
+
 fn run(
     shared_configs: SharedConfigs,
     status_structs: StatusStructs,
@@ -128,7 +168,14 @@ fn run(
     let (tx_command_cap, rx_command_cap) = mpsc::channel();
     let (tx_pipeconf, rx_pipeconf) = mpsc::channel();
 
-    let barrier = Arc::new(Barrier::new(4));
+    // Additional outputs, each with a playback device fed by the processing thread
+    let output_configs = active_config.output_configs();
+    let nbr_outputs = output_configs.len();
+    let (tx_outputs, rx_outputs): (Vec<_>, Vec<_>) = (0..nbr_outputs)
+        .map(|_| mpsc::sync_channel(active_config.devices.queuelimit()))
+        .unzip();
+
+    let barrier = Arc::new(Barrier::new(4 + nbr_outputs));
     let barrier_pb = barrier.clone();
     let barrier_cap = barrier.clone();
     let barrier_proc = barrier.clone();
@@ -143,15 +190,48 @@ fn run(
         barrier_proc,
         tx_pb,
         rx_cap,
+        tx_outputs,
         rx_pipeconf,
         status_structs.processing,
+        status_structs.capture.clone(),
+        status_structs.playback.clone(),
     );
 
     // Playback thread
+    {
+        let mut playback_status = status_structs.playback.write();
+        playback_status.meter_ballistics = active_config.devices.meter_ballistics;
+        playback_status.frames_played = 0;
+    }
     let mut playback_dev = audiodevice::new_playback_device(conf_pb.devices);
     let pb_handle = playback_dev
-        .start(rx_pb, barrier_pb, tx_status_pb, status_structs.playback)
+        .start(
+            rx_pb,
+            barrier_pb,
+            tx_status_pb.clone(),
+            status_structs.playback,
+        )
         .unwrap();
+    // The handles of all playback threads, the main one first and then the additional outputs.
+    // All of them must be joined before returning.
+    let mut pb_handles = Vec::with_capacity(1 + nbr_outputs);
+    pb_handles.push(*pb_handle);
+
+    // Playback threads of the additional outputs
+    for (output_conf, rx_output) in output_configs.into_iter().zip(rx_outputs) {
+        let output_status = Arc::new(RwLock::new(new_playback_status()));
+        output_status.write().meter_ballistics = active_config.devices.meter_ballistics;
+        let mut output_dev = audiodevice::new_playback_device(output_conf.devices);
+        let handle = output_dev
+            .start(
+                rx_output,
+                barrier.clone(),
+                tx_status_pb.clone(),
+                output_status,
+            )
+            .unwrap();
+        pb_handles.push(*handle);
+    }
 
     let used_channels = config::used_capture_channels(&active_config);
     debug!("Using channels {:?}", used_channels);
@@ -159,7 +239,10 @@ fn run(
         let mut capture_status = status_structs.capture.write();
         capture_status.state = ProcessingState::Starting;
         capture_status.used_channels = used_channels;
+        capture_status.meter_ballistics = active_config.devices.meter_ballistics;
+        capture_status.frames_captured = 0;
     }
+    status_structs.status.write().started = Some(Instant::now());
 
     // Capture thread
     let mut capture_dev = audiodevice::new_capture_device(conf_cap.devices);
@@ -173,6 +256,7 @@ fn run(
         )
         .unwrap();
 
+    let mut nbr_pb_ready = 0;
     let mut pb_ready = false;
     let mut cap_ready = false;
 
@@ -194,6 +278,7 @@ fn run(
                         let comp = config::config_diff(&active_config, &new_conf);
                         match comp {
                             config::ConfigChange::Pipeline
+                            | config::ConfigChange::PipelineBypass
                             | config::ConfigChange::MixerParameters
                             | config::ConfigChange::FilterParameters { .. } => {
                                 tx_pipeconf.send((comp, *new_conf.clone())).unwrap();
@@ -210,7 +295,7 @@ fn run(
                                     debug!("Capture thread has already exited");
                                 }
                                 trace!("Wait for pb..");
-                                pb_handle.join().unwrap();
+                                join_playback_threads(pb_handles);
                                 trace!("Wait for cap..");
                                 cap_handle.join().unwrap();
                                 *shared_configs.active.lock() = Some(*new_conf);
@@ -222,13 +307,22 @@ fn run(
                             }
                         };
                     },
+                    Ok(ControllerMessage::ReloadFilter(name)) => {
+                        debug!("Reloading coefficients of filter '{}'", name);
+                        let comp = config::ConfigChange::FilterParameters {
+                            filters: vec![name],
+                            mixers: Vec::new(),
+                            processors: Vec::new(),
+                        };
+                        tx_pipeconf.send((comp, active_config.clone())).unwrap();
+                    },
                     Ok(ControllerMessage::Stop) => {
                         debug!("Stop requested...");
                         if tx_command_cap.send(CommandMessage::Exit).is_err() {
                             debug!("Capture thread has already exited");
                         }
                         trace!("Wait for pb..");
-                        pb_handle.join().unwrap();
+                        join_playback_threads(pb_handles);
                         trace!("Wait for cap..");
                         cap_handle.join().unwrap();
                         {
@@ -246,7 +340,7 @@ fn run(
                             debug!("Capture thread has already exited");
                         }
                         trace!("Wait for pb..");
-                        pb_handle.join().unwrap();
+                        join_playback_threads(pb_handles);
                         trace!("Wait for cap..");
                         cap_handle.join().unwrap();
                         *shared_configs.previous.lock() = Some(active_config);
@@ -263,7 +357,8 @@ fn run(
                     Ok(msg) => match msg {
                         StatusMessage::PlaybackReady => {
                             debug!("Playback thread ready to start");
-                            pb_ready = true;
+                            nbr_pb_ready += 1;
+                            pb_ready = nbr_pb_ready == 1 + nbr_outputs;
                             if cap_ready {
                                 debug!("Both capture and playback ready, release barrier");
                                 barrier.wait();
@@ -284,6 +379,7 @@ fn run(
                         }
                         StatusMessage::PlaybackError(message) => {
                             error!("Playback error: {}", message);
+                            status_structs.status.write().recent_errors.push(format!("Playback error: {}", message));
                             if tx_command_cap.send(CommandMessage::Exit).is_err() {
                                 debug!("Capture thread has already exited");
                             }
@@ -294,6 +390,8 @@ fn run(
                             debug!("Wait for capture thread to exit..");
                             status_structs.status.write().stop_reason = StopReason::PlaybackError(message);
                             cap_handle.join().unwrap();
+                            debug!("Wait for playback threads to exit..");
+                            join_playback_threads(pb_handles);
                             {
                                 let mut active_cfg_shared = shared_configs.active.lock();
                                 let mut prev_cfg_shared = shared_configs.previous.lock();
@@ -305,13 +403,14 @@ fn run(
                         }
                         StatusMessage::CaptureError(message) => {
                             error!("Capture error: {}", message);
+                            status_structs.status.write().recent_errors.push(format!("Capture error: {}", message));
                             if is_starting {
                                 debug!("Error while starting, release barrier");
                                 barrier.wait();
                             }
                             debug!("Wait for playback thread to exit..");
                             status_structs.status.write().stop_reason = StopReason::CaptureError(message);
-                            pb_handle.join().unwrap();
+                            join_playback_threads(pb_handles);
                             {
                                 let mut active_cfg_shared = shared_configs.active.lock();
                                 let mut prev_cfg_shared = shared_configs.previous.lock();
@@ -323,6 +422,7 @@ fn run(
                         }
                         StatusMessage::PlaybackFormatChange(rate) => {
                             error!("Playback stopped due to external format change");
+                            status_structs.status.write().recent_errors.push(format!("Playback stopped due to external format change, new sample rate: {}", rate));
                             if tx_command_cap.send(CommandMessage::Exit).is_err() {
                                 debug!("Capture thread has already exited");
                             }
@@ -334,6 +434,8 @@ fn run(
                             status_structs.status.write().stop_reason =
                                 StopReason::PlaybackFormatChange(rate);
                             cap_handle.join().unwrap();
+                            debug!("Wait for playback threads to exit..");
+                            join_playback_threads(pb_handles);
                             {
                                 let mut active_cfg_shared = shared_configs.active.lock();
                                 let mut prev_cfg_shared = shared_configs.previous.lock();
@@ -345,6 +447,7 @@ fn run(
                         }
                         StatusMessage::CaptureFormatChange(rate) => {
                             error!("Capture stopped due to external format change");
+                            status_structs.status.write().recent_errors.push(format!("Capture stopped due to external format change, new sample rate: {}", rate));
                             if is_starting {
                                 debug!("Error while starting, release barrier");
                                 barrier.wait();
@@ -352,11 +455,11 @@ fn run(
                             debug!("Wait for playback thread to exit..");
                             status_structs.status.write().stop_reason =
                                 StopReason::CaptureFormatChange(rate);
-                            pb_handle.join().unwrap();
+                            join_playback_threads(pb_handles);
                             {
                                 let mut active_cfg_shared = shared_configs.active.lock();
                                 let mut prev_cfg_shared = shared_configs.previous.lock();
-                                *active_cfg_shared = None;
+                                *active_cfg_shared = config_for_new_capture_channels(&active_config);
                                 *prev_cfg_shared = Some(active_config);
                             }
                             trace!("All threads stopped, returning");
@@ -370,6 +473,8 @@ fn run(
                                     RwLockUpgradableReadGuard::upgrade(stat).stop_reason = StopReason::Done;
                                 }
                             }
+                            debug!("Wait for playback threads to exit..");
+                            join_playback_threads(pb_handles);
                             {
                                 let mut active_cfg_shared = shared_configs.active.lock();
                                 let mut prev_cfg_shared = shared_configs.previous.lock();
@@ -382,6 +487,12 @@ fn run(
                         StatusMessage::CaptureDone => {
                             info!("Capture finished");
                         }
+                        StatusMessage::PlaybackUnderrun => {
+                            status_structs.status.write().recent_errors.push("Playback buffer underrun".to_string());
+                        }
+                        StatusMessage::CaptureOverrun => {
+                            status_structs.status.write().recent_errors.push("Capture buffer overrun".to_string());
+                        }
                         StatusMessage::SetSpeed(speed) => {
                             debug!("SetSpeed message received");
                             if tx_command_cap
@@ -394,9 +505,11 @@ fn run(
                     },
                     Err(err) => {
                         warn!("Capture, Playback and Processing threads have exited: {}", err);
-                        status_structs.status.write().stop_reason = StopReason::UnknownError(
+                        let mut status = status_structs.status.write();
+                        status.stop_reason = StopReason::UnknownError(
                             "Capture, Playback and Processing threads have exited".to_string(),
                         );
+                        status.recent_errors.push("Capture, Playback and Processing threads have exited".to_string());
                         return Ok(ExitState::Restart);
                     }
                 }
@@ -475,6 +588,14 @@ fn main_process() -> i32 {
                 .long("check")
                 .requires("configfile"),
         )
+        .arg(
+            Arg::with_name("offline")
+                .help(
+                    "Process file as fast as possible and exit, requires File capture and playback",
+                )
+                .long("offline")
+                .requires("configfile"),
+        )
         .arg(
             Arg::with_name("verbosity")
                 .short("v")
@@ -620,12 +741,37 @@ fn main_process() -> i32 {
                     Err(String::from("Must be a valid IP address"))
                 }),
         )
+        .arg(
+            Arg::with_name("ws_max_connections")
+                .help("Maximum number of simultaneous websocket connections")
+                .long("ws-max-connections")
+                .display_order(200)
+                .takes_value(true)
+                .requires("port")
+                .validator(|v: String| -> Result<(), String> {
+                    if let Ok(max) = v.parse::<usize>() {
+                        if max > 0 {
+                            return Ok(());
+                        }
+                    }
+                    Err(String::from("Must be an integer larger than 0"))
+                }),
+        )
+        .arg(
+            Arg::with_name("config_dir")
+                .help("Directory of config files that can be listed and loaded via the websocket server")
+                .long("config-dir")
+                .display_order(200)
+                .takes_value(true)
+                .requires("port"),
+        )
         .arg(
             Arg::with_name("wait")
                 .short("w")
                 .long("wait")
                 .help("Wait for config from websocket")
-                .requires("port"),
+                .requires("port")
+                .conflicts_with("offline"),
         );
     #[cfg(feature = "secure-websocket")]
     let clapapp = clapapp
@@ -717,6 +863,7 @@ fn main_process() -> i32 {
         overrides.sample_format = matches
             .value_of("format")
             .map(|s| config::SampleFormat::from_name(s).unwrap());
+        overrides.offline = matches.is_present("offline");
     }
 
     let statefilename = matches.value_of("statefile").map(|path| path.to_string());
@@ -787,12 +934,18 @@ fn main_process() -> i32 {
         }
     }
 
+    let fader_presets = state
+        .as_ref()
+        .map(|s| s.fader_presets.clone())
+        .unwrap_or_default();
+
     // All state variables are prepared, save to the statefile if needed
     if let Some(fname) = &statefilename {
         let state_to_save = statefile::State {
             config_path: configname.clone(),
             volume: initial_volumes,
             mute: initial_mutes,
+            fader_presets: fader_presets.clone(),
         };
         if state.is_none() || state.map(|s| s != state_to_save).unwrap_or(false) {
             statefile::save_state_to_file(fname, &state_to_save);
@@ -910,18 +1063,21 @@ fn main_process() -> i32 {
         state: ProcessingState::Inactive,
         signal_rms: countertimer::ValueHistory::new(1024, 2),
         signal_peak: countertimer::ValueHistory::new(1024, 2),
+        true_peak: countertimer::TruePeakRecord::default(),
+        signal_minmax: audiodevice::SignalMinMax::default(),
+        recent_samples: audiodevice::RecentSamples::default(),
+        signal_duty: countertimer::DutyCycle::default(),
+        frames_captured: 0,
         used_channels: Vec::new(),
+        meter_ballistics: None,
     }));
-    let playback_status = Arc::new(RwLock::new(PlaybackStatus {
-        buffer_level: 0,
-        clipped_samples: 0,
-        update_interval: 1000,
-        signal_rms: countertimer::ValueHistory::new(1024, 2),
-        signal_peak: countertimer::ValueHistory::new(1024, 2),
-    }));
+    let playback_status = Arc::new(RwLock::new(new_playback_status()));
     let processing_params = Arc::new(ProcessingParameters::new(&initial_volumes, &initial_mutes));
+    processing_params.set_fader_presets(fader_presets);
     let processing_status = Arc::new(RwLock::new(ProcessingStatus {
         stop_reason: StopReason::None,
+        started: None,
+        recent_errors: RecentErrors::default(),
     }));
 
     let status_structs = StatusStructs {
@@ -956,11 +1112,20 @@ fn main_process() -> i32 {
                 processing_status,
                 state_change_notify: tx_state,
                 state_file_path: statefilename.clone(),
+                config_dir: matches.value_of("config_dir").map(|dir| dir.to_string()),
                 unsaved_state_change: unsaved_state_changes.clone(),
+                connected_clients: Arc::new(AtomicUsize::new(0)),
+                pipeline_solo: Arc::new(Mutex::new(None)),
+                pending_configs: Arc::new(Mutex::new(socketserver::PendingConfigs::default())),
+                pipeline_latency: Arc::new(Mutex::new(None)),
+                spectrum_analyzer: Arc::new(Mutex::new(audiodevice::SpectrumAnalyzer::default())),
             };
             let server_params = socketserver::ServerParameters {
                 port: serverport,
                 address: serveraddress,
+                max_connections: matches
+                    .value_of("ws_max_connections")
+                    .map(|max| max.parse::<usize>().unwrap()),
                 #[cfg(feature = "secure-websocket")]
                 cert_file: matches.value_of("cert"),
                 #[cfg(feature = "secure-websocket")]
@@ -1013,6 +1178,9 @@ fn main_process() -> i32 {
                     debug!("Config change command received");
                     *active_config.lock() = Some(*new_conf);
                 }
+                Ok(ControllerMessage::ReloadFilter(name)) => {
+                    debug!("Not processing, ignoring reload of filter '{}'", name);
+                }
                 Ok(ControllerMessage::Stop) => {
                     debug!("Stop command received");
                     *active_config.lock() = None;
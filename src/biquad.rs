@@ -11,6 +11,7 @@ use std::arch::x86_64::{
 
 use crate::config;
 use crate::filters::Filter;
+use num_complex::Complex;
 
 // Sample format
 //type SmpFmt = i16;
@@ -60,12 +61,21 @@ impl BiquadCoefficients {
         self.a2.abs() < 1.0 && (self.a1.abs() < (self.a2 + 1.0))
     }
 
+    /// Calculate the complex gain at the given frequency.
+    pub fn complex_gain(&self, freq: PrcFmt, fs: usize) -> Complex<PrcFmt> {
+        let pi = std::f64::consts::PI as PrcFmt;
+        let z = (Complex::i() * 2.0 * pi * freq / (fs as PrcFmt)).exp();
+        (self.b0 + self.b1 * z.powi(-1) + self.b2 * z.powi(-2))
+            / (1.0 + self.a1 * z.powi(-1) + self.a2 * z.powi(-2))
+    }
+
     /// Create biquad filters from config.
     /// Filter types
     /// - Free: just coefficients
     /// - Highpass: second order highpass specified by frequency and Q-value.
     /// - Lowpass: second order lowpass specified by frequency and Q-value.
     /// - Peaking: parametric peaking filter specified by gain, frequency and Q-value.
+    ///   With proportional Q, the Q-value increases with the magnitude of the gain.
     /// - Highshelf: shelving filter affecting high frequencies with arbitrary slope in between.
     ///   The frequency specified is the middle of the slope
     /// - Lowshelf: shelving filter affecting low frequencies with arbitrary slope in between.
@@ -134,6 +144,18 @@ impl BiquadCoefficients {
                 let a2 = 1.0 - (alpha / ampl);
                 BiquadCoefficients::normalize(a0, a1, a2, b0, b1, b2)
             }
+            config::BiquadParameters::Peaking(config::PeakingWidth::ProportionalQ {
+                freq,
+                base_q,
+                gain,
+                proportion,
+            }) => {
+                let q = proportional_q(base_q, gain, proportion);
+                BiquadCoefficients::from_config(
+                    fs,
+                    config::BiquadParameters::Peaking(config::PeakingWidth::Q { freq, gain, q }),
+                )
+            }
 
             config::BiquadParameters::Highshelf(config::ShelfSteepness::Q { freq, q, gain }) => {
                 let omega = 2.0 * (std::f64::consts::PI as PrcFmt) * freq / (fs as PrcFmt);
@@ -468,6 +490,16 @@ impl Biquad {
         out
     }
 
+    /// Calculate the complex gain at the given frequency.
+    pub fn complex_gain(&self, freq: PrcFmt) -> Complex<PrcFmt> {
+        self.coeffs.complex_gain(freq, self.samplerate)
+    }
+
+    /// Replace the coefficients while keeping the filter state.
+    pub fn set_coefficients(&mut self, coefficients: BiquadCoefficients) {
+        self.coeffs = coefficients;
+    }
+
     /// Flush stored subnormal numbers to zero.
     fn flush_subnormals(&mut self) {
         if self.s1.is_subnormal() {
@@ -508,6 +540,14 @@ impl Filter for Biquad {
     }
 }
 
+/// Calculate the effective Q-value of a proportional-Q peaking filter.
+/// The Q-value is scaled by the linear amplitude of the gain raised to the given proportion,
+/// so that it doubles for every 12 dB of boost or cut when the proportion is 1.0.
+/// A proportion of zero gives a constant-Q filter.
+pub fn proportional_q(base_q: PrcFmt, gain: PrcFmt, proportion: PrcFmt) -> PrcFmt {
+    base_q * PrcFmt::coerce(10.0).powf(proportion * gain.abs() / 40.0)
+}
+
 pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters) -> Res<()> {
     let maxfreq = samplerate as PrcFmt / 2.0;
     // Check frequency
@@ -518,6 +558,7 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
         | config::BiquadParameters::LowpassFO { freq, .. }
         | config::BiquadParameters::Peaking(config::PeakingWidth::Q { freq, .. })
         | config::BiquadParameters::Peaking(config::PeakingWidth::Bandwidth { freq, .. })
+        | config::BiquadParameters::Peaking(config::PeakingWidth::ProportionalQ { freq, .. })
         | config::BiquadParameters::Highshelf(config::ShelfSteepness::Q { freq, .. })
         | config::BiquadParameters::Lowshelf(config::ShelfSteepness::Q { freq, .. })
         | config::BiquadParameters::Highshelf(config::ShelfSteepness::Slope { freq, .. })
@@ -544,6 +585,10 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
         config::BiquadParameters::Highpass { q, .. }
         | config::BiquadParameters::Lowpass { q, .. }
         | config::BiquadParameters::Peaking(config::PeakingWidth::Q { q, .. })
+        | config::BiquadParameters::Peaking(config::PeakingWidth::ProportionalQ {
+            base_q: q,
+            ..
+        })
         | config::BiquadParameters::Notch(config::NotchWidth::Q { q, .. })
         | config::BiquadParameters::Bandpass(config::NotchWidth::Q { q, .. })
         | config::BiquadParameters::Allpass(config::NotchWidth::Q { q, .. })
@@ -570,6 +615,16 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
         }
         _ => {}
     }
+    // Check proportion
+    if let config::BiquadParameters::Peaking(config::PeakingWidth::ProportionalQ {
+        proportion,
+        ..
+    }) = parameters
+    {
+        if *proportion < 0.0 {
+            return Err(config::ConfigError::new("Proportion must be >= 0").into());
+        }
+    }
     // Check slope
     match parameters {
         config::BiquadParameters::Highshelf(config::ShelfSteepness::Slope { slope, .. })
@@ -619,7 +674,7 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
 
 #[cfg(test)]
 mod tests {
-    use crate::biquad::{validate_config, Biquad, BiquadCoefficients};
+    use crate::biquad::{proportional_q, validate_config, Biquad, BiquadCoefficients};
     use crate::config::{
         BiquadParameters, GeneralNotchParams, NotchWidth, PeakingWidth, ShelfSteepness,
     };
@@ -1073,6 +1128,39 @@ mod tests {
         assert!(validate_config(fs, &badconf3).is_err());
     }
 
+    #[test]
+    fn check_proportional_q() {
+        let fs = 48000;
+        assert!(is_close(proportional_q(1.0, 0.0, 1.0), 1.0, 1.0e-9));
+        assert!(is_close(proportional_q(1.0, 12.0, 1.0), 2.0, 0.01));
+        assert!(is_close(proportional_q(1.0, -12.0, 1.0), 2.0, 0.01));
+        assert!(is_close(proportional_q(1.0, 12.0, 0.0), 1.0, 1.0e-9));
+        let conf = BiquadParameters::Peaking(PeakingWidth::ProportionalQ {
+            freq: 1000.0,
+            base_q: 0.7,
+            gain: 6.0,
+            proportion: 1.0,
+        });
+        assert!(validate_config(fs, &conf).is_ok());
+        let coeffs = BiquadCoefficients::from_config(fs, conf);
+        let (gain_peak, _) = gain_and_phase(coeffs, 1000.0, fs);
+        assert!(is_close(gain_peak, 6.0, 0.01));
+        let badconf1 = BiquadParameters::Peaking(PeakingWidth::ProportionalQ {
+            freq: 1000.0,
+            base_q: 0.0,
+            gain: 6.0,
+            proportion: 1.0,
+        });
+        assert!(validate_config(fs, &badconf1).is_err());
+        let badconf2 = BiquadParameters::Peaking(PeakingWidth::ProportionalQ {
+            freq: 1000.0,
+            base_q: 0.7,
+            gain: 6.0,
+            proportion: -1.0,
+        });
+        assert!(validate_config(fs, &badconf2).is_err());
+    }
+
     #[test]
     fn check_slope() {
         let fs = 48000;
@@ -1095,4 +1183,17 @@ mod tests {
         });
         assert!(validate_config(fs, &badconf2).is_err());
     }
+
+    #[test]
+    fn lowpass_phase_at_cutoff() {
+        let conf = BiquadParameters::Lowpass {
+            freq: 1000.0,
+            q: 0.707,
+        };
+        let coeffs = BiquadCoefficients::from_config(48000, conf);
+        let phase = coeffs.complex_gain(1000.0, 48000).arg().to_degrees();
+        assert!(is_close(phase, -90.0, 1e-3));
+        let (_gain, phase_ref) = gain_and_phase(coeffs, 1000.0, 48000);
+        assert!(is_close(phase, phase_ref, 1e-4));
+    }
 }
@@ -6,12 +6,19 @@
 use crate::biquad;
 use crate::config;
 use crate::filters::Filter;
+use num_complex::Complex;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor};
 
 // Sample format
 //type SmpFmt = i16;
 use crate::PrcFmt;
 use crate::Res;
 
+// Frequencies of the shelf filters of a Tilt with the default pivot.
+const TILT_LOWSHELF_FREQ: PrcFmt = 110.0;
+const TILT_HIGHSHELF_FREQ: PrcFmt = 3500.0;
+
 #[derive(Clone, Debug)]
 pub struct BiquadCombo {
     samplerate: usize,
@@ -65,6 +72,74 @@ impl BiquadCombo {
         filters
     }
 
+    /// Make an allpass with the same phase as the sum of a Linkwitz-Riley lowpass and highpass.
+    /// The summed response is B(-s)/B(s), where B(s) is the Butterworth polynomial of half the order.
+    /// This gives one second order allpass section for each pair of Butterworth poles,
+    /// and a first order section for the real pole when the Butterworth order is odd.
+    fn make_allpass(fs: usize, freq: PrcFmt, qvalues: Vec<PrcFmt>) -> Vec<biquad::Biquad> {
+        let mut filters = Vec::with_capacity(qvalues.len());
+        for q in qvalues.iter() {
+            let filtconf = if q >= &0.0 {
+                config::BiquadParameters::Allpass(config::NotchWidth::Q { freq, q: *q })
+            } else {
+                config::BiquadParameters::AllpassFO { freq }
+            };
+            let coeffs = biquad::BiquadCoefficients::from_config(fs, filtconf);
+            let filt = biquad::Biquad::new("", fs, coeffs);
+            filters.push(filt);
+        }
+        filters
+    }
+
+    /// Make a Butterworth bandstop by transforming the poles of a lowpass prototype.
+    /// Each real pole gives one notch section, and each pair of complex poles gives two.
+    /// The sections are designed in the analog domain with prewarped edge frequencies,
+    /// and then converted with the bilinear transform.
+    fn make_bandstop(
+        fs: usize,
+        freq_low: PrcFmt,
+        freq_high: PrcFmt,
+        qvalues: Vec<PrcFmt>,
+    ) -> Vec<biquad::Biquad> {
+        let pi = std::f64::consts::PI as PrcFmt;
+        let k = 2.0 * fs as PrcFmt;
+        let w_low = k * (pi * freq_low / fs as PrcFmt).tan();
+        let w_high = k * (pi * freq_high / fs as PrcFmt).tan();
+        let w0_sq = w_low * w_high;
+        let bandwidth = w_high - w_low;
+        // Denominators s^2 + a1*s + a0 of the analog sections, the numerators are all s^2 + w0^2.
+        let mut denominators = Vec::with_capacity(2 * qvalues.len());
+        for q in qvalues.iter() {
+            if *q < 0.0 {
+                denominators.push((bandwidth, w0_sq));
+            } else {
+                let re = -1.0 / (2.0 * q);
+                let pole = Complex::new(re, (1.0 - re * re).sqrt());
+                let c = bandwidth / pole;
+                let root = (c * c - 4.0 * w0_sq).sqrt();
+                for r in [(c + root) / 2.0, (c - root) / 2.0] {
+                    denominators.push((-2.0 * r.re, r.norm_sqr()));
+                }
+            }
+        }
+        denominators
+            .iter()
+            .map(|(a1, a0)| {
+                let k_sq = k * k;
+                let norm = k_sq + a1 * k + a0;
+                let filtconf = config::BiquadParameters::Free {
+                    a1: 2.0 * (a0 - k_sq) / norm,
+                    a2: (k_sq - a1 * k + a0) / norm,
+                    b0: (k_sq + w0_sq) / norm,
+                    b1: 2.0 * (w0_sq - k_sq) / norm,
+                    b2: (k_sq + w0_sq) / norm,
+                };
+                let coeffs = biquad::BiquadCoefficients::from_config(fs, filtconf);
+                biquad::Biquad::new("", fs, coeffs)
+            })
+            .collect()
+    }
+
     fn linkwitzriley_q(order: usize) -> Vec<PrcFmt> {
         let mut q_temp = BiquadCombo::butterworth_q(order / 2);
         let mut qvalues;
@@ -80,16 +155,30 @@ impl BiquadCombo {
         qvalues
     }
 
-    fn make_tilt(fs: usize, gain: PrcFmt) -> Vec<biquad::Biquad> {
+    /// Get the frequencies of the low and high shelf of a Tilt.
+    /// The shelves are placed symmetrically on a logarithmic scale around the pivot frequency,
+    /// with the same spacing as for the default pivot of about 620 Hz.
+    fn tilt_shelf_freqs(pivot_freq: Option<PrcFmt>) -> (PrcFmt, PrcFmt) {
+        match pivot_freq {
+            Some(pivot) => {
+                let ratio = (TILT_HIGHSHELF_FREQ / TILT_LOWSHELF_FREQ).sqrt();
+                (pivot / ratio, pivot * ratio)
+            }
+            None => (TILT_LOWSHELF_FREQ, TILT_HIGHSHELF_FREQ),
+        }
+    }
+
+    fn make_tilt(fs: usize, gain: PrcFmt, pivot_freq: Option<PrcFmt>) -> Vec<biquad::Biquad> {
         let gain_low = -gain / 2.0;
         let gain_high = gain / 2.0;
+        let (freq_low, freq_high) = BiquadCombo::tilt_shelf_freqs(pivot_freq);
         let lsconf = config::BiquadParameters::Lowshelf(config::ShelfSteepness::Q {
-            freq: 110.0,
+            freq: freq_low,
             q: 0.35,
             gain: gain_low,
         });
         let hsconf = config::BiquadParameters::Highshelf(config::ShelfSteepness::Q {
-            freq: 3500.0,
+            freq: freq_high,
             q: 0.35,
             gain: gain_high,
         });
@@ -166,6 +255,33 @@ impl BiquadCombo {
         filters
     }
 
+    /// Smooth the gains of a graphic equalizer with a weighted moving average over neighboring bands.
+    /// Each band is averaged with its two neighbors, weighted by half the smoothing factor each.
+    /// A smoothing of 0 leaves the gains unchanged, and 1 gives the weights 1/4, 1/2, 1/4.
+    /// At the ends, the missing neighbor is replaced by the band itself.
+    fn smooth_graphic_gains(gains: &[f32], smoothing: f32) -> Vec<f32> {
+        if smoothing == 0.0 {
+            return gains.to_vec();
+        }
+        (0..gains.len())
+            .map(|band| {
+                let below = gains[band.saturating_sub(1)];
+                let above = gains[(band + 1).min(gains.len() - 1)];
+                (gains[band] + 0.5 * smoothing * (below + above)) / (1.0 + smoothing)
+            })
+            .collect()
+    }
+
+    fn make_from_table(samplerate: usize, rows: &[[PrcFmt; 5]]) -> Vec<biquad::Biquad> {
+        let mut filters = Vec::with_capacity(rows.len());
+        for [a1, a2, b0, b1, b2] in rows.iter() {
+            let coeffs = biquad::BiquadCoefficients::new(*a1, *a2, *b0, *b1, *b2);
+            let filt = biquad::Biquad::new("", samplerate, coeffs);
+            filters.push(filt);
+        }
+        filters
+    }
+
     pub fn from_config(
         name: &str,
         samplerate: usize,
@@ -191,6 +307,15 @@ impl BiquadCombo {
                     filters,
                 }
             }
+            config::BiquadComboParameters::LinkwitzRileyAllpass { order, freq } => {
+                let qvalues = BiquadCombo::butterworth_q(order / 2);
+                let filters = BiquadCombo::make_allpass(samplerate, freq, qvalues);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
             config::BiquadComboParameters::ButterworthHighpass { order, freq } => {
                 let qvalues = BiquadCombo::butterworth_q(order);
                 let filters = BiquadCombo::make_highpass(samplerate, freq, qvalues);
@@ -209,8 +334,37 @@ impl BiquadCombo {
                     filters,
                 }
             }
-            config::BiquadComboParameters::Tilt { gain } => {
-                let filters = BiquadCombo::make_tilt(samplerate, gain);
+            config::BiquadComboParameters::ButterworthBandpass {
+                order,
+                freq_low,
+                freq_high,
+            } => {
+                let qvalues = BiquadCombo::butterworth_q(order);
+                let mut filters = BiquadCombo::make_highpass(samplerate, freq_low, qvalues.clone());
+                filters.append(&mut BiquadCombo::make_lowpass(
+                    samplerate, freq_high, qvalues,
+                ));
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
+            config::BiquadComboParameters::ButterworthBandstop {
+                order,
+                freq_low,
+                freq_high,
+            } => {
+                let qvalues = BiquadCombo::butterworth_q(order);
+                let filters = BiquadCombo::make_bandstop(samplerate, freq_low, freq_high, qvalues);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
+            config::BiquadComboParameters::Tilt { gain, pivot_freq } => {
+                let filters = BiquadCombo::make_tilt(samplerate, gain, pivot_freq);
                 BiquadCombo {
                     samplerate,
                     name,
@@ -247,11 +401,12 @@ impl BiquadCombo {
                 }
             }
             config::BiquadComboParameters::GraphicEqualizer(params) => {
+                let gains = BiquadCombo::smooth_graphic_gains(&params.gains, params.smoothing());
                 let filters = BiquadCombo::make_graphic(
                     samplerate,
                     params.freq_min(),
                     params.freq_max(),
-                    &params.gains,
+                    &gains,
                 );
                 BiquadCombo {
                     samplerate,
@@ -259,10 +414,36 @@ impl BiquadCombo {
                     filters,
                 }
             }
+            config::BiquadComboParameters::CoefficientTable { filename, channel } => {
+                let rows = match read_coefficient_table(&filename, channel) {
+                    Ok(rows) => rows,
+                    Err(err) => {
+                        error!("{}", err);
+                        Vec::new()
+                    }
+                };
+                let filters = BiquadCombo::make_from_table(samplerate, &rows);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                }
+            }
         }
     }
 }
 
+impl BiquadCombo {
+    /// Calculate the complex gain at the given frequency.
+    pub fn complex_gain(&self, freq: PrcFmt) -> Complex<PrcFmt> {
+        self.filters
+            .iter()
+            .fold(Complex::new(1.0, 0.0), |acc, filter| {
+                acc * filter.complex_gain(freq)
+            })
+    }
+}
+
 impl Filter for BiquadCombo {
     fn name(&self) -> &str {
         &self.name
@@ -289,12 +470,89 @@ impl Filter for BiquadCombo {
     }
 }
 
+/// Read the biquads for one channel from a CSV coefficient table.
+/// Each row contains the channel number followed by the coefficients a1, a2, b0, b1, b2.
+/// Empty lines and lines starting with '#' are ignored.
+pub fn read_coefficient_table(filename: &str, channel: usize) -> Res<Vec<[PrcFmt; 5]>> {
+    let file: Box<dyn BufRead> = if config::is_url(filename) {
+        Box::new(Cursor::new(config::downloaded_file(filename)?))
+    } else {
+        match File::open(filename) {
+            Ok(f) => Box::new(BufReader::new(f)),
+            Err(err) => {
+                let msg = format!("Could not open coefficient table '{filename}'. Error: {err}");
+                return Err(config::ConfigError::new(&msg).into());
+            }
+        }
+    };
+    let mut rows = Vec::new();
+    for (nbr, line) in file.lines().enumerate() {
+        let line = match line {
+            Ok(l) => l,
+            Err(err) => {
+                let msg = format!(
+                    "Can't read line {} of file '{}'. Error: {}",
+                    nbr + 1,
+                    filename,
+                    err
+                );
+                return Err(config::ConfigError::new(&msg).into());
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(',').map(|field| field.trim());
+        let row_channel: usize = match fields.next().map(|field| field.parse()) {
+            Some(Ok(ch)) => ch,
+            _ => {
+                let msg = format!(
+                    "Can't parse channel number on line {} of file '{}'",
+                    nbr + 1,
+                    filename
+                );
+                return Err(config::ConfigError::new(&msg).into());
+            }
+        };
+        let mut values = Vec::with_capacity(5);
+        for field in fields {
+            match field.parse::<PrcFmt>() {
+                Ok(val) => values.push(val),
+                Err(err) => {
+                    let msg = format!(
+                        "Can't parse value on line {} of file '{}'. Error: {}",
+                        nbr + 1,
+                        filename,
+                        err
+                    );
+                    return Err(config::ConfigError::new(&msg).into());
+                }
+            }
+        }
+        if values.len() != 5 {
+            let msg = format!(
+                "Line {} of file '{}' has {} coefficients, expected 5 (a1, a2, b0, b1, b2)",
+                nbr + 1,
+                filename,
+                values.len()
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+        if row_channel == channel {
+            rows.push([values[0], values[1], values[2], values[3], values[4]]);
+        }
+    }
+    Ok(rows)
+}
+
 /// Validate a BiquadCombo convolution config.
 pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters) -> Res<()> {
     let maxfreq = samplerate as PrcFmt / 2.0;
     match conf {
         config::BiquadComboParameters::LinkwitzRileyHighpass { freq, order }
-        | config::BiquadComboParameters::LinkwitzRileyLowpass { freq, order } => {
+        | config::BiquadComboParameters::LinkwitzRileyLowpass { freq, order }
+        | config::BiquadComboParameters::LinkwitzRileyAllpass { freq, order } => {
             if *freq <= 0.0 {
                 return Err(config::ConfigError::new("Frequency must be > 0").into());
             } else if *freq >= maxfreq {
@@ -321,12 +579,52 @@ pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters)
             }
             Ok(())
         }
-        config::BiquadComboParameters::Tilt { gain } => {
+        config::BiquadComboParameters::ButterworthBandpass {
+            freq_low,
+            freq_high,
+            order,
+        }
+        | config::BiquadComboParameters::ButterworthBandstop {
+            freq_low,
+            freq_high,
+            order,
+        } => {
+            if *freq_low <= 0.0 {
+                return Err(config::ConfigError::new("Low frequency must be > 0").into());
+            } else if *freq_high >= maxfreq {
+                return Err(
+                    config::ConfigError::new("High frequency must be < samplerate/2").into(),
+                );
+            } else if *freq_low >= *freq_high {
+                return Err(config::ConfigError::new(
+                    "Low frequency must be lower than the high frequency",
+                )
+                .into());
+            }
+            if *order == 0 {
+                return Err(
+                    config::ConfigError::new("Butterworth order must be larger than zero").into(),
+                );
+            }
+            Ok(())
+        }
+        config::BiquadComboParameters::Tilt { gain, pivot_freq } => {
             if *gain <= -100.0 {
                 return Err(config::ConfigError::new("Gain must be > -100").into());
             } else if *gain >= 100.0 {
                 return Err(config::ConfigError::new("Gain must be < 100").into());
             }
+            if let Some(pivot) = pivot_freq {
+                let (_, freq_high) = BiquadCombo::tilt_shelf_freqs(Some(1.0));
+                let max_pivot = maxfreq / freq_high;
+                if !(*pivot > 0.0 && *pivot < max_pivot) {
+                    let msg = format!(
+                        "Tilt pivot frequency must be > 0 and < {:.0} Hz at this samplerate, got {}",
+                        max_pivot, pivot
+                    );
+                    return Err(config::ConfigError::new(&msg).into());
+                }
+            }
             Ok(())
         }
         config::BiquadComboParameters::FivePointPeq {
@@ -377,6 +675,32 @@ pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters)
                     .into());
                 }
             }
+            if !(0.0..=1.0).contains(&params.smoothing()) {
+                return Err(config::ConfigError::new(
+                    "Equalizer smoothing must be in the range 0 to 1",
+                )
+                .into());
+            }
+            Ok(())
+        }
+        config::BiquadComboParameters::CoefficientTable { filename, channel } => {
+            let rows = read_coefficient_table(filename, *channel)?;
+            if rows.is_empty() {
+                let msg = format!("No biquads found for channel {channel} in '{filename}'");
+                return Err(config::ConfigError::new(&msg).into());
+            }
+            for (nbr, [a1, a2, b0, b1, b2]) in rows.iter().enumerate() {
+                let coeffs = biquad::BiquadCoefficients::new(*a1, *a2, *b0, *b1, *b2);
+                if !coeffs.is_stable() {
+                    let msg = format!(
+                        "Biquad {} for channel {} in '{}' is unstable",
+                        nbr + 1,
+                        channel,
+                        filename
+                    );
+                    return Err(config::ConfigError::new(&msg).into());
+                }
+            }
             Ok(())
         }
     }
@@ -385,6 +709,7 @@ pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters)
 #[cfg(test)]
 mod tests {
     use crate::biquadcombo;
+    use crate::biquadcombo::BiquadCombo;
     use crate::config;
     use crate::PrcFmt;
 
@@ -500,4 +825,165 @@ mod tests {
         };
         assert!(biquadcombo::validate_config(fs, &badconf4).is_err());
     }
+
+    #[test]
+    fn check_butterworth_band() {
+        let fs = 48000;
+        let okconf = config::BiquadComboParameters::ButterworthBandpass {
+            freq_low: 100.0,
+            freq_high: 1000.0,
+            order: 3,
+        };
+        assert!(biquadcombo::validate_config(fs, &okconf).is_ok());
+        let badconf = config::BiquadComboParameters::ButterworthBandstop {
+            freq_low: 1000.0,
+            freq_high: 100.0,
+            order: 3,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf).is_err());
+        let badconf2 = config::BiquadComboParameters::ButterworthBandstop {
+            freq_low: 100.0,
+            freq_high: 25000.0,
+            order: 3,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf2).is_err());
+        let badconf3 = config::BiquadComboParameters::ButterworthBandpass {
+            freq_low: 100.0,
+            freq_high: 1000.0,
+            order: 0,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf3).is_err());
+    }
+
+    #[test]
+    fn lr_allpass_matches_crossover_sum() {
+        let fs = 48000;
+        for order in [2, 4, 6, 8] {
+            let lowpass = BiquadCombo::from_config(
+                "lp",
+                fs,
+                config::BiquadComboParameters::LinkwitzRileyLowpass {
+                    freq: 1000.0,
+                    order,
+                },
+            );
+            let highpass = BiquadCombo::from_config(
+                "hp",
+                fs,
+                config::BiquadComboParameters::LinkwitzRileyHighpass {
+                    freq: 1000.0,
+                    order,
+                },
+            );
+            let allpass = BiquadCombo::from_config(
+                "ap",
+                fs,
+                config::BiquadComboParameters::LinkwitzRileyAllpass {
+                    freq: 1000.0,
+                    order,
+                },
+            );
+            assert_eq!(allpass.filters.len(), (order + 2) / 4);
+            // The highpass is inverted for orders 2, 6, 10 etc, to sum to an allpass
+            let polarity = if order % 4 == 0 { 1.0 } else { -1.0 };
+            for freq in [20.0, 500.0, 1000.0, 2000.0, 15000.0] {
+                let sum = lowpass.complex_gain(freq) + polarity * highpass.complex_gain(freq);
+                let ap = allpass.complex_gain(freq);
+                assert!(is_close(ap.norm(), 1.0, 1e-5));
+                assert!(is_close((sum - ap).norm(), 0.0, 1e-4));
+            }
+        }
+        let badconf = config::BiquadComboParameters::LinkwitzRileyAllpass {
+            freq: 1000.0,
+            order: 3,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf).is_err());
+    }
+
+    #[test]
+    fn butterworth_bandstop_response() {
+        let fs = 48000;
+        for order in [1, 2, 3, 4] {
+            let conf = config::BiquadComboParameters::ButterworthBandstop {
+                freq_low: 200.0,
+                freq_high: 2000.0,
+                order,
+            };
+            let combo = BiquadCombo::from_config("test", fs, conf);
+            assert_eq!(combo.filters.len(), order);
+            let gain = |freq: PrcFmt| combo.complex_gain(freq).norm();
+            assert!(is_close(gain(0.0), 1.0, 1e-3));
+            assert!(is_close(gain(23999.0), 1.0, 1e-3));
+            assert!(is_close(gain(200.0), (0.5 as PrcFmt).sqrt(), 1e-4));
+            assert!(is_close(gain(2000.0), (0.5 as PrcFmt).sqrt(), 1e-4));
+            // The notch is at the geometric center of the prewarped edge frequencies
+            let pi = std::f64::consts::PI as PrcFmt;
+            let prewarp = |freq: PrcFmt| (pi * freq / fs as PrcFmt).tan();
+            let center = (prewarp(200.0) * prewarp(2000.0)).sqrt().atan() * fs as PrcFmt / pi;
+            assert!(gain(center) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn tilt_pivot() {
+        // The gain at the pivot is 0 dB, and the gain is symmetric around it
+        let pivot = 1000.0;
+        let conf = config::BiquadComboParameters::Tilt {
+            gain: 10.0,
+            pivot_freq: Some(pivot),
+        };
+        let tilt = BiquadCombo::from_config("test", 48000, conf);
+        let gain_db = |freq: PrcFmt| 20.0 * tilt.complex_gain(freq).norm().log10();
+        assert!(is_close(gain_db(pivot), 0.0, 0.1));
+        assert!(is_close(gain_db(pivot / 4.0), -gain_db(pivot * 4.0), 0.1));
+        assert!(is_close(gain_db(20.0), -5.0, 0.5));
+        let conf = config::BiquadComboParameters::Tilt {
+            gain: 10.0,
+            pivot_freq: Some(20000.0),
+        };
+        assert!(biquadcombo::validate_config(48000, &conf).is_err());
+    }
+
+    #[test]
+    fn smooth_graphic_gains() {
+        let gains = vec![0.0, 0.0, 4.0, 0.0, 0.0];
+        let unchanged = biquadcombo::BiquadCombo::smooth_graphic_gains(&gains, 0.0);
+        assert_eq!(unchanged, gains);
+        let smoothed = biquadcombo::BiquadCombo::smooth_graphic_gains(&gains, 1.0);
+        assert_eq!(smoothed, vec![0.0, 1.0, 2.0, 1.0, 0.0]);
+        // A flat curve stays flat, also at the ends
+        let flat = biquadcombo::BiquadCombo::smooth_graphic_gains(&[3.0; 4], 0.5);
+        assert_eq!(flat, vec![3.0; 4]);
+    }
+
+    #[test]
+    fn check_coefficient_table() {
+        let rows = biquadcombo::read_coefficient_table("testdata/biquad_table.csv", 0).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(compare_vecs(
+            rows[0].to_vec(),
+            vec![-1.8, 0.81, 1.0, -1.8, 0.81],
+            1.0e-9
+        ));
+        let rows = biquadcombo::read_coefficient_table("testdata/biquad_table.csv", 1).unwrap();
+        assert_eq!(rows.len(), 1);
+        let fs = 48000;
+        let okconf = config::BiquadComboParameters::CoefficientTable {
+            filename: "testdata/biquad_table.csv".to_string(),
+            channel: 1,
+        };
+        assert!(biquadcombo::validate_config(fs, &okconf).is_ok());
+        let combo = biquadcombo::BiquadCombo::from_config("test", fs, okconf);
+        assert!(is_close(combo.complex_gain(0.0).re, 0.8 / 0.3, 1.0e-5));
+        let missing_channel = config::BiquadComboParameters::CoefficientTable {
+            filename: "testdata/biquad_table.csv".to_string(),
+            channel: 2,
+        };
+        assert!(biquadcombo::validate_config(fs, &missing_channel).is_err());
+        let short_row = config::BiquadComboParameters::CoefficientTable {
+            filename: "testdata/biquad_table_bad.csv".to_string(),
+            channel: 0,
+        };
+        assert!(biquadcombo::validate_config(fs, &short_row).is_err());
+    }
 }
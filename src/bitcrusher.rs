@@ -0,0 +1,202 @@
+use crate::audiodevice::AudioChunk;
+use crate::config;
+use crate::filters::Processor;
+use crate::PrcFmt;
+use crate::Res;
+
+// Largest supported bit depth, the same as the largest sample format.
+const MAX_BITS: usize = 32;
+
+#[derive(Clone, Debug)]
+struct HoldState {
+    value: PrcFmt,
+    counter: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct BitCrusher {
+    pub name: String,
+    pub channels: usize,
+    pub bits: usize,
+    pub sample_rate_divisor: usize,
+    step: PrcFmt,
+    hold: Vec<HoldState>,
+}
+
+impl BitCrusher {
+    /// Creates a BitCrusher from a config struct
+    pub fn from_config(name: &str, config: config::BitCrusherParameters) -> Self {
+        let name = name.to_string();
+        let divisor = config.sample_rate_divisor();
+        debug!(
+            "Creating bit crusher '{}', channels: {}, bits: {}, sample rate divisor: {}",
+            name, config.channels, config.bits, divisor
+        );
+        let hold = vec![
+            HoldState {
+                value: 0.0,
+                counter: 0,
+            };
+            config.channels
+        ];
+        BitCrusher {
+            name,
+            channels: config.channels,
+            bits: config.bits,
+            sample_rate_divisor: divisor,
+            step: quantization_step(config.bits),
+            hold,
+        }
+    }
+
+    /// Quantize a sample with a mid-tread quantizer, that keeps the level of the signal unchanged.
+    fn quantize(&self, value: PrcFmt) -> PrcFmt {
+        ((value / self.step).round() * self.step).clamp(-1.0, 1.0)
+    }
+
+    fn process_channel(&mut self, channel: usize, waveform: &mut [PrcFmt]) {
+        let mut state = self.hold[channel].clone();
+        for sample in waveform.iter_mut() {
+            if state.counter == 0 {
+                state.value = self.quantize(*sample);
+            }
+            state.counter = (state.counter + 1) % self.sample_rate_divisor;
+            *sample = state.value;
+        }
+        self.hold[channel] = state;
+    }
+}
+
+/// The distance between quantization levels for a signal with full scale at +-1.0.
+fn quantization_step(bits: usize) -> PrcFmt {
+    (2.0 as PrcFmt).powi(1 - bits as i32)
+}
+
+impl Processor for BitCrusher {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply the bit depth and sample rate reduction to an AudioChunk, modifying it in-place.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        for (channel, waveform) in input.waveforms.iter_mut().enumerate() {
+            if !waveform.is_empty() {
+                self.process_channel(channel, waveform);
+            }
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::BitCrusher {
+            parameters: config, ..
+        } = config
+        {
+            self.bits = config.bits;
+            self.step = quantization_step(config.bits);
+            self.sample_rate_divisor = config.sample_rate_divisor();
+            for state in self.hold.iter_mut() {
+                state.counter = 0;
+            }
+            debug!(
+                "Updated bit crusher '{}', bits: {}, sample rate divisor: {}",
+                self.name, self.bits, self.sample_rate_divisor
+            );
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the bit crusher config, to give a helpful message intead of a panic.
+pub fn validate_bit_crusher(config: &config::BitCrusherParameters) -> Res<()> {
+    if config.bits == 0 || config.bits > MAX_BITS {
+        let msg = format!(
+            "Number of bits must be between 1 and {}, got {}.",
+            MAX_BITS, config.bits
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    if config.sample_rate_divisor() == 0 {
+        let msg = "Sample rate divisor must be at least 1.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::bitcrusher::{quantization_step, validate_bit_crusher, BitCrusher};
+    use crate::config::BitCrusherParameters;
+    use crate::filters::Processor;
+    use crate::PrcFmt;
+
+    fn params(bits: usize, divisor: usize) -> BitCrusherParameters {
+        BitCrusherParameters {
+            channels: 1,
+            bits,
+            sample_rate_divisor: Some(divisor),
+        }
+    }
+
+    #[test]
+    fn quantize_and_hold() {
+        let mut crusher = BitCrusher::from_config("test", params(3, 2));
+        let wave = vec![0.1, 0.9, 0.3, -0.3, -0.6, 0.0, 1.0];
+        let mut chunk = AudioChunk::new(vec![wave], 1.0, -1.0, 7, 7);
+        crusher.process_chunk(&mut chunk).unwrap();
+        assert_eq!(
+            chunk.waveforms[0],
+            vec![0.0, 0.0, 0.25, 0.25, -0.5, -0.5, 1.0]
+        );
+        // The hold continues into the next chunk
+        let mut chunk = AudioChunk::new(vec![vec![0.0, 0.5]], 1.0, -1.0, 2, 2);
+        crusher.process_chunk(&mut chunk).unwrap();
+        assert_eq!(chunk.waveforms[0], vec![1.0, 0.5]);
+    }
+
+    #[test]
+    fn quantization_levels_and_error() {
+        for bits in [1, 2, 4, 8, 16] {
+            let mut crusher = BitCrusher::from_config("test", params(bits, 1));
+            let step = quantization_step(bits);
+            let wave: Vec<PrcFmt> = (0..1001).map(|n| n as PrcFmt / 500.0 - 1.0).collect();
+            let mut chunk = AudioChunk::new(vec![wave.clone()], 1.0, -1.0, 1001, 1001);
+            crusher.process_chunk(&mut chunk).unwrap();
+            for (input, output) in wave.iter().zip(chunk.waveforms[0].iter()) {
+                // Every output is one of the levels, and the nearest one to the input
+                let levels = output / step;
+                assert!((levels - levels.round()).abs() < 1e-6);
+                assert!((output - input).abs() <= 0.5 * step + 1e-6);
+            }
+            assert_eq!(chunk.waveforms[0][0], -1.0);
+            assert_eq!(chunk.waveforms[0][1000], 1.0);
+        }
+    }
+
+    #[test]
+    fn quantization_keeps_level() {
+        // A rounding quantizer with full scale levels at +-1.0 doesn't change the level
+        let mut crusher = BitCrusher::from_config("test", params(6, 1));
+        let wave: Vec<PrcFmt> = (0..4800)
+            .map(|n| 0.5 * (2.0 * std::f64::consts::PI as PrcFmt * n as PrcFmt / 48.0).sin())
+            .collect();
+        let mut chunk = AudioChunk::new(vec![wave.clone()], 1.0, -1.0, 4800, 4800);
+        crusher.process_chunk(&mut chunk).unwrap();
+        let rms = |values: &[PrcFmt]| {
+            (values.iter().map(|v| v * v).sum::<PrcFmt>() / values.len() as PrcFmt).sqrt()
+        };
+        let ratio = rms(&chunk.waveforms[0]) / rms(&wave);
+        assert!((ratio - 1.0).abs() < 0.01, "ratio: {}", ratio);
+    }
+
+    #[test]
+    fn check_bit_crusher_config() {
+        assert!(validate_bit_crusher(&params(8, 1)).is_ok());
+        assert!(validate_bit_crusher(&params(0, 1)).is_err());
+        assert!(validate_bit_crusher(&params(33, 1)).is_err());
+        assert!(validate_bit_crusher(&params(8, 0)).is_err());
+    }
+}
@@ -15,6 +15,7 @@ pub struct Compressor {
     pub release: PrcFmt,
     pub threshold: PrcFmt,
     pub factor: PrcFmt,
+    pub knee_width: PrcFmt,
     pub makeup_gain: PrcFmt,
     pub limiter: Option<Limiter>,
     pub samplerate: usize,
@@ -53,14 +54,17 @@ impl Compressor {
 
         let scratch = vec![0.0; chunksize];
 
-        debug!("Creating compressor '{}', channels: {}, monitor_channels: {:?}, process_channels: {:?}, attack: {}, release: {}, threshold: {}, factor: {}, makeup_gain: {}, soft_clip: {}, clip_limit: {:?}", 
-                name, channels, process_channels, monitor_channels, attack, release, config.threshold, config.factor, config.makeup_gain(), config.soft_clip(), clip_limit);
+        debug!("Creating compressor '{}', channels: {}, monitor_channels: {:?}, process_channels: {:?}, attack: {}, release: {}, threshold: {}, factor: {}, knee_width: {}, makeup_gain: {}, soft_clip: {}, clip_limit: {:?}", 
+                name, channels, process_channels, monitor_channels, attack, release, config.threshold, config.factor, config.knee_width(), config.makeup_gain(), config.soft_clip(), clip_limit);
         let limiter = if let Some(limit) = config.clip_limit {
             let limitconf = config::LimiterParameters {
                 clip_limit: limit,
                 soft_clip: config.soft_clip,
+                curve: None,
+                knee: None,
+                lookahead_ms: None,
             };
-            Some(Limiter::from_config("Limiter", limitconf))
+            Some(Limiter::from_config("Limiter", limitconf, samplerate))
         } else {
             None
         };
@@ -74,6 +78,7 @@ impl Compressor {
             release,
             threshold: config.threshold,
             factor: config.factor,
+            knee_width: config.knee_width(),
             makeup_gain: config.makeup_gain(),
             limiter,
             samplerate,
@@ -110,11 +115,7 @@ impl Compressor {
     /// Calculate linear gain, store result in self.scratch
     fn calculate_linear_gain(&mut self) {
         for val in self.scratch.iter_mut() {
-            if *val > self.threshold {
-                *val = -(*val - self.threshold) * (self.factor - 1.0) / self.factor;
-            } else {
-                *val = 0.0;
-            }
+            *val = gain_reduction(*val, self.threshold, self.factor, self.knee_width);
             *val += self.makeup_gain;
             *val = (10.0 as PrcFmt).powf(*val / 20.0);
         }
@@ -133,6 +134,27 @@ impl Compressor {
     }
 }
 
+/// Calculate the gain reduction in dB for a given loudness.
+/// With a knee width larger than zero, the compression is faded in gradually
+/// over a region of that width centered on the threshold.
+/// The quadratic curve in this region meets the straight lines on both sides without steps or kinks.
+fn gain_reduction(
+    loudness: PrcFmt,
+    threshold: PrcFmt,
+    factor: PrcFmt,
+    knee_width: PrcFmt,
+) -> PrcFmt {
+    let over = loudness - threshold;
+    let slope = (factor - 1.0) / factor;
+    if 2.0 * over <= -knee_width {
+        0.0
+    } else if 2.0 * over < knee_width {
+        -slope * (over + knee_width / 2.0).powi(2) / (2.0 * knee_width)
+    } else {
+        -over * slope
+    }
+}
+
 impl Processor for Compressor {
     fn name(&self) -> &str {
         &self.name
@@ -151,8 +173,6 @@ impl Processor for Compressor {
     }
 
     fn update_parameters(&mut self, config: config::Processor) {
-        // TODO remove when there is more than one type of Processor.
-        #[allow(irrefutable_let_patterns)]
         if let config::Processor::Compressor {
             parameters: config, ..
         } = config
@@ -181,8 +201,11 @@ impl Processor for Compressor {
                 let limitconf = config::LimiterParameters {
                     clip_limit: limit,
                     soft_clip: config.soft_clip,
+                    curve: None,
+                    knee: None,
+                    lookahead_ms: None,
                 };
-                Some(Limiter::from_config("Limiter", limitconf))
+                Some(Limiter::from_config("Limiter", limitconf, self.samplerate))
             } else {
                 None
             };
@@ -193,11 +216,12 @@ impl Processor for Compressor {
             self.release = release;
             self.threshold = config.threshold;
             self.factor = config.factor;
+            self.knee_width = config.knee_width();
             self.makeup_gain = config.makeup_gain();
             self.limiter = limiter;
 
-            debug!("Updated compressor '{}', monitor_channels: {:?}, process_channels: {:?}, attack: {}, release: {}, threshold: {}, factor: {}, makeup_gain: {}, soft_clip: {}, clip_limit: {:?}", 
-                self.name, self.process_channels, self.monitor_channels, attack, release, config.threshold, config.factor, config.makeup_gain(), config.soft_clip(), clip_limit);
+            debug!("Updated compressor '{}', monitor_channels: {:?}, process_channels: {:?}, attack: {}, release: {}, threshold: {}, factor: {}, knee_width: {}, makeup_gain: {}, soft_clip: {}, clip_limit: {:?}", 
+                self.name, self.process_channels, self.monitor_channels, attack, release, config.threshold, config.factor, config.knee_width(), config.makeup_gain(), config.soft_clip(), clip_limit);
         } else {
             // This should never happen unless there is a bug somewhere else
             panic!("Invalid config change!");
@@ -216,6 +240,13 @@ pub fn validate_compressor(config: &config::CompressorParameters) -> Res<()> {
         let msg = "Release value must be larger than zero.";
         return Err(config::ConfigError::new(msg).into());
     }
+    if !config.knee_width().is_finite() || config.knee_width() < 0.0 {
+        let msg = format!(
+            "Knee width must be zero or positive, got {}.",
+            config.knee_width()
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
     for ch in config.monitor_channels().iter() {
         if *ch >= channels {
             let msg = format!(
@@ -238,3 +269,57 @@ pub fn validate_compressor(config: &config::CompressorParameters) -> Res<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::compressor::gain_reduction;
+    use crate::PrcFmt;
+
+    fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
+        (left - right).abs() < maxdiff
+    }
+
+    #[test]
+    fn hard_knee() {
+        assert!(is_close(gain_reduction(-30.0, -20.0, 4.0, 0.0), 0.0, 1e-9));
+        assert!(is_close(gain_reduction(-20.0, -20.0, 4.0, 0.0), 0.0, 1e-9));
+        assert!(is_close(gain_reduction(-12.0, -20.0, 4.0, 0.0), -6.0, 1e-9));
+    }
+
+    #[test]
+    fn soft_knee_is_continuous() {
+        let (threshold, factor, knee) = (-20.0, 4.0, 10.0);
+        // At the edges of the knee the curve meets the hard knee lines
+        assert!(is_close(
+            gain_reduction(-25.0, threshold, factor, knee),
+            0.0,
+            1e-9
+        ));
+        assert!(is_close(
+            gain_reduction(-15.0, threshold, factor, knee),
+            gain_reduction(-15.0, threshold, factor, 0.0),
+            1e-9
+        ));
+        // Above the knee, the soft and hard knee give the same result
+        assert!(is_close(
+            gain_reduction(-5.0, threshold, factor, knee),
+            gain_reduction(-5.0, threshold, factor, 0.0),
+            1e-9
+        ));
+        // At the threshold, the soft knee already compresses a little
+        assert!(is_close(
+            gain_reduction(threshold, threshold, factor, knee),
+            -0.75 * 25.0 / 20.0,
+            1e-9
+        ));
+        // No steps anywhere along the curve
+        let mut prev = gain_reduction(-40.0, threshold, factor, knee);
+        for n in 1..400 {
+            let loudness = -40.0 + 0.1 * n as PrcFmt;
+            let gain = gain_reduction(loudness, threshold, factor, knee);
+            assert!(gain <= prev);
+            assert!(prev - gain < 0.1);
+            prev = gain;
+        }
+    }
+}
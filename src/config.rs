@@ -1,10 +1,15 @@
+use crate::bitcrusher;
 use crate::compressor;
+use crate::crossfeed;
+use crate::dynamiceq;
 use crate::filters;
 use crate::mixer;
+use crate::phaserotation;
+use crate::subalign;
 use parking_lot::RwLock;
 use serde::{de, Deserialize, Serialize};
 //use serde_with;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error;
 use std::fmt;
 use std::fs::File;
@@ -21,6 +26,7 @@ pub struct Overrides {
     pub sample_format: Option<SampleFormat>,
     pub extra_samples: Option<usize>,
     pub channels: Option<usize>,
+    pub offline: bool,
 }
 
 lazy_static! {
@@ -29,7 +35,10 @@ lazy_static! {
         sample_format: None,
         extra_samples: None,
         channels: None,
+        offline: false,
     });
+    // Coefficient files given as URLs, downloaded when the config is loaded
+    static ref DOWNLOADED_FILES: RwLock<HashMap<String, Vec<u8>>> = RwLock::new(HashMap::new());
 }
 
 #[derive(Debug)]
@@ -130,7 +139,12 @@ pub enum CaptureDevice {
         channels: usize,
         device: String,
         format: SampleFormat,
+        #[serde(default)]
+        combined_rate_adjust: Option<bool>,
     },
+    #[cfg(target_os = "linux")]
+    #[serde(alias = "ALSAMULTI", alias = "alsamulti")]
+    AlsaMulti(CaptureDeviceAlsaMulti),
     #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
     #[serde(alias = "BLUEZ", alias = "bluez")]
     Bluez(CaptureDeviceBluez),
@@ -146,6 +160,10 @@ pub enum CaptureDevice {
     File(CaptureDeviceFile),
     #[serde(alias = "STDIN", alias = "stdin")]
     Stdin(CaptureDeviceStdin),
+    #[serde(alias = "FLACFILE", alias = "flacfile")]
+    FlacFile(CaptureDeviceFlacFile),
+    #[serde(alias = "TCPSTREAM", alias = "tcpstream")]
+    TcpStream(CaptureDeviceTcpStream),
     #[cfg(target_os = "macos")]
     #[serde(alias = "COREAUDIO", alias = "coreaudio")]
     CoreAudio(CaptureDeviceCA),
@@ -175,12 +193,16 @@ impl CaptureDevice {
         match self {
             #[cfg(target_os = "linux")]
             CaptureDevice::Alsa { channels, .. } => *channels,
+            #[cfg(target_os = "linux")]
+            CaptureDevice::AlsaMulti(dev) => dev.channels(),
             #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
             CaptureDevice::Bluez(dev) => dev.channels,
             #[cfg(feature = "pulse-backend")]
             CaptureDevice::Pulse { channels, .. } => *channels,
-            CaptureDevice::File(dev) => dev.channels,
-            CaptureDevice::Stdin(dev) => dev.channels,
+            CaptureDevice::File(dev) => dev.selected_channels(),
+            CaptureDevice::Stdin(dev) => dev.selected_channels(),
+            CaptureDevice::FlacFile(dev) => dev.stream_info.map_or(0, |info| info.channels),
+            CaptureDevice::TcpStream(dev) => dev.channels,
             #[cfg(target_os = "macos")]
             CaptureDevice::CoreAudio(dev) => dev.channels,
             #[cfg(target_os = "windows")]
@@ -200,12 +222,40 @@ impl CaptureDevice {
     }
 }
 
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureDeviceAlsaMulti {
+    pub devices: Vec<AlsaMultiMember>,
+    pub format: SampleFormat,
+}
+
+#[cfg(target_os = "linux")]
+impl CaptureDeviceAlsaMulti {
+    /// The total number of channels of all the devices.
+    pub fn channels(&self) -> usize {
+        self.devices.iter().map(|dev| dev.channels).sum()
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlsaMultiMember {
+    #[serde(deserialize_with = "validate_nonzero_usize")]
+    pub channels: usize,
+    pub device: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct CaptureDeviceFile {
     #[serde(deserialize_with = "validate_nonzero_usize")]
     pub channels: usize,
-    pub filename: String,
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub filenames: Option<Vec<String>>,
     pub format: SampleFormat,
     #[serde(default)]
     pub extra_samples: Option<usize>,
@@ -213,9 +263,14 @@ pub struct CaptureDeviceFile {
     pub skip_bytes: Option<usize>,
     #[serde(default)]
     pub read_bytes: Option<usize>,
+    #[serde(default)]
+    pub channel_select: Option<Vec<usize>>,
 }
 
 impl CaptureDeviceFile {
+    pub fn is_playlist(&self) -> bool {
+        self.filenames.is_some()
+    }
     pub fn extra_samples(&self) -> usize {
         self.extra_samples.unwrap_or_default()
     }
@@ -225,6 +280,52 @@ impl CaptureDeviceFile {
     pub fn read_bytes(&self) -> usize {
         self.read_bytes.unwrap_or_default()
     }
+    /// The number of channels after applying the channel selection.
+    pub fn selected_channels(&self) -> usize {
+        self.channel_select
+            .as_ref()
+            .map_or(self.channels, |select| select.len())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureDeviceFlacFile {
+    pub filename: String,
+    #[serde(default)]
+    pub extra_samples: Option<usize>,
+    /// The stream parameters, read from the file once during validation.
+    #[serde(skip)]
+    pub stream_info: Option<FlacParams>,
+}
+
+/// The stream parameters of a FLAC file, read from the header.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FlacParams {
+    pub samplerate: usize,
+    pub channels: usize,
+    pub bits_per_sample: usize,
+}
+
+impl CaptureDeviceFlacFile {
+    pub fn extra_samples(&self) -> usize {
+        self.extra_samples.unwrap_or_default()
+    }
+    /// Read the sample rate, number of channels and bit depth from the header of the file.
+    pub fn flac_params(&self) -> Res<FlacParams> {
+        let reader = claxon::FlacReader::open(&self.filename).map_err(|err| {
+            ConfigError::new(&format!(
+                "Could not read FLAC file '{}', error: {}",
+                self.filename, err
+            ))
+        })?;
+        let info = reader.streaminfo();
+        Ok(FlacParams {
+            samplerate: info.sample_rate as usize,
+            channels: info.channels as usize,
+            bits_per_sample: info.bits_per_sample as usize,
+        })
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -239,6 +340,8 @@ pub struct CaptureDeviceStdin {
     pub skip_bytes: Option<usize>,
     #[serde(default)]
     pub read_bytes: Option<usize>,
+    #[serde(default)]
+    pub channel_select: Option<Vec<usize>>,
 }
 
 impl CaptureDeviceStdin {
@@ -251,6 +354,30 @@ impl CaptureDeviceStdin {
     pub fn read_bytes(&self) -> usize {
         self.read_bytes.unwrap_or_default()
     }
+    /// The number of channels after applying the channel selection.
+    pub fn selected_channels(&self) -> usize {
+        self.channel_select
+            .as_ref()
+            .map_or(self.channels, |select| select.len())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureDeviceTcpStream {
+    pub address: String,
+    pub port: u16,
+    #[serde(deserialize_with = "validate_nonzero_usize")]
+    pub channels: usize,
+    pub format: SampleFormat,
+    #[serde(default)]
+    pub listen: Option<bool>,
+}
+
+impl CaptureDeviceTcpStream {
+    pub fn listen(&self) -> bool {
+        self.listen.unwrap_or_default()
+    }
 }
 
 #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
@@ -265,6 +392,8 @@ pub struct CaptureDeviceBluez {
     // from D-Bus properties
     pub format: SampleFormat,
     pub channels: usize,
+    #[serde(default)]
+    reconnect: Option<bool>,
 }
 
 #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
@@ -272,6 +401,10 @@ impl CaptureDeviceBluez {
     pub fn service(&self) -> String {
         self.service.clone().unwrap_or("org.bluealsa".to_string())
     }
+
+    pub fn reconnect(&self) -> bool {
+        self.reconnect.unwrap_or(false)
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -321,6 +454,8 @@ pub enum PlaybackDevice {
         channels: usize,
         device: String,
         format: SampleFormat,
+        #[serde(default)]
+        channel_order: Option<Vec<usize>>,
     },
     #[cfg(feature = "pulse-backend")]
     #[serde(alias = "PULSE", alias = "pulse")]
@@ -329,6 +464,8 @@ pub enum PlaybackDevice {
         channels: usize,
         device: String,
         format: SampleFormat,
+        #[serde(default)]
+        channel_order: Option<Vec<usize>>,
     },
     #[serde(alias = "FILE", alias = "file")]
     File {
@@ -336,12 +473,20 @@ pub enum PlaybackDevice {
         channels: usize,
         filename: String,
         format: SampleFormat,
+        #[serde(default)]
+        channel_order: Option<Vec<usize>>,
+        #[serde(default)]
+        wav_header_format: Option<WavHeaderFormat>,
     },
     #[serde(alias = "STDOUT", alias = "stdout")]
     Stdout {
         #[serde(deserialize_with = "validate_nonzero_usize")]
         channels: usize,
         format: SampleFormat,
+        #[serde(default)]
+        channel_order: Option<Vec<usize>>,
+        #[serde(default)]
+        wav_header_format: Option<WavHeaderFormat>,
     },
     #[cfg(target_os = "macos")]
     #[serde(alias = "COREAUDIO", alias = "coreaudio")]
@@ -364,9 +509,20 @@ pub enum PlaybackDevice {
         #[serde(deserialize_with = "validate_nonzero_usize")]
         channels: usize,
         device: String,
+        #[serde(default)]
+        channel_order: Option<Vec<usize>>,
     },
 }
 
+/// The header written before the samples by the File and Stdout playback devices.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum WavHeaderFormat {
+    Wav,
+    Rf64,
+    None,
+}
+
 impl PlaybackDevice {
     pub fn channels(&self) -> usize {
         match self {
@@ -393,6 +549,32 @@ impl PlaybackDevice {
             PlaybackDevice::Jack { channels, .. } => *channels,
         }
     }
+
+    pub fn channel_order(&self) -> Option<&Vec<usize>> {
+        match self {
+            #[cfg(target_os = "linux")]
+            PlaybackDevice::Alsa { channel_order, .. } => channel_order.as_ref(),
+            #[cfg(feature = "pulse-backend")]
+            PlaybackDevice::Pulse { channel_order, .. } => channel_order.as_ref(),
+            PlaybackDevice::File { channel_order, .. } => channel_order.as_ref(),
+            PlaybackDevice::Stdout { channel_order, .. } => channel_order.as_ref(),
+            #[cfg(target_os = "macos")]
+            PlaybackDevice::CoreAudio(dev) => dev.channel_order.as_ref(),
+            #[cfg(target_os = "windows")]
+            PlaybackDevice::Wasapi(dev) => dev.channel_order.as_ref(),
+            #[cfg(all(
+                feature = "cpal-backend",
+                feature = "jack-backend",
+                any(
+                    target_os = "linux",
+                    target_os = "dragonfly",
+                    target_os = "freebsd",
+                    target_os = "netbsd"
+                )
+            ))]
+            PlaybackDevice::Jack { channel_order, .. } => channel_order.as_ref(),
+        }
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -405,6 +587,8 @@ pub struct PlaybackDeviceWasapi {
     pub format: SampleFormat,
     #[serde(default)]
     exclusive: Option<bool>,
+    #[serde(default)]
+    pub channel_order: Option<Vec<usize>>,
 }
 
 #[cfg(target_os = "windows")]
@@ -425,6 +609,10 @@ pub struct PlaybackDeviceCA {
     pub format: Option<SampleFormat>,
     #[serde(default)]
     exclusive: Option<bool>,
+    #[serde(default)]
+    pub channel_order: Option<Vec<usize>>,
+    #[serde(default)]
+    pub aggregate: Option<CoreAudioAggregate>,
 }
 
 #[cfg(target_os = "macos")]
@@ -434,17 +622,54 @@ impl PlaybackDeviceCA {
     }
 }
 
+#[cfg(target_os = "macos")]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CoreAudioAggregate {
+    pub devices: Vec<CoreAudioSubDevice>,
+    #[serde(default)]
+    pub master: Option<String>,
+}
+
+#[cfg(target_os = "macos")]
+impl CoreAudioAggregate {
+    /// The UID of the sub-device that provides the clock, defaults to the first one.
+    pub fn master_uid(&self) -> &str {
+        self.master
+            .as_deref()
+            .unwrap_or_else(|| self.devices[0].uid.as_str())
+    }
+}
+
+#[cfg(target_os = "macos")]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CoreAudioSubDevice {
+    pub uid: String,
+    #[serde(deserialize_with = "validate_nonzero_usize")]
+    pub channels: usize,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Devices {
     pub samplerate: usize,
+    // The chunksize given in the config, left out when it should be calculated from chunksize_ms.
+    #[serde(rename = "chunksize", default)]
+    pub chunksize_setting: Option<usize>,
+    // The chunksize to use, resolved during validation and not serialized.
+    #[serde(skip)]
     pub chunksize: usize,
     #[serde(default)]
+    pub chunksize_ms: Option<f32>,
+    #[serde(default)]
     pub queuelimit: Option<usize>,
     #[serde(default)]
     pub silence_threshold: Option<PrcFmt>,
     #[serde(default)]
     pub silence_timeout: Option<PrcFmt>,
+    #[serde(default)]
+    pub silence_fade_ms: Option<f32>,
     pub capture: CaptureDevice,
     pub playback: PlaybackDevice,
     #[serde(default)]
@@ -463,6 +688,24 @@ pub struct Devices {
     pub rate_measure_interval: Option<f32>,
     #[serde(default)]
     pub volume_ramp_time: Option<f32>,
+    #[serde(default)]
+    pub meter_ballistics: Option<MeterBallistics>,
+    #[serde(default)]
+    pub clip_agc: Option<ClipAgc>,
+    #[serde(default)]
+    pub output_gate: Option<OutputGate>,
+    #[serde(default)]
+    pub warn_internal_clipping: Option<bool>,
+    #[serde(default)]
+    pub capture_preroll_ms: Option<f32>,
+    #[serde(default)]
+    pub rms_normalize: Option<RmsNormalize>,
+    #[serde(default)]
+    pub start_muted: Option<bool>,
+    #[serde(default)]
+    pub open_retry_count: Option<usize>,
+    #[serde(default)]
+    pub open_retry_interval_ms: Option<f32>,
 }
 
 // Getters for all the defaults
@@ -487,6 +730,10 @@ impl Devices {
         self.silence_timeout.unwrap_or(0.0)
     }
 
+    pub fn silence_fade_ms(&self) -> f32 {
+        self.silence_fade_ms.unwrap_or(0.0)
+    }
+
     pub fn capture_samplerate(&self) -> usize {
         self.capture_samplerate.unwrap_or(self.samplerate)
     }
@@ -506,6 +753,105 @@ impl Devices {
     pub fn ramp_time(&self) -> f32 {
         self.volume_ramp_time.unwrap_or(400.0)
     }
+
+    pub fn warn_internal_clipping(&self) -> bool {
+        self.warn_internal_clipping.unwrap_or(false)
+    }
+
+    pub fn capture_preroll_ms(&self) -> f32 {
+        self.capture_preroll_ms.unwrap_or(0.0)
+    }
+
+    pub fn start_muted(&self) -> bool {
+        self.start_muted.unwrap_or(false)
+    }
+
+    pub fn open_retry_count(&self) -> usize {
+        self.open_retry_count.unwrap_or(0)
+    }
+
+    pub fn open_retry_interval_ms(&self) -> f32 {
+        self.open_retry_interval_ms.unwrap_or(1000.0)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClipAgc {
+    #[serde(default)]
+    pub step: Option<f32>,
+    #[serde(default)]
+    pub recovery_rate: Option<f32>,
+    #[serde(default)]
+    pub floor: Option<f32>,
+}
+
+impl ClipAgc {
+    pub fn step(&self) -> f32 {
+        self.step.unwrap_or(1.0)
+    }
+
+    pub fn recovery_rate(&self) -> f32 {
+        self.recovery_rate.unwrap_or(0.5)
+    }
+
+    pub fn floor(&self) -> f32 {
+        self.floor.unwrap_or(-30.0)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OutputGate {
+    #[serde(default)]
+    pub threshold: Option<PrcFmt>,
+    #[serde(default)]
+    pub hold: Option<PrcFmt>,
+}
+
+impl OutputGate {
+    pub fn threshold(&self) -> PrcFmt {
+        self.threshold.unwrap_or(-80.0)
+    }
+
+    pub fn hold(&self) -> PrcFmt {
+        self.hold.unwrap_or(1.0)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RmsNormalize {
+    #[serde(default)]
+    pub target: Option<PrcFmt>,
+    #[serde(default)]
+    pub rate: Option<PrcFmt>,
+    #[serde(default)]
+    pub range: Option<PrcFmt>,
+}
+
+impl RmsNormalize {
+    pub fn target(&self) -> PrcFmt {
+        self.target.unwrap_or(-20.0)
+    }
+
+    pub fn rate(&self) -> PrcFmt {
+        self.rate.unwrap_or(1.0)
+    }
+
+    pub fn range(&self) -> PrcFmt {
+        self.range.unwrap_or(10.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum MeterBallistics {
+    // IEC 60268-10 Type I (DIN 45406): 5 ms integration time, falls 20 dB in 1.5 s.
+    PpmType1,
+    // IEC 60268-10 Type II (BBC): 10 ms integration time, falls 24 dB in 2.8 s.
+    PpmType2,
+    // IEC 60268-17 VU: 300 ms rise and fall time.
+    Vu,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -624,6 +970,10 @@ pub enum Filter {
         description: Option<String>,
         parameters: LimiterParameters,
     },
+    Polarity {
+        #[serde(default)]
+        description: Option<String>,
+    },
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -673,13 +1023,68 @@ pub enum ConvParameters {
     Wav(ConvParametersWav),
     Values {
         values: Vec<PrcFmt>,
+        #[serde(default)]
+        mode: Option<ConvMode>,
+        #[serde(default)]
+        engine: Option<ConvEngine>,
     },
     Dummy {
         #[serde(deserialize_with = "validate_nonzero_usize")]
         length: usize,
+        #[serde(default)]
+        mode: Option<ConvMode>,
+        #[serde(default)]
+        engine: Option<ConvEngine>,
     },
 }
 
+impl ConvParameters {
+    pub fn mode(&self) -> ConvMode {
+        match self {
+            ConvParameters::Raw(params) => params.mode,
+            ConvParameters::Wav(params) => params.mode,
+            ConvParameters::Values { mode, .. } | ConvParameters::Dummy { mode, .. } => *mode,
+        }
+        .unwrap_or(ConvMode::Uniform)
+    }
+
+    pub fn engine(&self) -> ConvEngine {
+        match self {
+            ConvParameters::Raw(params) => params.engine,
+            ConvParameters::Wav(params) => params.engine,
+            ConvParameters::Values { engine, .. } | ConvParameters::Dummy { engine, .. } => *engine,
+        }
+        .unwrap_or(ConvEngine::default_engine())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum ConvMode {
+    #[serde(alias = "uniform")]
+    Uniform,
+    #[serde(alias = "nonuniform")]
+    NonUniform,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum ConvEngine {
+    #[serde(alias = "realfft")]
+    RealFft,
+    #[serde(alias = "fftw")]
+    Fftw,
+}
+
+impl ConvEngine {
+    /// The engine used when none is given, FFTW when built with the FFTW feature.
+    pub fn default_engine() -> Self {
+        if cfg!(feature = "FFTW") {
+            ConvEngine::Fftw
+        } else {
+            ConvEngine::RealFft
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct ConvParametersRaw {
@@ -690,6 +1095,10 @@ pub struct ConvParametersRaw {
     skip_bytes_lines: Option<usize>,
     #[serde(default)]
     read_bytes_lines: Option<usize>,
+    #[serde(default)]
+    mode: Option<ConvMode>,
+    #[serde(default)]
+    engine: Option<ConvEngine>,
 }
 
 impl ConvParametersRaw {
@@ -709,15 +1118,68 @@ impl ConvParametersRaw {
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct ConvParametersWav {
-    pub filename: String,
+    #[serde(default)]
+    pub filename: Option<String>,
     #[serde(default)]
     channel: Option<usize>,
+    #[serde(default)]
+    pub sources: Option<Vec<ConvWavSource>>,
+    #[serde(default)]
+    mode: Option<ConvMode>,
+    #[serde(default)]
+    engine: Option<ConvEngine>,
 }
 
 impl ConvParametersWav {
     pub fn channel(&self) -> usize {
         self.channel.unwrap_or_default()
     }
+
+    /// The file to read, validation makes sure it is given when there are no sources.
+    pub fn filename(&self) -> &str {
+        self.filename.as_deref().unwrap_or_default()
+    }
+
+    /// Select the source for a pipeline channel, giving parameters that read a single file.
+    /// Without a list of sources, the parameters are returned unchanged.
+    pub fn for_channel(&self, pipeline_channel: usize) -> ConvParametersWav {
+        let mut params = self.clone();
+        if let Some(source) = self
+            .sources
+            .as_ref()
+            .and_then(|sources| sources.get(pipeline_channel))
+        {
+            params.filename = Some(source.filename.clone());
+            params.channel = source.channel;
+            params.sources = None;
+        }
+        params
+    }
+
+    /// Get all the filenames used by the filter, for replacing tokens and relative paths.
+    pub fn filenames_mut(&mut self) -> Vec<&mut String> {
+        match &mut self.sources {
+            Some(sources) => sources
+                .iter_mut()
+                .map(|source| &mut source.filename)
+                .collect(),
+            None => self.filename.iter_mut().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ConvWavSource {
+    pub filename: String,
+    #[serde(default)]
+    channel: Option<usize>,
+}
+
+impl ConvWavSource {
+    pub fn channel(&self) -> usize {
+        self.channel.unwrap_or_default()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -748,6 +1210,12 @@ pub enum PeakingWidth {
         bandwidth: PrcFmt,
         gain: PrcFmt,
     },
+    ProportionalQ {
+        freq: PrcFmt,
+        base_q: PrcFmt,
+        gain: PrcFmt,
+        proportion: PrcFmt,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -837,6 +1305,10 @@ pub enum BiquadComboParameters {
         freq: PrcFmt,
         order: usize,
     },
+    LinkwitzRileyAllpass {
+        freq: PrcFmt,
+        order: usize,
+    },
     ButterworthHighpass {
         freq: PrcFmt,
         order: usize,
@@ -845,8 +1317,20 @@ pub enum BiquadComboParameters {
         freq: PrcFmt,
         order: usize,
     },
+    ButterworthBandpass {
+        freq_low: PrcFmt,
+        freq_high: PrcFmt,
+        order: usize,
+    },
+    ButterworthBandstop {
+        freq_low: PrcFmt,
+        freq_high: PrcFmt,
+        order: usize,
+    },
     Tilt {
         gain: PrcFmt,
+        #[serde(default)]
+        pivot_freq: Option<PrcFmt>,
     },
     FivePointPeq {
         fls: PrcFmt,
@@ -866,6 +1350,10 @@ pub enum BiquadComboParameters {
         ghs: PrcFmt,
     },
     GraphicEqualizer(GraphicEqualizerParameters),
+    CoefficientTable {
+        filename: String,
+        channel: usize,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -876,6 +1364,8 @@ pub struct GraphicEqualizerParameters {
     #[serde(default)]
     freq_max: Option<f32>,
     pub gains: Vec<f32>,
+    #[serde(default)]
+    smoothing: Option<f32>,
 }
 
 impl GraphicEqualizerParameters {
@@ -886,6 +1376,10 @@ impl GraphicEqualizerParameters {
     pub fn freq_max(&self) -> f32 {
         self.freq_max.unwrap_or(20000.0)
     }
+
+    pub fn smoothing(&self) -> f32 {
+        self.smoothing.unwrap_or(0.0)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -931,6 +1425,10 @@ pub struct LoudnessParameters {
     pub fader: Option<LoudnessFader>,
     #[serde(default)]
     pub attenuate_mid: Option<bool>,
+    #[serde(default)]
+    pub normalize_output: Option<bool>,
+    #[serde(default)]
+    pub channel_gains: Option<Vec<f32>>,
 }
 
 impl LoudnessParameters {
@@ -949,6 +1447,18 @@ impl LoudnessParameters {
     pub fn attenuate_mid(&self) -> bool {
         self.attenuate_mid.unwrap_or_default()
     }
+
+    pub fn normalize_output(&self) -> bool {
+        self.normalize_output.unwrap_or_default()
+    }
+
+    /// Get the static gain offset in dB for a channel, zero if no channel gains are given.
+    pub fn channel_gain(&self, channel: usize) -> f32 {
+        self.channel_gains
+            .as_ref()
+            .and_then(|gains| gains.get(channel).copied())
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -1014,6 +1524,10 @@ pub enum TimeUnit {
     Millimetres,
     #[serde(rename = "samples")]
     Samples,
+    #[serde(rename = "ft")]
+    Feet,
+    #[serde(rename = "in")]
+    Inches,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -1040,6 +1554,8 @@ pub enum DitherParameters {
     ShibataLow882 { bits: usize },
     Shibata96 { bits: usize },
     ShibataLow96 { bits: usize },
+    Shibata1764 { bits: usize },
+    ShibataLow1764 { bits: usize },
     Shibata192 { bits: usize },
     ShibataLow192 { bits: usize },
 }
@@ -1084,6 +1600,8 @@ pub struct MixerSource {
     pub mute: Option<bool>,
     #[serde(default)]
     pub scale: Option<GainScale>,
+    #[serde(default)]
+    pub taper: Option<Vec<[PrcFmt; 2]>>,
 }
 
 impl MixerSource {
@@ -1124,8 +1642,26 @@ impl MixerMapping {
 pub struct Mixer {
     #[serde(default)]
     pub description: Option<String>,
+    #[serde(default)]
+    pub r#type: Option<MixerType>,
     pub channels: MixerChannels,
+    #[serde(default)]
     pub mapping: Vec<MixerMapping>,
+    #[serde(default)]
+    pub normalize: Option<bool>,
+}
+
+impl Mixer {
+    pub fn normalize(&self) -> bool {
+        self.normalize.unwrap_or_default()
+    }
+}
+
+/// Mixers that are generated from a template.
+/// They are expanded to a normal mixer with an explicit mapping when the config is validated.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum MixerType {
+    Downmix,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -1137,6 +1673,31 @@ pub enum Processor {
         description: Option<String>,
         parameters: CompressorParameters,
     },
+    DynamicEq {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: DynamicEqParameters,
+    },
+    SubAlign {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: SubAlignParameters,
+    },
+    BitCrusher {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: BitCrusherParameters,
+    },
+    PhaseRotation {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: PhaseRotationParameters,
+    },
+    Crossfeed {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: CrossfeedParameters,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -1157,6 +1718,8 @@ pub struct CompressorParameters {
     pub soft_clip: Option<bool>,
     #[serde(default)]
     pub clip_limit: Option<PrcFmt>,
+    #[serde(default)]
+    pub knee_width: Option<PrcFmt>,
 }
 
 impl CompressorParameters {
@@ -1175,6 +1738,83 @@ impl CompressorParameters {
     pub fn soft_clip(&self) -> bool {
         self.soft_clip.unwrap_or_default()
     }
+
+    pub fn knee_width(&self) -> PrcFmt {
+        self.knee_width.unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DynamicEqParameters {
+    pub channels: usize,
+    pub freq: PrcFmt,
+    pub q: PrcFmt,
+    pub threshold: PrcFmt,
+    pub ratio: PrcFmt,
+    pub attack: PrcFmt,
+    pub release: PrcFmt,
+    pub max_gain: PrcFmt,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SubAlignParameters {
+    pub channels: usize,
+    pub channel: usize,
+    pub freq: PrcFmt,
+    #[serde(default)]
+    pub q: Option<PrcFmt>,
+    pub delay_ms: PrcFmt,
+}
+
+impl SubAlignParameters {
+    pub fn q(&self) -> PrcFmt {
+        self.q.unwrap_or(0.707)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BitCrusherParameters {
+    pub channels: usize,
+    pub bits: usize,
+    #[serde(default)]
+    pub sample_rate_divisor: Option<usize>,
+}
+
+impl BitCrusherParameters {
+    pub fn sample_rate_divisor(&self) -> usize {
+        self.sample_rate_divisor.unwrap_or(1)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PhaseRotationParameters {
+    pub channels: usize,
+    #[serde(default)]
+    pub process_channels: Option<Vec<usize>>,
+    pub degrees: PrcFmt,
+    #[serde(default)]
+    pub fft_size: Option<usize>,
+}
+
+impl PhaseRotationParameters {
+    pub fn process_channels(&self) -> Vec<usize> {
+        self.process_channels.clone().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CrossfeedParameters {
+    pub channels: usize,
+    #[serde(default)]
+    pub process_channels: Option<Vec<usize>>,
+    pub level: PrcFmt,
+    pub freq: PrcFmt,
+    pub delay: PrcFmt,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -1184,15 +1824,45 @@ pub struct LimiterParameters {
     pub soft_clip: Option<bool>,
     #[serde(default)]
     pub clip_limit: PrcFmt,
+    #[serde(default)]
+    pub curve: Option<LimiterCurve>,
+    #[serde(default)]
+    pub knee: Option<PrcFmt>,
+    #[serde(default)]
+    pub lookahead_ms: Option<PrcFmt>,
 }
 
 impl LimiterParameters {
     pub fn soft_clip(&self) -> bool {
         self.soft_clip.unwrap_or_default()
     }
+
+    pub fn curve(&self) -> LimiterCurve {
+        self.curve.unwrap_or(LimiterCurve::Cubic)
+    }
+
+    pub fn knee(&self) -> PrcFmt {
+        self.knee.unwrap_or(6.0)
+    }
+
+    pub fn lookahead_ms(&self) -> PrcFmt {
+        self.lookahead_ms.unwrap_or_default()
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum LimiterCurve {
+    #[serde(alias = "cubic")]
+    Cubic,
+    #[serde(alias = "tanh")]
+    Tanh,
+    #[serde(alias = "arctan")]
+    Arctan,
+    #[serde(alias = "knee")]
+    Knee,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type")]
 #[serde(deny_unknown_fields)]
 pub enum PipelineStep {
@@ -1216,7 +1886,7 @@ impl PipelineStepMixer {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct PipelineStepFilter {
     pub channel: usize,
     pub names: Vec<String>,
@@ -1224,12 +1894,30 @@ pub struct PipelineStepFilter {
     pub description: Option<String>,
     #[serde(default)]
     pub bypassed: Option<bool>,
+    #[serde(default)]
+    pub level_match_on_bypass: Option<bool>,
+    #[serde(default)]
+    pub bypass_gain: Option<PrcFmt>,
+    #[serde(default)]
+    pub mix: Option<PrcFmt>,
 }
 
 impl PipelineStepFilter {
     pub fn is_bypassed(&self) -> bool {
         self.bypassed.unwrap_or_default()
     }
+
+    pub fn level_match_on_bypass(&self) -> bool {
+        self.level_match_on_bypass.unwrap_or_default()
+    }
+
+    pub fn bypass_gain(&self) -> PrcFmt {
+        self.bypass_gain.unwrap_or_default()
+    }
+
+    pub fn mix(&self) -> PrcFmt {
+        self.mix.unwrap_or(1.0)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -1247,9 +1935,29 @@ impl PipelineStepProcessor {
     }
 }
 
+impl PipelineStep {
+    pub fn is_bypassed(&self) -> bool {
+        match self {
+            PipelineStep::Mixer(step) => step.is_bypassed(),
+            PipelineStep::Filter(step) => step.is_bypassed(),
+            PipelineStep::Processor(step) => step.is_bypassed(),
+        }
+    }
+
+    pub fn set_bypassed(&mut self, bypassed: bool) {
+        match self {
+            PipelineStep::Mixer(step) => step.bypassed = Some(bypassed),
+            PipelineStep::Filter(step) => step.bypassed = Some(bypassed),
+            PipelineStep::Processor(step) => step.bypassed = Some(bypassed),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Configuration {
+    #[serde(default)]
+    pub config_version: Option<usize>,
     #[serde(default)]
     pub title: Option<String>,
     #[serde(default)]
@@ -1263,6 +1971,63 @@ pub struct Configuration {
     pub processors: Option<HashMap<String, Processor>>,
     #[serde(default)]
     pub pipeline: Option<Vec<PipelineStep>>,
+    #[serde(default)]
+    pub pipeline_by_channels: Option<HashMap<usize, Vec<PipelineStep>>>,
+    #[serde(default)]
+    pub outputs: Option<Vec<Output>>,
+    // The pipeline given in the config, kept to fall back to when no variant in pipeline_by_channels matches
+    #[serde(skip)]
+    pub default_pipeline: Option<Vec<PipelineStep>>,
+}
+
+/// An additional playback device with its own pipeline, fed from the same capture device.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    #[serde(default)]
+    pub description: Option<String>,
+    pub playback: PlaybackDevice,
+    #[serde(default)]
+    pub pipeline: Option<Vec<PipelineStep>>,
+}
+
+/// The newest version of the config format that this version of CamillaDSP understands.
+/// This is increased when the meaning of a config changes in a way that an older version
+/// would silently misinterpret.
+pub const CONFIG_VERSION: usize = 1;
+
+/// The longest total time to spend retrying to open a device.
+const MAX_OPEN_RETRY_TIME_MS: f32 = 30000.0;
+
+impl Configuration {
+    /// Get the version of the config format. Configs without a version are treated as version 1.
+    pub fn config_version(&self) -> usize {
+        self.config_version.unwrap_or(1)
+    }
+
+    /// Get a configuration for one of the additional outputs.
+    /// This has the playback device and pipeline of the output, and everything else from the main config.
+    /// Rate adjust is always disabled for the additional outputs,
+    /// since the capture device can only follow the main playback device.
+    pub fn output_config(&self, index: usize) -> Option<Configuration> {
+        let output = self.outputs.as_ref()?.get(index)?;
+        let mut conf = self.clone();
+        conf.devices.playback = output.playback.clone();
+        conf.devices.enable_rate_adjust = Some(false);
+        conf.pipeline = output.pipeline.clone();
+        conf.pipeline_by_channels = None;
+        conf.default_pipeline = None;
+        conf.outputs = None;
+        Some(conf)
+    }
+
+    /// Get the configurations for all the additional outputs.
+    pub fn output_configs(&self) -> Vec<Configuration> {
+        let nbr_outputs = self.outputs.as_ref().map_or(0, |outputs| outputs.len());
+        (0..nbr_outputs)
+            .filter_map(|index| self.output_config(index))
+            .collect()
+    }
 }
 
 fn validate_nonzero_usize<'de, D>(d: D) -> Result<usize, D::Error>
@@ -1279,7 +2044,89 @@ where
     Ok(value)
 }
 
-pub fn load_config(filename: &str) -> Res<Configuration> {
+/// Check if the name of a config or coefficient file is an http or https URL.
+pub fn is_url(name: &str) -> bool {
+    name.starts_with("http://") || name.starts_with("https://")
+}
+
+/// Timeout for downloading a config or coefficient file.
+#[cfg(feature = "http-config")]
+const DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Download a file into memory.
+/// Anything but a 200 OK response is treated as an error.
+#[cfg(feature = "http-config")]
+pub fn download(url: &str) -> Res<Vec<u8>> {
+    debug!("Downloading '{}'", url);
+    let agent = ureq::AgentBuilder::new().timeout(DOWNLOAD_TIMEOUT).build();
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, _)) => {
+            let msg =
+                format!("Could not download '{url}', the server responded with status {code}");
+            return Err(ConfigError::new(&msg).into());
+        }
+        Err(err) => {
+            let msg = format!("Could not download '{url}'. Error: {err}");
+            return Err(ConfigError::new(&msg).into());
+        }
+    };
+    if response.status() != 200 {
+        let msg = format!(
+            "Could not download '{url}', the server responded with status {}",
+            response.status()
+        );
+        return Err(ConfigError::new(&msg).into());
+    }
+    let mut data = Vec::new();
+    if let Err(err) = response.into_reader().read_to_end(&mut data) {
+        let msg = format!("Could not download '{url}'. Error: {err}");
+        return Err(ConfigError::new(&msg).into());
+    }
+    Ok(data)
+}
+
+#[cfg(not(feature = "http-config"))]
+pub fn download(url: &str) -> Res<Vec<u8>> {
+    let msg = format!("Could not download '{url}', http support requires the http-config feature");
+    Err(ConfigError::new(&msg).into())
+}
+
+/// Download all coefficient files of a config that are given as URLs.
+/// This is done once when the config is loaded, and the filters then read the files from memory.
+fn download_coefficient_files(config: &mut Configuration) -> Res<()> {
+    let mut downloaded = HashMap::new();
+    for filename in coefficient_filenames_mut(config) {
+        if is_url(filename) && !downloaded.contains_key(filename.as_str()) {
+            let data = download(filename)?;
+            downloaded.insert(filename.clone(), data);
+        }
+    }
+    DOWNLOADED_FILES.write().extend(downloaded);
+    Ok(())
+}
+
+/// Get the contents of a coefficient file that was downloaded when the config was loaded.
+pub fn downloaded_file(url: &str) -> Res<Vec<u8>> {
+    match DOWNLOADED_FILES.read().get(url) {
+        Some(data) => Ok(data.clone()),
+        None => {
+            let msg = format!("The coefficient file '{url}' has not been downloaded");
+            Err(ConfigError::new(&msg).into())
+        }
+    }
+}
+
+fn read_config_file(filename: &str) -> Res<String> {
+    if is_url(filename) {
+        return match String::from_utf8(download(filename)?) {
+            Ok(contents) => Ok(contents),
+            Err(err) => {
+                let msg = format!("Could not read config file '{filename}'. Error: {err}");
+                Err(ConfigError::new(&msg).into())
+            }
+        };
+    }
     let file = match File::open(filename) {
         Ok(f) => f,
         Err(err) => {
@@ -1289,13 +2136,25 @@ pub fn load_config(filename: &str) -> Res<Configuration> {
     };
     let mut buffered_reader = BufReader::new(file);
     let mut contents = String::new();
-    let _number_of_bytes: usize = match buffered_reader.read_to_string(&mut contents) {
-        Ok(number_of_bytes) => number_of_bytes,
-        Err(err) => {
-            let msg = format!("Could not read config file '{filename}'. Error: {err}");
-            return Err(ConfigError::new(&msg).into());
-        }
-    };
+    if let Err(err) = buffered_reader.read_to_string(&mut contents) {
+        let msg = format!("Could not read config file '{filename}'. Error: {err}");
+        return Err(ConfigError::new(&msg).into());
+    }
+    Ok(contents)
+}
+
+pub fn load_config(filename: &str) -> Res<Configuration> {
+    let contents = read_config_file(filename)?;
+    #[cfg(feature = "toml-config")]
+    if Path::new(filename).extension().and_then(|ext| ext.to_str()) == Some("toml") {
+        return match toml::from_str::<Configuration>(&contents) {
+            Ok(config) => Ok(config),
+            Err(err) => {
+                let msg = format!("Invalid config file!\n{err}");
+                Err(ConfigError::new(&msg).into())
+            }
+        };
+    }
     let configuration: Configuration = match serde_yaml::from_str(&contents) {
         Ok(config) => config,
         Err(err) => {
@@ -1312,23 +2171,30 @@ pub fn load_config(filename: &str) -> Res<Configuration> {
 
 fn apply_overrides(configuration: &mut Configuration) {
     let overrides = OVERRIDES.read();
-    if let Some(rate) = overrides.samplerate {
+    // A FLAC file knows its own sample rate, which then takes the place of the override.
+    let flac_samplerate = match &configuration.devices.capture {
+        CaptureDevice::FlacFile(dev) => dev.stream_info.map(|info| info.samplerate),
+        _ => None,
+    };
+    if let Some(rate) = flac_samplerate.or(overrides.samplerate) {
         let cfg_rate = configuration.devices.samplerate;
-        let cfg_chunksize = configuration.devices.chunksize;
 
         if configuration.devices.resampler.is_none() {
             debug!("Apply override for samplerate: {}", rate);
             configuration.devices.samplerate = rate;
-            let scaled_chunksize = if rate > cfg_rate {
-                cfg_chunksize * (rate as f32 / cfg_rate as f32).round() as usize
-            } else {
-                cfg_chunksize / (cfg_rate as f32 / rate as f32).round() as usize
-            };
-            debug!(
-                "Samplerate changed, adjusting chunksize: {} -> {}",
-                cfg_chunksize, scaled_chunksize
-            );
-            configuration.devices.chunksize = scaled_chunksize;
+            // A chunksize given by chunksize_ms is calculated for the new rate when resolved.
+            if let Some(cfg_chunksize) = configuration.devices.chunksize_setting {
+                let scaled_chunksize = if rate > cfg_rate {
+                    cfg_chunksize * (rate as f32 / cfg_rate as f32).round() as usize
+                } else {
+                    cfg_chunksize / (cfg_rate as f32 / rate as f32).round() as usize
+                };
+                debug!(
+                    "Samplerate changed, adjusting chunksize: {} -> {}",
+                    cfg_chunksize, scaled_chunksize
+                );
+                configuration.devices.chunksize_setting = Some(scaled_chunksize);
+            }
             #[allow(unreachable_patterns)]
             match &mut configuration.devices.capture {
                 CaptureDevice::File(dev) => {
@@ -1349,6 +2215,15 @@ fn apply_overrides(configuration: &mut Configuration) {
                     );
                     dev.extra_samples = Some(new_extra);
                 }
+                CaptureDevice::FlacFile(dev) => {
+                    let new_extra = dev.extra_samples() * rate / cfg_rate;
+                    debug!(
+                        "Scale extra samples: {} -> {}",
+                        dev.extra_samples(),
+                        new_extra
+                    );
+                    dev.extra_samples = Some(new_extra);
+                }
                 _ => {}
             }
         } else {
@@ -1370,6 +2245,9 @@ fn apply_overrides(configuration: &mut Configuration) {
             CaptureDevice::Stdin(dev) => {
                 dev.extra_samples = Some(extra);
             }
+            CaptureDevice::FlacFile(dev) => {
+                dev.extra_samples = Some(extra);
+            }
             _ => {}
         }
     }
@@ -1382,10 +2260,20 @@ fn apply_overrides(configuration: &mut Configuration) {
             CaptureDevice::Stdin(dev) => {
                 dev.channels = chans;
             }
+            CaptureDevice::FlacFile(_) => {
+                debug!("Ignoring channels override, using the number of channels of the FLAC file");
+            }
+            CaptureDevice::TcpStream(dev) => {
+                dev.channels = chans;
+            }
             #[cfg(target_os = "linux")]
             CaptureDevice::Alsa { channels, .. } => {
                 *channels = chans;
             }
+            #[cfg(target_os = "linux")]
+            CaptureDevice::AlsaMulti(_) => {
+                error!("Not possible to override capture channels for AlsaMulti, ignoring");
+            }
             #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
             CaptureDevice::Bluez(dev) => {
                 dev.channels = chans;
@@ -1426,10 +2314,20 @@ fn apply_overrides(configuration: &mut Configuration) {
             CaptureDevice::Stdin(dev) => {
                 dev.format = fmt;
             }
+            CaptureDevice::FlacFile(_) => {
+                debug!("Ignoring sample format override, using the format of the FLAC file");
+            }
+            CaptureDevice::TcpStream(dev) => {
+                dev.format = fmt;
+            }
             #[cfg(target_os = "linux")]
             CaptureDevice::Alsa { format, .. } => {
                 *format = fmt;
             }
+            #[cfg(target_os = "linux")]
+            CaptureDevice::AlsaMulti(dev) => {
+                dev.format = fmt;
+            }
             #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
             CaptureDevice::Bluez(dev) => {
                 dev.format = fmt;
@@ -1461,6 +2359,15 @@ fn apply_overrides(configuration: &mut Configuration) {
             }
         }
     }
+    if overrides.offline {
+        // Timing based features make no sense when not running in realtime.
+        // Pausing on silence would also drop audio, disable it.
+        debug!("Offline mode, disabling rate adjust, silence detection and rate change detection");
+        configuration.devices.enable_rate_adjust = Some(false);
+        configuration.devices.silence_timeout = Some(0.0);
+        configuration.devices.silence_fade_ms = Some(0.0);
+        configuration.devices.stop_on_rate_change = Some(false);
+    }
 }
 
 fn replace_tokens(string: &str, samplerate: usize, channels: usize) -> String {
@@ -1471,6 +2378,87 @@ fn replace_tokens(string: &str, samplerate: usize, channels: usize) -> String {
         .replace("$channels$", &ch)
 }
 
+/// Get the standard downmix gains in dB, as a list of (source channel, gain) for each output channel.
+/// The 5.1 and 7.1 layouts use the channel order L, R, C, LFE, SL, SR and L, R, C, LFE, BL, BR, SL, SR.
+/// The LFE channel is not included in the downmix.
+fn downmix_gains(channels_in: usize, channels_out: usize) -> Option<Vec<Vec<(usize, PrcFmt)>>> {
+    // -3 dB
+    let minus3 = 20.0 * (0.5 as PrcFmt).sqrt().log10();
+    match (channels_in, channels_out) {
+        (2, 1) => Some(vec![vec![(0, minus3), (1, minus3)]]),
+        (6, 2) => Some(vec![
+            vec![(0, 0.0), (2, minus3), (4, minus3)],
+            vec![(1, 0.0), (2, minus3), (5, minus3)],
+        ]),
+        (8, 2) => Some(vec![
+            vec![(0, 0.0), (2, minus3), (4, minus3), (6, minus3)],
+            vec![(1, 0.0), (2, minus3), (5, minus3), (7, minus3)],
+        ]),
+        _ => None,
+    }
+}
+
+/// Replace the Downmix mixers by normal mixers with the corresponding mapping.
+fn expand_downmix_mixers(config: &mut Configuration) -> Res<()> {
+    if let Some(mixers) = &mut config.mixers {
+        for (name, mixer) in mixers.iter_mut() {
+            if mixer.r#type != Some(MixerType::Downmix) {
+                continue;
+            }
+            if !mixer.mapping.is_empty() {
+                let msg = format!("Downmix mixer '{}' can't have a mapping", name);
+                return Err(ConfigError::new(&msg).into());
+            }
+            let gains =
+                downmix_gains(mixer.channels.r#in, mixer.channels.out).ok_or_else(|| {
+                    let msg = format!(
+                        "Downmix mixer '{}' doesn't support {} to {} channels, supported are 2 to 1, 6 to 2 and 8 to 2",
+                        name, mixer.channels.r#in, mixer.channels.out
+                    );
+                    ConfigError::new(&msg)
+                })?;
+            debug!(
+                "Expanding downmix mixer '{}' from {} to {} channels",
+                name, mixer.channels.r#in, mixer.channels.out
+            );
+            mixer.mapping = gains
+                .into_iter()
+                .enumerate()
+                .map(|(dest, sources)| {
+                    // Scale the gains so that the output can't clip
+                    let correction = if mixer.normalize() {
+                        let sum: PrcFmt = sources
+                            .iter()
+                            .map(|(_, gain)| (10.0 as PrcFmt).powf(*gain / 20.0))
+                            .sum();
+                        -20.0 * sum.log10()
+                    } else {
+                        0.0
+                    };
+                    MixerMapping {
+                        dest,
+                        sources: sources
+                            .into_iter()
+                            .map(|(channel, gain)| MixerSource {
+                                channel,
+                                gain: Some(gain + correction),
+                                inverted: None,
+                                mute: None,
+                                scale: None,
+                                taper: None,
+                            })
+                            .collect(),
+                        mute: None,
+                    }
+                })
+                .collect();
+            mixer.r#type = None;
+            mixer.normalize = None;
+        }
+    }
+    Ok(())
+}
+
 fn replace_tokens_in_config(config: &mut Configuration) {
     let samplerate = config.devices.samplerate;
     let num_channels = config.devices.capture.channels();
@@ -1487,88 +2475,206 @@ fn replace_tokens_in_config(config: &mut Configuration) {
                     parameters: ConvParameters::Wav(params),
                     ..
                 } => {
-                    params.filename = replace_tokens(&params.filename, samplerate, num_channels);
+                    for filename in params.filenames_mut() {
+                        *filename = replace_tokens(filename, samplerate, num_channels);
+                    }
+                }
+                Filter::BiquadCombo {
+                    parameters: BiquadComboParameters::CoefficientTable { filename, .. },
+                    ..
+                } => {
+                    *filename = replace_tokens(filename, samplerate, num_channels);
                 }
                 _ => {}
             }
         }
     }
     if let Some(pipeline) = &mut config.pipeline {
-        for mut step in pipeline.iter_mut() {
-            match &mut step {
-                PipelineStep::Filter(step) => {
-                    for name in step.names.iter_mut() {
-                        *name = replace_tokens(name, samplerate, num_channels);
-                    }
-                }
-                PipelineStep::Mixer(step) => {
-                    step.name = replace_tokens(&step.name, samplerate, num_channels);
-                }
-                PipelineStep::Processor(step) => {
-                    step.name = replace_tokens(&step.name, samplerate, num_channels);
-                }
-            }
+        replace_tokens_in_pipeline(pipeline, samplerate, num_channels);
+    }
+    if let Some(variants) = &mut config.pipeline_by_channels {
+        for (channels, pipeline) in variants.iter_mut() {
+            replace_tokens_in_pipeline(pipeline, samplerate, *channels);
+        }
+    }
+    if let Some(outputs) = &mut config.outputs {
+        for pipeline in outputs
+            .iter_mut()
+            .filter_map(|output| output.pipeline.as_mut())
+        {
+            replace_tokens_in_pipeline(pipeline, samplerate, num_channels);
         }
     }
 }
 
-// Check if coefficent files with relative paths are relative to the config file path, replace path if they are
-fn replace_relative_paths_in_config(config: &mut Configuration, configname: &str) {
-    if let Ok(config_file) = PathBuf::from(configname.to_owned()).canonicalize() {
-        if let Some(config_dir) = config_file.parent() {
-            if let Some(filters) = &mut config.filters {
-                for (_name, filter) in filters.iter_mut() {
-                    if let Filter::Conv {
-                        parameters: ConvParameters::Raw(params),
-                        ..
-                    } = filter
-                    {
-                        check_and_replace_relative_path(&mut params.filename, config_dir);
-                    } else if let Filter::Conv {
-                        parameters: ConvParameters::Wav(params),
-                        ..
-                    } = filter
-                    {
-                        check_and_replace_relative_path(&mut params.filename, config_dir);
-                    }
+fn replace_tokens_in_pipeline(pipeline: &mut [PipelineStep], samplerate: usize, channels: usize) {
+    for mut step in pipeline.iter_mut() {
+        match &mut step {
+            PipelineStep::Filter(step) => {
+                for name in step.names.iter_mut() {
+                    *name = replace_tokens(name, samplerate, channels);
                 }
             }
-        } else {
-            warn!("Can't find parent directory of config file");
+            PipelineStep::Mixer(step) => {
+                step.name = replace_tokens(&step.name, samplerate, channels);
+            }
+            PipelineStep::Processor(step) => {
+                step.name = replace_tokens(&step.name, samplerate, channels);
+            }
         }
-    } else {
-        warn!("Can't find absolute path of config file");
     }
 }
 
-fn check_and_replace_relative_path(path_str: &mut String, config_path: &Path) {
-    let path = PathBuf::from(path_str.to_owned());
-    if path.is_absolute() {
-        trace!("{} is absolute, no change", path_str);
+// Replace the pipeline by the variant matching the number of capture channels.
+// The default pipeline is kept, and is used again when there is no matching variant.
+fn select_pipeline_by_channels(config: &mut Configuration) {
+    let variants = match &config.pipeline_by_channels {
+        Some(variants) => variants,
+        None => return,
+    };
+    let default_pipeline = config
+        .default_pipeline
+        .get_or_insert_with(|| config.pipeline.clone().unwrap_or_default());
+    let channels = config.devices.capture.channels();
+    if let Some(pipeline) = variants.get(&channels) {
+        debug!("Using the pipeline variant for {} channels", channels);
+        config.pipeline = Some(pipeline.clone());
     } else {
-        debug!("{} is relative", path_str);
-        let mut in_config_dir = config_path.to_path_buf();
-        in_config_dir.push(&path_str);
-        if in_config_dir.exists() {
-            debug!("Using {} found relative to config file dir", path_str);
-            *path_str = in_config_dir.to_string_lossy().into();
-        } else {
-            trace!(
-                "{} not found relative to config file dir, not changing path",
-                path_str
+        debug!(
+            "No pipeline variant for {} channels, using the default pipeline",
+            channels
+        );
+        config.pipeline = Some(default_pipeline.clone());
+    }
+}
+
+/// Get a copy of a config where the capture device has a new number of channels,
+/// using the pipeline variant for that number of channels, or the default pipeline if there is no such variant.
+/// Returns None if the capture device doesn't support changing the number of channels, or if the new config is not valid.
+pub fn config_for_capture_channels(
+    config: &Configuration,
+    channels: usize,
+) -> Option<Configuration> {
+    let mut new_config = config.clone();
+    let updated = match &mut new_config.devices.capture {
+        #[cfg(target_os = "linux")]
+        CaptureDevice::Alsa {
+            channels: capture_channels,
+            ..
+        } => {
+            *capture_channels = channels;
+            true
+        }
+        _ => false,
+    };
+    if !updated {
+        return None;
+    }
+    match validate_config(&mut new_config, None) {
+        Ok(()) => Some(new_config),
+        Err(err) => {
+            warn!(
+                "Unable to use the pipeline for {} channels: {}",
+                channels, err
             );
+            None
         }
     }
 }
 
-#[derive(Debug)]
-pub enum ConfigChange {
-    FilterParameters {
-        filters: Vec<String>,
+/// Get the names of all coefficient files used by the filters of a config.
+fn coefficient_filenames_mut(config: &mut Configuration) -> Vec<&mut String> {
+    let mut filenames = Vec::new();
+    if let Some(filters) = &mut config.filters {
+        for (_name, filter) in filters.iter_mut() {
+            match filter {
+                Filter::Conv {
+                    parameters: ConvParameters::Raw(params),
+                    ..
+                } => filenames.push(&mut params.filename),
+                Filter::Conv {
+                    parameters: ConvParameters::Wav(params),
+                    ..
+                } => filenames.extend(params.filenames_mut()),
+                Filter::BiquadCombo {
+                    parameters: BiquadComboParameters::CoefficientTable { filename, .. },
+                    ..
+                } => filenames.push(filename),
+                _ => {}
+            }
+        }
+    }
+    filenames
+}
+
+/// Get the URL of the directory containing the file at a URL, ending with a slash.
+/// Any query string or fragment is dropped.
+fn url_base(url: &str) -> &str {
+    let path_end = url.find(['?', '#']).unwrap_or(url.len());
+    let url = &url[..path_end];
+    let path_start = url.find("://").map_or(0, |pos| pos + 3);
+    match url[path_start..].rfind('/') {
+        Some(pos) => &url[..path_start + pos + 1],
+        None => url,
+    }
+}
+
+// Check if coefficent files with relative paths are relative to the config file path, replace path if they are
+fn replace_relative_paths_in_config(config: &mut Configuration, configname: &str) {
+    if is_url(configname) {
+        // Relative paths in a downloaded config are relative to the URL of the config
+        let base = url_base(configname);
+        let separator = if base.ends_with('/') { "" } else { "/" };
+        for filename in coefficient_filenames_mut(config) {
+            if !is_url(filename) && !Path::new(filename.as_str()).is_absolute() {
+                debug!("{} is relative to the config URL", filename);
+                *filename = format!("{base}{separator}{filename}");
+            }
+        }
+        return;
+    }
+    if let Ok(config_file) = PathBuf::from(configname.to_owned()).canonicalize() {
+        if let Some(config_dir) = config_file.parent() {
+            for filename in coefficient_filenames_mut(config) {
+                check_and_replace_relative_path(filename, config_dir);
+            }
+        } else {
+            warn!("Can't find parent directory of config file");
+        }
+    } else {
+        warn!("Can't find absolute path of config file");
+    }
+}
+
+fn check_and_replace_relative_path(path_str: &mut String, config_path: &Path) {
+    let path = PathBuf::from(path_str.to_owned());
+    if path.is_absolute() {
+        trace!("{} is absolute, no change", path_str);
+    } else {
+        debug!("{} is relative", path_str);
+        let mut in_config_dir = config_path.to_path_buf();
+        in_config_dir.push(&path_str);
+        if in_config_dir.exists() {
+            debug!("Using {} found relative to config file dir", path_str);
+            *path_str = in_config_dir.to_string_lossy().into();
+        } else {
+            trace!(
+                "{} not found relative to config file dir, not changing path",
+                path_str
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigChange {
+    FilterParameters {
+        filters: Vec<String>,
         mixers: Vec<String>,
         processors: Vec<String>,
     },
     MixerParameters,
+    PipelineBypass,
     Pipeline,
     Devices,
     None,
@@ -1580,14 +2686,60 @@ pub fn load_validate_config(configname: &str) -> Res<Configuration> {
     Ok(configuration)
 }
 
+// Check if a filter is used in a bypassed filter step that has level matching enabled
+fn is_level_matched_on_bypass(conf: &Configuration, filter: &str) -> bool {
+    let output_pipelines = conf
+        .outputs
+        .iter()
+        .flatten()
+        .filter_map(|output| output.pipeline.as_ref());
+    let mut steps = conf.pipeline.iter().chain(output_pipelines).flatten();
+    steps.any(|step| match step {
+        PipelineStep::Filter(step) => {
+            step.is_bypassed()
+                && step.level_match_on_bypass()
+                && step.names.iter().any(|name| name == filter)
+        }
+        _ => false,
+    })
+}
+
+/// Check if the only difference between two configs is the bypassed property of filter and processor steps.
+/// Bypassing mixers and level matched filter steps changes the structure of the pipeline, and isn't included.
+fn only_bypass_changed(currentconf: &Configuration, newconf: &Configuration) -> bool {
+    let mut newconf = newconf.clone();
+    if let (Some(current), Some(new)) = (&currentconf.pipeline, &mut newconf.pipeline) {
+        if current.len() != new.len() {
+            return false;
+        }
+        for (current_step, new_step) in current.iter().zip(new.iter_mut()) {
+            match (current_step, new_step) {
+                (PipelineStep::Filter(current_step), PipelineStep::Filter(new_step))
+                    if !current_step.level_match_on_bypass() =>
+                {
+                    new_step.bypassed = current_step.bypassed;
+                }
+                (PipelineStep::Processor(current_step), PipelineStep::Processor(new_step)) => {
+                    new_step.bypassed = current_step.bypassed;
+                }
+                _ => {}
+            }
+        }
+    }
+    *currentconf == newconf
+}
+
 pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> ConfigChange {
     if currentconf == newconf {
         return ConfigChange::None;
     }
-    if currentconf.devices != newconf.devices {
+    if currentconf.devices != newconf.devices || currentconf.outputs != newconf.outputs {
         return ConfigChange::Devices;
     }
     if currentconf.pipeline != newconf.pipeline {
+        if only_bypass_changed(currentconf, newconf) {
+            return ConfigChange::PipelineBypass;
+        }
         return ConfigChange::Pipeline;
     }
     if currentconf.mixers != newconf.mixers {
@@ -1610,14 +2762,35 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
                     | (Filter::Dither { .. }, Filter::Dither { .. })
                     | (Filter::DiffEq { .. }, Filter::DiffEq { .. })
                     | (Filter::Volume { .. }, Filter::Volume { .. })
+                    | (Filter::Polarity { .. }, Filter::Polarity { .. })
                     | (Filter::Loudness { .. }, Filter::Loudness { .. }) => {}
                     _ => {
                         // A filter changed type, need to rebuild the pipeline
                         return ConfigChange::Pipeline;
                     }
                 };
+                // A convolution filter that changes engine must be rebuilt
+                if let (
+                    Filter::Conv {
+                        parameters: new_params,
+                        ..
+                    },
+                    Filter::Conv {
+                        parameters: current_params,
+                        ..
+                    },
+                ) = (params, current_filter)
+                {
+                    if new_params.engine() != current_params.engine() {
+                        return ConfigChange::Pipeline;
+                    }
+                }
                 // Only parameters changed, ok to update
                 if params != current_filter {
+                    // The gain of a level matched bypassed step depends on the filter parameters
+                    if is_level_matched_on_bypass(newconf, filter) {
+                        return ConfigChange::Pipeline;
+                    }
                     filters.push(filter.to_string());
                 }
             }
@@ -1637,6 +2810,10 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
         for (proc, params) in newprocs {
             // The pipeline didn't change, any added compressor isn't included and can be skipped
             if let Some(current_proc) = oldprocs.get(proc) {
+                if std::mem::discriminant(params) != std::mem::discriminant(current_proc) {
+                    // A processor changed type, need to rebuild the pipeline
+                    return ConfigChange::Pipeline;
+                }
                 if params != current_proc {
                     processors.push(proc.to_string());
                 }
@@ -1650,15 +2827,179 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
     }
 }
 
+/// Resolve the chunksize to use, the explicit chunksize if given, otherwise calculated from chunksize_ms.
+/// This is done after applying overrides, to use the final samplerate.
+/// The result is only stored in the unserialized chunksize field,
+/// so that a config read back from a running instance still has the original settings.
+fn resolve_chunksize(conf: &mut Configuration) -> Res<()> {
+    let devices = &mut conf.devices;
+    if let Some(chunksize) = devices.chunksize_setting {
+        if chunksize == 0 {
+            return Err(ConfigError::new("chunksize must be larger than zero").into());
+        }
+        if let Some(ms) = devices.chunksize_ms {
+            debug!(
+                "Both chunksize and chunksize_ms ({} ms) are given, using chunksize: {}",
+                ms, chunksize
+            );
+        }
+        devices.chunksize = chunksize;
+        return Ok(());
+    }
+    match devices.chunksize_ms {
+        Some(ms) if ms.is_finite() && ms > 0.0 => {
+            let chunksize = ((ms * devices.samplerate as f32 / 1000.0).round() as usize).max(1);
+            info!(
+                "Using chunksize {} for {} ms at {} Hz",
+                chunksize, ms, devices.samplerate
+            );
+            devices.chunksize = chunksize;
+            Ok(())
+        }
+        Some(ms) => {
+            let msg = format!("chunksize_ms must be larger than zero, got {}", ms);
+            Err(ConfigError::new(&msg).into())
+        }
+        None => Err(
+            ConfigError::new("A chunksize must be given, using chunksize or chunksize_ms").into(),
+        ),
+    }
+}
+
 /// Validate the loaded configuration, stop on errors and print a helpful message.
 pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<()> {
+    if conf.config_version() > CONFIG_VERSION {
+        warn!(
+            "The config is for version {} of the config format, but this version of CamillaDSP only supports up to version {}. Some settings may be misinterpreted.",
+            conf.config_version(),
+            CONFIG_VERSION
+        );
+    }
+    // The stream info of a FLAC file is needed for the overrides and the channel checks
+    if let CaptureDevice::FlacFile(dev) = &mut conf.devices.capture {
+        dev.stream_info = Some(dev.flac_params()?);
+    }
     // pre-process by applying overrides and replacing tokens
     apply_overrides(conf);
+    resolve_chunksize(conf)?;
+    select_pipeline_by_channels(conf);
     replace_tokens_in_config(conf);
+    expand_downmix_mixers(conf)?;
     if let Some(fname) = filename {
         replace_relative_paths_in_config(conf, fname);
     }
+    download_coefficient_files(conf)?;
 
+    if let CaptureDevice::File(dev) = &conf.devices.capture {
+        match (&dev.filename, &dev.filenames) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::new(
+                    "File capture device can't have both filename and filenames",
+                )
+                .into());
+            }
+            (None, None) => {
+                return Err(ConfigError::new(
+                    "File capture device needs either filename or filenames",
+                )
+                .into());
+            }
+            (None, Some(names)) if names.is_empty() => {
+                return Err(ConfigError::new("File capture playlist is empty").into());
+            }
+            _ => {}
+        }
+    }
+    let channel_select = match &conf.devices.capture {
+        CaptureDevice::File(dev) => Some((&dev.channel_select, dev.channels)),
+        CaptureDevice::Stdin(dev) => Some((&dev.channel_select, dev.channels)),
+        _ => None,
+    };
+    if let Some((Some(select), channels)) = channel_select {
+        if select.is_empty() {
+            return Err(ConfigError::new("channel_select can't be empty").into());
+        }
+        if let Some(index) = select.iter().find(|index| **index >= channels) {
+            let msg = format!(
+                "Invalid channel {} in channel_select, max is {}",
+                index,
+                channels - 1
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
+    if let PlaybackDevice::File {
+        format: SampleFormat::S24LE,
+        wav_header_format: Some(WavHeaderFormat::Wav | WavHeaderFormat::Rf64),
+        ..
+    }
+    | PlaybackDevice::Stdout {
+        format: SampleFormat::S24LE,
+        wav_header_format: Some(WavHeaderFormat::Wav | WavHeaderFormat::Rf64),
+        ..
+    } = &conf.devices.playback
+    {
+        return Err(ConfigError::new(
+            "A WAV header can't be used with the S24LE format, use S24LE3 or S32LE instead",
+        )
+        .into());
+    }
+    if OVERRIDES.read().offline {
+        if let CaptureDevice::File(dev) = &conf.devices.capture {
+            if dev.is_playlist() {
+                return Err(ConfigError::new(
+                    "Offline mode can't be used with a looping File capture playlist",
+                )
+                .into());
+            }
+        }
+        if !matches!(
+            conf.devices.capture,
+            CaptureDevice::File(_) | CaptureDevice::Stdin(_) | CaptureDevice::FlacFile(_)
+        ) {
+            return Err(ConfigError::new(
+                "Offline mode requires a File, Stdin or FlacFile capture device",
+            )
+            .into());
+        }
+        if !matches!(
+            conf.devices.playback,
+            PlaybackDevice::File { .. } | PlaybackDevice::Stdout { .. }
+        ) {
+            return Err(
+                ConfigError::new("Offline mode requires a File or Stdout playback device").into(),
+            );
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if let CaptureDevice::AlsaMulti(dev) = &conf.devices.capture {
+        if dev.devices.is_empty() {
+            return Err(
+                ConfigError::new("AlsaMulti capture device needs at least one device").into(),
+            );
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if let CaptureDevice::Alsa {
+        combined_rate_adjust: Some(true),
+        ..
+    } = &conf.devices.capture
+    {
+        if !conf.devices.rate_adjust() {
+            return Err(
+                ConfigError::new("combined_rate_adjust requires enable_rate_adjust").into(),
+            );
+        }
+        if !matches!(
+            conf.devices.resampler,
+            Some(Resampler::AsyncSinc { .. }) | Some(Resampler::AsyncPoly { .. })
+        ) {
+            return Err(ConfigError::new(
+                "combined_rate_adjust requires an asynchronous resampler",
+            )
+            .into());
+        }
+    }
     if conf.devices.target_level() >= 2 * conf.devices.chunksize {
         let msg = format!(
             "target_level can't be larger than {}",
@@ -1683,9 +3024,65 @@ pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<
             return Err(ConfigError::new("silence_timeout cannot be negative").into());
         }
     }
+    if let Some(fade) = conf.devices.silence_fade_ms {
+        if fade < 0.0 {
+            return Err(ConfigError::new("silence_fade_ms cannot be negative").into());
+        }
+        if fade > 1000.0 * conf.devices.silence_timeout() as f32 {
+            return Err(
+                ConfigError::new("silence_fade_ms can't be longer than silence_timeout").into(),
+            );
+        }
+    }
+    if conf.devices.capture_preroll_ms() < 0.0 {
+        return Err(ConfigError::new("capture_preroll_ms cannot be negative").into());
+    }
+    let retry_interval = conf.devices.open_retry_interval_ms();
+    if !retry_interval.is_finite() || retry_interval < 0.0 {
+        return Err(ConfigError::new("open_retry_interval_ms cannot be negative").into());
+    }
+    // Commands are not handled while the devices are opening, keep the total wait short
+    let retry_time = conf.devices.open_retry_count() as f32 * retry_interval;
+    if retry_time > MAX_OPEN_RETRY_TIME_MS {
+        let msg = format!(
+            "The total open retry time (open_retry_count * open_retry_interval_ms) can't be longer than {} ms, got {} ms",
+            MAX_OPEN_RETRY_TIME_MS, retry_time
+        );
+        return Err(ConfigError::new(&msg).into());
+    }
     if conf.devices.ramp_time() < 0.0 {
         return Err(ConfigError::new("Volume ramp time cannot be negative").into());
     }
+    if let Some(agc) = &conf.devices.clip_agc {
+        if agc.step() <= 0.0 {
+            return Err(ConfigError::new("clip_agc step must be larger than zero").into());
+        }
+        if agc.recovery_rate() < 0.0 {
+            return Err(ConfigError::new("clip_agc recovery_rate cannot be negative").into());
+        }
+        if agc.floor() < -150.0 {
+            return Err(ConfigError::new("clip_agc floor must be larger than -150 dB").into());
+        }
+    }
+    if let Some(gate) = &conf.devices.output_gate {
+        if gate.threshold() > 0.0 {
+            return Err(ConfigError::new("output_gate threshold must be less than 0 dB").into());
+        }
+        if gate.hold() < 0.0 {
+            return Err(ConfigError::new("output_gate hold cannot be negative").into());
+        }
+    }
+    if let Some(normalize) = &conf.devices.rms_normalize {
+        if normalize.target() > 0.0 {
+            return Err(ConfigError::new("rms_normalize target must be less than 0 dB").into());
+        }
+        if normalize.rate() <= 0.0 {
+            return Err(ConfigError::new("rms_normalize rate must be larger than zero").into());
+        }
+        if !(0.0..=40.0).contains(&normalize.range()) {
+            return Err(ConfigError::new("rms_normalize range must be between 0 and 40 dB").into());
+        }
+    }
     #[cfg(target_os = "windows")]
     if let CaptureDevice::Wasapi(dev) = &conf.devices.capture {
         if dev.format == SampleFormat::FLOAT64LE {
@@ -1766,123 +3163,444 @@ pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<
             )
             .into());
         }
+        if let Some(aggregate) = &dev.aggregate {
+            validate_coreaudio_aggregate(aggregate, dev)?;
+        }
     }
-    let mut num_channels = conf.devices.capture.channels();
-    let fs = conf.devices.samplerate;
+    validate_output(conf)?;
+    let num_channels_out = conf.devices.playback.channels();
+    if let Some(variants) = &conf.pipeline_by_channels {
+        for (channels, pipeline) in variants.iter() {
+            let channels_out = match validate_pipeline(conf, pipeline, *channels) {
+                Ok(channels_out) => channels_out,
+                Err(err) => {
+                    let msg = format!("Invalid pipeline for {channels} channels. Reason: {err}");
+                    return Err(ConfigError::new(&msg).into());
+                }
+            };
+            if channels_out != num_channels_out {
+                let msg = format!(
+                    "Pipeline for {channels} channels outputs {channels_out} channels, playback device has {num_channels_out}."
+                );
+                return Err(ConfigError::new(&msg).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the sub-devices of a CoreAudio aggregate device add up to the playback device.
+#[cfg(target_os = "macos")]
+fn validate_coreaudio_aggregate(aggregate: &CoreAudioAggregate, dev: &PlaybackDeviceCA) -> Res<()> {
+    if aggregate.devices.is_empty() {
+        return Err(
+            ConfigError::new("A CoreAudio aggregate device needs at least one sub-device").into(),
+        );
+    }
+    if dev.device.is_some() {
+        return Err(ConfigError::new(
+            "The playback device name can not be given when using an aggregate device",
+        )
+        .into());
+    }
+    for (idx, sub) in aggregate.devices.iter().enumerate() {
+        if aggregate.devices[..idx].iter().any(|d| d.uid == sub.uid) {
+            let msg = format!(
+                "Aggregate sub-device '{}' is listed more than once",
+                sub.uid
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
+    let master = aggregate.master_uid();
+    if !aggregate.devices.iter().any(|d| d.uid == master) {
+        let msg = format!("Aggregate clock master '{master}' is not one of the sub-devices");
+        return Err(ConfigError::new(&msg).into());
+    }
+    let total: usize = aggregate.devices.iter().map(|d| d.channels).sum();
+    if total != dev.channels {
+        let msg = format!(
+            "Aggregate sub-devices have {total} channels in total, playback device has {}",
+            dev.channels
+        );
+        return Err(ConfigError::new(&msg).into());
+    }
+    Ok(())
+}
+
+/// Validate the pipeline and playback device of the main output and any additional outputs.
+fn validate_output(conf: &Configuration) -> Res<()> {
+    validate_pipeline_and_playback(conf)?;
+    if let Some(outputs) = &conf.outputs {
+        if OVERRIDES.read().offline && !outputs.is_empty() {
+            return Err(
+                ConfigError::new("Offline mode can't be used with additional outputs").into(),
+            );
+        }
+    }
+    for (index, output_conf) in conf.output_configs().iter().enumerate() {
+        if let Err(err) = validate_pipeline_and_playback(output_conf) {
+            let msg = format!("Invalid output {index}. Reason: {err}");
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
+    Ok(())
+}
+
+/// Check that the pipeline outputs the number of channels expected by the playback device.
+fn validate_pipeline_and_playback(conf: &Configuration) -> Res<()> {
+    let num_channels = match &conf.pipeline {
+        Some(pipeline) => validate_pipeline(conf, pipeline, conf.devices.capture.channels())?,
+        None => conf.devices.capture.channels(),
+    };
+    let num_channels_out = conf.devices.playback.channels();
+    if num_channels != num_channels_out {
+        let msg = format!(
+            "Pipeline outputs {num_channels} channels, playback device has {num_channels_out}."
+        );
+        return Err(ConfigError::new(&msg).into());
+    }
+    if let Some(order) = conf.devices.playback.channel_order() {
+        if order.len() != num_channels_out {
+            let msg = format!(
+                "Playback channel_order has {} entries, playback device has {num_channels_out} channels.",
+                order.len()
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+        if let Some(channel) = order.iter().find(|ch| **ch >= num_channels_out) {
+            let msg = format!(
+                "Invalid channel {channel} in playback channel_order, max is {}.",
+                num_channels_out - 1
+            );
+            return Err(ConfigError::new(&msg).into());
+        }
+    }
+    Ok(())
+}
+
+/// Validate the steps of a pipeline, starting with the given number of channels.
+/// Returns the number of channels at the output of the pipeline.
+fn validate_pipeline(
+    conf: &Configuration,
+    pipeline: &[PipelineStep],
+    num_channels: usize,
+) -> Res<usize> {
+    let num_channels_out = walk_pipeline(conf, pipeline, num_channels, &mut Vec::new())?;
+    for (name, channel) in duplicate_filters(pipeline) {
+        warn!(
+            "Filter '{}' is applied more than once to channel {}, check that this is intended",
+            name, channel
+        );
+    }
+    Ok(num_channels_out)
+}
+
+/// Find filters that are applied more than once to the same channel.
+/// A mixer creates a new set of channels, so only the steps between mixers are compared.
+fn duplicate_filters(pipeline: &[PipelineStep]) -> Vec<(String, usize)> {
+    let mut applied = HashSet::new();
+    let mut duplicates = Vec::new();
+    for step in pipeline {
+        match step {
+            PipelineStep::Mixer(step) if !step.is_bypassed() => applied.clear(),
+            PipelineStep::Filter(step) if !step.is_bypassed() => {
+                for name in &step.names {
+                    let entry = (name.clone(), step.channel);
+                    if !applied.insert(entry.clone()) && !duplicates.contains(&entry) {
+                        duplicates.push(entry);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    duplicates
+}
+
+/// Get the number of channels entering and leaving each step of the active pipeline.
+pub fn pipeline_channel_flow(conf: &Configuration) -> Res<Vec<(usize, usize)>> {
+    let mut flow = Vec::new();
     if let Some(pipeline) = &conf.pipeline {
-        for step in pipeline {
-            match step {
-                PipelineStep::Mixer(step) => {
-                    if !step.is_bypassed() {
-                        if let Some(mixers) = &conf.mixers {
-                            if !mixers.contains_key(&step.name) {
-                                let msg = format!("Use of missing mixer '{}'", &step.name);
+        walk_pipeline(conf, pipeline, conf.devices.capture.channels(), &mut flow)?;
+    }
+    Ok(flow)
+}
+
+/// Walk through and validate the steps of a pipeline,
+/// recording the number of channels entering and leaving each step.
+fn walk_pipeline(
+    conf: &Configuration,
+    pipeline: &[PipelineStep],
+    mut num_channels: usize,
+    flow: &mut Vec<(usize, usize)>,
+) -> Res<usize> {
+    let fs = conf.devices.samplerate;
+    for step in pipeline {
+        let channels_in = num_channels;
+        match step {
+            PipelineStep::Mixer(step) => {
+                if !step.is_bypassed() {
+                    if let Some(mixers) = &conf.mixers {
+                        if !mixers.contains_key(&step.name) {
+                            let msg = format!("Use of missing mixer '{}'", &step.name);
+                            return Err(ConfigError::new(&msg).into());
+                        } else {
+                            let chan_in = mixers.get(&step.name).unwrap().channels.r#in;
+                            if chan_in != num_channels {
+                                let msg = format!(
+                                    "Mixer '{}' has wrong number of input channels. Expected {}, found {}.",
+                                    &step.name, num_channels, chan_in
+                                );
                                 return Err(ConfigError::new(&msg).into());
-                            } else {
-                                let chan_in = mixers.get(&step.name).unwrap().channels.r#in;
-                                if chan_in != num_channels {
-                                    let msg = format!(
-                                        "Mixer '{}' has wrong number of input channels. Expected {}, found {}.",
-                                        &step.name, num_channels, chan_in
-                                    );
+                            }
+                            num_channels = mixers.get(&step.name).unwrap().channels.out;
+                            match mixer::validate_mixer(mixers.get(&step.name).unwrap()) {
+                                Ok(_) => {}
+                                Err(err) => {
+                                    let msg =
+                                        format!("Invalid mixer '{}'. Reason: {}", &step.name, err);
                                     return Err(ConfigError::new(&msg).into());
                                 }
-                                num_channels = mixers.get(&step.name).unwrap().channels.out;
-                                match mixer::validate_mixer(mixers.get(&step.name).unwrap()) {
-                                    Ok(_) => {}
-                                    Err(err) => {
-                                        let msg = format!(
-                                            "Invalid mixer '{}'. Reason: {}",
-                                            &step.name, err
-                                        );
-                                        return Err(ConfigError::new(&msg).into());
+                            }
+                        }
+                    } else {
+                        let msg = format!("Use of missing mixer '{}'", &step.name);
+                        return Err(ConfigError::new(&msg).into());
+                    }
+                }
+            }
+            PipelineStep::Filter(step) => {
+                // A level matched step still uses the filter configs when bypassed
+                if !step.is_bypassed() || step.level_match_on_bypass() {
+                    if step.channel >= num_channels {
+                        let msg = format!("Use of non existing channel {}", step.channel);
+                        return Err(ConfigError::new(&msg).into());
+                    }
+                    if !(0.0..=1.0).contains(&step.mix()) {
+                        let msg = format!(
+                            "Filter step mix must be between 0 and 1, got {}",
+                            step.mix()
+                        );
+                        return Err(ConfigError::new(&msg).into());
+                    }
+                    for name in &step.names {
+                        if let Some(filters) = &conf.filters {
+                            if !filters.contains_key(name) {
+                                let msg = format!("Use of missing filter '{name}'");
+                                return Err(ConfigError::new(&msg).into());
+                            }
+                            match filters::validate_filter(fs, filters.get(name).unwrap()) {
+                                Ok(_) => {}
+                                Err(err) => {
+                                    let msg = format!("Invalid filter '{name}'. Reason: {err}");
+                                    return Err(ConfigError::new(&msg).into());
+                                }
+                            }
+                            match filters.get(name).unwrap() {
+                                Filter::Loudness { parameters, .. } => {
+                                    if let Some(gains) = &parameters.channel_gains {
+                                        if gains.len() != num_channels {
+                                            let msg = format!(
+                                                "Loudness filter '{}' has wrong number of channel gains. Expected {}, found {}.",
+                                                name, num_channels, gains.len()
+                                            );
+                                            return Err(ConfigError::new(&msg).into());
+                                        }
                                     }
                                 }
+                                Filter::Conv {
+                                    parameters: ConvParameters::Wav(params),
+                                    ..
+                                } => {
+                                    if let Some(sources) = &params.sources {
+                                        if sources.len() != num_channels {
+                                            let msg = format!(
+                                                "Conv filter '{}' has wrong number of sources. Expected {}, found {}.",
+                                                name, num_channels, sources.len()
+                                            );
+                                            return Err(ConfigError::new(&msg).into());
+                                        }
+                                    }
+                                }
+                                _ => {}
                             }
                         } else {
-                            let msg = format!("Use of missing mixer '{}'", &step.name);
+                            let msg = format!("Use of missing filter '{name}'");
                             return Err(ConfigError::new(&msg).into());
                         }
                     }
                 }
-                PipelineStep::Filter(step) => {
-                    if !step.is_bypassed() {
-                        if step.channel >= num_channels {
-                            let msg = format!("Use of non existing channel {}", step.channel);
+            }
+            PipelineStep::Processor(step) => {
+                if !step.is_bypassed() {
+                    if let Some(processors) = &conf.processors {
+                        if !processors.contains_key(&step.name) {
+                            let msg = format!("Use of missing processor '{}'", step.name);
                             return Err(ConfigError::new(&msg).into());
-                        }
-                        for name in &step.names {
-                            if let Some(filters) = &conf.filters {
-                                if !filters.contains_key(name) {
-                                    let msg = format!("Use of missing filter '{name}'");
-                                    return Err(ConfigError::new(&msg).into());
+                        } else {
+                            let procconf = processors.get(&step.name).unwrap();
+                            match procconf {
+                                Processor::Compressor { parameters, .. } => {
+                                    let channels = parameters.channels;
+                                    if channels != num_channels {
+                                        let msg = format!(
+                                            "Compressor '{}' has wrong number of channels. Expected {}, found {}.",
+                                            step.name, num_channels, channels
+                                        );
+                                        return Err(ConfigError::new(&msg).into());
+                                    }
+                                    match compressor::validate_compressor(parameters) {
+                                        Ok(_) => {}
+                                        Err(err) => {
+                                            let msg = format!(
+                                                "Invalid processor '{}'. Reason: {}",
+                                                step.name, err
+                                            );
+                                            return Err(ConfigError::new(&msg).into());
+                                        }
+                                    }
                                 }
-                                match filters::validate_filter(fs, filters.get(name).unwrap()) {
-                                    Ok(_) => {}
-                                    Err(err) => {
-                                        let msg = format!("Invalid filter '{name}'. Reason: {err}");
+                                Processor::DynamicEq { parameters, .. } => {
+                                    let channels = parameters.channels;
+                                    if channels != num_channels {
+                                        let msg = format!(
+                                            "DynamicEq '{}' has wrong number of channels. Expected {}, found {}.",
+                                            step.name, num_channels, channels
+                                        );
                                         return Err(ConfigError::new(&msg).into());
                                     }
+                                    match dynamiceq::validate_dynamic_eq(
+                                        conf.devices.samplerate,
+                                        parameters,
+                                    ) {
+                                        Ok(_) => {}
+                                        Err(err) => {
+                                            let msg = format!(
+                                                "Invalid processor '{}'. Reason: {}",
+                                                step.name, err
+                                            );
+                                            return Err(ConfigError::new(&msg).into());
+                                        }
+                                    }
                                 }
-                            } else {
-                                let msg = format!("Use of missing filter '{name}'");
-                                return Err(ConfigError::new(&msg).into());
-                            }
-                        }
-                    }
-                }
-                PipelineStep::Processor(step) => {
-                    if !step.is_bypassed() {
-                        if let Some(processors) = &conf.processors {
-                            if !processors.contains_key(&step.name) {
-                                let msg = format!("Use of missing processor '{}'", step.name);
-                                return Err(ConfigError::new(&msg).into());
-                            } else {
-                                let procconf = processors.get(&step.name).unwrap();
-                                match procconf {
-                                    Processor::Compressor { parameters, .. } => {
-                                        let channels = parameters.channels;
-                                        if channels != num_channels {
+                                Processor::SubAlign { parameters, .. } => {
+                                    let channels = parameters.channels;
+                                    if channels != num_channels {
+                                        let msg = format!(
+                                            "SubAlign '{}' has wrong number of channels. Expected {}, found {}.",
+                                            step.name, num_channels, channels
+                                        );
+                                        return Err(ConfigError::new(&msg).into());
+                                    }
+                                    match subalign::validate_sub_align(
+                                        conf.devices.samplerate,
+                                        parameters,
+                                    ) {
+                                        Ok(_) => {}
+                                        Err(err) => {
                                             let msg = format!(
-                                                "Compressor '{}' has wrong number of channels. Expected {}, found {}.",
-                                                step.name, num_channels, channels
+                                                "Invalid processor '{}'. Reason: {}",
+                                                step.name, err
                                             );
                                             return Err(ConfigError::new(&msg).into());
                                         }
-                                        match compressor::validate_compressor(parameters) {
-                                            Ok(_) => {}
-                                            Err(err) => {
-                                                let msg = format!(
-                                                    "Invalid processor '{}'. Reason: {}",
-                                                    step.name, err
-                                                );
-                                                return Err(ConfigError::new(&msg).into());
-                                            }
+                                    }
+                                }
+                                Processor::BitCrusher { parameters, .. } => {
+                                    let channels = parameters.channels;
+                                    if channels != num_channels {
+                                        let msg = format!(
+                                            "BitCrusher '{}' has wrong number of channels. Expected {}, found {}.",
+                                            step.name, num_channels, channels
+                                        );
+                                        return Err(ConfigError::new(&msg).into());
+                                    }
+                                    match bitcrusher::validate_bit_crusher(parameters) {
+                                        Ok(_) => {}
+                                        Err(err) => {
+                                            let msg = format!(
+                                                "Invalid processor '{}'. Reason: {}",
+                                                step.name, err
+                                            );
+                                            return Err(ConfigError::new(&msg).into());
+                                        }
+                                    }
+                                }
+                                Processor::PhaseRotation { parameters, .. } => {
+                                    let channels = parameters.channels;
+                                    if channels != num_channels {
+                                        let msg = format!(
+                                            "PhaseRotation '{}' has wrong number of channels. Expected {}, found {}.",
+                                            step.name, num_channels, channels
+                                        );
+                                        return Err(ConfigError::new(&msg).into());
+                                    }
+                                    match phaserotation::validate_phase_rotation(
+                                        parameters,
+                                        conf.devices.chunksize,
+                                    ) {
+                                        Ok(_) => {}
+                                        Err(err) => {
+                                            let msg = format!(
+                                                "Invalid processor '{}'. Reason: {}",
+                                                step.name, err
+                                            );
+                                            return Err(ConfigError::new(&msg).into());
+                                        }
+                                    }
+                                }
+                                Processor::Crossfeed { parameters, .. } => {
+                                    let channels = parameters.channels;
+                                    if channels != num_channels {
+                                        let msg = format!(
+                                            "Crossfeed '{}' has wrong number of channels. Expected {}, found {}.",
+                                            step.name, num_channels, channels
+                                        );
+                                        return Err(ConfigError::new(&msg).into());
+                                    }
+                                    match crossfeed::validate_crossfeed(
+                                        parameters,
+                                        conf.devices.samplerate,
+                                    ) {
+                                        Ok(_) => {}
+                                        Err(err) => {
+                                            let msg = format!(
+                                                "Invalid processor '{}'. Reason: {}",
+                                                step.name, err
+                                            );
+                                            return Err(ConfigError::new(&msg).into());
                                         }
                                     }
                                 }
                             }
-                        } else {
-                            let msg = format!("Use of missing processor '{}'", step.name);
-                            return Err(ConfigError::new(&msg).into());
                         }
+                    } else {
+                        let msg = format!("Use of missing processor '{}'", step.name);
+                        return Err(ConfigError::new(&msg).into());
                     }
                 }
             }
         }
+        flow.push((channels_in, num_channels));
     }
-    let num_channels_out = conf.devices.playback.channels();
-    if num_channels != num_channels_out {
-        let msg = format!(
-            "Pipeline outputs {num_channels} channels, playback device has {num_channels_out}."
-        );
-        return Err(ConfigError::new(&msg).into());
-    }
-    Ok(())
+    Ok(num_channels)
 }
 
-/// Get a vector telling which channels are actually used in the pipeline
+/// Get a vector telling which channels are actually used in the pipelines of all outputs
 pub fn used_capture_channels(conf: &Configuration) -> Vec<bool> {
+    let mut used_channels = pipeline_used_capture_channels(conf);
+    for output_conf in conf.output_configs().iter() {
+        let output_used = pipeline_used_capture_channels(output_conf);
+        used_channels
+            .iter_mut()
+            .zip(output_used.iter())
+            .for_each(|(used, output_used)| *used |= *output_used);
+    }
+    used_channels
+}
+
+fn pipeline_used_capture_channels(conf: &Configuration) -> Vec<bool> {
     if let Some(pipeline) = &conf.pipeline {
         for step in pipeline.iter() {
             if let PipelineStep::Mixer(mix) = step {
@@ -1897,3 +3615,261 @@ pub fn used_capture_channels(conf: &Configuration) -> Vec<bool> {
     let capture_channels = conf.devices.capture.channels();
     vec![true; capture_channels]
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{url_base, validate_config, CaptureDevice, Configuration, PipelineStep};
+
+    fn set_capture_channels(conf: &mut Configuration, channels: usize) {
+        if let CaptureDevice::Stdin(dev) = &mut conf.devices.capture {
+            dev.channels = channels;
+        }
+    }
+
+    fn first_step_is_mixer(conf: &Configuration) -> bool {
+        matches!(
+            conf.pipeline.as_ref().unwrap().first(),
+            Some(PipelineStep::Mixer(_))
+        )
+    }
+
+    #[test]
+    fn pipeline_by_channels_falls_back_to_default() {
+        let conf_yaml = r#"
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  cut:
+    type: Gain
+    parameters:
+      gain: -6.0
+mixers:
+  upmix:
+    channels:
+      in: 1
+      out: 2
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 0
+      - dest: 1
+        sources:
+          - channel: 0
+pipeline:
+  - type: Filter
+    channel: 0
+    names: [cut]
+pipeline_by_channels:
+  1:
+    - type: Mixer
+      name: upmix
+"#;
+        let mut conf: Configuration = serde_yaml::from_str(conf_yaml).unwrap();
+        validate_config(&mut conf, None).unwrap();
+        assert!(!first_step_is_mixer(&conf));
+
+        set_capture_channels(&mut conf, 1);
+        validate_config(&mut conf, None).unwrap();
+        assert!(first_step_is_mixer(&conf));
+
+        set_capture_channels(&mut conf, 2);
+        validate_config(&mut conf, None).unwrap();
+        assert!(!first_step_is_mixer(&conf));
+    }
+
+    fn flac_config(filename: &str) -> Configuration {
+        let conf_yaml = format!(
+            r#"
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: FlacFile
+    filename: {filename}
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+"#
+        );
+        serde_yaml::from_str(&conf_yaml).unwrap()
+    }
+
+    #[test]
+    fn flac_stream_info_read_at_validation() {
+        let mut conf = flac_config("testdata/int16_stereo.flac");
+        assert_eq!(conf.devices.capture.channels(), 0);
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf.devices.capture.channels(), 2);
+
+        let mut conf = flac_config("testdata/missing.flac");
+        assert!(validate_config(&mut conf, None).is_err());
+    }
+
+    fn chunksize_config(capture: &str, chunksize: &str) -> Configuration {
+        let conf_yaml = format!(
+            r#"
+devices:
+  samplerate: 48000
+  {chunksize}
+  capture:
+    {capture}
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+"#
+        );
+        serde_yaml::from_str(&conf_yaml).unwrap()
+    }
+
+    const STDIN_CAPTURE: &str = "{type: Stdin, channels: 2, format: S16LE}";
+    const FLAC_CAPTURE: &str = "{type: FlacFile, filename: testdata/int16_stereo.flac}";
+
+    #[test]
+    fn chunksize_precedence() {
+        let mut conf = chunksize_config(STDIN_CAPTURE, "chunksize: 1024");
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf.devices.chunksize, 1024);
+
+        let mut conf = chunksize_config(STDIN_CAPTURE, "chunksize_ms: 10.0");
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf.devices.chunksize, 480);
+
+        // An explicit chunksize takes precedence over chunksize_ms
+        let mut conf = chunksize_config(STDIN_CAPTURE, "chunksize: 1024\n  chunksize_ms: 10.0");
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf.devices.chunksize, 1024);
+    }
+
+    #[test]
+    fn chunksize_invalid_values() {
+        for chunksize in [
+            "chunksize: 0",
+            "chunksize_ms: 0.0",
+            "chunksize_ms: -5.0",
+            "chunksize_ms: .nan",
+            "queuelimit: 4",
+        ] {
+            let mut conf = chunksize_config(STDIN_CAPTURE, chunksize);
+            assert!(validate_config(&mut conf, None).is_err(), "{}", chunksize);
+        }
+    }
+
+    #[test]
+    fn chunksize_resolved_after_overrides() {
+        // The FLAC file overrides the samplerate with 44100 Hz
+        let mut conf = chunksize_config(FLAC_CAPTURE, "chunksize_ms: 10.0");
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf.devices.samplerate, 44100);
+        assert_eq!(conf.devices.chunksize, 441);
+
+        // An explicit chunksize is scaled by integer factors only
+        let mut conf = chunksize_config(FLAC_CAPTURE, "chunksize: 1024");
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf.devices.chunksize, 1024);
+    }
+
+    #[test]
+    fn derived_chunksize_not_kept_in_config() {
+        let mut conf = chunksize_config(STDIN_CAPTURE, "chunksize_ms: 10.0");
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf.devices.chunksize, 480);
+
+        // Read back the config, change the samplerate and apply it again
+        let mut conf: Configuration =
+            serde_yaml::from_str(&serde_yaml::to_string(&conf).unwrap()).unwrap();
+        assert_eq!(conf.devices.chunksize_setting, None);
+        conf.devices.samplerate = 96000;
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf.devices.chunksize, 960);
+    }
+
+    #[test]
+    fn open_retry_time_is_limited() {
+        let mut conf = chunksize_config(
+            STDIN_CAPTURE,
+            "chunksize: 1024\n  open_retry_count: 30\n  open_retry_interval_ms: 1000.0",
+        );
+        validate_config(&mut conf, None).unwrap();
+        conf.devices.open_retry_count = Some(31);
+        assert!(validate_config(&mut conf, None).is_err());
+    }
+
+    fn mixer_config(mixer: &str) -> Configuration {
+        let conf_yaml = format!(
+            r#"
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 6
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+mixers:
+  tostereo:
+    {mixer}
+pipeline:
+  - type: Mixer
+    name: tostereo
+"#
+        );
+        serde_yaml::from_str(&conf_yaml).unwrap()
+    }
+
+    #[test]
+    fn downmix_mixer_expanded() {
+        let mut conf = mixer_config("{type: Downmix, channels: {in: 6, out: 2}, normalize: true}");
+        validate_config(&mut conf, None).unwrap();
+        let mixer = &conf.mixers.as_ref().unwrap()["tostereo"];
+        assert_eq!(mixer.r#type, None);
+        assert_eq!(mixer.mapping.len(), 2);
+        // Front at 0 dB and center and surround at -3 dB,
+        // all lowered by the normalization so that the sum of the gains is unity
+        let expected = [
+            [(0, -7.6555), (2, -10.6658), (4, -10.6658)],
+            [(1, -7.6555), (2, -10.6658), (5, -10.6658)],
+        ];
+        for (mapping, expected) in mixer.mapping.iter().zip(expected.iter()) {
+            assert_eq!(mapping.sources.len(), 3);
+            for (source, (channel, gain)) in mapping.sources.iter().zip(expected.iter()) {
+                assert_eq!(source.channel, *channel);
+                assert!((source.gain.unwrap() - gain).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn mixer_without_mapping_rejected() {
+        let mut conf = mixer_config("{channels: {in: 6, out: 2}}");
+        assert!(validate_config(&mut conf, None).is_err());
+        let mut conf = mixer_config("{type: Downmix, channels: {in: 6, out: 1}}");
+        assert!(validate_config(&mut conf, None).is_err());
+    }
+
+    #[test]
+    fn url_base_ignores_query() {
+        assert_eq!(
+            url_base("http://server/configs/room.yml"),
+            "http://server/configs/"
+        );
+        assert_eq!(
+            url_base("https://server/configs/room.yml?token=a/b#top"),
+            "https://server/configs/"
+        );
+        assert_eq!(url_base("http://server?file=a/b"), "http://server");
+    }
+}
@@ -111,6 +111,41 @@ pub fn buffer_to_chunk_rawbytes(
     AudioChunk::new(wfs, maxvalue, minvalue, num_frames, num_valid_frames)
 }
 
+/// Convert a buffer of interleaved u8 to an AudioChunk, keeping only the selected channels.
+/// The `used_channels` mask refers to the selected channels, in the order they are selected.
+pub fn buffer_to_chunk_rawbytes_selected(
+    buffer: &[u8],
+    channels: usize,
+    sampleformat: &SampleFormat,
+    valid_bytes: usize,
+    channel_select: &[usize],
+    used_channels: &[bool],
+) -> AudioChunk {
+    let mut raw_used = vec![false; channels];
+    for (channel, used) in channel_select.iter().zip(used_channels) {
+        raw_used[*channel] |= *used;
+    }
+    let chunk = buffer_to_chunk_rawbytes(buffer, channels, sampleformat, valid_bytes, &raw_used);
+    let wfs = channel_select
+        .iter()
+        .zip(used_channels)
+        .map(|(channel, used)| {
+            if *used {
+                chunk.waveforms[*channel].clone()
+            } else {
+                Vec::new()
+            }
+        })
+        .collect();
+    AudioChunk::new(
+        wfs,
+        chunk.maxval,
+        chunk.minval,
+        chunk.frames,
+        chunk.valid_frames,
+    )
+}
+
 /// Convert an AudioChunk to an interleaved queue of ints, only used by CPAL backend.
 #[cfg(feature = "cpal-backend")]
 pub fn chunk_to_queue_int<T: num_traits::cast::NumCast>(
@@ -287,7 +322,9 @@ pub fn queue_to_chunk_float<T: num_traits::cast::AsPrimitive<PrcFmt>>(
 mod tests {
     use crate::audiodevice::AudioChunk;
     use crate::config::SampleFormat;
-    use crate::conversions::{buffer_to_chunk_rawbytes, chunk_to_buffer_rawbytes};
+    use crate::conversions::{
+        buffer_to_chunk_rawbytes, buffer_to_chunk_rawbytes_selected, chunk_to_buffer_rawbytes,
+    };
     #[cfg(feature = "cpal-backend")]
     use crate::conversions::{
         chunk_to_queue_float, chunk_to_queue_int, queue_to_chunk_float, queue_to_chunk_int,
@@ -440,6 +477,25 @@ mod tests {
         assert_eq!(waveforms[0], chunk2.waveforms[0]);
     }
 
+    #[test]
+    fn from_buffer_selected_channels() {
+        let waveforms = vec![vec![-0.5, 0.5], vec![0.0, 0.25], vec![0.125, -0.25]];
+        let chunk = AudioChunk::new(waveforms.clone(), 0.0, 0.0, 2, 2);
+        let mut buffer = vec![0u8; 3 * 2 * 2];
+        chunk_to_buffer_rawbytes(&chunk, &mut buffer, &SampleFormat::S16LE);
+        let chunk2 = buffer_to_chunk_rawbytes_selected(
+            &buffer,
+            3,
+            &SampleFormat::S16LE,
+            buffer.len(),
+            &[2, 0],
+            &[true, true],
+        );
+        assert_eq!(chunk2.channels, 2);
+        assert_eq!(chunk2.waveforms[0], waveforms[2]);
+        assert_eq!(chunk2.waveforms[1], waveforms[0]);
+    }
+
     #[test]
     fn to_from_buffer_24() {
         let waveforms = vec![vec![-0.5, 0.0, 0.5]; 1];
@@ -8,7 +8,7 @@ use dispatch::Semaphore;
 use parking_lot::{RwLock, RwLockUpgradableReadGuard};
 use rubato::VecResampler;
 use std::collections::VecDeque;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::mem;
 use std::os::raw::{c_char, c_void};
 use std::ptr::{null, null_mut};
@@ -84,6 +84,169 @@ fn release_ownership(device_id: AudioDeviceID) -> Res<()> {
     Ok(())
 }
 
+/// A temporary aggregate device, that is destroyed when dropped.
+struct AggregateDevice {
+    device_id: AudioDeviceID,
+}
+
+impl Drop for AggregateDevice {
+    fn drop(&mut self) {
+        let status = unsafe { AudioHardwareDestroyAggregateDevice(self.device_id) };
+        if status == kAudioHardwareNoError as i32 {
+            debug!("Destroyed aggregate device");
+        } else {
+            warn!("Could not destroy aggregate device, error: {status}");
+        }
+    }
+}
+
+/// Create a CFString, the caller is responsible for releasing it.
+unsafe fn create_cfstring(value: &CStr) -> CFStringRef {
+    CFStringCreateWithCString(kCFAllocatorDefault, value.as_ptr(), kCFStringEncodingUTF8)
+}
+
+/// Insert a value in a dictionary and release it.
+/// The key is one of the null terminated CoreAudio dictionary keys.
+unsafe fn set_dict_value(dict: CFMutableDictionaryRef, key: &[u8], value: CFTypeRef) {
+    let cf_key = CFStringCreateWithCString(
+        kCFAllocatorDefault,
+        key.as_ptr() as *const c_char,
+        kCFStringEncodingUTF8,
+    );
+    CFDictionarySetValue(dict, cf_key as *const c_void, value);
+    CFRelease(cf_key as CFTypeRef);
+    CFRelease(value);
+}
+
+unsafe fn set_dict_int(dict: CFMutableDictionaryRef, key: &[u8], value: i32) {
+    let number = CFNumberCreate(
+        kCFAllocatorDefault,
+        kCFNumberSInt32Type as CFNumberType,
+        &value as *const i32 as *const c_void,
+    );
+    set_dict_value(dict, key, number as CFTypeRef);
+}
+
+fn get_device_id_from_uid(uid: &CStr) -> Option<AudioDeviceID> {
+    let property_address = AudioObjectPropertyAddress {
+        mSelector: kAudioHardwarePropertyTranslateUIDToDevice,
+        mScope: kAudioObjectPropertyScopeGlobal,
+        mElement: kAudioObjectPropertyElementMain,
+    };
+    let mut device_id = kAudioObjectUnknown as AudioDeviceID;
+    let mut data_size = mem::size_of::<AudioDeviceID>() as u32;
+    let status = unsafe {
+        let cf_uid = create_cfstring(uid);
+        let status = AudioObjectGetPropertyData(
+            kAudioObjectSystemObject as AudioObjectID,
+            &property_address as *const _,
+            mem::size_of::<CFStringRef>() as u32,
+            &cf_uid as *const _ as *const c_void,
+            &mut data_size as *mut _,
+            &mut device_id as *mut _ as *mut c_void,
+        );
+        CFRelease(cf_uid as CFTypeRef);
+        status
+    };
+    if status != kAudioHardwareNoError as i32 || device_id == kAudioObjectUnknown as AudioDeviceID {
+        return None;
+    }
+    Some(device_id)
+}
+
+/// Combine several devices into a private aggregate device.
+/// A private device is only visible to this process, and is removed by the system
+/// if CamillaDSP exits without destroying it.
+fn create_aggregate_device(aggregate: &config::CoreAudioAggregate) -> Res<AggregateDevice> {
+    let master = aggregate.master_uid();
+    let mut uids = Vec::with_capacity(aggregate.devices.len());
+    for sub in aggregate.devices.iter() {
+        let uid = CString::new(sub.uid.as_str()).map_err(|e| ConfigError::new(&format!("{e}")))?;
+        if get_device_id_from_uid(&uid).is_none() {
+            let msg = format!("Could not find aggregate sub-device with UID '{}'", sub.uid);
+            return Err(ConfigError::new(&msg).into());
+        }
+        uids.push(uid);
+    }
+    let aggregate_uid = CString::new(format!("CamillaDSP-aggregate-{}", std::process::id()))
+        .map_err(|e| ConfigError::new(&format!("{e}")))?;
+    let master_uid = CString::new(master).map_err(|e| ConfigError::new(&format!("{e}")))?;
+
+    let mut device_id = kAudioObjectUnknown as AudioDeviceID;
+    let status = unsafe {
+        let sub_devices = CFArrayCreateMutable(kCFAllocatorDefault, 0, &kCFTypeArrayCallBacks);
+        for (sub, uid) in aggregate.devices.iter().zip(uids.iter()) {
+            let sub_description = CFDictionaryCreateMutable(
+                kCFAllocatorDefault,
+                0,
+                &kCFTypeDictionaryKeyCallBacks,
+                &kCFTypeDictionaryValueCallBacks,
+            );
+            set_dict_value(
+                sub_description,
+                kAudioSubDeviceUIDKey,
+                create_cfstring(uid) as CFTypeRef,
+            );
+            // Let the sub-devices that don't provide the clock follow the master
+            set_dict_int(
+                sub_description,
+                kAudioSubDeviceDriftCompensationKey,
+                (sub.uid != master) as i32,
+            );
+            CFArrayAppendValue(sub_devices, sub_description as *const c_void);
+            CFRelease(sub_description as CFTypeRef);
+        }
+
+        let description = CFDictionaryCreateMutable(
+            kCFAllocatorDefault,
+            0,
+            &kCFTypeDictionaryKeyCallBacks,
+            &kCFTypeDictionaryValueCallBacks,
+        );
+        set_dict_value(
+            description,
+            kAudioAggregateDeviceUIDKey,
+            create_cfstring(&aggregate_uid) as CFTypeRef,
+        );
+        set_dict_value(
+            description,
+            kAudioAggregateDeviceNameKey,
+            create_cfstring(&aggregate_uid) as CFTypeRef,
+        );
+        set_dict_value(
+            description,
+            kAudioAggregateDeviceMainSubDeviceKey,
+            create_cfstring(&master_uid) as CFTypeRef,
+        );
+        set_dict_value(
+            description,
+            kAudioAggregateDeviceSubDeviceListKey,
+            sub_devices as CFTypeRef,
+        );
+        set_dict_int(description, kAudioAggregateDeviceIsPrivateKey, 1);
+
+        let status = AudioHardwareCreateAggregateDevice(
+            description as CFDictionaryRef,
+            &mut device_id as *mut AudioDeviceID,
+        );
+        CFRelease(description as CFTypeRef);
+        status
+    };
+    if status != kAudioHardwareNoError as i32 || device_id == kAudioObjectUnknown as AudioDeviceID {
+        let msg = format!("Could not create aggregate device, error: {status}");
+        return Err(ConfigError::new(&msg).into());
+    }
+    debug!(
+        "Created aggregate device with sub-devices {:?}, clock master: {master}",
+        aggregate
+            .devices
+            .iter()
+            .map(|d| d.uid.as_str())
+            .collect::<Vec<_>>()
+    );
+    Ok(AggregateDevice { device_id })
+}
+
 #[derive(Clone, Debug)]
 pub struct CoreaudioPlaybackDevice {
     pub devname: Option<String>,
@@ -95,6 +258,8 @@ pub struct CoreaudioPlaybackDevice {
     pub target_level: usize,
     pub adjust_period: f32,
     pub enable_rate_adjust: bool,
+    pub aggregate: Option<config::CoreAudioAggregate>,
+    pub open_retry: OpenRetry,
 }
 
 #[derive(Clone, Debug)]
@@ -110,6 +275,7 @@ pub struct CoreaudioCaptureDevice {
     pub silence_timeout: PrcFmt,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
+    pub open_retry: OpenRetry,
 }
 
 pub fn list_device_names(input: bool) -> Vec<String> {
@@ -164,12 +330,15 @@ fn device_supports_scope(device_id: u32, input: bool) -> bool {
 
 fn open_coreaudio_playback(
     devname: &Option<String>,
+    aggregate: Option<&AggregateDevice>,
     samplerate: usize,
     channels: usize,
     sample_format: &Option<SampleFormat>,
     exclusive: bool,
 ) -> Res<(AudioUnit, AudioDeviceID)> {
-    let device_id = if let Some(name) = devname {
+    let device_id = if let Some(aggregate) = aggregate {
+        aggregate.device_id
+    } else if let Some(name) = devname {
         trace!("Available playback devices: {:?}", list_device_names(false));
         match get_device_id_from_name(name) {
             Some(dev) => dev,
@@ -347,11 +516,13 @@ impl PlaybackDevice for CoreaudioPlaybackDevice {
         playback_status: Arc<RwLock<PlaybackStatus>>,
     ) -> Res<Box<thread::JoinHandle<()>>> {
         let devname = self.devname.clone();
+        let open_retry = self.open_retry;
         let samplerate = self.samplerate;
         let chunksize = self.chunksize;
         let channels = self.channels;
         let sample_format = self.sample_format;
         let exclusive = self.exclusive;
+        let aggregate = self.aggregate.clone();
         let target_level = if self.target_level > 0 {
             self.target_level
         } else {
@@ -374,6 +545,9 @@ impl PlaybackDevice for CoreaudioPlaybackDevice {
                     rms: vec![0.0; channels],
                     peak: vec![0.0; channels],
                 };
+                let mut level_meter =
+                    LevelMeter::new(playback_status.read().meter_ballistics, samplerate);
+                let mut true_peak_meter = countertimer::TruePeakMeter::default();
                 let blockalign = 4 * channels;
                 // Rough guess of the number of frames per callback.
                 let callback_frames = 512;
@@ -385,12 +559,25 @@ impl PlaybackDevice for CoreaudioPlaybackDevice {
                 let mut sample_queue: VecDeque<u8> =
                     VecDeque::with_capacity(16 * chunksize * blockalign);
 
-                let (mut audio_unit, device_id) = match open_coreaudio_playback(
-                    &devname,
-                    samplerate,
-                    channels,
-                    &sample_format,
-                    exclusive,
+                // The aggregate device is destroyed when this is dropped at the end of the thread.
+                let (_aggregate_device, mut audio_unit, device_id) = match open_with_retry(
+                    open_retry,
+                    devname.as_deref().unwrap_or("default"),
+                    || {
+                        let aggregate_device = aggregate
+                            .as_ref()
+                            .map(create_aggregate_device)
+                            .transpose()?;
+                        let (audio_unit, device_id) = open_coreaudio_playback(
+                            &devname,
+                            aggregate_device.as_ref(),
+                            samplerate,
+                            channels,
+                            &sample_format,
+                            exclusive,
+                        )?;
+                        Ok((aggregate_device, audio_unit, device_id))
+                    },
                 ) {
                     Ok(audio_unit) => audio_unit,
                     Err(err) => {
@@ -519,7 +706,8 @@ impl PlaybackDevice for CoreaudioPlaybackDevice {
                                     playback_status.write().buffer_level = av_delay as usize;
                                 }
                             }
-                            chunk.update_stats(&mut chunk_stats);
+                            level_meter.update(&chunk, &mut chunk_stats);
+                            true_peak_meter.update(&chunk);
                             let mut buf = vec![
                                 0u8;
                                 channels
@@ -542,6 +730,9 @@ impl PlaybackDevice for CoreaudioPlaybackDevice {
                                 playback_status
                                     .signal_peak
                                     .add_record(chunk_stats.peak_linear());
+                                playback_status.signal_minmax.update(&chunk);
+                                playback_status.true_peak.add_record(&mut true_peak_meter);
+                                playback_status.frames_played += chunk.valid_frames as u64;
                             }
                             match tx_dev.send(PlaybackDeviceMessage::Data(buf)) {
                                 Ok(_) => {}
@@ -602,6 +793,7 @@ impl CaptureDevice for CoreaudioCaptureDevice {
         capture_status: Arc<RwLock<CaptureStatus>>,
     ) -> Res<Box<thread::JoinHandle<()>>> {
         let devname = self.devname.clone();
+        let open_retry = self.open_retry;
         let samplerate = self.samplerate;
         let capture_samplerate = self.capture_samplerate;
         let chunksize = self.chunksize;
@@ -644,7 +836,7 @@ impl CaptureDevice for CoreaudioCaptureDevice {
                 let device_sph = semaphore.clone();
 
                 trace!("Build input stream");
-                let (mut audio_unit, device_id) = match open_coreaudio_capture(&devname, capture_samplerate, channels, &sample_format) {
+                let (mut audio_unit, device_id) = match open_with_retry(open_retry, devname.as_deref().unwrap_or("default"), || open_coreaudio_capture(&devname, capture_samplerate, channels, &sample_format)) {
                     Ok(audio_unit) => audio_unit,
                     Err(err) => {
                         status_channel
@@ -735,6 +927,8 @@ impl CaptureDevice for CoreaudioCaptureDevice {
                 let mut valuewatcher = countertimer::ValueWatcher::new(capture_samplerate as f32, RATE_CHANGE_THRESHOLD_VALUE, RATE_CHANGE_THRESHOLD_COUNT);
                 let mut value_range = 0.0;
                 let mut chunk_stats = ChunkStats{rms: vec![0.0; channels], peak: vec![0.0; channels]};
+                let mut level_meter = LevelMeter::new(capture_status.read().meter_ballistics, capture_samplerate);
+                let mut true_peak_meter = countertimer::TruePeakMeter::default();
                 let mut rate_adjust = 0.0;
                 let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, capture_samplerate, chunksize);
                 let mut state = ProcessingState::Running;
@@ -916,12 +1110,18 @@ impl CaptureDevice for CoreaudioCaptureDevice {
                         }
                     }
                     prev_len = data_queue.len();
-                    chunk.update_stats(&mut chunk_stats);
+                    level_meter.update(&chunk, &mut chunk_stats);
+                    true_peak_meter.update(&chunk);
                     //trace!("Capture rms {:?}, peak {:?}", chunk_stats.rms_db(), chunk_stats.peak_db());
                     {
                         let mut capture_status = capture_status.write();
                         capture_status.signal_rms.add_record_squared(chunk_stats.rms_linear());
                         capture_status.signal_peak.add_record(chunk_stats.peak_linear());
+                        capture_status.signal_minmax.update(&chunk);
+                        capture_status.true_peak.add_record(&mut true_peak_meter);
+                        capture_status.recent_samples.update(&chunk);
+                        capture_status.signal_duty.update(silence_counter.is_signal(chunk.maxval - chunk.minval), chunk.valid_frames);
+                        capture_status.frames_captured += chunk.valid_frames as u64;
                     }
                     value_range = chunk.maxval - chunk.minval;
                     state = silence_counter.update(value_range);
@@ -1,3 +1,4 @@
+use crate::audiodevice::AudioChunk;
 use crate::NewValue;
 use crate::PrcFmt;
 use crate::ProcessingState;
@@ -29,6 +30,11 @@ impl SilenceCounter {
         }
     }
 
+    /// Check if a chunk with the given value range contains signal, meaning that it's above the silence threshold.
+    pub fn is_signal(&self, value_range: PrcFmt) -> bool {
+        value_range > self.silence_threshold
+    }
+
     pub fn update(&mut self, value_range: PrcFmt) -> ProcessingState {
         let mut state = ProcessingState::Running;
         if self.silence_limit_nbr > 0 {
@@ -52,6 +58,151 @@ impl SilenceCounter {
     }
 }
 
+/// Keeps track of the fraction of the captured frames that contained signal.
+#[derive(Clone, Debug, Default)]
+pub struct DutyCycle {
+    signal_frames: u64,
+    total_frames: u64,
+}
+
+impl DutyCycle {
+    pub fn update(&mut self, signal: bool, frames: usize) {
+        self.total_frames += frames as u64;
+        if signal {
+            self.signal_frames += frames as u64;
+        }
+    }
+
+    /// Get the fraction of frames with signal since the last reset, or zero if nothing was captured.
+    pub fn fraction(&self) -> f32 {
+        if self.total_frames == 0 {
+            return 0.0;
+        }
+        (self.signal_frames as f64 / self.total_frames as f64) as f32
+    }
+
+    pub fn reset(&mut self) {
+        self.signal_frames = 0;
+        self.total_frames = 0;
+    }
+}
+
+// Oversampling factor and number of taps per phase of the true peak interpolation filter.
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS: usize = 12;
+
+/// Measures the true peak value of each channel, including the peaks between the samples.
+/// The signal is oversampled 4x with a polyphase windowed sinc filter.
+/// Measuring is off until enabled, to avoid spending time on it when no client needs the values.
+/// The meter is owned by the device thread, and measures outside of the status lock.
+/// The results are then collected by a `TruePeakRecord` in the status.
+#[derive(Clone, Debug, Default)]
+pub struct TruePeakMeter {
+    enabled: bool,
+    phases: Vec<Vec<PrcFmt>>,
+    history: Vec<Vec<PrcFmt>>,
+    peak: Vec<PrcFmt>,
+}
+
+impl TruePeakMeter {
+    pub fn enable(&mut self) {
+        if self.enabled {
+            return;
+        }
+        // Hann windowed sinc with a cutoff at the Nyquist frequency of the original rate.
+        // The sum of the coefficients of each phase is close to one.
+        let pi = std::f64::consts::PI as PrcFmt;
+        let length = TRUE_PEAK_OVERSAMPLING * TRUE_PEAK_TAPS;
+        let center = (length - 1) as PrcFmt / 2.0;
+        let coeffs: Vec<PrcFmt> = (0..length)
+            .map(|n| {
+                let x = (n as PrcFmt - center) / TRUE_PEAK_OVERSAMPLING as PrcFmt;
+                let window = 0.5 - 0.5 * (2.0 * pi * (n as PrcFmt + 0.5) / length as PrcFmt).cos();
+                window * (pi * x).sin() / (pi * x)
+            })
+            .collect();
+        self.phases = (0..TRUE_PEAK_OVERSAMPLING)
+            .map(|phase| {
+                coeffs
+                    .iter()
+                    .skip(phase)
+                    .step_by(TRUE_PEAK_OVERSAMPLING)
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        self.enabled = true;
+    }
+
+    pub fn update(&mut self, chunk: &AudioChunk) {
+        if !self.enabled {
+            return;
+        }
+        if self.history.len() != chunk.channels {
+            self.history = vec![vec![0.0; TRUE_PEAK_TAPS]; chunk.channels];
+            self.peak = vec![0.0; chunk.channels];
+        }
+        for ((waveform, history), peak) in chunk
+            .waveforms
+            .iter()
+            .zip(self.history.iter_mut())
+            .zip(self.peak.iter_mut())
+        {
+            *peak = 0.0;
+            for sample in waveform.iter().take(chunk.valid_frames) {
+                history.copy_within(1.., 0);
+                history[TRUE_PEAK_TAPS - 1] = *sample;
+                *peak = peak.max(sample.abs());
+                for phase in self.phases.iter() {
+                    let value: PrcFmt = phase
+                        .iter()
+                        .zip(history.iter().rev())
+                        .map(|(coeff, value)| coeff * value)
+                        .sum();
+                    *peak = peak.max(value.abs());
+                }
+            }
+        }
+    }
+
+    /// Get the true peak of each channel in the latest chunk, empty if measuring isn't enabled.
+    pub fn peak_linear(&self) -> Vec<f32> {
+        self.peak.iter().map(|val| *val as f32).collect()
+    }
+}
+
+/// The highest true peak of each channel since the values were last read.
+/// Reading the values also enables the meter in the device thread.
+#[derive(Clone, Debug, Default)]
+pub struct TruePeakRecord {
+    enabled: bool,
+    peak: Vec<f32>,
+}
+
+impl TruePeakRecord {
+    /// Add the peaks of the latest chunk from a meter, and enable the meter if the values have been read.
+    pub fn add_record(&mut self, meter: &mut TruePeakMeter) {
+        if !self.enabled {
+            return;
+        }
+        meter.enable();
+        if self.peak.len() != meter.peak.len() {
+            self.peak = vec![0.0; meter.peak.len()];
+        }
+        for (record, value) in self.peak.iter_mut().zip(meter.peak.iter()) {
+            *record = record.max(*value as f32);
+        }
+    }
+
+    /// Get the highest true peak of each channel since the last read, and start over.
+    pub fn take_peak_linear(&mut self) -> Vec<f32> {
+        self.enabled = true;
+        let values = self.peak.clone();
+        self.peak.iter_mut().for_each(|val| *val = 0.0);
+        values
+    }
+}
+
 /// A simple stopwatch for measuring time.
 pub struct Stopwatch {
     start_time: Instant,
@@ -222,6 +373,9 @@ pub struct ValueHistory {
     peak: Vec<f32>,
     nbr_values: usize,
     history_length: usize,
+    decay_rate: f32,
+    decaying_peak: Vec<f32>,
+    decay_time: Instant,
 }
 
 impl ValueHistory {
@@ -231,6 +385,9 @@ impl ValueHistory {
             peak: vec![0.0; nbr_values],
             nbr_values,
             history_length,
+            decay_rate: 0.0,
+            decaying_peak: Vec::new(),
+            decay_time: Instant::now(),
         }
     }
 
@@ -268,8 +425,8 @@ impl ValueHistory {
         self.add_record(values);
     }
 
-    // Get the average since the given Instance
-    pub fn average_since(&self, time: Instant) -> Option<HistoryRecord> {
+    // Get the sum since the given Instance, together with the number of summed records
+    pub fn sum_since(&self, time: Instant) -> Option<(usize, HistoryRecord)> {
         let mut scratch = vec![0.0; self.nbr_values];
         let mut nbr_summed = 0;
         for record in self.buffer.iter() {
@@ -287,10 +444,23 @@ impl ValueHistory {
             return None;
         }
         let last = self.last().unwrap();
-        scratch.iter_mut().for_each(|val| *val /= nbr_summed as f32);
-        Some(HistoryRecord {
-            values: scratch,
-            time: last.time,
+        Some((
+            nbr_summed,
+            HistoryRecord {
+                values: scratch,
+                time: last.time,
+            },
+        ))
+    }
+
+    // Get the average since the given Instance
+    pub fn average_since(&self, time: Instant) -> Option<HistoryRecord> {
+        self.sum_since(time).map(|(nbr_summed, mut record)| {
+            record
+                .values
+                .iter_mut()
+                .for_each(|val| *val /= nbr_summed as f32);
+            record
         })
     }
 
@@ -337,6 +507,44 @@ impl ValueHistory {
     pub fn clear_history(&mut self) {
         self.buffer.clear();
         self.reset_global_max();
+        self.decaying_peak.clear();
+    }
+
+    // Set the decay rate in dB per second of the peak returned by decaying_peak.
+    // A rate of zero disables the decay.
+    pub fn set_decay_rate(&mut self, rate: f32) {
+        self.decay_rate = rate;
+        self.decaying_peak.clear();
+    }
+
+    // Get a peak value that falls at the decay rate, based on the time since the previous call.
+    // New values larger than the decayed peak replace it.
+    // Returns the last record when there is no decay.
+    pub fn decaying_peak(&mut self) -> Option<HistoryRecord> {
+        let last = self.last()?;
+        if self.decay_rate <= 0.0 {
+            return Some(last);
+        }
+        let now = Instant::now();
+        if self.decaying_peak.len() != self.nbr_values {
+            self.decaying_peak = last.values;
+        } else {
+            let elapsed = now.duration_since(self.decay_time).as_secs_f32();
+            let factor = 10.0_f32.powf(-self.decay_rate * elapsed / 20.0);
+            let newest = self
+                .max_since(self.decay_time)
+                .map(|record| record.values)
+                .unwrap_or_else(|| vec![0.0; self.nbr_values]);
+            self.decaying_peak
+                .iter_mut()
+                .zip(newest.iter())
+                .for_each(|(peak, val)| *peak = (*peak * factor).max(*val));
+        }
+        self.decay_time = now;
+        Some(HistoryRecord {
+            time: last.time,
+            values: self.decaying_peak.clone(),
+        })
     }
 
     // Get the square root of the average since the given Instance.
@@ -365,9 +573,12 @@ impl ValueHistory {
 
 #[cfg(test)]
 mod tests {
+    use crate::audiodevice::AudioChunk;
     use crate::countertimer::{
-        Averager, SilenceCounter, Stopwatch, TimeAverage, ValueHistory, ValueWatcher,
+        Averager, DutyCycle, SilenceCounter, Stopwatch, TimeAverage, TruePeakMeter, TruePeakRecord,
+        ValueHistory, ValueWatcher,
     };
+    use crate::PrcFmt;
     use crate::ProcessingState;
     use std::time::Instant;
     use std::{thread, time};
@@ -463,6 +674,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dutycycle() {
+        let counter = SilenceCounter::new(-40.0, 0.0, 48000, 1024);
+        assert!(counter.is_signal(0.1));
+        assert!(!counter.is_signal(0.001));
+        let mut duty = DutyCycle::default();
+        assert_eq!(duty.fraction(), 0.0);
+        duty.update(counter.is_signal(0.1), 1024);
+        duty.update(counter.is_signal(0.001), 1024);
+        duty.update(counter.is_signal(0.001), 1024);
+        duty.update(counter.is_signal(0.1), 1024);
+        assert_eq!(duty.fraction(), 0.5);
+        duty.reset();
+        assert_eq!(duty.fraction(), 0.0);
+    }
+
+    #[test]
+    fn truepeak_finds_intersample_peaks() {
+        // A sine at a quarter of the samplerate, sampled 45 degrees off its peaks
+        let pi = std::f64::consts::PI as PrcFmt;
+        let wave: Vec<PrcFmt> = (0..1024)
+            .map(|n| (pi * (0.5 * n as PrcFmt + 0.25)).sin())
+            .collect();
+        let chunk = AudioChunk::new(vec![wave], 1.0, -1.0, 1024, 1024);
+        let mut meter = TruePeakMeter::default();
+        meter.update(&chunk);
+        assert!(meter.peak_linear().is_empty());
+        meter.enable();
+        meter.update(&chunk);
+        let peak = meter.peak_linear()[0];
+        assert!(peak > 0.98 && peak < 1.02, "true peak: {}", peak);
+    }
+
+    #[test]
+    fn truepeak_record_keeps_max_until_read() {
+        let loud = AudioChunk::new(vec![vec![0.5, -0.8, 0.5, 0.0]], 0.5, -0.8, 4, 4);
+        let quiet = AudioChunk::new(vec![vec![0.1; 16]], 0.1, 0.1, 16, 16);
+        let mut meter = TruePeakMeter::default();
+        let mut record = TruePeakRecord::default();
+        meter.update(&loud);
+        record.add_record(&mut meter);
+        // The first read enables the meter, which starts measuring from the next chunk
+        assert!(record.take_peak_linear().is_empty());
+        meter.update(&loud);
+        record.add_record(&mut meter);
+        meter.update(&loud);
+        record.add_record(&mut meter);
+        meter.update(&quiet);
+        record.add_record(&mut meter);
+        let peak = record.take_peak_linear()[0];
+        assert!(peak >= 0.8, "true peak: {}", peak);
+        // Reading resets the values, read once more to get the loud chunk out of the filter history
+        meter.update(&quiet);
+        record.add_record(&mut meter);
+        record.take_peak_linear();
+        meter.update(&quiet);
+        record.add_record(&mut meter);
+        let peak = record.take_peak_linear()[0];
+        assert!(peak < 0.5, "true peak: {}", peak);
+    }
+
     #[test]
     fn silencecounter_largechunksize() {
         let mut counter = SilenceCounter::new(-40.0, 1.0, 48000, 23000);
@@ -628,4 +900,23 @@ mod tests {
             format!("{:?}", hist.last().unwrap().values)
         );
     }
+
+    #[test]
+    fn test_valuehistory_decaying_peak() {
+        let mut hist = ValueHistory::new(10, 1);
+        assert!(hist.decaying_peak().is_none());
+        hist.add_record(vec![1.0]);
+        hist.add_record(vec![0.5]);
+        // Without decay, the last value is returned
+        assert_eq!(hist.decaying_peak().unwrap().values, vec![0.5]);
+        hist.set_decay_rate(20.0);
+        assert_eq!(hist.decaying_peak().unwrap().values, vec![0.5]);
+        spinsleep(100);
+        // 20 dB/s for 0.1 s is a decay by 2 dB
+        let decayed = hist.decaying_peak().unwrap().values[0];
+        assert!(decayed < 0.5 * 0.8);
+        assert!(decayed > 0.5 * 0.75);
+        hist.add_record(vec![0.45]);
+        assert_eq!(hist.decaying_peak().unwrap().values, vec![0.45]);
+    }
 }
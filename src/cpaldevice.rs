@@ -239,6 +239,8 @@ impl PlaybackDevice for CpalPlaybackDevice {
                         let mut buffer_avg = countertimer::Averager::new();
                         let mut timer = countertimer::Stopwatch::new();
                         let mut chunk_stats = ChunkStats{rms: vec![0.0; channels], peak: vec![0.0; channels]};
+                        let mut level_meter = LevelMeter::new(playback_status.read().meter_ballistics, samplerate);
+                        let mut true_peak_meter = countertimer::TruePeakMeter::default();
 
                         let stream = match sample_format {
                             SampleFormat::S16LE => {
@@ -371,11 +373,15 @@ impl PlaybackDevice for CpalPlaybackDevice {
                         loop {
                             match channel.recv() {
                                 Ok(AudioMessage::Audio(chunk)) => {
-                                    chunk.update_stats(&mut chunk_stats);
+                                    level_meter.update(&chunk, &mut chunk_stats);
+                                    true_peak_meter.update(&chunk);
                                     {
                                         let mut playback_status = playback_status.write();
                                         playback_status.signal_rms.add_record_squared(chunk_stats.rms_linear());
                                         playback_status.signal_peak.add_record(chunk_stats.peak_linear());
+                                        playback_status.signal_minmax.update(&chunk);
+                                        playback_status.true_peak.add_record(&mut true_peak_meter);
+                                        playback_status.frames_played += chunk.valid_frames as u64;
                                     }
                                     buffer_avg.add_value(
                                         (buffer_fill.load(Ordering::Relaxed) / channels_clone)
@@ -566,6 +572,8 @@ impl CaptureDevice for CpalCaptureDevice {
                         let rate_measure_interval_ms = (1000.0 * rate_measure_interval) as u64;
                         let mut value_range = 0.0;
                         let mut chunk_stats = ChunkStats{rms: vec![0.0; channels], peak: vec![0.0; channels]};
+                        let mut level_meter = LevelMeter::new(capture_status.read().meter_ballistics, capture_samplerate);
+                        let mut true_peak_meter = countertimer::TruePeakMeter::default();
                         let mut rate_adjust = 0.0;
                         let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, capture_samplerate, chunksize);
                         let mut state = ProcessingState::Running;
@@ -690,12 +698,18 @@ impl CaptureDevice for CpalCaptureDevice {
                                 }
                                 trace!("Measured sample rate is {:.1} Hz", measured_rate_f);
                             }
-                            chunk.update_stats(&mut chunk_stats);
+                            level_meter.update(&chunk, &mut chunk_stats);
+                            true_peak_meter.update(&chunk);
                             //trace!("Capture rms {:?}, peak {:?}", chunk_stats.rms_db(), chunk_stats.peak_db());
                             {
                                 let mut capture_status = capture_status.write();
                                 capture_status.signal_rms.add_record_squared(chunk_stats.rms_linear());
                                 capture_status.signal_peak.add_record(chunk_stats.peak_linear());
+                                capture_status.signal_minmax.update(&chunk);
+                                capture_status.true_peak.add_record(&mut true_peak_meter);
+                                capture_status.recent_samples.update(&chunk);
+                                capture_status.signal_duty.update(silence_counter.is_signal(chunk.maxval - chunk.minval), chunk.valid_frames);
+                                capture_status.frames_captured += chunk.valid_frames as u64;
                             }
                             value_range = chunk.maxval - chunk.minval;
                             state = silence_counter.update(value_range);
@@ -0,0 +1,286 @@
+use crate::audiodevice::AudioChunk;
+use crate::basicfilters::Delay;
+use crate::biquad::{Biquad, BiquadCoefficients};
+use crate::config;
+use crate::filters::{Filter, Processor};
+use crate::PrcFmt;
+use crate::Res;
+
+/// The path from one side of the stereo pair to the other,
+/// a lowpass filter followed by a delay.
+struct CrossPath {
+    lowpass: Biquad,
+    delay: Delay,
+    delay_ms: PrcFmt,
+}
+
+fn lowpass_coefficients(samplerate: usize, freq: PrcFmt) -> BiquadCoefficients {
+    BiquadCoefficients::from_config(samplerate, config::BiquadParameters::LowpassFO { freq })
+}
+
+fn delay_filter(name: &str, samplerate: usize, delay_ms: PrcFmt) -> Delay {
+    let delay_samples = delay_ms * samplerate as PrcFmt / 1000.0;
+    Delay::new(name, samplerate, delay_samples, true)
+}
+
+impl CrossPath {
+    fn new(name: &str, samplerate: usize, freq: PrcFmt, delay_ms: PrcFmt) -> Self {
+        let lowpass = Biquad::new(name, samplerate, lowpass_coefficients(samplerate, freq));
+        let delay = delay_filter(name, samplerate, delay_ms);
+        CrossPath {
+            lowpass,
+            delay,
+            delay_ms,
+        }
+    }
+
+    /// Update the lowpass coefficients in place, keeping the filter state.
+    /// The delay line is only rebuilt if the delay was changed.
+    fn update(&mut self, name: &str, samplerate: usize, freq: PrcFmt, delay_ms: PrcFmt) {
+        self.lowpass
+            .set_coefficients(lowpass_coefficients(samplerate, freq));
+        if delay_ms != self.delay_ms {
+            self.delay = delay_filter(name, samplerate, delay_ms);
+            self.delay_ms = delay_ms;
+        }
+    }
+
+    fn process(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        self.lowpass.process_waveform(waveform)?;
+        self.delay.process_waveform(waveform)
+    }
+}
+
+pub struct Crossfeed {
+    pub name: String,
+    pub samplerate: usize,
+    pub channels: [usize; 2],
+    gain: PrcFmt,
+    normalization: PrcFmt,
+    paths: [CrossPath; 2],
+    buffers: [Vec<PrcFmt>; 2],
+}
+
+/// Get the two channels of the stereo pair to process.
+fn stereo_pair(config: &config::CrossfeedParameters) -> Vec<usize> {
+    config
+        .process_channels
+        .clone()
+        .unwrap_or_else(|| (0..config.channels).collect())
+}
+
+impl Crossfeed {
+    /// Creates a Crossfeed from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::CrossfeedParameters,
+        samplerate: usize,
+        chunksize: usize,
+    ) -> Self {
+        let name = name.to_string();
+        let pair = stereo_pair(&config);
+        debug!(
+            "Creating crossfeed '{}', channels: {:?}, level: {} dB, freq: {} Hz, delay: {} ms",
+            name, pair, config.level, config.freq, config.delay
+        );
+        let gain = (10.0 as PrcFmt).powf(config.level / 20.0);
+        let paths = [
+            CrossPath::new(&name, samplerate, config.freq, config.delay),
+            CrossPath::new(&name, samplerate, config.freq, config.delay),
+        ];
+        Crossfeed {
+            name,
+            samplerate,
+            channels: [pair[0], pair[1]],
+            gain,
+            // Keep the level of low frequencies panned to one side unchanged
+            normalization: 1.0 / (1.0 + gain),
+            paths,
+            buffers: [vec![0.0; chunksize], vec![0.0; chunksize]],
+        }
+    }
+}
+
+impl Processor for Crossfeed {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Mix a lowpass filtered and delayed copy of each channel into the other one.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        let [left, right] = self.channels;
+        let [to_left, to_right] = &mut self.buffers;
+        to_left.clear();
+        to_left.extend_from_slice(&input.waveforms[right]);
+        to_right.clear();
+        to_right.extend_from_slice(&input.waveforms[left]);
+        if !to_left.is_empty() {
+            self.paths[0].process(to_left)?;
+        }
+        if !to_right.is_empty() {
+            self.paths[1].process(to_right)?;
+        }
+        for (channel, crossed) in [(left, &*to_left), (right, &*to_right)] {
+            let waveform = &mut input.waveforms[channel];
+            if waveform.is_empty() {
+                if crossed.is_empty() {
+                    continue;
+                }
+                *waveform = vec![0.0; crossed.len()];
+            }
+            if crossed.is_empty() {
+                for value in waveform.iter_mut() {
+                    *value *= self.normalization;
+                }
+            } else {
+                for (value, cross) in waveform.iter_mut().zip(crossed.iter()) {
+                    *value = self.normalization * (*value + self.gain * cross);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::Crossfeed {
+            parameters: config, ..
+        } = config
+        {
+            let pair = stereo_pair(&config);
+            debug!(
+                "Updating crossfeed '{}', channels: {:?}, level: {} dB, freq: {} Hz, delay: {} ms",
+                self.name, pair, config.level, config.freq, config.delay
+            );
+            self.channels = [pair[0], pair[1]];
+            self.gain = (10.0 as PrcFmt).powf(config.level / 20.0);
+            self.normalization = 1.0 / (1.0 + self.gain);
+            for path in self.paths.iter_mut() {
+                path.update(&self.name, self.samplerate, config.freq, config.delay);
+            }
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the crossfeed config, to give a helpful message intead of a panic.
+pub fn validate_crossfeed(config: &config::CrossfeedParameters, samplerate: usize) -> Res<()> {
+    let pair = stereo_pair(config);
+    if pair.len() != 2 {
+        let msg = format!(
+            "Crossfeed must be applied to exactly two channels, got {}.",
+            pair.len()
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    if pair[0] == pair[1] {
+        let msg = format!("Crossfeed channels must be different, got {:?}.", pair);
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    for ch in pair.iter() {
+        if *ch >= config.channels {
+            let msg = format!(
+                "Invalid channel to process: {}, max is: {}.",
+                *ch,
+                config.channels - 1
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    if !config.level.is_finite() {
+        let msg = "Crossfeed level must be a finite number.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    let maxfreq = samplerate as PrcFmt / 2.0;
+    if config.freq <= 0.0 || config.freq >= maxfreq {
+        let msg = format!(
+            "Crossfeed frequency must be in the range 0 to {} Hz, got {}.",
+            maxfreq, config.freq
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    if !config.delay.is_finite() || config.delay < 0.0 {
+        let msg = format!(
+            "Crossfeed delay must be zero or positive, got {}.",
+            config.delay
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config::CrossfeedParameters;
+    use crate::crossfeed::{validate_crossfeed, Crossfeed};
+    use crate::filters::Processor;
+
+    fn params(channels: usize, process_channels: Option<Vec<usize>>) -> CrossfeedParameters {
+        CrossfeedParameters {
+            channels,
+            process_channels,
+            level: -6.0,
+            freq: 700.0,
+            delay: 0.3,
+        }
+    }
+
+    fn is_close(left: f64, right: f64, maxdiff: f64) -> bool {
+        (left - right).abs() < maxdiff
+    }
+
+    #[test]
+    fn crossfeed_constant_signal() {
+        let mut crossfeed = Crossfeed::from_config("test", params(2, None), 48000, 1024);
+        let gain = (10.0_f64).powf(-6.0 / 20.0);
+        // Run until the lowpass filters have settled
+        let mut chunk = AudioChunk::new(vec![vec![], vec![]], 1.0, -1.0, 1024, 1024);
+        for _ in 0..10 {
+            chunk.waveforms = vec![vec![1.0; 1024], vec![0.0; 1024]];
+            crossfeed.process_chunk(&mut chunk).unwrap();
+        }
+        let left = chunk.waveforms[0][1023] as f64;
+        let right = chunk.waveforms[1][1023] as f64;
+        assert!(is_close(left, 1.0 / (1.0 + gain), 1e-4));
+        assert!(is_close(right, gain / (1.0 + gain), 1e-4));
+    }
+
+    #[test]
+    fn crossfeed_update_keeps_state() {
+        let mut crossfeed = Crossfeed::from_config("test", params(2, None), 48000, 1024);
+        let mut chunk = AudioChunk::new(vec![vec![], vec![]], 1.0, -1.0, 1024, 1024);
+        for _ in 0..10 {
+            chunk.waveforms = vec![vec![1.0; 1024], vec![0.0; 1024]];
+            crossfeed.process_chunk(&mut chunk).unwrap();
+        }
+        let settled = chunk.waveforms[1][1023] as f64;
+        let conf = crate::config::Processor::Crossfeed {
+            description: None,
+            parameters: params(2, None),
+        };
+        crossfeed.update_parameters(conf);
+        chunk.waveforms = vec![vec![1.0; 1024], vec![0.0; 1024]];
+        crossfeed.process_chunk(&mut chunk).unwrap();
+        // The first sample continues from the settled value, the filters were not reset
+        assert!(is_close(chunk.waveforms[1][0] as f64, settled, 1e-4));
+    }
+
+    #[test]
+    fn check_crossfeed_config() {
+        assert!(validate_crossfeed(&params(2, None), 48000).is_ok());
+        assert!(validate_crossfeed(&params(4, Some(vec![2, 3])), 48000).is_ok());
+        assert!(validate_crossfeed(&params(1, None), 48000).is_err());
+        assert!(validate_crossfeed(&params(4, None), 48000).is_err());
+        assert!(validate_crossfeed(&params(4, Some(vec![0, 1, 2])), 48000).is_err());
+        assert!(validate_crossfeed(&params(4, Some(vec![1, 1])), 48000).is_err());
+        assert!(validate_crossfeed(&params(2, Some(vec![1, 2])), 48000).is_err());
+        let mut conf = params(2, None);
+        conf.freq = 30000.0;
+        assert!(validate_crossfeed(&conf, 48000).is_err());
+        let mut conf = params(2, None);
+        conf.delay = -1.0;
+        assert!(validate_crossfeed(&conf, 48000).is_err());
+    }
+}
@@ -436,6 +436,96 @@ impl<'a> NoiseShaper<'a> {
         ])
     }
 
+    // Derived from the 192 kHz Shibata filters above, since SSRC has no filters for 176.4 kHz.
+    // The noise spectrum of the 192 kHz filter is kept the same in Hz up to 88.2 kHz,
+    // and turned back into a filter with the same number of taps using the minimum phase
+    // (cepstrum) method. This raises the noise by about 0.9 dB compared to the 192 kHz filter.
+    pub fn shibata_1764() -> Self {
+        Self::new(&[
+            2.263_213_608_690_662,
+            -1.127_501_574_414_912_6,
+            -0.428_279_953_288_966_4,
+            -0.049_545_415_936_182_63,
+            0.216_454_705_805_492_53,
+            0.271_606_899_244_786_76,
+            -0.101_413_478_742_914_98,
+            -0.004_565_354_445_781_366,
+            -0.039_504_777_860_472_74,
+            -0.006_397_926_198_380_166,
+            -0.031_412_365_394_593_175,
+            0.011_765_752_098_791_406,
+            0.001_927_139_951_340_851_6,
+            0.006_939_915_392_019_961,
+            -0.005_060_304_417_837_074,
+            0.008_736_486_377_994_557,
+            -0.001_368_349_762_943_070_3,
+            0.003_131_207_409_805_053,
+            -0.002_885_125_950_434_275_2,
+            0.001_011_086_395_717_496_1,
+            -0.004_632_242_551_933_128,
+            0.000_351_527_002_675_486_4,
+            -0.002_521_766_583_851_961,
+            0.001_680_301_327_059_439,
+            -0.002_699_118_295_042_669,
+            0.001_977_676_993_572_643_7,
+            -0.000_869_418_049_656_704_2,
+            0.002_043_895_940_936_700_5,
+            -0.000_833_118_538_694_617_3,
+            0.000_907_573_726_952_129_2,
+            -0.001_959_903_051_098_478_6,
+            0.000_252_198_203_021_476_45,
+            -0.001_580_909_624_652_283_6,
+            0.001_153_601_156_277_342_2,
+            -0.001_238_647_235_779_249,
+            0.001_107_039_088_848_787,
+            -0.001_174_517_991_786_243_5,
+            0.000_997_461_034_112_573_3,
+            -0.000_951_664_098_811_981_6,
+            0.000_869_692_116_971_390_7,
+            -0.000_975_716_856_995_407_2,
+            0.000_826_024_188_092_638_8,
+            -0.000_817_565_446_730_582_9,
+            0.000_761_284_190_669_698_9,
+            -0.000_695_468_705_963_956_9,
+            0.000_638_994_661_349_025,
+            -0.000_619_608_124_853_146_4,
+            0.000_578_682_679_136_025_7,
+            -0.000_625_896_255_421_913_1,
+            0.000_427_999_258_755_000_86,
+            -0.000_493_800_603_804_601_9,
+            0.000_486_959_600_963_295_54,
+            -0.000_473_043_260_640_566_13,
+            0.000_457_660_883_395_131_8,
+            -0.000_442_179_075_776_324_5,
+            0.000_427_059_267_351_448_36,
+        ])
+    }
+
+    pub fn shibata_low_1764() -> Self {
+        Self::new(&[
+            0.953_552_075_713_241_6,
+            -0.036_406_229_903_382_8,
+            0.012_585_567_877_642_5,
+            -0.006_467_446_908_630_115_6,
+            0.004_390_353_374_421_151,
+            -0.007_204_554_529_063_528,
+            -0.016_599_958_477_333_032,
+            -0.012_368_916_476_839_296,
+            -0.004_286_989_106_565_052,
+            -0.001_622_889_396_872_547_8,
+            0.000_582_318_076_038_765_1,
+            -0.000_323_735_728_740_333_94,
+            1.949_657_964_770_138_3e-5,
+            8.890_258_241_639_73e-5,
+            -0.000_683_480_562_724_530_1,
+            0.001_579_743_825_420_016_6,
+            -0.009_328_563_338_859_817,
+            -0.023_172_876_762_664_57,
+            -0.003_231_919_964_588_927,
+            0.000_240_713_821_946_442_18,
+        ])
+    }
+
     pub fn process(&mut self, scaled: PrcFmt, dither: PrcFmt) -> PrcFmt {
         let mut filt_buf = 0.0;
         for (item, coeff) in self.buffer.iter().zip(self.filter) {
@@ -528,6 +618,12 @@ impl<'a> Dither<'a> {
             config::DitherParameters::ShibataLow192 { bits } => {
                 (bits, Some(NoiseShaper::shibata_low_192()))
             }
+            config::DitherParameters::Shibata1764 { bits } => {
+                (bits, Some(NoiseShaper::shibata_1764()))
+            }
+            config::DitherParameters::ShibataLow1764 { bits } => {
+                (bits, Some(NoiseShaper::shibata_low_1764()))
+            }
         };
 
         match conf {
@@ -608,7 +704,9 @@ pub fn validate_config(conf: &config::DitherParameters) -> Res<()> {
         | config::DitherParameters::Shibata96 { bits }
         | config::DitherParameters::ShibataLow96 { bits }
         | config::DitherParameters::Shibata192 { bits }
-        | config::DitherParameters::ShibataLow192 { bits } => bits,
+        | config::DitherParameters::ShibataLow192 { bits }
+        | config::DitherParameters::Shibata1764 { bits }
+        | config::DitherParameters::ShibataLow1764 { bits } => bits,
     };
     if *bits <= 1 {
         return Err(config::ConfigError::new("Dither bit depth must be at least 2").into());
@@ -823,4 +921,19 @@ mod tests {
             1e-9
         ));
     }
+
+    #[test]
+    fn test_shibata_1764() {
+        let mut waveform = vec![-1.0, -0.5, -1.0 / 3.0, 0.0, 1.0 / 3.0, 0.5, 1.0];
+        let waveform2 = waveform.clone();
+        let conf = DitherParameters::Shibata1764 { bits: 8 };
+        let mut dith = Dither::from_config("test", conf);
+        dith.process_waveform(&mut waveform).unwrap();
+        assert!(compare_waveforms(waveform.clone(), waveform2, 1.0 / 16.0));
+        assert!(is_close(
+            (128.0 * waveform[2]).round(),
+            128.0 * waveform[2],
+            1e-9
+        ));
+    }
 }
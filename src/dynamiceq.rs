@@ -0,0 +1,260 @@
+use crate::audiodevice::AudioChunk;
+use crate::biquad::{Biquad, BiquadCoefficients};
+use crate::config;
+use crate::filters::Filter;
+use crate::filters::Processor;
+use crate::PrcFmt;
+use crate::Res;
+
+// Number of samples between updates of the peaking filter coefficients.
+const CONTROL_BLOCK: usize = 32;
+
+#[derive(Clone, Debug)]
+struct DynamicEqChannel {
+    detector: Biquad,
+    peaking: Biquad,
+    prev_loudness: PrcFmt,
+}
+
+#[derive(Clone, Debug)]
+pub struct DynamicEq {
+    pub name: String,
+    pub channels: usize,
+    pub freq: PrcFmt,
+    pub q: PrcFmt,
+    pub attack: PrcFmt,
+    pub release: PrcFmt,
+    pub threshold: PrcFmt,
+    pub ratio: PrcFmt,
+    pub max_gain: PrcFmt,
+    pub samplerate: usize,
+    scratch: Vec<PrcFmt>,
+    bands: Vec<DynamicEqChannel>,
+}
+
+fn detector_coefficients(samplerate: usize, freq: PrcFmt, q: PrcFmt) -> BiquadCoefficients {
+    BiquadCoefficients::from_config(
+        samplerate,
+        config::BiquadParameters::Bandpass(config::NotchWidth::Q { freq, q }),
+    )
+}
+
+fn peaking_coefficients(
+    samplerate: usize,
+    freq: PrcFmt,
+    q: PrcFmt,
+    gain: PrcFmt,
+) -> BiquadCoefficients {
+    BiquadCoefficients::from_config(
+        samplerate,
+        config::BiquadParameters::Peaking(config::PeakingWidth::Q { freq, gain, q }),
+    )
+}
+
+impl DynamicEq {
+    /// Creates a DynamicEq from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::DynamicEqParameters,
+        samplerate: usize,
+        chunksize: usize,
+    ) -> Self {
+        let name = name.to_string();
+        let srate = samplerate as PrcFmt;
+        let attack = (-1.0 / srate / config.attack).exp();
+        let release = (-1.0 / srate / config.release).exp();
+        let detector = Biquad::new(
+            "detector",
+            samplerate,
+            detector_coefficients(samplerate, config.freq, config.q),
+        );
+        let peaking = Biquad::new(
+            "peaking",
+            samplerate,
+            peaking_coefficients(samplerate, config.freq, config.q, 0.0),
+        );
+        let bands = vec![
+            DynamicEqChannel {
+                detector,
+                peaking,
+                prev_loudness: -100.0,
+            };
+            config.channels
+        ];
+        let scratch = vec![0.0; chunksize];
+
+        debug!("Creating dynamic eq '{}', channels: {}, freq: {}, q: {}, attack: {}, release: {}, threshold: {}, ratio: {}, max_gain: {}",
+                name, config.channels, config.freq, config.q, attack, release, config.threshold, config.ratio, config.max_gain);
+
+        DynamicEq {
+            name,
+            channels: config.channels,
+            freq: config.freq,
+            q: config.q,
+            attack,
+            release,
+            threshold: config.threshold,
+            ratio: config.ratio,
+            max_gain: config.max_gain,
+            samplerate,
+            scratch,
+            bands,
+        }
+    }
+
+    /// Calculate the gain in dB of the peaking filter for a given band loudness.
+    fn calculate_gain(&self, loudness: PrcFmt) -> PrcFmt {
+        if loudness > self.threshold {
+            let gain = -(loudness - self.threshold) * (self.ratio - 1.0) / self.ratio;
+            gain.clamp(-self.max_gain, self.max_gain)
+        } else {
+            0.0
+        }
+    }
+
+    fn process_channel(&mut self, channel: usize, waveform: &mut [PrcFmt]) -> Res<()> {
+        let nbr_samples = waveform.len();
+        self.scratch[..nbr_samples].copy_from_slice(waveform);
+        let mut band = self.bands[channel].clone();
+        band.detector
+            .process_waveform(&mut self.scratch[..nbr_samples])?;
+        for (block, detected) in waveform
+            .chunks_mut(CONTROL_BLOCK)
+            .zip(self.scratch[..nbr_samples].chunks(CONTROL_BLOCK))
+        {
+            for val in detected.iter() {
+                let loudness = 20.0 * (val.abs() + 1.0e-9).log10();
+                band.prev_loudness = if loudness >= band.prev_loudness {
+                    self.attack * band.prev_loudness + (1.0 - self.attack) * loudness
+                } else {
+                    self.release * band.prev_loudness + (1.0 - self.release) * loudness
+                };
+            }
+            let gain = self.calculate_gain(band.prev_loudness);
+            band.peaking.set_coefficients(peaking_coefficients(
+                self.samplerate,
+                self.freq,
+                self.q,
+                gain,
+            ));
+            band.peaking.process_waveform(block)?;
+        }
+        self.bands[channel] = band;
+        Ok(())
+    }
+}
+
+impl Processor for DynamicEq {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply a DynamicEq to an AudioChunk, modifying it in-place.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        for (channel, waveform) in input.waveforms.iter_mut().enumerate() {
+            self.process_channel(channel, waveform)?;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::DynamicEq {
+            parameters: config, ..
+        } = config
+        {
+            let srate = self.samplerate as PrcFmt;
+            self.attack = (-1.0 / srate / config.attack).exp();
+            self.release = (-1.0 / srate / config.release).exp();
+            self.freq = config.freq;
+            self.q = config.q;
+            self.threshold = config.threshold;
+            self.ratio = config.ratio;
+            self.max_gain = config.max_gain;
+            let detector_coeffs = detector_coefficients(self.samplerate, config.freq, config.q);
+            for band in self.bands.iter_mut() {
+                band.detector.set_coefficients(detector_coeffs);
+            }
+
+            debug!("Updated dynamic eq '{}', freq: {}, q: {}, attack: {}, release: {}, threshold: {}, ratio: {}, max_gain: {}",
+                self.name, config.freq, config.q, self.attack, self.release, config.threshold, config.ratio, config.max_gain);
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the dynamic eq config, to give a helpful message intead of a panic.
+pub fn validate_dynamic_eq(samplerate: usize, config: &config::DynamicEqParameters) -> Res<()> {
+    let maxfreq = samplerate as PrcFmt / 2.0;
+    if config.freq <= 0.0 {
+        let msg = "Frequency must be > 0.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.freq >= maxfreq {
+        let msg = format!("Frequency must be < samplerate/2, got {}.", config.freq);
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    if config.q <= 0.0 {
+        let msg = "Q must be > 0.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.attack <= 0.0 {
+        let msg = "Attack value must be larger than zero.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.release <= 0.0 {
+        let msg = "Release value must be larger than zero.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.ratio <= 0.0 {
+        let msg = "Ratio must be larger than zero.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.max_gain <= 0.0 {
+        let msg = "Max gain must be larger than zero.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::DynamicEqParameters;
+    use crate::dynamiceq::{validate_dynamic_eq, DynamicEq};
+    use crate::PrcFmt;
+
+    fn params(ratio: PrcFmt) -> DynamicEqParameters {
+        DynamicEqParameters {
+            channels: 1,
+            freq: 1000.0,
+            q: 2.0,
+            threshold: -20.0,
+            ratio,
+            attack: 0.01,
+            release: 0.1,
+            max_gain: 6.0,
+        }
+    }
+
+    #[test]
+    fn gain_follows_ratio_and_is_limited() {
+        let deq = DynamicEq::from_config("test", params(2.0), 44100, 1024);
+        assert_eq!(deq.calculate_gain(-30.0), 0.0);
+        assert!((deq.calculate_gain(-10.0) + 5.0).abs() < 1.0e-6);
+        assert!((deq.calculate_gain(0.0) + 6.0).abs() < 1.0e-6);
+        let deq = DynamicEq::from_config("test", params(0.5), 44100, 1024);
+        assert!((deq.calculate_gain(-10.0) - 6.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn check_dynamic_eq_config() {
+        let mut conf = params(2.0);
+        assert!(validate_dynamic_eq(44100, &conf).is_ok());
+        conf.freq = 25000.0;
+        assert!(validate_dynamic_eq(44100, &conf).is_err());
+        conf.freq = 1000.0;
+        conf.q = 0.0;
+        assert!(validate_dynamic_eq(44100, &conf).is_err());
+    }
+}
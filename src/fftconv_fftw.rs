@@ -98,6 +98,76 @@ pub struct FftConv {
     temp_buf: AlignedVec<ComplexFmt>,
     output_buf: AlignedVec<PrcFmt>,
     index: usize,
+    tail: Option<Box<FftConvTail>>,
+}
+
+// The tail of a non-uniform convolution uses blocks of this many chunks.
+const NONUNIFORM_TAIL_FACTOR: usize = 8;
+
+/// The tail part of a non-uniform partitioned convolution.
+/// Input chunks are collected into larger blocks that are convolved with the tail of the impulse response.
+/// The tail starts one block minus one chunk into the impulse response,
+/// which gives exactly enough time to collect a block without adding any latency.
+struct FftConvTail {
+    conv: FftConv,
+    input_buf: Vec<PrcFmt>,
+    output_buf: Vec<PrcFmt>,
+    index: usize,
+}
+
+impl FftConvTail {
+    fn new(name: &str, block_length: usize, coeffs: &[PrcFmt]) -> Self {
+        FftConvTail {
+            conv: FftConv::new(name, block_length, coeffs),
+            input_buf: vec![0.0; block_length],
+            output_buf: vec![0.0; block_length],
+            index: 0,
+        }
+    }
+
+    /// Store a chunk of input, and run the convolution when a full block has been collected.
+    fn push_input(&mut self, input: &[PrcFmt]) {
+        let len = input.len();
+        self.input_buf[self.index..self.index + len].copy_from_slice(input);
+        self.index += len;
+        if self.index == self.input_buf.len() {
+            self.conv.process_waveform(&mut self.input_buf).unwrap();
+            std::mem::swap(&mut self.input_buf, &mut self.output_buf);
+            self.index = 0;
+        }
+    }
+
+    /// Add the part of the tail output that matches the latest chunk of input.
+    fn add_output(&self, waveform: &mut [PrcFmt]) {
+        for (item, tail) in waveform
+            .iter_mut()
+            .zip(self.output_buf.iter().skip(self.index))
+        {
+            *item += tail;
+        }
+    }
+}
+
+/// Read the filter coefficients of a Conv filter.
+fn read_coeffs(conf: config::ConvParameters) -> Vec<PrcFmt> {
+    match conf {
+        config::ConvParameters::Values { values, .. } => values,
+        config::ConvParameters::Raw(params) => filters::read_coeff_file(
+            &params.filename,
+            &params.format(),
+            params.read_bytes_lines(),
+            params.skip_bytes_lines(),
+        )
+        .unwrap(),
+        config::ConvParameters::Wav(params) => {
+            filters::read_wav(params.filename(), params.channel()).unwrap()
+        }
+        config::ConvParameters::Dummy { length, .. } => {
+            let mut values = vec![0.0; length];
+            values[0] = 1.0;
+            values
+        }
+    }
 }
 
 impl FftConv {
@@ -142,29 +212,71 @@ impl FftConv {
             output_buf,
             temp_buf,
             index: 0,
+            tail: None,
         }
     }
 
+    /// Create a new FFT convolution filter using non-uniform partitions.
+    /// The head of the impulse response uses partitions of one chunk,
+    /// and the tail uses partitions of several chunks.
+    pub fn new_nonuniform(name: &str, data_length: usize, coeffs: &[PrcFmt]) -> Self {
+        let tail_block_length = NONUNIFORM_TAIL_FACTOR * data_length;
+        let head_length = tail_block_length - data_length;
+        if coeffs.len() <= head_length {
+            debug!(
+                "Conv {} is too short for non-uniform partitions, using uniform",
+                name
+            );
+            return FftConv::new(name, data_length, coeffs);
+        }
+        let mut conv = FftConv::new(name, data_length, &coeffs[0..head_length]);
+        debug!(
+            "Conv {} is using a tail with blocks of {} samples",
+            name, tail_block_length
+        );
+        conv.tail = Some(Box::new(FftConvTail::new(
+            name,
+            tail_block_length,
+            &coeffs[head_length..],
+        )));
+        conv
+    }
+
     pub fn from_config(name: &str, data_length: usize, conf: config::ConvParameters) -> Self {
-        let values = match conf {
-            config::ConvParameters::Values { values } => values,
-            config::ConvParameters::Raw(params) => filters::read_coeff_file(
-                &params.filename,
-                &params.format(),
-                params.read_bytes_lines(),
-                params.skip_bytes_lines(),
-            )
-            .unwrap(),
-            config::ConvParameters::Wav(params) => {
-                filters::read_wav(&params.filename, params.channel()).unwrap()
-            }
-            config::ConvParameters::Dummy { length } => {
-                let mut values = vec![0.0; length];
-                values[0] = 1.0;
-                values
-            }
-        };
-        FftConv::new(name, data_length, &values)
+        let mode = conf.mode();
+        let values = read_coeffs(conf);
+        match mode {
+            config::ConvMode::Uniform => FftConv::new(name, data_length, &values),
+            config::ConvMode::NonUniform => FftConv::new_nonuniform(name, data_length, &values),
+        }
+    }
+
+    /// Replace the filter coefficients, keeping the input history if the number of segments is unchanged.
+    fn set_coeffs(&mut self, coeffs: &[PrcFmt]) {
+        let nsegments = ((coeffs.len() as PrcFmt) / (self.npoints as PrcFmt)).ceil() as usize;
+
+        if nsegments == self.nsegments {
+            // Same length, lets keep history
+        } else {
+            // length changed, clearing history
+            self.nsegments = nsegments;
+            let input_f = vec![AlignedVec::<ComplexFmt>::new(self.npoints + 1); nsegments];
+            self.input_f = input_f;
+        }
+
+        let mut coeffs_f = vec![AlignedVec::<ComplexFmt>::new(self.npoints + 1); nsegments];
+        let mut coeffs_al = vec![AlignedVec::<PrcFmt>::new(2 * self.npoints); nsegments];
+
+        debug!("conv using {} segments", nsegments);
+
+        for (n, coeff) in coeffs.iter().enumerate() {
+            coeffs_al[n / self.npoints][n % self.npoints] = coeff / (2.0 * self.npoints as PrcFmt);
+        }
+
+        for (segment, segment_f) in coeffs_al.iter_mut().zip(coeffs_f.iter_mut()) {
+            self.fft.r2c(segment, segment_f).unwrap();
+        }
+        self.coeffs_f = coeffs_f;
     }
 }
 
@@ -175,6 +287,10 @@ impl Filter for FftConv {
 
     /// Process a waveform by FT, then multiply transform with transform of filter, and then transform back.
     fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        if let Some(tail) = &mut self.tail {
+            tail.push_input(waveform);
+        }
+
         // Copy to input buffer
         self.input_buf[0..self.npoints].copy_from_slice(waveform);
 
@@ -210,6 +326,10 @@ impl Filter for FftConv {
         }
         self.overlap
             .copy_from_slice(&self.output_buf[self.npoints..]);
+
+        if let Some(tail) = &self.tail {
+            tail.add_output(waveform);
+        }
         Ok(())
     }
 
@@ -218,50 +338,26 @@ impl Filter for FftConv {
             parameters: conf, ..
         } = conf
         {
-            let coeffs = match conf {
-                config::ConvParameters::Values { values } => values,
-                config::ConvParameters::Raw(params) => filters::read_coeff_file(
-                    &params.filename,
-                    &params.format(),
-                    params.read_bytes_lines(),
-                    params.skip_bytes_lines(),
-                )
-                .unwrap(),
-                config::ConvParameters::Wav(params) => {
-                    filters::read_wav(&params.filename, params.channel()).unwrap()
+            let mode = conf.mode();
+            let coeffs = read_coeffs(conf);
+            let tail_block_length = NONUNIFORM_TAIL_FACTOR * self.npoints;
+            let head_length = tail_block_length - self.npoints;
+            if mode == config::ConvMode::NonUniform && coeffs.len() > head_length {
+                self.set_coeffs(&coeffs[0..head_length]);
+                match &mut self.tail {
+                    Some(tail) => tail.conv.set_coeffs(&coeffs[head_length..]),
+                    None => {
+                        self.tail = Some(Box::new(FftConvTail::new(
+                            &self.name,
+                            tail_block_length,
+                            &coeffs[head_length..],
+                        )))
+                    }
                 }
-                config::ConvParameters::Dummy { length } => {
-                    let mut values = vec![0.0; length];
-                    values[0] = 1.0;
-                    values
-                }
-            };
-
-            let nsegments = ((coeffs.len() as PrcFmt) / (self.npoints as PrcFmt)).ceil() as usize;
-
-            if nsegments == self.nsegments {
-                // Same length, lets keep history
             } else {
-                // length changed, clearing history
-                self.nsegments = nsegments;
-                let input_f = vec![AlignedVec::<ComplexFmt>::new(self.npoints + 1); nsegments];
-                self.input_f = input_f;
-            }
-
-            let mut coeffs_f = vec![AlignedVec::<ComplexFmt>::new(self.npoints + 1); nsegments];
-            let mut coeffs_al = vec![AlignedVec::<PrcFmt>::new(2 * self.npoints); nsegments];
-
-            debug!("conv using {} segments", nsegments);
-
-            for (n, coeff) in coeffs.iter().enumerate() {
-                coeffs_al[n / self.npoints][n % self.npoints] =
-                    coeff / (2.0 * self.npoints as PrcFmt);
+                self.set_coeffs(&coeffs);
+                self.tail = None;
             }
-
-            for (segment, segment_f) in coeffs_al.iter_mut().zip(coeffs_f.iter_mut()) {
-                self.fft.r2c(segment, segment_f).unwrap();
-            }
-            self.coeffs_f = coeffs_f;
         } else {
             // This should never happen unless there is a bug somewhere else
             panic!("Invalid config change!");
@@ -285,13 +381,7 @@ pub fn validate_config(conf: &config::ConvParameters) -> Res<()> {
             }
             Ok(())
         }
-        config::ConvParameters::Wav(params) => {
-            let coeffs = filters::read_wav(&params.filename, params.channel())?;
-            if coeffs.is_empty() {
-                return Err(config::ConfigError::new("Conv coefficients are empty").into());
-            }
-            Ok(())
-        }
+        config::ConvParameters::Wav(params) => filters::validate_wav_params(params),
     }
 }
 
@@ -319,7 +409,11 @@ mod tests {
     #[test]
     fn check_result() {
         let coeffs = vec![0.5, 0.5];
-        let conf = ConvParameters::Values { values: coeffs };
+        let conf = ConvParameters::Values {
+            values: coeffs,
+            mode: None,
+            engine: None,
+        };
         let mut filter = FftConv::from_config("test", 8, conf);
         let mut wave1 = vec![1.0, 1.0, 1.0, 0.0, 0.0, -1.0, 0.0, 0.0];
         let expected = vec![0.5, 1.0, 1.0, 0.5, 0.0, -0.5, -0.5, 0.0];
@@ -359,4 +453,22 @@ mod tests {
         assert!(compare_waveforms(wave4, exp4, 1e-5));
         assert!(compare_waveforms(wave5, exp5, 1e-5));
     }
+
+    #[test]
+    fn check_result_nonuniform() {
+        let coeffs: Vec<PrcFmt> = (0..150)
+            .map(|m| ((m * 7 % 13) as PrcFmt - 6.0) / (1.0 + m as PrcFmt))
+            .collect();
+        let mut uniform = FftConv::new("test", 8, &coeffs);
+        let mut nonuniform = FftConv::new_nonuniform("test", 8, &coeffs);
+        for chunk in 0..40 {
+            let mut wave_u: Vec<PrcFmt> = (0..8)
+                .map(|n| ((chunk * 8 + n) * 5 % 11) as PrcFmt - 5.0)
+                .collect();
+            let mut wave_nu = wave_u.clone();
+            uniform.process_waveform(&mut wave_u).unwrap();
+            nonuniform.process_waveform(&mut wave_nu).unwrap();
+            assert!(compare_waveforms(wave_u, wave_nu, 1e-4));
+        }
+    }
 }
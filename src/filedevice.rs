@@ -1,7 +1,9 @@
 use crate::audiodevice::*;
 use crate::config;
-use crate::config::SampleFormat;
-use crate::conversions::{buffer_to_chunk_rawbytes, chunk_to_buffer_rawbytes};
+use crate::config::{SampleFormat, WavHeaderFormat};
+use crate::conversions::{
+    buffer_to_chunk_rawbytes, buffer_to_chunk_rawbytes_selected, chunk_to_buffer_rawbytes,
+};
 use crate::countertimer;
 
 use std::error::Error;
@@ -23,8 +25,10 @@ use rubato::VecResampler;
 use crate::filedevice_bluez;
 #[cfg(not(target_os = "linux"))]
 use crate::filereader::BlockingReader;
+use crate::filereader::{connect_tcp_stream, FlacFileReader, PlaylistReader, TcpListenReader};
 #[cfg(target_os = "linux")]
 use crate::filereader_nonblock::NonBlockingReader;
+use crate::wavwriter::{wav_header, WavHeaderGuard};
 use crate::CommandMessage;
 use crate::PrcFmt;
 use crate::ProcessingState;
@@ -38,14 +42,18 @@ pub struct FilePlaybackDevice {
     pub samplerate: usize,
     pub channels: usize,
     pub sample_format: SampleFormat,
+    pub wav_header_format: WavHeaderFormat,
 }
 
 #[derive(Clone)]
 pub enum CaptureSource {
     Filename(String),
+    Playlist(Vec<String>),
+    Flac(String),
     Stdin,
+    Tcp(String, u16, bool),
     #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
-    BluezDBus(String, String),
+    BluezDBus(String, String, bool),
 }
 
 #[derive(Clone)]
@@ -67,6 +75,7 @@ pub struct FileCaptureDevice {
     pub extra_samples: usize,
     pub skip_bytes: usize,
     pub read_bytes: usize,
+    pub channel_select: Option<Vec<usize>>,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
 }
@@ -79,6 +88,7 @@ struct CaptureChannels {
 
 struct CaptureParams {
     channels: usize,
+    channel_select: Option<Vec<usize>>,
     sample_format: SampleFormat,
     store_bytes_per_sample: usize,
     extra_bytes: usize,
@@ -116,20 +126,25 @@ impl PlaybackDevice for FilePlaybackDevice {
     ) -> Res<Box<thread::JoinHandle<()>>> {
         let destination = self.destination.clone();
         let chunksize = self.chunksize;
+        let samplerate = self.samplerate;
         let channels = self.channels;
         let store_bytes_per_sample = self.sample_format.bytes_per_sample();
         let sample_format = self.sample_format;
+        let wav_header_format = self.wav_header_format;
         let handle = thread::Builder::new()
             .name("FilePlayback".to_string())
             .spawn(move || {
-                let file_res: Result<Box<dyn Write>, std::io::Error> = match destination {
-                    PlaybackDest::Filename(filename) => {
-                        File::create(filename).map(|f| Box::new(f) as Box<dyn Write>)
-                    }
-                    PlaybackDest::Stdout => Ok(Box::new(stdout())),
-                };
+                // A file gets a second handle, for updating the header when the stream ends.
+                let file_res: Result<(Box<dyn Write>, Option<File>), std::io::Error> =
+                    match destination {
+                        PlaybackDest::Filename(filename) => File::create(filename).and_then(|f| {
+                            let header_file = f.try_clone()?;
+                            Ok((Box::new(f) as Box<dyn Write>, Some(header_file)))
+                        }),
+                        PlaybackDest::Stdout => Ok((Box::new(stdout()), None)),
+                    };
                 match file_res {
-                    Ok(mut file) => {
+                    Ok((mut file, header_file)) => {
                         match status_channel.send(StatusMessage::PlaybackReady) {
                             Ok(()) => {}
                             Err(_err) => {}
@@ -138,7 +153,36 @@ impl PlaybackDevice for FilePlaybackDevice {
                             rms: vec![0.0; channels],
                             peak: vec![0.0; channels],
                         };
+                        let mut level_meter =
+                            LevelMeter::new(playback_status.read().meter_ballistics, samplerate);
+                        let mut true_peak_meter = countertimer::TruePeakMeter::default();
                         barrier.wait();
+                        // Updates the header with the final size on any exit from the loop
+                        let mut header_guard = None;
+                        if wav_header_format != WavHeaderFormat::None {
+                            debug!("Writing {:?} header", wav_header_format);
+                            let header = wav_header(
+                                wav_header_format,
+                                &sample_format,
+                                channels,
+                                samplerate,
+                                None,
+                            );
+                            if let Err(err) = file.write_all(&header) {
+                                status_channel
+                                    .send(StatusMessage::PlaybackError(err.to_string()))
+                                    .unwrap_or(());
+                            }
+                            header_guard = header_file.map(|header_file| {
+                                WavHeaderGuard::new(
+                                    header_file,
+                                    wav_header_format,
+                                    sample_format,
+                                    channels,
+                                    samplerate,
+                                )
+                            });
+                        }
                         debug!("starting playback loop");
                         let mut buffer = vec![0u8; chunksize * channels * store_bytes_per_sample];
                         loop {
@@ -151,14 +195,19 @@ impl PlaybackDevice for FilePlaybackDevice {
                                     );
                                     let write_res = file.write_all(&buffer[0..valid_bytes]);
                                     match write_res {
-                                        Ok(_) => {}
+                                        Ok(_) => {
+                                            if let Some(guard) = &mut header_guard {
+                                                guard.add_data_bytes(valid_bytes);
+                                            }
+                                        }
                                         Err(err) => {
                                             status_channel
                                                 .send(StatusMessage::PlaybackError(err.to_string()))
                                                 .unwrap_or(());
                                         }
                                     };
-                                    chunk.update_stats(&mut chunk_stats);
+                                    level_meter.update(&chunk, &mut chunk_stats);
+                                    true_peak_meter.update(&chunk);
                                     {
                                         let mut playback_status = playback_status.write();
                                         if nbr_clipped > 0 {
@@ -170,6 +219,9 @@ impl PlaybackDevice for FilePlaybackDevice {
                                         playback_status
                                             .signal_peak
                                             .add_record(chunk_stats.peak_linear());
+                                        playback_status.signal_minmax.update(&chunk);
+                                        playback_status.true_peak.add_record(&mut true_peak_meter);
+                                        playback_status.frames_played += chunk.valid_frames as u64;
                                     }
                                     trace!(
                                         "Playback signal RMS: {:?}, peak: {:?}",
@@ -181,6 +233,8 @@ impl PlaybackDevice for FilePlaybackDevice {
                                     trace!("Pause message received");
                                 }
                                 Ok(AudioMessage::EndOfStream) => {
+                                    // Update the header before reporting that playback is done
+                                    drop(header_guard.take());
                                     status_channel
                                         .send(StatusMessage::PlaybackDone)
                                         .unwrap_or(());
@@ -282,16 +336,25 @@ fn capture_loop(
         params.chunksize,
     );
 
+    let out_channels = params
+        .channel_select
+        .as_ref()
+        .map_or(params.channels, |select| select.len());
     let mut chunk_stats = ChunkStats {
-        rms: vec![0.0; params.channels],
-        peak: vec![0.0; params.channels],
+        rms: vec![0.0; out_channels],
+        peak: vec![0.0; out_channels],
     };
+    let mut level_meter = LevelMeter::new(
+        params.capture_status.read().meter_ballistics,
+        params.capture_samplerate,
+    );
+    let mut true_peak_meter = countertimer::TruePeakMeter::default();
     let mut value_range = 0.0;
     let mut rate_adjust = 0.0;
     let mut state = ProcessingState::Running;
     let mut prev_state = ProcessingState::Running;
     let mut stalled = false;
-    let mut channel_mask = vec![true; params.channels];
+    let mut channel_mask = vec![true; out_channels];
     loop {
         match msg_channels.command.try_recv() {
             Ok(CommandMessage::Exit) => {
@@ -363,7 +426,7 @@ fn capture_loop(
                         extra_bytes_left / params.store_bytes_per_sample / params.channels;
                     send_silence(
                         extra_samples,
-                        params.channels,
+                        out_channels,
                         params.chunksize,
                         &msg_channels.audio,
                         &mut resampler,
@@ -471,14 +534,25 @@ fn capture_loop(
                     .unwrap_or(());
             }
         };
-        let mut chunk = buffer_to_chunk_rawbytes(
-            &buf[0..bytes_to_capture],
-            params.channels,
-            &params.sample_format,
-            bytes_read,
-            &params.capture_status.read().used_channels,
-        );
-        chunk.update_stats(&mut chunk_stats);
+        let mut chunk = match &params.channel_select {
+            Some(select) => buffer_to_chunk_rawbytes_selected(
+                &buf[0..bytes_to_capture],
+                params.channels,
+                &params.sample_format,
+                bytes_read,
+                select,
+                &params.capture_status.read().used_channels,
+            ),
+            None => buffer_to_chunk_rawbytes(
+                &buf[0..bytes_to_capture],
+                params.channels,
+                &params.sample_format,
+                bytes_read,
+                &params.capture_status.read().used_channels,
+            ),
+        };
+        level_meter.update(&chunk, &mut chunk_stats);
+        true_peak_meter.update(&chunk);
         //trace!(
         //    "Capture rms {:?}, peak {:?}",
         //    chunk_stats.rms_db(),
@@ -492,6 +566,14 @@ fn capture_loop(
             capture_status
                 .signal_peak
                 .add_record(chunk_stats.peak_linear());
+            capture_status.signal_minmax.update(&chunk);
+            capture_status.true_peak.add_record(&mut true_peak_meter);
+            capture_status.recent_samples.update(&chunk);
+            capture_status.signal_duty.update(
+                silence_counter.is_signal(chunk.maxval - chunk.minval),
+                chunk.valid_frames,
+            );
+            capture_status.frames_captured += chunk.valid_frames as u64;
         }
         value_range = chunk.maxval - chunk.minval;
         state = silence_counter.update(value_range);
@@ -540,6 +622,7 @@ impl CaptureDevice for FileCaptureDevice {
         capture_status: Arc<RwLock<CaptureStatus>>,
     ) -> Res<Box<thread::JoinHandle<()>>> {
         let source = self.source.clone();
+        let is_playlist = matches!(source, CaptureSource::Playlist(_));
         let samplerate = self.samplerate;
         let chunksize = self.chunksize;
         let capture_samplerate = self.capture_samplerate;
@@ -559,6 +642,10 @@ impl CaptureDevice for FileCaptureDevice {
         let extra_bytes = self.extra_samples * store_bytes_per_sample * channels;
         let skip_bytes = self.skip_bytes;
         let read_bytes = self.read_bytes;
+        let channel_select = self.channel_select.clone();
+        let out_channels = channel_select
+            .as_ref()
+            .map_or(channels, |select| select.len());
         let silence_timeout = self.silence_timeout;
         let silence_threshold = self.silence_threshold;
         let stop_on_rate_change = self.stop_on_rate_change;
@@ -568,13 +655,14 @@ impl CaptureDevice for FileCaptureDevice {
             .spawn(move || {
                 let resampler = new_resampler(
                     &resampler_config,
-                    channels,
+                    out_channels,
                     samplerate,
                     capture_samplerate,
                     chunksize,
                 );
                 let params = CaptureParams {
                     channels,
+                    channel_select,
                     sample_format,
                     store_bytes_per_sample,
                     extra_bytes,
@@ -594,7 +682,25 @@ impl CaptureDevice for FileCaptureDevice {
                 let file_res: Result<Box<dyn Reader>, std::io::Error> = match source {
                     CaptureSource::Filename(filename) => File::open(filename)
                         .map(|f| Box::new(BlockingReader::new(f)) as Box<dyn Reader>),
+                    CaptureSource::Playlist(filenames) => PlaylistReader::new(
+                        filenames,
+                        skip_bytes,
+                        channels * store_bytes_per_sample,
+                    )
+                    .map(|r| Box::new(r) as Box<dyn Reader>),
+                    CaptureSource::Flac(filename) => {
+                        FlacFileReader::open(&filename).map(|r| Box::new(r) as Box<dyn Reader>)
+                    }
                     CaptureSource::Stdin => Ok(Box::new(BlockingReader::new(stdin()))),
+                    CaptureSource::Tcp(address, port, true) => TcpListenReader::bind(
+                        &address,
+                        port,
+                        Duration::from_millis(2 * 1000 * chunksize as u64 / samplerate as u64),
+                        Box::new(|s| Box::new(BlockingReader::new(s))),
+                    )
+                    .map(|r| Box::new(r) as Box<dyn Reader>),
+                    CaptureSource::Tcp(address, port, false) => connect_tcp_stream(&address, port)
+                        .map(|s| Box::new(BlockingReader::new(s)) as Box<dyn Reader>),
                 };
                 #[cfg(target_os = "linux")]
                 let file_res: Result<Box<dyn Reader>, Box<dyn Error>> = match source {
@@ -609,16 +715,53 @@ impl CaptureDevice for FileCaptureDevice {
                             )) as Box<dyn Reader>
                         })
                         .map_err(|e| e.into()),
+                    CaptureSource::Playlist(filenames) => PlaylistReader::new(
+                        filenames,
+                        skip_bytes,
+                        channels * store_bytes_per_sample,
+                    )
+                    .map(|r| Box::new(r) as Box<dyn Reader>)
+                    .map_err(|e| e.into()),
+                    CaptureSource::Flac(filename) => FlacFileReader::open(&filename)
+                        .map(|r| Box::new(r) as Box<dyn Reader>)
+                        .map_err(|e| e.into()),
                     CaptureSource::Stdin => Ok(Box::new(NonBlockingReader::new(
                         stdin(),
                         2 * 1000 * chunksize as u64 / samplerate as u64,
                     ))),
+                    CaptureSource::Tcp(address, port, true) => {
+                        let timeout_ms = 2 * 1000 * chunksize as u64 / samplerate as u64;
+                        TcpListenReader::bind(
+                            &address,
+                            port,
+                            Duration::from_millis(timeout_ms),
+                            Box::new(move |s| Box::new(NonBlockingReader::new(s, timeout_ms))),
+                        )
+                        .map(|r| Box::new(r) as Box<dyn Reader>)
+                        .map_err(|e| e.into())
+                    }
+                    CaptureSource::Tcp(address, port, false) => connect_tcp_stream(&address, port)
+                        .map(|s| {
+                            Box::new(NonBlockingReader::new(
+                                s,
+                                2 * 1000 * chunksize as u64 / samplerate as u64,
+                            )) as Box<dyn Reader>
+                        })
+                        .map_err(|e| e.into()),
                     #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
-                    CaptureSource::BluezDBus(service, path) => {
+                    CaptureSource::BluezDBus(service, path, false) => {
                         filedevice_bluez::open_bluez_dbus_fd(service, path, chunksize, samplerate)
                             .map(|r| r as Box<dyn Reader>)
                             .map_err(|e| e.into())
                     }
+                    #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
+                    CaptureSource::BluezDBus(service, path, true) => {
+                        filedevice_bluez::ReconnectingBluezReader::new(
+                            service, path, chunksize, samplerate,
+                        )
+                        .map(|r| Box::new(r) as Box<dyn Reader>)
+                        .map_err(|e| e.into())
+                    }
                 };
                 match file_res {
                     Ok(mut file) => {
@@ -632,7 +775,8 @@ impl CaptureDevice for FileCaptureDevice {
                             status: status_channel,
                             command: command_channel,
                         };
-                        if skip_bytes > 0 {
+                        // A playlist skips the header of each file by itself.
+                        if skip_bytes > 0 && !is_playlist {
                             debug!("skipping the first {} bytes", skip_bytes);
                             let mut tempbuf = vec![0u8; skip_bytes];
                             let _ = file.read(&mut tempbuf);
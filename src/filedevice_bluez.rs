@@ -1,13 +1,20 @@
+use std::error::Error;
 use std::io;
 use std::io::Read;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use zbus::blocking::Connection;
 use zbus::zvariant::OwnedFd;
 use zbus::Message;
 
+use crate::filedevice::{ReadResult, Reader};
 use crate::filereader_nonblock::NonBlockingReader;
 
+// Time between attempts to open the transport again after it was lost.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct WrappedBluezFd {
     pipe_fd: zbus::zvariant::OwnedFd,
     _ctrl_fd: zbus::zvariant::OwnedFd,
@@ -52,3 +59,90 @@ pub fn open_bluez_dbus_fd(
     ));
     return Ok(reader);
 }
+
+/// A reader that opens the BlueALSA transport again when it gets disconnected,
+/// for example when the source device goes out of range.
+/// While the transport is unavailable, every read times out without data,
+/// which puts the capture in the stalled state.
+pub struct ReconnectingBluezReader {
+    service: String,
+    path: String,
+    chunksize: usize,
+    samplerate: usize,
+    inner: Option<Box<NonBlockingReader<WrappedBluezFd>>>,
+    last_attempt: Instant,
+}
+
+impl ReconnectingBluezReader {
+    pub fn new(
+        service: String,
+        path: String,
+        chunksize: usize,
+        samplerate: usize,
+    ) -> Result<Self, zbus::Error> {
+        let inner = open_bluez_dbus_fd(service.clone(), path.clone(), chunksize, samplerate)?;
+        Ok(ReconnectingBluezReader {
+            service,
+            path,
+            chunksize,
+            samplerate,
+            inner: Some(inner),
+            last_attempt: Instant::now(),
+        })
+    }
+
+    fn reconnect(&mut self) {
+        let since_last = self.last_attempt.elapsed();
+        if since_last < RECONNECT_INTERVAL {
+            thread::sleep(RECONNECT_INTERVAL - since_last);
+        }
+        self.last_attempt = Instant::now();
+        match open_bluez_dbus_fd(
+            self.service.clone(),
+            self.path.clone(),
+            self.chunksize,
+            self.samplerate,
+        ) {
+            Ok(reader) => {
+                info!("Reconnected to BlueALSA transport {}", self.path);
+                self.inner = Some(reader);
+            }
+            Err(err) => {
+                trace!("BlueALSA transport not available: {}", err);
+            }
+        }
+    }
+}
+
+impl Reader for ReconnectingBluezReader {
+    fn read(&mut self, data: &mut [u8]) -> Result<ReadResult, Box<dyn Error>> {
+        if self.inner.is_none() {
+            self.reconnect();
+        }
+        let result = match &mut self.inner {
+            Some(reader) => reader.read(data),
+            None => return Ok(ReadResult::Timeout(0)),
+        };
+        match result {
+            Ok(ReadResult::EndOfFile(bytes)) => {
+                warn!(
+                    "BlueALSA transport {} was closed, waiting for it to reappear",
+                    self.path
+                );
+                self.inner = None;
+                self.last_attempt = Instant::now();
+                Ok(ReadResult::Timeout(bytes))
+            }
+            Err(err) => {
+                warn!(
+                    "Error reading BlueALSA transport {}: {}, waiting for it to reappear",
+                    self.path, err
+                );
+                self.inner = None;
+                self.last_attempt = Instant::now();
+                Ok(ReadResult::Timeout(0))
+            }
+            other => other,
+        }
+    }
+}
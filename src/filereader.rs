@@ -1,7 +1,9 @@
 use std::error::Error;
+use std::fs::File;
 use std::io::ErrorKind;
-use std::io::Read;
-use std::time::Duration;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
 
 use crate::filedevice::{ReadResult, Reader};
 
@@ -40,3 +42,333 @@ impl<R: Read> Reader for BlockingReader<R> {
         }
     }
 }
+
+/// Reader for a list of raw files, played in a loop.
+/// At the end of each file, the next one is opened and reading continues
+/// without any gap. Any trailing incomplete frame of a file is dropped
+/// to keep the channels aligned.
+pub struct PlaylistReader {
+    filenames: Vec<String>,
+    index: usize,
+    skip_bytes: usize,
+    bytes_per_frame: usize,
+    file_bytes: usize,
+    current: BlockingReader<File>,
+}
+
+impl PlaylistReader {
+    pub fn new(
+        filenames: Vec<String>,
+        skip_bytes: usize,
+        bytes_per_frame: usize,
+    ) -> Result<Self, std::io::Error> {
+        // Check that all files can be opened before starting.
+        for filename in filenames.iter() {
+            File::open(filename)?;
+        }
+        let current = open_playlist_file(&filenames[0], skip_bytes)?;
+        Ok(PlaylistReader {
+            filenames,
+            index: 0,
+            skip_bytes,
+            bytes_per_frame,
+            file_bytes: 0,
+            current,
+        })
+    }
+}
+
+fn open_playlist_file(
+    filename: &str,
+    skip_bytes: usize,
+) -> Result<BlockingReader<File>, std::io::Error> {
+    let mut file = File::open(filename)?;
+    if skip_bytes > 0 {
+        file.seek(SeekFrom::Start(skip_bytes as u64))?;
+    }
+    Ok(BlockingReader::new(file))
+}
+
+impl Reader for PlaylistReader {
+    fn read(&mut self, data: &mut [u8]) -> Result<ReadResult, Box<dyn Error>> {
+        let requested = data.len();
+        let mut filled = 0;
+        let mut empty_files = 0;
+        while filled < requested {
+            match self.current.read(&mut data[filled..])? {
+                ReadResult::EndOfFile(bytes) => {
+                    self.file_bytes += bytes;
+                    let partial = self.file_bytes % self.bytes_per_frame;
+                    filled = (filled + bytes).saturating_sub(partial);
+                    if self.file_bytes < self.bytes_per_frame {
+                        empty_files += 1;
+                        if empty_files >= self.filenames.len() {
+                            warn!("All files in playlist are empty");
+                            return Ok(ReadResult::EndOfFile(filled));
+                        }
+                    } else {
+                        empty_files = 0;
+                    }
+                    self.index = (self.index + 1) % self.filenames.len();
+                    debug!(
+                        "Reached end of file, continuing with '{}'",
+                        self.filenames[self.index]
+                    );
+                    self.current =
+                        open_playlist_file(&self.filenames[self.index], self.skip_bytes)?;
+                    self.file_bytes = 0;
+                }
+                ReadResult::Complete(bytes) | ReadResult::Timeout(bytes) => {
+                    self.file_bytes += bytes;
+                    filled += bytes;
+                }
+            }
+        }
+        Ok(ReadResult::Complete(requested))
+    }
+}
+
+/// Reader for FLAC files.
+/// The decoded samples are returned as interleaved S32LE bytes,
+/// with the samples shifted up to use the full 32 bits regardless of the bit depth of the file.
+pub struct FlacFileReader {
+    reader: claxon::FlacReader<File>,
+    shift: u32,
+    block_buffer: Vec<i32>,
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl FlacFileReader {
+    pub fn open(filename: &str) -> Result<Self, std::io::Error> {
+        let reader = claxon::FlacReader::open(filename)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err.to_string()))?;
+        let shift = 32 - reader.streaminfo().bits_per_sample;
+        Ok(FlacFileReader {
+            reader,
+            shift,
+            block_buffer: Vec::new(),
+            bytes: Vec::new(),
+            position: 0,
+        })
+    }
+
+    // Decode the next block of the file. Returns false at the end of the file.
+    fn decode_next_block(&mut self) -> Result<bool, Box<dyn Error>> {
+        let buffer = std::mem::take(&mut self.block_buffer);
+        match self.reader.blocks().read_next_or_eof(buffer)? {
+            Some(block) => {
+                self.bytes.clear();
+                self.position = 0;
+                for frame in 0..block.duration() {
+                    for channel in 0..block.channels() {
+                        let value = block.sample(channel, frame) << self.shift;
+                        self.bytes.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+                self.block_buffer = block.into_buffer();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl Reader for FlacFileReader {
+    fn read(&mut self, data: &mut [u8]) -> Result<ReadResult, Box<dyn Error>> {
+        let requested = data.len();
+        let mut filled = 0;
+        while filled < requested {
+            if self.position == self.bytes.len() && !self.decode_next_block()? {
+                return Ok(ReadResult::EndOfFile(filled));
+            }
+            let bytes = (requested - filled).min(self.bytes.len() - self.position);
+            data[filled..filled + bytes]
+                .copy_from_slice(&self.bytes[self.position..self.position + bytes]);
+            self.position += bytes;
+            filled += bytes;
+        }
+        Ok(ReadResult::Complete(requested))
+    }
+}
+
+// Give up connecting to a TCP server after this time.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// Interval for checking for a new connection while listening.
+const TCP_ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Connect to a TCP server for capturing.
+pub fn connect_tcp_stream(address: &str, port: u16) -> Result<TcpStream, std::io::Error> {
+    debug!("Connecting to {}:{}", address, port);
+    let mut last_error = std::io::Error::new(
+        ErrorKind::NotFound,
+        format!("Could not resolve address {}", address),
+    );
+    for addr in (address, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TCP_CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+/// Reader for a TCP stream in listen mode.
+/// The connection is accepted by the capture loop, and not when the device is opened,
+/// so that waiting for a client doesn't block the start of processing,
+/// and capture can still be stopped while waiting.
+/// Reads time out without data until a client has connected.
+pub struct TcpListenReader {
+    listener: TcpListener,
+    timeout: Duration,
+    new_reader: Box<dyn Fn(TcpStream) -> Box<dyn Reader>>,
+    reader: Option<Box<dyn Reader>>,
+}
+
+impl TcpListenReader {
+    pub fn bind(
+        address: &str,
+        port: u16,
+        timeout: Duration,
+        new_reader: Box<dyn Fn(TcpStream) -> Box<dyn Reader>>,
+    ) -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind((address, port))?;
+        listener.set_nonblocking(true)?;
+        info!("Waiting for a connection on {}:{}", address, port);
+        Ok(TcpListenReader {
+            listener,
+            timeout,
+            new_reader,
+            reader: None,
+        })
+    }
+
+    // Wait up to the timeout for a client to connect.
+    fn accept(&self) -> Result<Option<TcpStream>, std::io::Error> {
+        let start = Instant::now();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    info!("Accepted connection from {}", peer);
+                    stream.set_nonblocking(false)?;
+                    return Ok(Some(stream));
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    let elapsed = start.elapsed();
+                    if elapsed >= self.timeout {
+                        return Ok(None);
+                    }
+                    std::thread::sleep(TCP_ACCEPT_POLL_INTERVAL.min(self.timeout - elapsed));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Reader for TcpListenReader {
+    fn read(&mut self, data: &mut [u8]) -> Result<ReadResult, Box<dyn Error>> {
+        if self.reader.is_none() {
+            match self.accept()? {
+                Some(stream) => self.reader = Some((self.new_reader)(stream)),
+                None => return Ok(ReadResult::Timeout(0)),
+            }
+        }
+        match &mut self.reader {
+            Some(reader) => reader.read(data),
+            None => Ok(ReadResult::Timeout(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filedevice::{ReadResult, Reader};
+    use crate::filereader::{
+        connect_tcp_stream, BlockingReader, FlacFileReader, PlaylistReader, TcpListenReader,
+    };
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    #[test]
+    fn playlist_loops_gapless() {
+        let int16 = std::fs::read("testdata/int16.raw").unwrap();
+        let float32 = std::fs::read("testdata/float32.raw").unwrap();
+        let mut reader = PlaylistReader::new(
+            vec![
+                "testdata/int16.raw".to_string(),
+                "testdata/float32.raw".to_string(),
+            ],
+            0,
+            4,
+        )
+        .unwrap();
+        let mut data = vec![0u8; 32];
+        let res = reader.read(&mut data).unwrap();
+        assert!(matches!(res, ReadResult::Complete(32)));
+        // int16.raw is 10 bytes, the last incomplete frame is dropped
+        let mut expected = int16[0..8].to_vec();
+        expected.extend_from_slice(&float32);
+        expected.extend_from_slice(&int16[0..4]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn read_flac_file() {
+        // 16 frames of 16-bit stereo, the left channel counts up and the right counts down
+        let mut reader = FlacFileReader::open("testdata/int16_stereo.flac").unwrap();
+        let mut data = vec![0u8; 160];
+        let res = reader.read(&mut data).unwrap();
+        assert!(matches!(res, ReadResult::EndOfFile(128)));
+        let values: Vec<i32> = data[0..128]
+            .chunks_exact(4)
+            .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        let expected: Vec<i32> = (0..16)
+            .flat_map(|n| [(1000 * n) << 16, (-1000 * n) << 16])
+            .collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn read_tcp_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sender = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&[1, 2, 3, 4, 5, 6]).unwrap();
+        });
+        let stream = connect_tcp_stream("127.0.0.1", port).unwrap();
+        let mut reader = BlockingReader::new(stream);
+        let mut buf = [0u8; 4];
+        assert!(matches!(reader.read(&mut buf), Ok(ReadResult::Complete(4))));
+        assert_eq!(buf, [1, 2, 3, 4]);
+        sender.join().unwrap();
+        // The sender has closed the connection
+        assert!(matches!(
+            reader.read(&mut buf),
+            Ok(ReadResult::EndOfFile(2))
+        ));
+        assert_eq!(buf[..2], [5, 6]);
+    }
+
+    #[test]
+    fn listen_tcp_stream() {
+        let mut reader = TcpListenReader::bind(
+            "127.0.0.1",
+            0,
+            Duration::from_millis(20),
+            Box::new(|stream| Box::new(BlockingReader::new(stream))),
+        )
+        .unwrap();
+        let port = reader.listener.local_addr().unwrap().port();
+        let mut buf = [0u8; 4];
+        // Reads time out while waiting for a client
+        assert!(matches!(reader.read(&mut buf), Ok(ReadResult::Timeout(0))));
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client.write_all(&[1, 2, 3, 4]).unwrap();
+        assert!(matches!(reader.read(&mut buf), Ok(ReadResult::Complete(4))));
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+}
@@ -1,25 +1,31 @@
+use crate::audiodevice;
 use crate::audiodevice::AudioChunk;
 use crate::basicfilters;
 use crate::biquad;
 use crate::biquadcombo;
+use crate::bitcrusher;
 use crate::compressor;
 use crate::config;
 use crate::conversions;
+use crate::crossfeed;
 use crate::diffeq;
 use crate::dither;
-#[cfg(not(feature = "FFTW"))]
+use crate::dynamiceq;
 use crate::fftconv;
 #[cfg(feature = "FFTW")]
-use crate::fftconv_fftw as fftconv;
+use crate::fftconv_fftw;
 use crate::limiter;
 use crate::loudness;
 use crate::mixer;
+use crate::phaserotation;
+use crate::subalign;
+use num_complex::Complex;
 use rawsample::SampleReader;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::BufReader;
-use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::io::{BufRead, Cursor, Read, Seek, SeekFrom};
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -68,6 +74,9 @@ const SUBTYPE_PCM: Guid = Guid {
     data4: [128, 0, 0, 170, 0, 56, 155, 113],
 };
 
+/// Reference frequency for estimating the gain of bypassed filter steps
+const LEVEL_MATCH_FREQUENCY: PrcFmt = 1000.0;
+
 #[derive(Debug)]
 pub struct WavParams {
     sample_format: config::FileFormat,
@@ -106,13 +115,20 @@ pub fn pad_vector(values: &[PrcFmt], length: usize) -> Vec<PrcFmt> {
     new_values
 }
 
-pub fn read_coeff_file(
-    filename: &str,
-    format: &config::FileFormat,
-    read_bytes_lines: usize,
-    skip_bytes_lines: usize,
-) -> Res<Vec<PrcFmt>> {
-    let mut coefficients = Vec::<PrcFmt>::new();
+/// A coefficient file opened for reading, either a local file or one downloaded into memory.
+trait CoeffReader: BufRead + Seek {}
+
+impl<T: BufRead + Seek> CoeffReader for T {}
+
+/// Open a coefficient file. If the name is an http or https URL,
+/// the file is read from the copy that was downloaded when the config was loaded.
+/// Returns the reader together with the size of the file in bytes.
+fn open_coeff_file(filename: &str) -> Res<(Box<dyn CoeffReader>, u64)> {
+    if config::is_url(filename) {
+        let data = config::downloaded_file(filename)?;
+        let size = data.len() as u64;
+        return Ok((Box::new(Cursor::new(data)), size));
+    }
     let f = match File::open(filename) {
         Ok(f) => f,
         Err(err) => {
@@ -120,7 +136,34 @@ pub fn read_coeff_file(
             return Err(config::ConfigError::new(&msg).into());
         }
     };
-    let mut file = BufReader::new(&f);
+    let size = f.metadata()?.len();
+    Ok((Box::new(BufReader::new(f)), size))
+}
+
+pub fn read_coeff_file(
+    filename: &str,
+    format: &config::FileFormat,
+    read_bytes_lines: usize,
+    skip_bytes_lines: usize,
+) -> Res<Vec<PrcFmt>> {
+    let (mut file, _) = open_coeff_file(filename)?;
+    read_coeffs_from(
+        &mut file,
+        filename,
+        format,
+        read_bytes_lines,
+        skip_bytes_lines,
+    )
+}
+
+fn read_coeffs_from<R: BufRead + Seek>(
+    mut file: R,
+    filename: &str,
+    format: &config::FileFormat,
+    read_bytes_lines: usize,
+    skip_bytes_lines: usize,
+) -> Res<Vec<PrcFmt>> {
+    let mut coefficients = Vec::<PrcFmt>::new();
     let read_bytes_lines = if read_bytes_lines > 0 {
         read_bytes_lines
     } else {
@@ -185,9 +228,15 @@ pub fn read_coeff_file(
 }
 
 pub fn find_data_in_wav(filename: &str) -> Res<WavParams> {
-    let f = File::open(filename)?;
-    let filesize = f.metadata()?.len();
-    let mut file = BufReader::new(&f);
+    let (mut file, filesize) = open_coeff_file(filename)?;
+    find_data_in_wav_stream(&mut file, filesize, filename)
+}
+
+fn find_data_in_wav_stream<R: Read + Seek>(
+    mut file: R,
+    filesize: u64,
+    filename: &str,
+) -> Res<WavParams> {
     let mut header = [0; 12];
     let _ = file.read(&mut header)?;
 
@@ -217,9 +266,9 @@ pub fn find_data_in_wav(filename: &str) -> Res<WavParams> {
     let mut data_offset = 0;
     let mut data_length = 0;
 
-    while (!found_fmt || !found_data) && next_chunk_location < filesize {
+    while (!found_fmt || !found_data) && next_chunk_location + 8 <= filesize {
         file.seek(SeekFrom::Start(next_chunk_location))?;
-        let _ = file.read(&mut buffer)?;
+        file.read_exact(&mut buffer)?;
         let chunk_length = u32::from_le_bytes(buffer[4..8].try_into().unwrap());
         trace!("Analyzing wav chunk of length: {}", chunk_length);
         let is_data = buffer.iter().take(4).zip(data_b).all(|(a, b)| *a == *b);
@@ -227,7 +276,7 @@ pub fn find_data_in_wav(filename: &str) -> Res<WavParams> {
         if is_fmt && (chunk_length == 16 || chunk_length == 18 || chunk_length == 40) {
             found_fmt = true;
             let mut data = vec![0; chunk_length as usize];
-            let _ = file.read(&mut data).unwrap();
+            file.read_exact(&mut data)?;
             let formatcode = u16::from_le_bytes(data[0..2].try_into().unwrap());
             channels = u16::from_le_bytes(data[2..4].try_into().unwrap());
             sample_rate = u32::from_le_bytes(data[4..8].try_into().unwrap());
@@ -295,7 +344,8 @@ pub fn find_data_in_wav(filename: &str) -> Res<WavParams> {
                 data_length
             )
         }
-        next_chunk_location += 8 + chunk_length as u64;
+        // Chunks are word aligned, a chunk with odd length is followed by a padding byte.
+        next_chunk_location += 8 + chunk_length as u64 + (chunk_length % 2) as u64;
     }
     if found_data && found_fmt {
         trace!("Wav file with parameters: format: {:?},  samplerate: {}, channels: {}, data_length: {}, data_offset: {}", sample_format, sample_rate, channels, data_length, data_offset);
@@ -312,7 +362,8 @@ pub fn find_data_in_wav(filename: &str) -> Res<WavParams> {
 }
 
 pub fn read_wav(filename: &str, channel: usize) -> Res<Vec<PrcFmt>> {
-    let params = find_data_in_wav(filename)?;
+    let (mut file, filesize) = open_coeff_file(filename)?;
+    let params = find_data_in_wav_stream(&mut file, filesize, filename)?;
     if channel >= params.channels {
         let msg = format!(
             "Cant read channel {} of file '{}' which contains {} channels.",
@@ -321,7 +372,8 @@ pub fn read_wav(filename: &str, channel: usize) -> Res<Vec<PrcFmt>> {
         return Err(config::ConfigError::new(&msg).into());
     }
 
-    let alldata = read_coeff_file(
+    let alldata = read_coeffs_from(
+        &mut file,
         filename,
         &params.sample_format,
         params.data_length,
@@ -346,9 +398,105 @@ pub fn read_wav(filename: &str, channel: usize) -> Res<Vec<PrcFmt>> {
     Ok(data)
 }
 
+/// Check that a Wav Conv filter has either a single file or a list of sources, and that they can be read.
+pub fn validate_wav_params(params: &config::ConvParametersWav) -> Res<()> {
+    match (&params.filename, &params.sources) {
+        (Some(_), Some(_)) => {
+            Err(config::ConfigError::new("Conv filter can't have both filename and sources").into())
+        }
+        (None, None) => {
+            Err(config::ConfigError::new("Conv filter needs either filename or sources").into())
+        }
+        (None, Some(sources)) => validate_wav_sources(sources),
+        (Some(filename), None) => {
+            let coeffs = read_wav(filename, params.channel())?;
+            if coeffs.is_empty() {
+                return Err(config::ConfigError::new("Conv coefficients are empty").into());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Check that all the sources of a Wav Conv filter can be read,
+/// and that the selected impulse responses have the same length and samplerate.
+pub fn validate_wav_sources(sources: &[config::ConvWavSource]) -> Res<()> {
+    if sources.is_empty() {
+        return Err(config::ConfigError::new("Conv sources list is empty").into());
+    }
+    let mut first: Option<(&str, usize, usize)> = None;
+    for source in sources {
+        let samplerate = find_data_in_wav(&source.filename)?.sample_rate;
+        let length = read_wav(&source.filename, source.channel())?.len();
+        if length == 0 {
+            let msg = format!("Conv coefficients from '{}' are empty", source.filename);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+        match first {
+            None => first = Some((&source.filename, length, samplerate)),
+            Some((first_name, first_length, first_samplerate)) => {
+                if length != first_length {
+                    let msg = format!(
+                        "Conv sources have different lengths, '{}' has {} and '{}' has {}.",
+                        first_name, first_length, source.filename, length
+                    );
+                    return Err(config::ConfigError::new(&msg).into());
+                }
+                if samplerate != first_samplerate {
+                    let msg = format!(
+                        "Conv sources have different samplerates, '{}' has {} and '{}' has {}.",
+                        first_name, first_samplerate, source.filename, samplerate
+                    );
+                    return Err(config::ConfigError::new(&msg).into());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolve a filter config for the channel it is applied to.
+/// A Wav Conv filter with a list of sources reads the file selected for the channel.
+fn config_for_channel(filter_config: config::Filter, channel: usize) -> config::Filter {
+    match filter_config {
+        config::Filter::Conv {
+            description,
+            parameters: config::ConvParameters::Wav(params),
+        } => config::Filter::Conv {
+            description,
+            parameters: config::ConvParameters::Wav(params.for_channel(channel)),
+        },
+        other => other,
+    }
+}
+
+/// Create a convolution filter using the engine selected in the config.
+fn new_conv(
+    name: &str,
+    waveform_length: usize,
+    parameters: config::ConvParameters,
+) -> Box<dyn Filter> {
+    match parameters.engine() {
+        #[cfg(feature = "FFTW")]
+        config::ConvEngine::Fftw => Box::new(fftconv_fftw::FftConv::from_config(
+            name,
+            waveform_length,
+            parameters,
+        )),
+        _ => Box::new(fftconv::FftConv::from_config(
+            name,
+            waveform_length,
+            parameters,
+        )),
+    }
+}
+
 pub struct FilterGroup {
     channel: usize,
     filters: Vec<Box<dyn Filter>>,
+    // The fraction of processed signal in the output, the rest is the unprocessed input.
+    mix: PrcFmt,
+    dry: Vec<PrcFmt>,
 }
 
 impl FilterGroup {
@@ -356,6 +504,7 @@ impl FilterGroup {
     pub fn from_config(
         channel: usize,
         names: &[String],
+        mix: PrcFmt,
         filter_configs: HashMap<String, config::Filter>,
         waveform_length: usize,
         sample_freq: usize,
@@ -364,57 +513,63 @@ impl FilterGroup {
         debug!("Build filter group from config");
         let mut filters = Vec::<Box<dyn Filter>>::new();
         for name in names {
-            let filter_cfg = filter_configs[name].clone();
+            let filter_cfg = config_for_channel(filter_configs[name].clone(), channel);
             trace!("Create filter {} with config {:?}", name, filter_cfg);
-            let filter: Box<dyn Filter> =
-                match filter_cfg {
-                    config::Filter::Conv { parameters, .. } => Box::new(
-                        fftconv::FftConv::from_config(name, waveform_length, parameters),
-                    ),
-                    config::Filter::Biquad { parameters, .. } => Box::new(biquad::Biquad::new(
+            let filter: Box<dyn Filter> = match filter_cfg {
+                config::Filter::Conv { parameters, .. } => {
+                    new_conv(name, waveform_length, parameters)
+                }
+                config::Filter::Biquad { parameters, .. } => Box::new(biquad::Biquad::new(
+                    name,
+                    sample_freq,
+                    biquad::BiquadCoefficients::from_config(sample_freq, parameters),
+                )),
+                config::Filter::BiquadCombo { parameters, .. } => Box::new(
+                    biquadcombo::BiquadCombo::from_config(name, sample_freq, parameters),
+                ),
+                config::Filter::Delay { parameters, .. } => Box::new(
+                    basicfilters::Delay::from_config(name, sample_freq, parameters),
+                ),
+                config::Filter::Gain { parameters, .. } => {
+                    Box::new(basicfilters::Gain::from_config(name, parameters))
+                }
+                config::Filter::Volume { parameters, .. } => {
+                    Box::new(basicfilters::Volume::from_config(
                         name,
+                        parameters,
+                        waveform_length,
                         sample_freq,
-                        biquad::BiquadCoefficients::from_config(sample_freq, parameters),
-                    )),
-                    config::Filter::BiquadCombo { parameters, .. } => Box::new(
-                        biquadcombo::BiquadCombo::from_config(name, sample_freq, parameters),
-                    ),
-                    config::Filter::Delay { parameters, .. } => Box::new(
-                        basicfilters::Delay::from_config(name, sample_freq, parameters),
-                    ),
-                    config::Filter::Gain { parameters, .. } => {
-                        Box::new(basicfilters::Gain::from_config(name, parameters))
-                    }
-                    config::Filter::Volume { parameters, .. } => {
-                        Box::new(basicfilters::Volume::from_config(
-                            name,
-                            parameters,
-                            waveform_length,
-                            sample_freq,
-                            processing_params.clone(),
-                        ))
-                    }
-                    config::Filter::Loudness { parameters, .. } => {
-                        Box::new(loudness::Loudness::from_config(
-                            name,
-                            parameters,
-                            sample_freq,
-                            processing_params.clone(),
-                        ))
-                    }
-                    config::Filter::Dither { parameters, .. } => {
-                        Box::new(dither::Dither::from_config(name, parameters))
-                    }
-                    config::Filter::DiffEq { parameters, .. } => {
-                        Box::new(diffeq::DiffEq::from_config(name, parameters))
-                    }
-                    config::Filter::Limiter { parameters, .. } => {
-                        Box::new(limiter::Limiter::from_config(name, parameters))
-                    }
-                };
+                        processing_params.clone(),
+                    ))
+                }
+                config::Filter::Loudness { parameters, .. } => {
+                    Box::new(loudness::Loudness::from_config(
+                        name,
+                        parameters,
+                        channel,
+                        sample_freq,
+                        processing_params.clone(),
+                    ))
+                }
+                config::Filter::Dither { parameters, .. } => {
+                    Box::new(dither::Dither::from_config(name, parameters))
+                }
+                config::Filter::DiffEq { parameters, .. } => {
+                    Box::new(diffeq::DiffEq::from_config(name, parameters))
+                }
+                config::Filter::Polarity { .. } => Box::new(basicfilters::Polarity::new(name)),
+                config::Filter::Limiter { parameters, .. } => {
+                    Box::new(limiter::Limiter::from_config(name, parameters, sample_freq))
+                }
+            };
             filters.push(filter);
         }
-        FilterGroup { channel, filters }
+        FilterGroup {
+            channel,
+            filters,
+            mix,
+            dry: Vec::with_capacity(waveform_length),
+        }
     }
 
     pub fn update_parameters(
@@ -424,7 +579,10 @@ impl FilterGroup {
     ) {
         for filter in &mut self.filters {
             if changed.iter().any(|n| n == filter.name()) {
-                filter.update_parameters(filterconfigs[filter.name()].clone());
+                filter.update_parameters(config_for_channel(
+                    filterconfigs[filter.name()].clone(),
+                    self.channel,
+                ));
             }
         }
     }
@@ -456,8 +614,19 @@ impl FilterGroup {
                     "xorpd xmm15, xmm15"
                 )
             }
+            let waveform = &mut input.waveforms[self.channel];
+            if self.mix < 1.0 {
+                self.dry.clear();
+                self.dry.extend_from_slice(waveform);
+            }
             for filter in &mut self.filters {
-                filter.process_waveform(&mut input.waveforms[self.channel])?;
+                filter.process_waveform(waveform)?;
+            }
+            if self.mix < 1.0 {
+                let dry_gain = 1.0 - self.mix;
+                for (value, dry) in waveform.iter_mut().zip(self.dry.iter()) {
+                    *value = self.mix * *value + dry_gain * dry;
+                }
             }
         }
         Ok(())
@@ -474,9 +643,78 @@ pub enum PipelineStep {
 
 pub struct Pipeline {
     steps: Vec<PipelineStep>,
+    // Index in the pipeline config of each built step, and if it is currently bypassed
+    step_indices: Vec<usize>,
+    bypassed: Vec<bool>,
+    // Built steps that replace a bypassed filter step by a level matching gain
+    level_match: Vec<bool>,
     volume: basicfilters::Volume,
     secs_per_chunk: f32,
     processing_params: Arc<ProcessingParameters>,
+    clipping_monitor: Option<ClippingMonitor>,
+}
+
+/// Logs a warning when the peak level after a pipeline step gets close to 0 dBFS.
+/// The warnings for each step are rate limited.
+struct ClippingMonitor {
+    labels: Vec<String>,
+    last_warning: Vec<Option<Instant>>,
+}
+
+impl ClippingMonitor {
+    // Peak level that triggers a warning, -0.5 dBFS.
+    const WARNING_LEVEL: PrcFmt = 0.944;
+    // Shortest time between warnings for the same step, in seconds.
+    const WARNING_INTERVAL: u64 = 10;
+
+    fn new(labels: Vec<String>) -> Self {
+        let last_warning = vec![None; labels.len()];
+        ClippingMonitor {
+            labels,
+            last_warning,
+        }
+    }
+
+    fn check(&mut self, step_idx: usize, waveforms: &[Vec<PrcFmt>]) {
+        let peak = waveforms
+            .iter()
+            .flatten()
+            .fold(0.0, |max: PrcFmt, value| max.max(value.abs()));
+        if peak < Self::WARNING_LEVEL {
+            return;
+        }
+        let interval = std::time::Duration::from_secs(Self::WARNING_INTERVAL);
+        if self.last_warning[step_idx].map_or(true, |time| time.elapsed() >= interval) {
+            warn!(
+                "Signal peak after {} is {:.1} dBFS, the output may clip",
+                self.labels[step_idx],
+                20.0 * peak.log10()
+            );
+            self.last_warning[step_idx] = Some(Instant::now());
+        }
+    }
+}
+
+/// Describe a pipeline step for log messages.
+fn step_label(step: &config::PipelineStep) -> String {
+    let (label, description) = match step {
+        config::PipelineStep::Mixer(step) => (format!("mixer '{}'", step.name), &step.description),
+        config::PipelineStep::Filter(step) => (
+            format!(
+                "filter step on channel {} ({})",
+                step.channel,
+                step.names.join(", ")
+            ),
+            &step.description,
+        ),
+        config::PipelineStep::Processor(step) => {
+            (format!("processor '{}'", step.name), &step.description)
+        }
+    };
+    match description {
+        Some(desc) => format!("{label} \"{desc}\""),
+        None => label,
+    }
 }
 
 impl Pipeline {
@@ -488,7 +726,12 @@ impl Pipeline {
         debug!("Build new pipeline");
         trace!("Pipeline config {:?}", conf.pipeline);
         let mut steps = Vec::<PipelineStep>::new();
-        for step in conf.pipeline.unwrap_or_default() {
+        let mut labels = Vec::<String>::new();
+        let mut step_indices = Vec::<usize>::new();
+        let mut level_match = Vec::<bool>::new();
+        for (step_idx, step) in conf.pipeline.unwrap_or_default().into_iter().enumerate() {
+            let label = step_label(&step);
+            let is_bypassed = step.is_bypassed();
             match step {
                 config::PipelineStep::Mixer(step) => {
                     if !step.is_bypassed() {
@@ -502,12 +745,32 @@ impl Pipeline {
                         let fltgrp = FilterGroup::from_config(
                             step.channel,
                             &step.names,
+                            step.mix(),
                             conf.filters.as_ref().unwrap().clone(),
                             conf.devices.chunksize,
                             conf.devices.samplerate,
                             processing_params.clone(),
                         );
                         steps.push(PipelineStep::FilterStep(fltgrp));
+                    } else if step.level_match_on_bypass() {
+                        let gain = bypass_level_match_gain(
+                            conf.devices.samplerate,
+                            &step,
+                            conf.filters.as_ref().unwrap(),
+                        );
+                        debug!(
+                            "Replacing bypassed filter step for channel {} by a gain of {} dB",
+                            step.channel, gain
+                        );
+                        let fltgrp = FilterGroup {
+                            channel: step.channel,
+                            filters: vec![Box::new(basicfilters::Gain::new(
+                                "", gain, false, false, false,
+                            ))],
+                            mix: 1.0,
+                            dry: Vec::new(),
+                        };
+                        steps.push(PipelineStep::FilterStep(fltgrp));
                     }
                 }
                 config::PipelineStep::Processor(step) => {
@@ -521,16 +784,66 @@ impl Pipeline {
                                     conf.devices.samplerate,
                                     conf.devices.chunksize,
                                 );
-                                Box::new(comp)
+                                Box::new(comp) as Box<dyn Processor>
+                            }
+                            config::Processor::DynamicEq { parameters, .. } => {
+                                let deq = dynamiceq::DynamicEq::from_config(
+                                    &step.name,
+                                    parameters,
+                                    conf.devices.samplerate,
+                                    conf.devices.chunksize,
+                                );
+                                Box::new(deq) as Box<dyn Processor>
+                            }
+                            config::Processor::SubAlign { parameters, .. } => {
+                                let align = subalign::SubAlign::from_config(
+                                    &step.name,
+                                    parameters,
+                                    conf.devices.samplerate,
+                                );
+                                Box::new(align) as Box<dyn Processor>
+                            }
+                            config::Processor::BitCrusher { parameters, .. } => {
+                                let crusher =
+                                    bitcrusher::BitCrusher::from_config(&step.name, parameters);
+                                Box::new(crusher) as Box<dyn Processor>
+                            }
+                            config::Processor::PhaseRotation { parameters, .. } => {
+                                let rotation = phaserotation::PhaseRotation::from_config(
+                                    &step.name,
+                                    parameters,
+                                    conf.devices.samplerate,
+                                    conf.devices.chunksize,
+                                );
+                                Box::new(rotation) as Box<dyn Processor>
+                            }
+                            config::Processor::Crossfeed { parameters, .. } => {
+                                let crossfeed = crossfeed::Crossfeed::from_config(
+                                    &step.name,
+                                    parameters,
+                                    conf.devices.samplerate,
+                                    conf.devices.chunksize,
+                                );
+                                Box::new(crossfeed) as Box<dyn Processor>
                             }
                         };
                         steps.push(PipelineStep::ProcessorStep(proc));
                     }
                 }
             }
+            if steps.len() > labels.len() {
+                labels.push(label);
+                step_indices.push(step_idx);
+                level_match.push(is_bypassed);
+            }
         }
+        let clipping_monitor = if conf.devices.warn_internal_clipping() {
+            Some(ClippingMonitor::new(labels))
+        } else {
+            None
+        };
         let current_volume = processing_params.current_volume(0);
-        let mute = processing_params.is_mute(0);
+        let mute = processing_params.is_output_muted(0);
         let volume = basicfilters::Volume::new(
             "default",
             conf.devices.ramp_time(),
@@ -542,11 +855,16 @@ impl Pipeline {
             0,
         );
         let secs_per_chunk = conf.devices.chunksize as f32 / conf.devices.samplerate as f32;
+        let bypassed = vec![false; steps.len()];
         Pipeline {
             steps,
+            step_indices,
+            bypassed,
+            level_match,
             volume,
             secs_per_chunk,
             processing_params,
+            clipping_monitor,
         }
     }
 
@@ -579,11 +897,63 @@ impl Pipeline {
         }
     }
 
+    /// Apply changed bypass settings of filter and processor steps, without rebuilding the pipeline.
+    /// Steps that were bypassed when the pipeline was built don't exist,
+    /// and if any of them is no longer bypassed this returns false, meaning the pipeline must be rebuilt.
+    /// The same applies to filter steps that should be replaced by a level matching gain when bypassed.
+    pub fn update_bypass(&mut self, conf: &config::Configuration) -> bool {
+        let pipeline = conf.pipeline.as_deref().unwrap_or_default();
+        let missing_step = pipeline.iter().enumerate().any(|(idx, step)| {
+            !step.is_bypassed()
+                && !self
+                    .step_indices
+                    .iter()
+                    .zip(self.level_match.iter())
+                    .any(|(built_idx, level_match)| *built_idx == idx && !level_match)
+        });
+        if missing_step {
+            return false;
+        }
+        let level_match_on_bypass = |step: &config::PipelineStep| matches!(step, config::PipelineStep::Filter(flt) if flt.level_match_on_bypass());
+        let needs_level_match =
+            self.step_indices
+                .iter()
+                .zip(self.level_match.iter())
+                .any(|(idx, level_match)| {
+                    !level_match
+                        && pipeline.get(*idx).map_or(false, |step| {
+                            step.is_bypassed() && level_match_on_bypass(step)
+                        })
+                });
+        if needs_level_match {
+            return false;
+        }
+        for ((bypassed, idx), level_match) in self
+            .bypassed
+            .iter_mut()
+            .zip(self.step_indices.iter())
+            .zip(self.level_match.iter())
+        {
+            if *level_match {
+                continue;
+            }
+            let bypass = pipeline.get(*idx).map_or(false, |step| step.is_bypassed());
+            if bypass != *bypassed {
+                debug!("Setting bypass of pipeline step {} to {}", idx, bypass);
+                *bypassed = bypass;
+            }
+        }
+        true
+    }
+
     /// Process an AudioChunk by calling either a MixerStep or a FilterStep
     pub fn process_chunk(&mut self, mut chunk: AudioChunk) -> AudioChunk {
         let start = Instant::now();
         self.volume.process_chunk(&mut chunk);
-        for mut step in &mut self.steps {
+        for (step_idx, mut step) in self.steps.iter_mut().enumerate() {
+            if self.bypassed[step_idx] {
+                continue;
+            }
             match &mut step {
                 PipelineStep::MixerStep(mix) => {
                     chunk = mix.process_chunk(&chunk);
@@ -595,6 +965,14 @@ impl Pipeline {
                     comp.process_chunk(&mut chunk).unwrap();
                 }
             }
+            if let Some(monitor) = &mut self.clipping_monitor {
+                match step {
+                    PipelineStep::FilterStep(flt) => {
+                        monitor.check(step_idx, &chunk.waveforms[flt.channel..=flt.channel])
+                    }
+                    _ => monitor.check(step_idx, &chunk.waveforms),
+                }
+            }
         }
         let secs_elapsed = start.elapsed().as_secs_f32();
         let load = 100.0 * secs_elapsed / self.secs_per_chunk;
@@ -604,10 +982,232 @@ impl Pipeline {
     }
 }
 
+/// Estimate the broadband gain in dB of a filter step, used to keep the level when the step is bypassed.
+/// Filters built from biquads are evaluated at a reference frequency,
+/// the configured `bypass_gain` is added to account for all other filters.
+fn bypass_level_match_gain(
+    fs: usize,
+    step: &config::PipelineStepFilter,
+    filter_configs: &HashMap<String, config::Filter>,
+) -> PrcFmt {
+    let mut gain = step.bypass_gain();
+    for name in step.names.iter() {
+        if let Ok(response) = complex_gain(fs, &filter_configs[name], LEVEL_MATCH_FREQUENCY) {
+            gain += 20.0 * response.norm().log10();
+        }
+    }
+    gain
+}
+
+/// Calculate the complex gain of a filter at the given frequency.
+/// This is only supported for the filter types that are built from biquads.
+pub fn complex_gain(
+    fs: usize,
+    filter_config: &config::Filter,
+    freq: PrcFmt,
+) -> Res<Complex<PrcFmt>> {
+    match filter_config {
+        config::Filter::Biquad { parameters, .. } => Ok(biquad::BiquadCoefficients::from_config(
+            fs,
+            parameters.clone(),
+        )
+        .complex_gain(freq, fs)),
+        config::Filter::BiquadCombo { parameters, .. } => Ok(
+            biquadcombo::BiquadCombo::from_config("", fs, parameters.clone()).complex_gain(freq),
+        ),
+        _ => Err(config::ConfigError::new(
+            "The response can only be calculated for Biquad and BiquadCombo filters",
+        )
+        .into()),
+    }
+}
+
+/// Calculate the complex gain of a filter at each of the given frequencies.
+/// In addition to the types supported by `complex_gain`, this supports Conv filters,
+/// where the response is calculated from the impulse response.
+pub fn frequency_response(
+    fs: usize,
+    filter_config: &config::Filter,
+    freqs: &[PrcFmt],
+) -> Res<Vec<Complex<PrcFmt>>> {
+    match filter_config {
+        config::Filter::Conv { parameters, .. } => {
+            let coeffs = conv_coeffs(parameters)?;
+            Ok(freqs
+                .iter()
+                .map(|freq| impulse_response_gain(&coeffs, fs, *freq))
+                .collect())
+        }
+        _ => freqs
+            .iter()
+            .map(|freq| complex_gain(fs, filter_config, *freq))
+            .collect(),
+    }
+}
+
+// Number of samples between recalculations of the phasor, to avoid accumulating rounding errors.
+const PHASOR_RESET_INTERVAL: usize = 1024;
+
+/// Evaluate the discrete time Fourier transform of an impulse response at a single frequency.
+fn impulse_response_gain(coeffs: &[PrcFmt], fs: usize, freq: PrcFmt) -> Complex<PrcFmt> {
+    let pi = std::f64::consts::PI as PrcFmt;
+    let omega = 2.0 * pi * freq / fs as PrcFmt;
+    let step = Complex::from_polar(1.0, -omega);
+    let mut sum = Complex::new(0.0, 0.0);
+    for (block_nbr, block) in coeffs.chunks(PHASOR_RESET_INTERVAL).enumerate() {
+        let mut phasor =
+            Complex::from_polar(1.0, -omega * (block_nbr * PHASOR_RESET_INTERVAL) as PrcFmt);
+        for coeff in block {
+            sum += phasor * coeff;
+            phasor *= step;
+        }
+    }
+    sum
+}
+
+/// Read the coefficients of a Conv filter.
+fn conv_coeffs(conf: &config::ConvParameters) -> Res<Vec<PrcFmt>> {
+    match conf {
+        config::ConvParameters::Values { values, .. } => Ok(values.clone()),
+        config::ConvParameters::Dummy { length, .. } => {
+            let mut values = vec![0.0; *length];
+            if let Some(first) = values.first_mut() {
+                *first = 1.0;
+            }
+            Ok(values)
+        }
+        config::ConvParameters::Raw(params) => read_coeff_file(
+            &params.filename,
+            &params.format(),
+            params.read_bytes_lines(),
+            params.skip_bytes_lines(),
+        ),
+        config::ConvParameters::Wav(params) => read_wav(params.filename(), params.channel()),
+    }
+}
+
+/// Get the number of coefficients of a Conv filter.
+fn conv_length(conf: &config::ConvParameters) -> Res<usize> {
+    match conf {
+        config::ConvParameters::Values { values, .. } => Ok(values.len()),
+        config::ConvParameters::Dummy { length, .. } => Ok(*length),
+        _ => Ok(conv_coeffs(conf)?.len()),
+    }
+}
+
+/// Get the latency of a filter in samples.
+/// Conv filters contribute the length of their coefficients, Delay filters their configured delay,
+/// and Limiter filters their lookahead.
+/// All other filter types are counted as having no latency.
+pub fn filter_latency(fs: usize, filter_config: &config::Filter) -> Res<PrcFmt> {
+    match filter_config {
+        config::Filter::Conv { parameters, .. } => Ok(conv_length(parameters)? as PrcFmt),
+        config::Filter::Delay { parameters, .. } => {
+            Ok(basicfilters::delay_in_samples(fs, parameters))
+        }
+        config::Filter::Limiter { parameters, .. } => {
+            Ok(limiter::lookahead_samples(fs, parameters) as PrcFmt)
+        }
+        _ => Ok(0.0),
+    }
+}
+
+/// Calculate the latency of the pipeline, in frames at the processing samplerate.
+/// The latency is followed separately for each channel through the pipeline,
+/// and each mixer output gets the largest latency of its sources.
+/// The result is the largest latency of the output channels, plus the delay of the resampler.
+pub fn pipeline_latency(conf: &config::Configuration) -> Res<PrcFmt> {
+    let fs = conf.devices.samplerate;
+    let mut latencies: Vec<PrcFmt> = vec![0.0; conf.devices.capture.channels()];
+    for step in conf.pipeline.iter().flatten() {
+        match step {
+            config::PipelineStep::Mixer(step) if !step.is_bypassed() => {
+                let mixer = conf
+                    .mixers
+                    .as_ref()
+                    .and_then(|mixers| mixers.get(&step.name))
+                    .ok_or_else(|| {
+                        config::ConfigError::new(&format!("Use of missing mixer '{}'", step.name))
+                    })?;
+                let mut mixed: Vec<PrcFmt> = vec![0.0; mixer.channels.out];
+                for mapping in mixer.mapping.iter().filter(|mapping| !mapping.is_mute()) {
+                    for source in mapping.sources.iter().filter(|source| !source.is_mute()) {
+                        if let (Some(dest), Some(latency)) =
+                            (mixed.get_mut(mapping.dest), latencies.get(source.channel))
+                        {
+                            *dest = latency.max(*dest);
+                        }
+                    }
+                }
+                latencies = mixed;
+            }
+            config::PipelineStep::Filter(step) if !step.is_bypassed() => {
+                let mut step_latency = 0.0;
+                for name in step.names.iter() {
+                    let filter_config = conf
+                        .filters
+                        .as_ref()
+                        .and_then(|filters| filters.get(name))
+                        .ok_or_else(|| {
+                            config::ConfigError::new(&format!("Use of missing filter '{name}'"))
+                        })?;
+                    step_latency += filter_latency(
+                        fs,
+                        &config_for_channel(filter_config.clone(), step.channel),
+                    )?;
+                }
+                if let Some(latency) = latencies.get_mut(step.channel) {
+                    *latency += step_latency;
+                }
+            }
+            config::PipelineStep::Processor(step) if !step.is_bypassed() => {
+                match conf
+                    .processors
+                    .as_ref()
+                    .and_then(|processors| processors.get(&step.name))
+                {
+                    Some(config::Processor::PhaseRotation { parameters, .. }) => {
+                        let chunksize = conf.devices.chunksize;
+                        let fft_size = phaserotation::fft_size(parameters, chunksize);
+                        let rotation_latency =
+                            phaserotation::latency(fft_size, chunksize) as PrcFmt;
+                        let mut channels = parameters.process_channels();
+                        if channels.is_empty() {
+                            channels = (0..parameters.channels).collect();
+                        }
+                        for channel in channels {
+                            if let Some(latency) = latencies.get_mut(channel) {
+                                *latency += rotation_latency;
+                            }
+                        }
+                    }
+                    Some(config::Processor::SubAlign { parameters, .. }) => {
+                        if let Some(latency) = latencies.get_mut(parameters.channel) {
+                            *latency += subalign::delay_samples(fs, parameters);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    let pipeline_latency = latencies.iter().fold(0.0, |max: PrcFmt, val| max.max(*val));
+    Ok(pipeline_latency + audiodevice::resampler_delay(&conf.devices) as PrcFmt)
+}
+
 /// Validate the filter config, to give a helpful message intead of a panic.
 pub fn validate_filter(fs: usize, filter_config: &config::Filter) -> Res<()> {
     match filter_config {
-        config::Filter::Conv { parameters, .. } => fftconv::validate_config(parameters),
+        config::Filter::Conv { parameters, .. } => {
+            if !cfg!(feature = "FFTW") && parameters.engine() == config::ConvEngine::Fftw {
+                return Err(config::ConfigError::new(
+                    "The Fftw convolution engine is not available, CamillaDSP was built without the FFTW feature",
+                )
+                .into());
+            }
+            fftconv::validate_config(parameters)
+        }
         config::Filter::Biquad { parameters, .. } => biquad::validate_config(fs, parameters),
         config::Filter::Delay { parameters, .. } => basicfilters::validate_delay_config(parameters),
         config::Filter::Gain { parameters, .. } => basicfilters::validate_gain_config(parameters),
@@ -621,15 +1221,25 @@ pub fn validate_filter(fs: usize, filter_config: &config::Filter) -> Res<()> {
             biquadcombo::validate_config(fs, parameters)
         }
         config::Filter::Limiter { parameters, .. } => limiter::validate_config(parameters),
+        config::Filter::Polarity { .. } => Ok(()),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::basicfilters::{Gain, Polarity};
+    use crate::config;
     use crate::config::FileFormat;
-    use crate::filters::{find_data_in_wav, read_wav};
+    use crate::filters::{
+        complex_gain, frequency_response, pipeline_latency, ClippingMonitor, FilterGroup, Pipeline,
+    };
+    use crate::filters::{find_data_in_wav, read_wav, validate_wav_params, validate_wav_sources};
     use crate::filters::{pad_vector, read_coeff_file};
     use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::collections::HashMap;
+    use std::sync::Arc;
 
     fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
         println!("{} - {} = {}", left, right, left - right);
@@ -782,6 +1392,65 @@ mod tests {
         assert_eq!(info.channels, 1);
     }
 
+    #[test]
+    pub fn test_analyze_wav_with_extra_chunks() {
+        // Has LIST, fact, cue and junk chunks, some with odd length, before the data chunk,
+        // and a LIST chunk after it.
+        let info = find_data_in_wav("testdata/int32_chunks.wav").unwrap();
+        assert_eq!(info.sample_format, FileFormat::S32LE);
+        assert_eq!(info.sample_rate, 44100);
+        assert_eq!(info.data_offset, 130);
+        assert_eq!(info.data_length, 20);
+        assert_eq!(info.channels, 1);
+        let values = read_wav("testdata/int32_chunks.wav", 0).unwrap();
+        let expected: Vec<PrcFmt> = vec![-1.0, -0.5, 0.0, 0.5, 1.0];
+        assert!(compare_waveforms(&values, &expected, 1e-9));
+    }
+
+    #[test]
+    pub fn test_wav_sources() {
+        let params: config::ConvParametersWav = serde_yaml::from_str(
+            r#"
+sources:
+  - filename: testdata/float32_stereo.wav
+  - filename: testdata/float32_stereo.wav
+    channel: 1
+"#,
+        )
+        .unwrap();
+        assert!(validate_wav_sources(params.sources.as_ref().unwrap()).is_ok());
+        let selected = params.for_channel(1);
+        let values = read_wav(selected.filename(), selected.channel()).unwrap();
+        let expected: Vec<PrcFmt> = vec![0.0, 0.25, 0.5, 0.75, 1.0];
+        assert!(compare_waveforms(&values, &expected, 1e-9));
+
+        // Same length, but different samplerates
+        let mixed: Vec<config::ConvWavSource> = serde_yaml::from_str(
+            r#"
+- filename: testdata/float32_stereo.wav
+- filename: testdata/int32.wav
+"#,
+        )
+        .unwrap();
+        assert!(validate_wav_sources(&mixed).is_err());
+    }
+
+    #[test]
+    pub fn test_wav_filename_or_sources() {
+        let parse =
+            |yaml: &str| -> config::ConvParametersWav { serde_yaml::from_str(yaml).unwrap() };
+        let single = parse("filename: testdata/float32_stereo.wav");
+        assert!(validate_wav_params(&single).is_ok());
+        let sources = parse("sources: [{filename: testdata/float32_stereo.wav}]");
+        assert!(validate_wav_params(&sources).is_ok());
+        let both = parse(
+            "{filename: testdata/float32_stereo.wav, sources: [{filename: testdata/float32_stereo.wav}]}",
+        );
+        assert!(validate_wav_params(&both).is_err());
+        let neither = parse("channel: 0");
+        assert!(validate_wav_params(&neither).is_err());
+    }
+
     #[test]
     pub fn test_read_wav() {
         let values = read_wav("testdata/int32.wav", 0).unwrap();
@@ -791,4 +1460,379 @@ mod tests {
         let bad = read_wav("testdata/int32.wav", 1);
         assert!(bad.is_err());
     }
+
+    #[test]
+    fn lr4_crossover_sums_flat() {
+        let lowpass = config::Filter::BiquadCombo {
+            description: None,
+            parameters: config::BiquadComboParameters::LinkwitzRileyLowpass {
+                freq: 1000.0,
+                order: 4,
+            },
+        };
+        let highpass = config::Filter::BiquadCombo {
+            description: None,
+            parameters: config::BiquadComboParameters::LinkwitzRileyHighpass {
+                freq: 1000.0,
+                order: 4,
+            },
+        };
+        for freq in [100.0, 1000.0, 5000.0] {
+            let sum = complex_gain(44100, &lowpass, freq).unwrap()
+                + complex_gain(44100, &highpass, freq).unwrap();
+            assert!(is_close(sum.norm(), 1.0, 1.0e-3));
+        }
+    }
+
+    #[test]
+    fn conv_frequency_response() {
+        // Moving average of two samples, a zero at half the sample rate
+        let fir = config::Filter::Conv {
+            description: None,
+            parameters: config::ConvParameters::Values {
+                values: vec![0.5, 0.5],
+                mode: None,
+                engine: None,
+            },
+        };
+        let response = frequency_response(48000, &fir, &[0.0, 12000.0, 24000.0]).unwrap();
+        assert!(is_close(response[0].norm(), 1.0, 1.0e-6));
+        assert!(is_close(
+            response[1].norm(),
+            0.5_f64.sqrt() as PrcFmt,
+            1.0e-6
+        ));
+        assert!(is_close(response[1].arg().to_degrees(), -45.0, 1.0e-3));
+        assert!(is_close(response[2].norm(), 0.0, 1.0e-6));
+    }
+
+    #[test]
+    fn check_pipeline_latency() {
+        let conf_yaml = r#"
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  fir:
+    type: Conv
+    parameters:
+      type: Values
+      values: [1.0, 0.0, 0.0, 0.0]
+  delay:
+    type: Delay
+    parameters:
+      delay: 1.0
+      unit: ms
+  eq:
+    type: Biquad
+    parameters:
+      type: Lowpass
+      freq: 1000.0
+      q: 0.7
+mixers:
+  swap:
+    channels:
+      in: 2
+      out: 2
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 1
+      - dest: 1
+        sources:
+          - channel: 0
+pipeline:
+  - type: Filter
+    channel: 0
+    names: [fir, eq]
+  - type: Mixer
+    name: swap
+  - type: Filter
+    channel: 0
+    names: [delay]
+"#;
+        let mut conf: config::Configuration = serde_yaml::from_str(conf_yaml).unwrap();
+        // The FIR ends up on channel 1 after the mixer, and the delay is added to channel 0
+        assert!(is_close(pipeline_latency(&conf).unwrap(), 48.0, 1e-3));
+        // A SubAlign delaying channel 1 by 2 ms makes it the channel with the largest latency
+        let sub_align: config::Processor = serde_yaml::from_str(
+            r#"
+type: SubAlign
+parameters:
+  channels: 2
+  channel: 1
+  freq: 80.0
+  delay_ms: 2.0
+"#,
+        )
+        .unwrap();
+        conf.processors = Some(HashMap::from([("sub".to_string(), sub_align)]));
+        conf.pipeline
+            .as_mut()
+            .unwrap()
+            .push(serde_yaml::from_str("{type: Processor, name: sub}").unwrap());
+        assert!(is_close(pipeline_latency(&conf).unwrap(), 100.0, 1e-3));
+    }
+
+    #[test]
+    fn bypass_step_without_rebuild() {
+        let conf_yaml = r#"
+devices:
+  samplerate: 48000
+  chunksize: 4
+  capture:
+    type: Stdin
+    channels: 1
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 1
+    format: S16LE
+filters:
+  cut:
+    type: Gain
+    parameters:
+      gain: -6.0
+pipeline:
+  - type: Filter
+    channel: 0
+    names: [cut]
+  - type: Filter
+    channel: 0
+    names: [cut]
+    bypassed: true
+"#;
+        let mut conf: config::Configuration = serde_yaml::from_str(conf_yaml).unwrap();
+        let params = Arc::new(ProcessingParameters::default());
+        let mut pipeline = Pipeline::from_config(conf.clone(), params);
+        let gain = (10.0 as PrcFmt).powf(-6.0 / 20.0);
+        let output_level = |pipeline: &mut Pipeline| {
+            let chunk = AudioChunk::new(vec![vec![1.0; 4]], 1.0, 1.0, 4, 4);
+            pipeline.process_chunk(chunk).waveforms[0][3]
+        };
+        assert!(is_close(output_level(&mut pipeline), gain, 1e-6));
+
+        // Bypassing the built step is done live
+        conf.pipeline.as_mut().unwrap()[0].set_bypassed(true);
+        assert!(pipeline.update_bypass(&conf));
+        assert!(is_close(output_level(&mut pipeline), 1.0, 1e-6));
+        conf.pipeline.as_mut().unwrap()[0].set_bypassed(false);
+        assert!(pipeline.update_bypass(&conf));
+        assert!(is_close(output_level(&mut pipeline), gain, 1e-6));
+
+        // The second step was never built and needs a rebuild
+        conf.pipeline.as_mut().unwrap()[1].set_bypassed(false);
+        assert!(!pipeline.update_bypass(&conf));
+    }
+
+    #[test]
+    fn bypass_keeps_level_match_gain() {
+        let conf_yaml = r#"
+devices:
+  samplerate: 48000
+  chunksize: 4
+  capture:
+    type: Stdin
+    channels: 1
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 1
+    format: S16LE
+filters:
+  cut:
+    type: Gain
+    parameters:
+      gain: -6.0
+pipeline:
+  - type: Filter
+    channel: 0
+    names: [cut]
+    bypassed: true
+    level_match_on_bypass: true
+    bypass_gain: -6.0
+  - type: Filter
+    channel: 0
+    names: [cut]
+"#;
+        let mut conf: config::Configuration = serde_yaml::from_str(conf_yaml).unwrap();
+        let params = Arc::new(ProcessingParameters::default());
+        let mut pipeline = Pipeline::from_config(conf.clone(), params);
+        let gain = (10.0 as PrcFmt).powf(-6.0 / 20.0);
+        let output_level = |pipeline: &mut Pipeline| {
+            let chunk = AudioChunk::new(vec![vec![1.0; 4]], 1.0, 1.0, 4, 4);
+            pipeline.process_chunk(chunk).waveforms[0][3]
+        };
+        assert!(is_close(output_level(&mut pipeline), gain * gain, 1e-6));
+
+        // Toggling the other step leaves the level matching gain active
+        conf.pipeline.as_mut().unwrap()[1].set_bypassed(true);
+        assert!(pipeline.update_bypass(&conf));
+        assert!(is_close(output_level(&mut pipeline), gain, 1e-6));
+
+        // Enabling the replaced step, or bypassing another one with level matching, needs a rebuild
+        conf.pipeline.as_mut().unwrap()[0].set_bypassed(false);
+        assert!(!pipeline.update_bypass(&conf));
+        let mut pipeline =
+            Pipeline::from_config(conf.clone(), Arc::new(ProcessingParameters::default()));
+        conf.pipeline.as_mut().unwrap()[0].set_bypassed(true);
+        assert!(!pipeline.update_bypass(&conf));
+    }
+
+    #[test]
+    fn clipping_warning_threshold() {
+        let mut monitor = ClippingMonitor::new(vec!["step".to_string(); 2]);
+        // -0.6 dBFS is below the threshold
+        monitor.check(0, &[vec![0.1, -0.93], vec![0.5; 4]]);
+        assert!(monitor.last_warning[0].is_none());
+        // -0.4 dBFS on any channel triggers it, for this step only
+        monitor.check(0, &[vec![0.1, -0.955], vec![0.5; 4]]);
+        assert!(monitor.last_warning[0].is_some());
+        assert!(monitor.last_warning[1].is_none());
+        // Further warnings for the same step are rate limited
+        let first_warning = monitor.last_warning[0];
+        monitor.check(0, &[vec![1.5; 4]]);
+        assert_eq!(monitor.last_warning[0], first_warning);
+    }
+
+    #[test]
+    fn clipping_warning_in_pipeline() {
+        let conf_yaml = r#"
+devices:
+  samplerate: 48000
+  chunksize: 4
+  warn_internal_clipping: true
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  boost:
+    type: Gain
+    parameters:
+      gain: 6.0
+  cut:
+    type: Gain
+    parameters:
+      gain: -6.0
+pipeline:
+  - type: Filter
+    channel: 1
+    names: [boost]
+  - type: Filter
+    channel: 0
+    names: [cut]
+"#;
+        let mut conf: config::Configuration = serde_yaml::from_str(conf_yaml).unwrap();
+        let params = Arc::new(ProcessingParameters::default());
+        let mut pipeline = Pipeline::from_config(conf.clone(), params.clone());
+        let chunk = AudioChunk::new(vec![vec![0.6; 4], vec![0.6; 4]], 0.6, 0.6, 4, 4);
+        pipeline.process_chunk(chunk);
+        // Filter steps are checked on their own channel only,
+        // the cut step doesn't warn for the boosted channel 1.
+        let monitor = pipeline.clipping_monitor.as_ref().unwrap();
+        assert!(monitor.last_warning[0].is_some());
+        assert!(monitor.last_warning[1].is_none());
+
+        // No monitor unless enabled
+        conf.devices.warn_internal_clipping = None;
+        let pipeline = Pipeline::from_config(conf, params);
+        assert!(pipeline.clipping_monitor.is_none());
+    }
+
+    #[test]
+    fn loudness_channel_gains() {
+        let conf_yaml = r#"
+devices:
+  samplerate: 48000
+  chunksize: 4
+  capture:
+    type: Stdin
+    channels: 3
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 3
+    format: S16LE
+filters:
+  loudness:
+    type: Loudness
+    parameters:
+      reference_level: -20.0
+      channel_gains: [-6.0, 0.0, 3.0]
+pipeline:
+  - type: Filter
+    channel: 2
+    names: [loudness]
+  - type: Filter
+    channel: 0
+    names: [loudness]
+  - type: Filter
+    channel: 1
+    names: [loudness]
+"#;
+        let mut conf: config::Configuration = serde_yaml::from_str(conf_yaml).unwrap();
+        let params = Arc::new(ProcessingParameters::default());
+        // At 0 dB volume the loudness correction is inactive, only the channel gains remain
+        let mut pipeline = Pipeline::from_config(conf.clone(), params);
+        let output_levels = |pipeline: &mut Pipeline| {
+            let chunk = AudioChunk::new(vec![vec![1.0; 4]; 3], 1.0, 1.0, 4, 4);
+            let output = pipeline.process_chunk(chunk);
+            output
+                .waveforms
+                .iter()
+                .map(|wf| 20.0 * wf[3].log10())
+                .collect::<Vec<PrcFmt>>()
+        };
+        let levels = output_levels(&mut pipeline);
+        assert!(compare_waveforms(&levels, &[-6.0, 0.0, 3.0], 1e-4));
+
+        // Updated gains are applied to the same channels
+        if let Some(config::Filter::Loudness { parameters, .. }) =
+            conf.filters.as_mut().unwrap().get_mut("loudness")
+        {
+            parameters.channel_gains = Some(vec![0.0, 2.0, -4.0]);
+        }
+        pipeline.update_parameters(conf, &["loudness".to_string()], &[], &[]);
+        let levels = output_levels(&mut pipeline);
+        assert!(compare_waveforms(&levels, &[0.0, 2.0, -4.0], 1e-4));
+    }
+
+    #[test]
+    fn filter_step_mix() {
+        let mut group = FilterGroup {
+            channel: 1,
+            filters: vec![Box::new(Gain::new("gain", 20.0, false, false, false))],
+            mix: 0.25,
+            dry: Vec::new(),
+        };
+        let mut chunk = AudioChunk::new(vec![vec![1.0; 4], vec![1.0; 4]], 1.0, -1.0, 4, 4);
+        group.process_chunk(&mut chunk).unwrap();
+        assert_eq!(chunk.waveforms[0], vec![1.0; 4]);
+        for value in chunk.waveforms[1].iter() {
+            assert!(is_close(*value, 0.25 * 10.0 + 0.75, 1e-6));
+        }
+        // An equal mix of a signal and its inverse cancels out
+        let mut group = FilterGroup {
+            channel: 0,
+            filters: vec![Box::new(Polarity::new("invert"))],
+            mix: 0.5,
+            dry: Vec::new(),
+        };
+        let mut chunk = AudioChunk::new(vec![vec![0.5, -0.3, 0.1]], 1.0, -1.0, 3, 3);
+        group.process_chunk(&mut chunk).unwrap();
+        assert_eq!(chunk.waveforms[0], vec![0.0; 3]);
+    }
 }
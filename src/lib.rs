@@ -12,6 +12,8 @@ extern crate lazy_static;
 #[cfg(target_os = "macos")]
 extern crate coreaudio;
 extern crate crossbeam_channel;
+#[cfg(feature = "websocket")]
+extern crate data_encoding;
 #[cfg(target_os = "macos")]
 extern crate dispatch;
 #[cfg(feature = "pulse-backend")]
@@ -27,12 +29,13 @@ extern crate num_traits;
 extern crate rand;
 extern crate rand_distr;
 extern crate rawsample;
-#[cfg(not(feature = "FFTW"))]
 extern crate realfft;
 extern crate rubato;
 extern crate serde;
 extern crate serde_with;
 extern crate signal_hook;
+#[cfg(feature = "toml-config")]
+extern crate toml;
 #[cfg(feature = "websocket")]
 extern crate tungstenite;
 #[cfg(target_os = "windows")]
@@ -45,12 +48,14 @@ extern crate log;
 
 use parking_lot::{Mutex, RwLock};
 use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
 use std::error;
 use std::fmt;
 use std::sync::{
     atomic::{AtomicBool, AtomicU32, Ordering},
     Arc,
 };
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 // Sample format
 #[cfg(feature = "32bit")]
@@ -80,6 +85,7 @@ pub mod audiodevice;
 pub mod basicfilters;
 pub mod biquad;
 pub mod biquadcombo;
+pub mod bitcrusher;
 pub mod compressor;
 pub mod config;
 pub mod conversions;
@@ -88,16 +94,16 @@ pub mod coreaudiodevice;
 pub mod countertimer;
 #[cfg(feature = "cpal-backend")]
 pub mod cpaldevice;
+pub mod crossfeed;
 pub mod diffeq;
 pub mod dither;
-#[cfg(not(feature = "FFTW"))]
+pub mod dynamiceq;
 pub mod fftconv;
 #[cfg(feature = "FFTW")]
 pub mod fftconv_fftw;
 pub mod filedevice;
 #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
 pub mod filedevice_bluez;
-#[cfg(not(target_os = "linux"))]
 pub mod filereader;
 #[cfg(target_os = "linux")]
 pub mod filereader_nonblock;
@@ -106,14 +112,17 @@ pub mod helpers;
 pub mod limiter;
 pub mod loudness;
 pub mod mixer;
+pub mod phaserotation;
 pub mod processing;
 #[cfg(feature = "pulse-backend")]
 pub mod pulsedevice;
 #[cfg(feature = "websocket")]
 pub mod socketserver;
 pub mod statefile;
+pub mod subalign;
 #[cfg(target_os = "windows")]
 pub mod wasapidevice;
+pub mod wavwriter;
 
 pub enum StatusMessage {
     PlaybackReady,
@@ -125,6 +134,8 @@ pub enum StatusMessage {
     PlaybackDone,
     CaptureDone,
     SetSpeed(f64),
+    PlaybackUnderrun,
+    CaptureOverrun,
 }
 
 pub enum CommandMessage {
@@ -141,6 +152,8 @@ pub enum ExitState {
 pub enum ControllerMessage {
     // Config must be boxed, to prevent "large size difference between variants" warning
     ConfigChanged(Box<config::Configuration>),
+    // Re-read the coefficients of a filter of the active config
+    ReloadFilter(String),
     Stop,
     Exit,
 }
@@ -166,9 +179,15 @@ pub struct CaptureStatus {
     pub signal_range: f32,
     pub signal_rms: countertimer::ValueHistory,
     pub signal_peak: countertimer::ValueHistory,
+    pub true_peak: countertimer::TruePeakRecord,
+    pub signal_minmax: audiodevice::SignalMinMax,
+    pub recent_samples: audiodevice::RecentSamples,
+    pub signal_duty: countertimer::DutyCycle,
+    pub frames_captured: u64,
     pub state: ProcessingState,
     pub rate_adjust: f32,
     pub used_channels: Vec<bool>,
+    pub meter_ballistics: Option<config::MeterBallistics>,
 }
 
 #[derive(Clone, Debug)]
@@ -178,6 +197,66 @@ pub struct PlaybackStatus {
     pub buffer_level: usize,
     pub signal_rms: countertimer::ValueHistory,
     pub signal_peak: countertimer::ValueHistory,
+    pub true_peak: countertimer::TruePeakRecord,
+    pub signal_minmax: audiodevice::SignalMinMax,
+    pub frames_played: u64,
+    pub meter_ballistics: Option<config::MeterBallistics>,
+}
+
+/// A request for playing calibration noise on one playback channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationNoiseRequest {
+    pub channel: usize,
+    pub level: f32,
+    pub duration: f32,
+}
+
+/// A recording of a number of frames of the processed output, stored as interleaved samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputSnapshot {
+    pub frames: usize,
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+impl OutputSnapshot {
+    pub fn new(frames: usize) -> Self {
+        OutputSnapshot {
+            frames,
+            channels: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn frames_recorded(&self) -> usize {
+        if self.channels == 0 {
+            return 0;
+        }
+        self.samples.len() / self.channels
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.channels > 0 && self.frames_recorded() >= self.frames
+    }
+
+    /// Append frames from a processed chunk, until the wanted number of frames has been recorded.
+    pub fn record(&mut self, chunk: &audiodevice::AudioChunk) {
+        let channels = chunk.waveforms.len();
+        if self.channels == 0 {
+            self.channels = channels;
+            self.samples.reserve(self.frames * self.channels);
+        }
+        if self.is_complete() || channels != self.channels {
+            return;
+        }
+        let nbr_frames = chunk.valid_frames.min(self.frames - self.frames_recorded());
+        for frame in 0..nbr_frames {
+            for waveform in chunk.waveforms.iter() {
+                let value = waveform.get(frame).copied().unwrap_or_default();
+                self.samples.push(value as f32);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -189,6 +268,14 @@ pub struct ProcessingParameters {
     current_volume: [AtomicU32; Self::NUM_FADERS],
     mute: [AtomicBool; Self::NUM_FADERS],
     processing_load: AtomicU32,
+    bypass_all: AtomicBool,
+    start_mute: AtomicBool,
+    agc_reduction: AtomicU32,
+    calibration_noise: Mutex<Option<processing::CalibrationNoise>>,
+    calibration_noise_pending: AtomicBool,
+    output_snapshot: Mutex<Option<OutputSnapshot>>,
+    output_snapshot_recording: AtomicBool,
+    fader_presets: Mutex<BTreeMap<String, statefile::FaderPreset>>,
 }
 
 impl ProcessingParameters {
@@ -221,6 +308,14 @@ impl ProcessingParameters {
                 AtomicBool::new(initial_mutes[4]),
             ],
             processing_load: AtomicU32::new(0.0f32.to_bits()),
+            bypass_all: AtomicBool::new(false),
+            start_mute: AtomicBool::new(false),
+            agc_reduction: AtomicU32::new(0.0f32.to_bits()),
+            calibration_noise: Mutex::new(None),
+            calibration_noise_pending: AtomicBool::new(false),
+            output_snapshot: Mutex::new(None),
+            output_snapshot_recording: AtomicBool::new(false),
+            fader_presets: Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -244,7 +339,11 @@ impl ProcessingParameters {
         self.mute[fader].load(Ordering::Relaxed)
     }
 
+    /// Set the mute of a fader. Unmuting the main fader also clears the start mute.
     pub fn set_mute(&self, fader: usize, mute: bool) {
+        if fader == 0 && !mute {
+            self.set_start_mute(false);
+        }
         self.mute[fader].store(mute, Ordering::Relaxed)
     }
 
@@ -252,6 +351,41 @@ impl ProcessingParameters {
         self.mute[fader].fetch_xor(true, Ordering::Relaxed)
     }
 
+    /// The mute of the main fader that is held while processing is starting.
+    /// It is kept apart from the mute set by the user, and the main fader is muted if either is set.
+    pub fn is_start_muted(&self) -> bool {
+        self.start_mute.load(Ordering::Relaxed)
+    }
+
+    pub fn set_start_mute(&self, mute: bool) {
+        self.start_mute.store(mute, Ordering::Relaxed)
+    }
+
+    /// Check if the output of a fader is muted, by the user or by the start mute.
+    pub fn is_output_muted(&self, fader: usize) -> bool {
+        self.is_mute(fader) || (fader == 0 && self.is_start_muted())
+    }
+
+    /// The reduction in dB of the main volume made by the clip AGC.
+    /// It is kept apart from the volume set by the user, and subtracted from it when the volume is applied.
+    pub fn agc_reduction(&self) -> f32 {
+        f32::from_bits(self.agc_reduction.load(Ordering::Relaxed))
+    }
+
+    pub fn set_agc_reduction(&self, reduction: f32) {
+        self.agc_reduction
+            .store(reduction.to_bits(), Ordering::Relaxed)
+    }
+
+    /// Get the volume to apply for a fader, the target volume minus any clip AGC reduction of the main fader.
+    pub fn output_volume(&self, fader: usize) -> f32 {
+        if fader == 0 {
+            self.target_volume(fader) - self.agc_reduction()
+        } else {
+            self.target_volume(fader)
+        }
+    }
+
     pub fn volumes(&self) -> [f32; Self::NUM_FADERS] {
         [
             f32::from_bits(self.target_volume[0].load(Ordering::Relaxed)),
@@ -280,6 +414,116 @@ impl ProcessingParameters {
     pub fn processing_load(&self) -> f32 {
         f32::from_bits(self.processing_load.load(Ordering::Relaxed))
     }
+
+    pub fn bypass_all(&self) -> bool {
+        self.bypass_all.load(Ordering::Relaxed)
+    }
+
+    pub fn set_bypass_all(&self, bypass: bool) {
+        self.bypass_all.store(bypass, Ordering::Relaxed)
+    }
+
+    /// Hand over calibration noise to the processing thread, replacing any noise that has not started yet.
+    pub fn request_calibration_noise(&self, noise: processing::CalibrationNoise) {
+        *self.calibration_noise.lock() = Some(noise);
+        self.calibration_noise_pending
+            .store(true, Ordering::Release);
+    }
+
+    /// Take the requested calibration noise, if any.
+    /// This is called for every chunk, and the lock is only taken when there is a request.
+    pub fn take_calibration_noise(&self) -> Option<processing::CalibrationNoise> {
+        if !self
+            .calibration_noise_pending
+            .swap(false, Ordering::Acquire)
+        {
+            return None;
+        }
+        self.calibration_noise.lock().take()
+    }
+
+    /// Start recording a snapshot of the output.
+    /// Returns false if there already is a snapshot in progress.
+    pub fn start_output_snapshot(&self, frames: usize) -> bool {
+        let mut snapshot = self.output_snapshot.lock();
+        if snapshot.is_some() {
+            return false;
+        }
+        *snapshot = Some(OutputSnapshot::new(frames));
+        self.output_snapshot_recording
+            .store(true, Ordering::Release);
+        true
+    }
+
+    /// Record a processed chunk into the snapshot in progress, if any.
+    /// This is called for every chunk, and the lock is only taken while a snapshot is being recorded.
+    pub fn record_output_snapshot(&self, chunk: &audiodevice::AudioChunk) {
+        if !self.output_snapshot_recording.load(Ordering::Acquire) {
+            return;
+        }
+        let mut snapshot = self.output_snapshot.lock();
+        match snapshot.as_mut() {
+            Some(snap) => {
+                snap.record(chunk);
+                if snap.is_complete() {
+                    self.output_snapshot_recording
+                        .store(false, Ordering::Release);
+                }
+            }
+            None => self
+                .output_snapshot_recording
+                .store(false, Ordering::Release),
+        }
+    }
+
+    /// Take the snapshot if it is complete.
+    pub fn take_output_snapshot(&self) -> Option<OutputSnapshot> {
+        let mut snapshot = self.output_snapshot.lock();
+        if snapshot.as_ref().map_or(false, |snap| snap.is_complete()) {
+            return snapshot.take();
+        }
+        None
+    }
+
+    pub fn cancel_output_snapshot(&self) {
+        *self.output_snapshot.lock() = None;
+        self.output_snapshot_recording
+            .store(false, Ordering::Release);
+    }
+
+    pub fn fader_presets(&self) -> BTreeMap<String, statefile::FaderPreset> {
+        self.fader_presets.lock().clone()
+    }
+
+    pub fn set_fader_presets(&self, presets: BTreeMap<String, statefile::FaderPreset>) {
+        *self.fader_presets.lock() = presets;
+    }
+
+    /// Store the volume and mute settings of all faders under a name,
+    /// replacing any previous preset with the same name.
+    pub fn save_fader_preset(&self, name: &str) {
+        let preset = statefile::FaderPreset {
+            mute: self.mutes(),
+            volume: self.volumes(),
+        };
+        self.fader_presets.lock().insert(name.to_string(), preset);
+    }
+
+    /// Apply a stored preset to all faders.
+    /// Only the target volumes are set, so the volume filters ramp to the new values.
+    /// Returns false if there is no preset with the given name.
+    pub fn recall_fader_preset(&self, name: &str) -> bool {
+        let presets = self.fader_presets.lock();
+        let preset = match presets.get(name) {
+            Some(preset) => preset,
+            None => return false,
+        };
+        for fader in 0..Self::NUM_FADERS {
+            self.set_target_volume(fader, preset.volume[fader]);
+            self.set_mute(fader, preset.mute[fader]);
+        }
+        true
+    }
 }
 
 impl Default for ProcessingParameters {
@@ -306,6 +550,39 @@ impl Default for ProcessingParameters {
 #[derive(Clone, Debug)]
 pub struct ProcessingStatus {
     pub stop_reason: StopReason,
+    pub started: Option<Instant>,
+    pub recent_errors: RecentErrors,
+}
+
+/// An error or warning event, with the time given as seconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ErrorEvent {
+    pub time: f64,
+    pub message: String,
+}
+
+/// A ring buffer keeping the most recent error and warning events, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct RecentErrors {
+    events: VecDeque<ErrorEvent>,
+}
+
+impl RecentErrors {
+    pub const CAPACITY: usize = 50;
+
+    pub fn push(&mut self, message: String) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |duration| duration.as_secs_f64());
+        if self.events.len() >= Self::CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(ErrorEvent { time, message });
+    }
+
+    pub fn events(&self) -> Vec<ErrorEvent> {
+        self.events.iter().cloned().collect()
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize)]
@@ -389,3 +666,40 @@ pub fn list_available_devices(backend: &str, input: bool) -> Vec<(String, String
         _ => Vec::new(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::{OutputSnapshot, ProcessingParameters};
+
+    #[test]
+    fn output_snapshot_stops_when_full() {
+        let chunk = AudioChunk::new(vec![vec![1.0; 4], vec![2.0; 4]], 2.0, 1.0, 4, 3);
+        let mut snapshot = OutputSnapshot::new(5);
+        snapshot.record(&chunk);
+        assert_eq!(snapshot.frames_recorded(), 3);
+        assert!(!snapshot.is_complete());
+        snapshot.record(&chunk);
+        assert_eq!(snapshot.frames_recorded(), 5);
+        assert!(snapshot.is_complete());
+        snapshot.record(&chunk);
+        assert_eq!(snapshot.frames_recorded(), 5);
+        assert_eq!(snapshot.samples, vec![1.0, 2.0].repeat(5));
+    }
+
+    #[test]
+    fn output_snapshot_recording() {
+        let params = ProcessingParameters::default();
+        let chunk = AudioChunk::new(vec![vec![1.0; 4]], 1.0, 1.0, 4, 4);
+        params.record_output_snapshot(&chunk);
+        assert!(params.take_output_snapshot().is_none());
+        assert!(params.start_output_snapshot(6));
+        assert!(!params.start_output_snapshot(6));
+        params.record_output_snapshot(&chunk);
+        assert!(params.take_output_snapshot().is_none());
+        params.record_output_snapshot(&chunk);
+        let snapshot = params.take_output_snapshot().unwrap();
+        assert_eq!(snapshot.frames_recorded(), 6);
+        assert!(params.start_output_snapshot(6));
+    }
+}
@@ -1,43 +1,153 @@
 use crate::config;
+use crate::config::LimiterCurve;
 use crate::filters::Filter;
 use crate::PrcFmt;
 use crate::Res;
+use std::collections::VecDeque;
 
 const CUBEFACTOR: PrcFmt = 1.0 / 6.75; // = 1 / (2 * 1.5^3)
+const ARCTANFACTOR: PrcFmt = 2.0 / std::f64::consts::PI as PrcFmt; // unity slope at zero, limit at 1
+
+// Longest allowed lookahead, since it adds to the latency.
+const MAX_LOOKAHEAD_MS: PrcFmt = 50.0;
+// Recalculate the sum of the gain window at this interval, to avoid accumulating rounding errors.
+const GAIN_SUM_RESET_INTERVAL: u64 = 65536;
+
+/// Gain reduction that starts before the peaks arrive, by delaying the signal.
+/// The gain needed at each sample is the smallest gain required by any sample in the next `length` samples.
+/// This is then smoothed with a moving average over the same number of samples,
+/// which gives a gradual gain change that still reaches the required gain at each peak.
+#[derive(Clone, Debug)]
+struct Lookahead {
+    length: usize,
+    delay: VecDeque<PrcFmt>,
+    // Candidates for the smallest required gain, as (sample index, gain), with increasing gain.
+    min_gains: VecDeque<(u64, PrcFmt)>,
+    gains: VecDeque<PrcFmt>,
+    gain_sum: PrcFmt,
+    index: u64,
+}
+
+impl Lookahead {
+    fn new(length: usize) -> Self {
+        Lookahead {
+            length,
+            delay: VecDeque::from(vec![0.0; length]),
+            min_gains: VecDeque::with_capacity(length + 1),
+            gains: VecDeque::from(vec![1.0; length + 1]),
+            gain_sum: (length + 1) as PrcFmt,
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, waveform: &mut [PrcFmt], clip_limit: PrcFmt) {
+        for value in waveform.iter_mut() {
+            let magnitude = value.abs();
+            let required = if magnitude > clip_limit {
+                clip_limit / magnitude
+            } else {
+                1.0
+            };
+            self.index += 1;
+            while matches!(self.min_gains.back(), Some((_, gain)) if *gain >= required) {
+                self.min_gains.pop_back();
+            }
+            self.min_gains.push_back((self.index, required));
+            while matches!(self.min_gains.front(), Some((index, _)) if index + (self.length as u64) < self.index)
+            {
+                self.min_gains.pop_front();
+            }
+            let min_gain = self.min_gains.front().map_or(1.0, |(_, gain)| *gain);
+            self.gains.push_back(min_gain);
+            self.gain_sum += min_gain - self.gains.pop_front().unwrap_or(1.0);
+            if self.index % GAIN_SUM_RESET_INTERVAL == 0 {
+                self.gain_sum = self.gains.iter().sum();
+            }
+            let gain = (self.gain_sum / (self.length + 1) as PrcFmt).min(1.0);
+            self.delay.push_back(*value);
+            *value = self.delay.pop_front().unwrap_or_default() * gain;
+        }
+    }
+}
+
+/// Get the lookahead in samples.
+pub fn lookahead_samples(samplerate: usize, config: &config::LimiterParameters) -> usize {
+    (config.lookahead_ms() * samplerate as PrcFmt / 1000.0).round() as usize
+}
 
 #[derive(Clone, Debug)]
 pub struct Limiter {
     pub name: String,
     pub soft_clip: bool,
     pub clip_limit: PrcFmt,
+    pub curve: LimiterCurve,
+    pub knee_start: PrcFmt,
+    pub samplerate: usize,
+    lookahead: Option<Lookahead>,
 }
 
 impl Limiter {
     /// Creates a Compressor from a config struct
-    pub fn from_config(name: &str, config: config::LimiterParameters) -> Self {
+    pub fn from_config(name: &str, config: config::LimiterParameters, samplerate: usize) -> Self {
         let clip_limit = (10.0 as PrcFmt).powf(config.clip_limit / 20.0);
+        let knee_start = (10.0 as PrcFmt).powf(-config.knee() / 20.0);
+        let lookahead_length = lookahead_samples(samplerate, &config);
 
         debug!(
-            "Creating limiter '{}', soft_clip: {}, clip_limit dB: {}, linear: {}",
+            "Creating limiter '{}', soft_clip: {}, curve: {:?}, clip_limit dB: {}, linear: {}, lookahead: {} samples",
             name,
             config.soft_clip(),
+            config.curve(),
             config.clip_limit,
-            clip_limit
+            clip_limit,
+            lookahead_length
         );
 
         Limiter {
             name: name.to_string(),
             soft_clip: config.soft_clip(),
             clip_limit,
+            curve: config.curve(),
+            knee_start,
+            samplerate,
+            lookahead: (lookahead_length > 0).then(|| Lookahead::new(lookahead_length)),
         }
     }
 
     fn apply_soft_clip(&self, input: &mut [PrcFmt]) {
-        for val in input.iter_mut() {
-            let mut scaled = *val / self.clip_limit;
-            scaled = scaled.clamp(-1.5, 1.5);
-            scaled -= CUBEFACTOR * scaled.powi(3);
-            *val = scaled * self.clip_limit;
+        match self.curve {
+            LimiterCurve::Cubic => {
+                for val in input.iter_mut() {
+                    let mut scaled = *val / self.clip_limit;
+                    scaled = scaled.clamp(-1.5, 1.5);
+                    scaled -= CUBEFACTOR * scaled.powi(3);
+                    *val = scaled * self.clip_limit;
+                }
+            }
+            LimiterCurve::Tanh => {
+                for val in input.iter_mut() {
+                    *val = (*val / self.clip_limit).tanh() * self.clip_limit;
+                }
+            }
+            LimiterCurve::Arctan => {
+                for val in input.iter_mut() {
+                    let scaled = *val / self.clip_limit;
+                    *val = ARCTANFACTOR * (scaled / ARCTANFACTOR).atan() * self.clip_limit;
+                }
+            }
+            LimiterCurve::Knee => {
+                // Linear below the knee, then a tanh curve that approaches the clip limit.
+                let start = self.knee_start;
+                let width = 1.0 - start;
+                for val in input.iter_mut() {
+                    let scaled = *val / self.clip_limit;
+                    let magnitude = scaled.abs();
+                    if magnitude > start {
+                        let clipped = start + width * ((magnitude - start) / width).tanh();
+                        *val = clipped.copysign(scaled) * self.clip_limit;
+                    }
+                }
+            }
         }
     }
 
@@ -63,6 +173,9 @@ impl Filter for Limiter {
 
     /// Apply a Compressor to an AudioChunk, modifying it in-place.
     fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        if let Some(lookahead) = &mut self.lookahead {
+            lookahead.process(waveform, self.clip_limit);
+        }
         self.apply_clip(waveform);
         Ok(())
     }
@@ -76,12 +189,26 @@ impl Filter for Limiter {
 
             self.soft_clip = config.soft_clip();
             self.clip_limit = clip_limit;
+            self.curve = config.curve();
+            self.knee_start = (10.0 as PrcFmt).powf(-config.knee() / 20.0);
+            let lookahead_length = lookahead_samples(self.samplerate, &config);
+            // Keep the delayed samples if the lookahead is unchanged
+            if self
+                .lookahead
+                .as_ref()
+                .map_or(0, |lookahead| lookahead.length)
+                != lookahead_length
+            {
+                self.lookahead = (lookahead_length > 0).then(|| Lookahead::new(lookahead_length));
+            }
             debug!(
-                "Updated limiter '{}', soft_clip: {}, clip_limit dB: {}, linear: {}",
+                "Updated limiter '{}', soft_clip: {}, curve: {:?}, clip_limit dB: {}, linear: {}, lookahead: {} samples",
                 self.name,
                 config.soft_clip(),
+                config.curve(),
                 config.clip_limit,
-                clip_limit
+                clip_limit,
+                lookahead_length
             );
         } else {
             // This should never happen unless there is a bug somewhere else
@@ -90,7 +217,149 @@ impl Filter for Limiter {
     }
 }
 
-/// Validate the limiter config, always return ok to allow any config.
-pub fn validate_config(_config: &config::LimiterParameters) -> Res<()> {
+/// Validate the limiter config.
+pub fn validate_config(config: &config::LimiterParameters) -> Res<()> {
+    if let Some(knee) = config.knee {
+        if knee <= 0.0 || knee > 40.0 {
+            let msg = format!("Limiter knee must be larger than 0 and at most 40 dB, got {knee}");
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    if let Some(lookahead) = config.lookahead_ms {
+        if !(0.0..=MAX_LOOKAHEAD_MS).contains(&lookahead) {
+            let msg = format!(
+                "Limiter lookahead must be between 0 and {MAX_LOOKAHEAD_MS} ms, got {lookahead}"
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{LimiterCurve, LimiterParameters};
+    use crate::filters::Filter;
+    use crate::limiter::{validate_config, Limiter};
+    use crate::PrcFmt;
+
+    fn params(lookahead_ms: Option<PrcFmt>) -> LimiterParameters {
+        LimiterParameters {
+            soft_clip: None,
+            clip_limit: -6.0,
+            curve: None,
+            knee: None,
+            lookahead_ms,
+        }
+    }
+
+    #[test]
+    fn lookahead_reduces_gain_before_peak() {
+        // 1 ms at 48 kHz gives a lookahead of 48 samples
+        let mut limiter = Limiter::from_config("test", params(Some(1.0)), 48000);
+        let limit = (10.0 as PrcFmt).powf(-6.0 / 20.0);
+        let mut waveform = vec![0.1; 256];
+        waveform[100] = 1.0;
+        let input = waveform.clone();
+        limiter.process_waveform(&mut waveform).unwrap();
+        // The signal is delayed by the lookahead
+        assert_eq!(waveform[..48], vec![0.0; 48]);
+        assert!((waveform[148] - limit).abs() < 1e-6);
+        // The gain is lowered gradually before the peak, and restored after
+        assert!(waveform[140] < input[92]);
+        assert!(waveform[147] < waveform[140]);
+        assert!((waveform[255] - input[207]).abs() < 1e-9);
+        // Nothing is clipped, the peak is reached via gain reduction
+        for (output, input) in waveform[48..].iter().zip(input.iter()) {
+            assert!(output.abs() <= limit + 1e-9);
+            assert!(output.abs() <= input.abs() + 1e-9);
+        }
+    }
+
+    fn soft_clip_limiter(curve: LimiterCurve) -> Limiter {
+        let conf = LimiterParameters {
+            soft_clip: Some(true),
+            clip_limit: -6.0,
+            curve: Some(curve),
+            knee: Some(6.0),
+            lookahead_ms: None,
+        };
+        Limiter::from_config("test", conf, 48000)
+    }
+
+    const CURVES: [LimiterCurve; 4] = [
+        LimiterCurve::Cubic,
+        LimiterCurve::Tanh,
+        LimiterCurve::Arctan,
+        LimiterCurve::Knee,
+    ];
+
+    #[test]
+    fn soft_clip_knee_start() {
+        for curve in CURVES {
+            let limiter = soft_clip_limiter(curve);
+            let limit = limiter.clip_limit;
+            // All curves have unity slope at zero, and leave quiet signals almost unchanged
+            let mut quiet = vec![0.01 * limit, -0.01 * limit];
+            limiter.apply_clip(&mut quiet);
+            assert!(
+                (quiet[0] - 0.01 * limit).abs() < 1e-4 * limit,
+                "{:?}",
+                curve
+            );
+            assert!(
+                (quiet[1] + 0.01 * limit).abs() < 1e-4 * limit,
+                "{:?}",
+                curve
+            );
+        }
+        // The knee curve is linear up to the knee, 6 dB below the limit
+        let limiter = soft_clip_limiter(LimiterCurve::Knee);
+        let start = limiter.knee_start * limiter.clip_limit;
+        let mut values = vec![0.5 * start, 0.999 * start, 1.1 * start];
+        limiter.apply_clip(&mut values);
+        assert_eq!(values[0], 0.5 * start);
+        assert_eq!(values[1], 0.999 * start);
+        assert!(values[2] < 1.1 * start && values[2] > start);
+    }
+
+    #[test]
+    fn soft_clip_limit() {
+        for curve in CURVES {
+            let limiter = soft_clip_limiter(curve);
+            let limit = limiter.clip_limit;
+            let mut loud = vec![100.0 * limit, -100.0 * limit];
+            limiter.apply_clip(&mut loud);
+            // The output approaches, but never goes above, the limit
+            assert!(loud[0] <= limit && loud[0] > 0.99 * limit, "{:?}", curve);
+            assert!(loud[1] >= -limit && loud[1] < -0.99 * limit, "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn soft_clip_monotonic() {
+        for curve in CURVES {
+            let limiter = soft_clip_limiter(curve);
+            let input: Vec<PrcFmt> = (-300..=300)
+                .map(|n| n as PrcFmt / 100.0 * limiter.clip_limit)
+                .collect();
+            let mut output = input.clone();
+            limiter.apply_clip(&mut output);
+            for pair in output.windows(2) {
+                assert!(pair[1] >= pair[0], "{:?}", curve);
+            }
+            // The curves are symmetric around zero
+            for (neg, pos) in output.iter().zip(output.iter().rev()) {
+                assert!((neg + pos).abs() < 1e-6, "{:?}", curve);
+            }
+        }
+    }
+
+    #[test]
+    fn check_lookahead_config() {
+        assert!(validate_config(&params(None)).is_ok());
+        assert!(validate_config(&params(Some(5.0))).is_ok());
+        assert!(validate_config(&params(Some(-1.0))).is_err());
+        assert!(validate_config(&params(Some(100.0))).is_err());
+    }
+}
@@ -19,6 +19,10 @@ pub struct Loudness {
     low_biquad: biquad::Biquad,
     fader: usize,
     active: bool,
+    normalize_output: bool,
+    attenuate_mid: bool,
+    channel: usize,
+    channel_gain: PrcFmt,
     gain: Option<Gain>,
 }
 
@@ -27,10 +31,43 @@ fn rel_boost(level: f32, reference: f32) -> f32 {
     rel_boost.clamp(0.0, 1.0)
 }
 
+/// Calculate the broadband gain that compensates for the boost of the shelving filters.
+/// The power gain of the shelves is averaged over third-octave bands from 20 Hz to 20 kHz,
+/// corresponding to the spectrum of pink noise.
+fn normalizing_gain(high_biquad: &biquad::Biquad, low_biquad: &biquad::Biquad) -> PrcFmt {
+    let nbr_bands = 31;
+    let power_sum: PrcFmt = (0..nbr_bands)
+        .map(|band| {
+            let freq = 20.0 * (2.0 as PrcFmt).powf(band as PrcFmt / 3.0);
+            (high_biquad.complex_gain(freq) * low_biquad.complex_gain(freq)).norm_sqr()
+        })
+        .sum();
+    -10.0 * (power_sum / nbr_bands as PrcFmt).log10()
+}
+
+fn gain_parameters(gain: PrcFmt) -> config::GainParameters {
+    config::GainParameters {
+        gain,
+        inverted: None,
+        mute: None,
+        scale: None,
+    }
+}
+
+/// Add the static gain offset of the channel to the compensation gain, if any.
+fn total_gain(compensation: Option<PrcFmt>, channel_gain: PrcFmt) -> Option<PrcFmt> {
+    match compensation {
+        Some(gain) => Some(gain + channel_gain),
+        None if channel_gain != 0.0 => Some(channel_gain),
+        None => None,
+    }
+}
+
 impl Loudness {
     pub fn from_config(
         name: &str,
         conf: config::LoudnessParameters,
+        channel: usize,
         samplerate: usize,
         processing_params: Arc<ProcessingParameters>,
     ) -> Self {
@@ -51,24 +88,22 @@ impl Loudness {
             slope: 12.0,
             gain: low_boost,
         });
-        let gain = if conf.attenuate_mid() {
-            let max_gain = low_boost.max(high_boost);
-            let gain_params = config::GainParameters {
-                gain: -max_gain,
-                inverted: None,
-                mute: None,
-                scale: None,
-            };
-            Some(Gain::from_config("midgain", gain_params))
-        } else {
-            None
-        };
-
         let high_biquad_coeffs =
             biquad::BiquadCoefficients::from_config(samplerate, highshelf_conf);
         let low_biquad_coeffs = biquad::BiquadCoefficients::from_config(samplerate, lowshelf_conf);
         let high_biquad = biquad::Biquad::new("highshelf", samplerate, high_biquad_coeffs);
         let low_biquad = biquad::Biquad::new("lowshelf", samplerate, low_biquad_coeffs);
+
+        let compensation = if conf.normalize_output() {
+            Some(normalizing_gain(&high_biquad, &low_biquad))
+        } else if conf.attenuate_mid() {
+            Some(-low_boost.max(high_boost))
+        } else {
+            None
+        };
+        let channel_gain = conf.channel_gain(channel) as PrcFmt;
+        let gain = total_gain(compensation, channel_gain)
+            .map(|value| Gain::from_config("loudnessgain", gain_parameters(value)));
         Loudness {
             name: name.to_string(),
             current_volume: current_volume as PrcFmt,
@@ -80,9 +115,38 @@ impl Loudness {
             processing_params,
             fader,
             active,
+            normalize_output: conf.normalize_output(),
+            attenuate_mid: conf.attenuate_mid(),
+            channel,
+            channel_gain,
             gain,
         }
     }
+
+    /// Update the broadband gain from the current shelf boosts and the channel gain offset.
+    fn update_gain(&mut self, low_boost: PrcFmt, high_boost: PrcFmt) {
+        let compensation = if self.normalize_output {
+            Some(normalizing_gain(&self.high_biquad, &self.low_biquad))
+        } else if self.attenuate_mid {
+            Some(-low_boost.max(high_boost))
+        } else {
+            None
+        };
+        match total_gain(compensation, self.channel_gain) {
+            Some(value) => {
+                let gain_params = gain_parameters(value);
+                if let Some(gain) = &mut self.gain {
+                    gain.update_parameters(config::Filter::Gain {
+                        description: None,
+                        parameters: gain_params,
+                    });
+                } else {
+                    self.gain = Some(Gain::from_config("loudnessgain", gain_params))
+                }
+            }
+            None => self.gain = None,
+        }
+    }
 }
 
 impl Filter for Loudness {
@@ -123,27 +187,16 @@ impl Filter for Loudness {
                 parameters: lowshelf_conf,
                 description: None,
             });
-            if let Some(gain) = &mut self.gain {
-                let max_gain = low_boost.max(high_boost);
-                let gain_params = config::GainParameters {
-                    gain: -max_gain,
-                    inverted: None,
-                    mute: None,
-                    scale: None,
-                };
-                gain.update_parameters(config::Filter::Gain {
-                    description: None,
-                    parameters: gain_params,
-                });
-            }
+            self.update_gain(low_boost, high_boost);
         }
         if self.active {
             trace!("Applying loudness biquads");
             self.high_biquad.process_waveform(waveform).unwrap();
             self.low_biquad.process_waveform(waveform).unwrap();
-            if let Some(gain) = &mut self.gain {
-                gain.process_waveform(waveform).unwrap();
-            }
+        }
+        // The gain also carries the static channel offset, and is applied also when inactive
+        if let Some(gain) = &mut self.gain {
+            gain.process_waveform(waveform).unwrap();
         }
         Ok(())
     }
@@ -178,25 +231,10 @@ impl Filter for Loudness {
                 parameters: lowshelf_conf,
                 description: None,
             });
-            if conf.attenuate_mid() {
-                let max_gain = low_boost.max(high_boost);
-                let gain_params = config::GainParameters {
-                    gain: -max_gain,
-                    inverted: None,
-                    mute: None,
-                    scale: None,
-                };
-                if let Some(gain) = &mut self.gain {
-                    gain.update_parameters(config::Filter::Gain {
-                        description: None,
-                        parameters: gain_params,
-                    });
-                } else {
-                    self.gain = Some(Gain::from_config("midgain", gain_params))
-                }
-            } else {
-                self.gain = None
-            }
+            self.normalize_output = conf.normalize_output();
+            self.attenuate_mid = conf.attenuate_mid();
+            self.channel_gain = conf.channel_gain(self.channel) as PrcFmt;
+            self.update_gain(low_boost, high_boost);
 
             self.reference_level = conf.reference_level;
             self.high_boost = conf.high_boost();
@@ -222,6 +260,16 @@ pub fn validate_config(conf: &config::LoudnessParameters) -> Res<()> {
         return Err(config::ConfigError::new("High boost cannot be larger than 20").into());
     } else if conf.low_boost() > 20.0 {
         return Err(config::ConfigError::new("Low boost cannot be larger than 20").into());
+    } else if conf.attenuate_mid() && conf.normalize_output() {
+        return Err(config::ConfigError::new(
+            "Only one of attenuate_mid and normalize_output can be enabled",
+        )
+        .into());
+    }
+    if let Some(gains) = &conf.channel_gains {
+        if gains.iter().any(|gain| !gain.is_finite()) {
+            return Err(config::ConfigError::new("Channel gains must be finite numbers").into());
+        }
     }
     Ok(())
 }
@@ -9,6 +9,8 @@ pub struct Mixer {
     pub channels_in: usize,
     pub channels_out: usize,
     pub mapping: Vec<Vec<MixerSource>>,
+    // Gains before the latest parameter update, ramped from during the next chunk
+    previous_mapping: Option<Vec<Vec<MixerSource>>>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -29,63 +31,78 @@ fn calculate_gain(gain_value: PrcFmt, inverted: bool, linear: bool) -> PrcFmt {
     gain
 }
 
+/// Get the sources of each output channel.
+/// Muted sources are kept with zero gain, so that muting and unmuting is ramped like any other gain change.
+fn build_mapping(config: config::Mixer) -> Vec<Vec<MixerSource>> {
+    let mut mapping = vec![Vec::<MixerSource>::new(); config.channels.out];
+    for cfg_mapping in config.mapping {
+        let dest = cfg_mapping.dest;
+        let mapping_muted = cfg_mapping.is_mute();
+        for cfg_src in cfg_mapping.sources {
+            let gain = if mapping_muted || cfg_src.is_mute() {
+                0.0
+            } else {
+                let gain_value = cfg_src.gain();
+                let inverted = cfg_src.is_inverted();
+                let linear = cfg_src.scale() == config::GainScale::Linear;
+                calculate_gain(gain_value, inverted, linear)
+            };
+            let src = MixerSource {
+                channel: cfg_src.channel,
+                gain,
+            };
+            mapping[dest].push(src);
+        }
+    }
+    mapping
+}
+
+/// Check if two mappings use the same sources for every output, so that only the gains differ.
+fn same_routing(left: &[Vec<MixerSource>], right: &[Vec<MixerSource>]) -> bool {
+    left.len() == right.len()
+        && left.iter().zip(right.iter()).all(|(left, right)| {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .zip(right.iter())
+                    .all(|(left, right)| left.channel == right.channel)
+        })
+}
+
 impl Mixer {
     /// Creates a Mixer from a config struct
     pub fn from_config(name: String, config: config::Mixer) -> Self {
         let ch_in = config.channels.r#in;
         let ch_out = config.channels.out;
-        let mut mapping = vec![Vec::<MixerSource>::new(); ch_out];
-        for cfg_mapping in config.mapping {
-            if !cfg_mapping.is_mute() {
-                let dest = cfg_mapping.dest;
-                for cfg_src in cfg_mapping.sources {
-                    if !cfg_src.is_mute() {
-                        let gain_value = cfg_src.gain();
-                        let inverted = cfg_src.is_inverted();
-                        let linear = cfg_src.scale() == config::GainScale::Linear;
-                        let gain = calculate_gain(gain_value, inverted, linear);
-                        let src = MixerSource {
-                            channel: cfg_src.channel,
-                            gain,
-                        };
-                        mapping[dest].push(src);
-                    }
-                }
-            }
-        }
+        let mapping = build_mapping(config);
         Mixer {
             name,
             channels_in: ch_in,
             channels_out: ch_out,
             mapping,
+            previous_mapping: None,
         }
     }
 
+    /// Update the mixer parameters.
+    /// When only gains have changed, the next chunk ramps from the old to the new gains to avoid clicks.
     pub fn update_parameters(&mut self, config: config::Mixer) {
         let ch_in = config.channels.r#in;
         let ch_out = config.channels.out;
-        let mut mapping = vec![Vec::<MixerSource>::new(); ch_out];
-        for cfg_mapping in config.mapping {
-            let dest = cfg_mapping.dest;
-            for cfg_src in cfg_mapping.sources {
-                let gain_value = cfg_src.gain();
-                let inverted = cfg_src.is_inverted();
-                let linear = cfg_src.scale() == config::GainScale::Linear;
-                let gain = calculate_gain(gain_value, inverted, linear);
-                let src = MixerSource {
-                    channel: cfg_src.channel,
-                    gain,
-                };
-                mapping[dest].push(src);
-            }
+        let mapping = build_mapping(config);
+        if same_routing(&self.mapping, &mapping) {
+            self.previous_mapping = Some(std::mem::replace(&mut self.mapping, mapping));
+        } else {
+            self.previous_mapping = None;
+            self.mapping = mapping;
         }
         self.channels_in = ch_in;
         self.channels_out = ch_out;
-        self.mapping = mapping;
     }
 
     /// Apply a Mixer to an AudioChunk, yielding a new AudioChunk with a possibly different number of channels.
     pub fn process_chunk(&mut self, input: &AudioChunk) -> AudioChunk {
+        let previous_mapping = self.previous_mapping.take();
         let mut waveforms = Vec::<Vec<PrcFmt>>::with_capacity(self.channels_out);
         for out_chan in 0..self.channels_out {
             waveforms.push(vec![0.0; input.frames]);
@@ -93,8 +110,21 @@ impl Mixer {
                 let source_chan = self.mapping[out_chan][source].channel;
                 if !input.waveforms[source_chan].is_empty() {
                     let gain = self.mapping[out_chan][source].gain;
-                    for n in 0..input.frames {
-                        waveforms[out_chan][n] += gain * input.waveforms[source_chan][n];
+                    if let Some(previous) = &previous_mapping {
+                        let start_gain = previous[out_chan][source].gain;
+                        let step = (gain - start_gain) / input.frames as PrcFmt;
+                        for (n, (out, value)) in waveforms[out_chan]
+                            .iter_mut()
+                            .zip(input.waveforms[source_chan].iter())
+                            .enumerate()
+                        {
+                            let ramp_gain = start_gain + step * (n + 1) as PrcFmt;
+                            *out += ramp_gain * value;
+                        }
+                    } else if gain != 0.0 {
+                        for n in 0..input.frames {
+                            waveforms[out_chan][n] += gain * input.waveforms[source_chan][n];
+                        }
                     }
                 }
             }
@@ -104,10 +134,40 @@ impl Mixer {
     }
 }
 
+/// Look up the gain in dB for a fader position in the range 0.0 to 1.0.
+/// The taper is a list of (position, gain) breakpoints sorted by position,
+/// and the gain is interpolated linearly between them.
+/// Positions outside the taper are given the gain of the nearest end point.
+pub fn taper_gain(taper: &[[PrcFmt; 2]], position: PrcFmt) -> PrcFmt {
+    let first = taper[0];
+    let last = taper[taper.len() - 1];
+    if position <= first[0] {
+        return first[1];
+    }
+    if position >= last[0] {
+        return last[1];
+    }
+    for points in taper.windows(2) {
+        let [pos_a, gain_a] = points[0];
+        let [pos_b, gain_b] = points[1];
+        if position <= pos_b {
+            return gain_a + (gain_b - gain_a) * (position - pos_a) / (pos_b - pos_a);
+        }
+    }
+    last[1]
+}
+
 /// Validate the mixer config, to give a helpful message intead of a panic.
 pub fn validate_mixer(mixer_config: &config::Mixer) -> Res<()> {
     let chan_in = mixer_config.channels.r#in;
     let chan_out = mixer_config.channels.out;
+    // Downmix mixers have already been expanded, any other mixer needs a mapping
+    if mixer_config.mapping.is_empty() {
+        return Err(config::ConfigError::new(
+            "Mixer has no mapping, only a Downmix mixer can leave it out.",
+        )
+        .into());
+    }
     for mapping in mixer_config.mapping.iter() {
         if mapping.dest >= chan_out {
             let msg = format!(
@@ -126,8 +186,32 @@ pub fn validate_mixer(mixer_config: &config::Mixer) -> Res<()> {
                 );
                 return Err(config::ConfigError::new(&msg).into());
             }
+            if let Some(taper) = &source.taper {
+                validate_taper(taper)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_taper(taper: &[[PrcFmt; 2]]) -> Res<()> {
+    if taper.len() < 2 {
+        return Err(config::ConfigError::new("A taper needs at least two points.").into());
+    }
+    for [position, _] in taper.iter() {
+        if !(0.0..=1.0).contains(position) {
+            let msg = format!(
+                "Invalid taper position {}, must be in the range 0.0 to 1.0.",
+                position
+            );
+            return Err(config::ConfigError::new(&msg).into());
         }
     }
+    if taper.windows(2).any(|points| points[1][0] <= points[0][0]) {
+        return Err(
+            config::ConfigError::new("The taper positions must be in increasing order.").into(),
+        );
+    }
     Ok(())
 }
 
@@ -149,9 +233,10 @@ pub fn used_input_channels(mixer_config: &config::Mixer) -> Vec<bool> {
 
 #[cfg(test)]
 mod tests {
+    use crate::audiodevice::AudioChunk;
     use crate::config::{Mixer, MixerChannels, MixerMapping, MixerSource};
     use crate::mixer;
-    use crate::mixer::used_input_channels;
+    use crate::mixer::{taper_gain, used_input_channels, validate_mixer};
 
     #[test]
     fn check_all_used() {
@@ -162,6 +247,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src1 = MixerSource {
             channel: 1,
@@ -169,6 +255,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src2 = MixerSource {
             channel: 0,
@@ -176,6 +263,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src3 = MixerSource {
             channel: 1,
@@ -183,6 +271,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let map0 = MixerMapping {
             dest: 0,
@@ -206,8 +295,10 @@ mod tests {
         };
         let conf = Mixer {
             description: None,
+            r#type: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
+            normalize: None,
         };
         let used = used_input_channels(&conf);
         assert_eq!(used, vec![true, true]);
@@ -222,6 +313,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src1 = MixerSource {
             channel: 1,
@@ -229,6 +321,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src2 = MixerSource {
             channel: 1,
@@ -236,6 +329,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src3 = MixerSource {
             channel: 1,
@@ -243,6 +337,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let map0 = MixerMapping {
             dest: 0,
@@ -266,8 +361,10 @@ mod tests {
         };
         let conf = Mixer {
             description: None,
+            r#type: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
+            normalize: None,
         };
         let used = used_input_channels(&conf);
         assert_eq!(used, vec![false, true]);
@@ -282,6 +379,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(true),
             scale: None,
+            taper: None,
         };
         let src1 = MixerSource {
             channel: 1,
@@ -289,6 +387,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src2 = MixerSource {
             channel: 0,
@@ -296,6 +395,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(true),
             scale: None,
+            taper: None,
         };
         let src3 = MixerSource {
             channel: 1,
@@ -303,6 +403,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let map0 = MixerMapping {
             dest: 0,
@@ -326,8 +427,10 @@ mod tests {
         };
         let conf = Mixer {
             description: None,
+            r#type: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
+            normalize: None,
         };
         let used = used_input_channels(&conf);
         assert_eq!(used, vec![false, true]);
@@ -342,6 +445,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src1 = MixerSource {
             channel: 1,
@@ -349,6 +453,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src2 = MixerSource {
             channel: 0,
@@ -356,6 +461,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src3 = MixerSource {
             channel: 1,
@@ -363,6 +469,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let map0 = MixerMapping {
             dest: 0,
@@ -386,8 +493,10 @@ mod tests {
         };
         let conf = Mixer {
             description: None,
+            r#type: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
+            normalize: None,
         };
         let used = used_input_channels(&conf);
         assert_eq!(used, vec![false, true]);
@@ -402,6 +511,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src1 = MixerSource {
             channel: 1,
@@ -409,6 +519,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src2 = MixerSource {
             channel: 0,
@@ -416,6 +527,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src3 = MixerSource {
             channel: 1,
@@ -423,6 +535,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let map0 = MixerMapping {
             dest: 0,
@@ -446,8 +559,10 @@ mod tests {
         };
         let conf = Mixer {
             description: None,
+            r#type: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
+            normalize: None,
         };
         let mix = mixer::Mixer::from_config("dummy".to_string(), conf);
         assert_eq!(mix.channels_in, 2);
@@ -489,6 +604,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src1 = MixerSource {
             channel: 1,
@@ -496,6 +612,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src2 = MixerSource {
             channel: 0,
@@ -503,6 +620,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let src3 = MixerSource {
             channel: 1,
@@ -510,6 +628,7 @@ mod tests {
             inverted: Some(false),
             mute: Some(false),
             scale: None,
+            taper: None,
         };
         let map0 = MixerMapping {
             dest: 0,
@@ -533,26 +652,150 @@ mod tests {
         };
         let conf = Mixer {
             description: None,
+            r#type: None,
             channels: chans,
             mapping: vec![map0, map1, map2, map3],
+            normalize: None,
         };
         let mix = mixer::Mixer::from_config("dummy".to_string(), conf);
         assert_eq!(mix.channels_in, 2);
         assert_eq!(mix.channels_out, 4);
 
-        //let exp_src0 = mixer::MixerSource {channel: 0, gain: 1.0};
+        // Muted mappings keep their sources, with zero gain
+        let exp_src0 = mixer::MixerSource {
+            channel: 0,
+            gain: 0.0,
+        };
         let exp_src1 = mixer::MixerSource {
             channel: 1,
             gain: 1.0,
         };
-        //let exp_src2 = mixer::MixerSource {channel: 0, gain: 1.0};
+        let exp_src2 = mixer::MixerSource {
+            channel: 0,
+            gain: 0.0,
+        };
         let exp_src3 = mixer::MixerSource {
             channel: 1,
             gain: 1.0,
         };
 
-        let exp_map = vec![vec![], vec![exp_src1], vec![], vec![exp_src3]];
+        let exp_map = vec![
+            vec![exp_src0],
+            vec![exp_src1],
+            vec![exp_src2],
+            vec![exp_src3],
+        ];
 
         assert_eq!(mix.mapping, exp_map);
     }
+
+    #[test]
+    fn check_taper() {
+        let taper = [[0.0, -100.0], [0.5, -20.0], [1.0, 0.0]];
+        assert_eq!(taper_gain(&taper, 0.0), -100.0);
+        assert_eq!(taper_gain(&taper, 0.25), -60.0);
+        assert_eq!(taper_gain(&taper, 0.5), -20.0);
+        assert_eq!(taper_gain(&taper, 0.75), -10.0);
+        assert_eq!(taper_gain(&taper, 1.0), 0.0);
+        assert_eq!(taper_gain(&taper, 1.5), 0.0);
+
+        let chans = MixerChannels { r#in: 1, out: 1 };
+        let mut src = MixerSource {
+            channel: 0,
+            gain: None,
+            inverted: None,
+            mute: None,
+            scale: None,
+            taper: Some(taper.to_vec()),
+        };
+        let mut conf = Mixer {
+            description: None,
+            r#type: None,
+            channels: chans,
+            mapping: vec![MixerMapping {
+                dest: 0,
+                sources: vec![src.clone()],
+                mute: None,
+            }],
+            normalize: None,
+        };
+        assert!(validate_mixer(&conf).is_ok());
+        src.taper = Some(vec![[0.0, -100.0], [1.2, 0.0]]);
+        conf.mapping[0].sources = vec![src.clone()];
+        assert!(validate_mixer(&conf).is_err());
+        src.taper = Some(vec![[0.5, -100.0], [0.2, 0.0]]);
+        conf.mapping[0].sources = vec![src];
+        assert!(validate_mixer(&conf).is_err());
+    }
+
+    #[test]
+    fn ramp_gain_change() {
+        let src = MixerSource {
+            channel: 0,
+            gain: Some(1.0),
+            inverted: None,
+            mute: None,
+            scale: Some(crate::config::GainScale::Linear),
+            taper: None,
+        };
+        let mut conf = Mixer {
+            description: None,
+            r#type: None,
+            channels: MixerChannels { r#in: 1, out: 1 },
+            mapping: vec![MixerMapping {
+                dest: 0,
+                sources: vec![src],
+                mute: None,
+            }],
+            normalize: None,
+        };
+        let mut mix = mixer::Mixer::from_config("dummy".to_string(), conf.clone());
+        let chunk = AudioChunk::new(vec![vec![1.0; 4]], 1.0, 1.0, 4, 4);
+        conf.mapping[0].sources[0].gain = Some(0.0);
+        mix.update_parameters(conf);
+        let output = mix.process_chunk(&chunk);
+        assert_eq!(output.waveforms[0], vec![0.75, 0.5, 0.25, 0.0]);
+        let output = mix.process_chunk(&chunk);
+        assert_eq!(output.waveforms[0], vec![0.0; 4]);
+    }
+
+    #[test]
+    fn ramp_mute() {
+        let src = MixerSource {
+            channel: 0,
+            gain: Some(1.0),
+            inverted: None,
+            mute: None,
+            scale: Some(crate::config::GainScale::Linear),
+            taper: None,
+        };
+        let mut conf = Mixer {
+            description: None,
+            r#type: None,
+            channels: MixerChannels { r#in: 1, out: 1 },
+            mapping: vec![MixerMapping {
+                dest: 0,
+                sources: vec![src],
+                mute: None,
+            }],
+            normalize: None,
+        };
+        let mut mix = mixer::Mixer::from_config("dummy".to_string(), conf.clone());
+        let chunk = AudioChunk::new(vec![vec![1.0; 4]], 1.0, 1.0, 4, 4);
+        conf.mapping[0].sources[0].mute = Some(true);
+        mix.update_parameters(conf.clone());
+        let output = mix.process_chunk(&chunk);
+        assert_eq!(output.waveforms[0], vec![0.75, 0.5, 0.25, 0.0]);
+        let output = mix.process_chunk(&chunk);
+        assert_eq!(output.waveforms[0], vec![0.0; 4]);
+        conf.mapping[0].mute = Some(true);
+        conf.mapping[0].sources[0].mute = Some(false);
+        mix.update_parameters(conf.clone());
+        let output = mix.process_chunk(&chunk);
+        assert_eq!(output.waveforms[0], vec![0.0; 4]);
+        conf.mapping[0].mute = Some(false);
+        mix.update_parameters(conf);
+        let output = mix.process_chunk(&chunk);
+        assert_eq!(output.waveforms[0], vec![0.25, 0.5, 0.75, 1.0]);
+    }
 }
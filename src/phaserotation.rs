@@ -0,0 +1,297 @@
+use crate::audiodevice::AudioChunk;
+use crate::config;
+use crate::filters::Processor;
+use crate::helpers::multiply_elements;
+use crate::PrcFmt;
+use crate::Res;
+use num_complex::Complex;
+use num_traits::Zero;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+// Smallest fft size used when none is given.
+const DEFAULT_MIN_FFT_SIZE: usize = 4096;
+
+/// Overlap-save convolution state for one channel.
+struct ChannelState {
+    channel: usize,
+    history: Vec<PrcFmt>,
+}
+
+pub struct PhaseRotation {
+    pub name: String,
+    pub degrees: PrcFmt,
+    pub fft_size: usize,
+    pub chunksize: usize,
+    pub samplerate: usize,
+    channels: Vec<ChannelState>,
+    coeffs_f: Vec<Complex<PrcFmt>>,
+    fft: Arc<dyn RealToComplex<PrcFmt>>,
+    ifft: Arc<dyn ComplexToReal<PrcFmt>>,
+    input_buf: Vec<PrcFmt>,
+    input_f: Vec<Complex<PrcFmt>>,
+    temp_buf: Vec<Complex<PrcFmt>>,
+    output_buf: Vec<PrcFmt>,
+    scratch_fw: Vec<Complex<PrcFmt>>,
+    scratch_inv: Vec<Complex<PrcFmt>>,
+}
+
+/// Get the fft size, or the default of at least twice the chunksize.
+pub fn fft_size(config: &config::PhaseRotationParameters, chunksize: usize) -> usize {
+    config.fft_size.unwrap_or_else(|| {
+        (2 * chunksize)
+            .max(DEFAULT_MIN_FFT_SIZE)
+            .next_power_of_two()
+    })
+}
+
+/// The impulse response is as long as possible while still fitting in the fft together with a chunk.
+/// It is kept at an odd length, to give a center tap and a latency of a whole number of samples.
+fn filter_length(fft_size: usize, chunksize: usize) -> usize {
+    let length = fft_size - chunksize + 1;
+    if length % 2 == 0 {
+        length - 1
+    } else {
+        length
+    }
+}
+
+/// Get the latency in samples, given by the position of the center tap of the impulse response.
+pub fn latency(fft_size: usize, chunksize: usize) -> usize {
+    (filter_length(fft_size, chunksize) - 1) / 2
+}
+
+/// Calculate the impulse response of the phase rotation.
+/// This is a mix of a delayed impulse and a windowed Hilbert transformer, y = cos(a)*x - sin(a)*H(x),
+/// which turns cos(wt) into cos(wt + a).
+fn impulse_response(degrees: PrcFmt, length: usize) -> Vec<PrcFmt> {
+    let pi = std::f64::consts::PI as PrcFmt;
+    let angle = degrees.to_radians();
+    let center = (length - 1) / 2;
+    let mut coeffs = vec![0.0; length];
+    coeffs[center] = angle.cos();
+    for (n, coeff) in coeffs.iter_mut().enumerate() {
+        let k = n as i64 - center as i64;
+        if k % 2 != 0 {
+            let phase = 2.0 * pi * n as PrcFmt / (length - 1) as PrcFmt;
+            let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            *coeff = -angle.sin() * window * 2.0 / (pi * k as PrcFmt);
+        }
+    }
+    coeffs
+}
+
+impl PhaseRotation {
+    /// Creates a PhaseRotation from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::PhaseRotationParameters,
+        samplerate: usize,
+        chunksize: usize,
+    ) -> Self {
+        let name = name.to_string();
+        let fft_size = fft_size(&config, chunksize);
+        let mut process_channels = config.process_channels();
+        if process_channels.is_empty() {
+            process_channels = (0..config.channels).collect();
+        }
+        let latency = latency(fft_size, chunksize);
+        debug!(
+            "Creating phase rotation '{}', channels: {}, process_channels: {:?}, degrees: {}, fft_size: {}",
+            name, config.channels, process_channels, config.degrees, fft_size
+        );
+        info!(
+            "Phase rotation '{}' adds a latency of {} samples ({:.2} ms)",
+            name,
+            latency,
+            1000.0 * latency as PrcFmt / samplerate as PrcFmt
+        );
+
+        let mut planner = RealFftPlanner::<PrcFmt>::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let ifft = planner.plan_fft_inverse(fft_size);
+        let mut scratch_fw = fft.make_scratch_vec();
+        let scratch_inv = ifft.make_scratch_vec();
+
+        let mut coeffs_padded = vec![0.0; fft_size];
+        for (padded, coeff) in coeffs_padded.iter_mut().zip(impulse_response(
+            config.degrees,
+            filter_length(fft_size, chunksize),
+        )) {
+            *padded = coeff / fft_size as PrcFmt;
+        }
+        let mut coeffs_f = fft.make_output_vec();
+        fft.process_with_scratch(&mut coeffs_padded, &mut coeffs_f, &mut scratch_fw)
+            .unwrap();
+
+        let channels = process_channels
+            .iter()
+            .map(|channel| ChannelState {
+                channel: *channel,
+                history: vec![0.0; fft_size],
+            })
+            .collect();
+
+        PhaseRotation {
+            name,
+            degrees: config.degrees,
+            fft_size,
+            chunksize,
+            samplerate,
+            channels,
+            coeffs_f,
+            input_buf: vec![0.0; fft_size],
+            input_f: vec![Complex::zero(); fft_size / 2 + 1],
+            temp_buf: vec![Complex::zero(); fft_size / 2 + 1],
+            output_buf: vec![0.0; fft_size],
+            fft,
+            ifft,
+            scratch_fw,
+            scratch_inv,
+        }
+    }
+}
+
+impl Processor for PhaseRotation {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply the phase rotation to the selected channels, using overlap-save convolution.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        let new_samples = self.chunksize;
+        let old_samples = self.fft_size - new_samples;
+        for state in self.channels.iter_mut() {
+            let waveform = &mut input.waveforms[state.channel];
+            if waveform.is_empty() {
+                continue;
+            }
+            state.history.copy_within(new_samples.., 0);
+            state.history[old_samples..].copy_from_slice(waveform);
+            self.input_buf.copy_from_slice(&state.history);
+            self.fft
+                .process_with_scratch(&mut self.input_buf, &mut self.input_f, &mut self.scratch_fw)
+                .unwrap();
+            multiply_elements(&mut self.temp_buf, &self.input_f, &self.coeffs_f);
+            self.ifft
+                .process_with_scratch(
+                    &mut self.temp_buf,
+                    &mut self.output_buf,
+                    &mut self.scratch_inv,
+                )
+                .unwrap();
+            waveform.copy_from_slice(&self.output_buf[old_samples..]);
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::PhaseRotation {
+            parameters: config, ..
+        } = config
+        {
+            *self = PhaseRotation::from_config(&self.name, config, self.samplerate, self.chunksize);
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the phase rotation config, to give a helpful message intead of a panic.
+pub fn validate_phase_rotation(
+    config: &config::PhaseRotationParameters,
+    chunksize: usize,
+) -> Res<()> {
+    let channels = config.channels;
+    for ch in config.process_channels().iter() {
+        if *ch >= channels {
+            let msg = format!(
+                "Invalid channel to process: {}, max is: {}.",
+                *ch,
+                channels - 1
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    if !config.degrees.is_finite() {
+        let msg = "Phase rotation must be a finite number of degrees.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    let fft_size = fft_size(config, chunksize);
+    if !fft_size.is_power_of_two() {
+        let msg = format!("FFT size must be a power of two, got {}.", fft_size);
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    if fft_size < 2 * chunksize {
+        let msg = format!(
+            "FFT size must be at least twice the chunksize of {}, got {}.",
+            chunksize, fft_size
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config::PhaseRotationParameters;
+    use crate::filters::Processor;
+    use crate::phaserotation::{latency, validate_phase_rotation, PhaseRotation};
+    use crate::PrcFmt;
+    use std::f64::consts::PI;
+
+    fn params(degrees: PrcFmt, fft_size: Option<usize>) -> PhaseRotationParameters {
+        PhaseRotationParameters {
+            channels: 2,
+            process_channels: Some(vec![1]),
+            degrees,
+            fft_size,
+        }
+    }
+
+    #[test]
+    fn rotate_sine_90_degrees() {
+        let chunksize = 256;
+        let freq = 1000.0;
+        let samplerate = 48000;
+        let mut rotation =
+            PhaseRotation::from_config("test", params(90.0, Some(4096)), samplerate, chunksize);
+        let delay = latency(4096, chunksize);
+        let omega = 2.0 * PI as PrcFmt * freq / samplerate as PrcFmt;
+        for n in 0..20 {
+            let wave: Vec<PrcFmt> = (0..chunksize)
+                .map(|i| (omega * (n * chunksize + i) as PrcFmt).sin())
+                .collect();
+            let mut chunk = AudioChunk::new(
+                vec![wave.clone(), wave.clone()],
+                1.0,
+                -1.0,
+                chunksize,
+                chunksize,
+            );
+            rotation.process_chunk(&mut chunk).unwrap();
+            // The channel that isn't processed is left as it is
+            assert_eq!(chunk.waveforms[0], wave);
+            if n * chunksize > 2 * delay {
+                for (i, value) in chunk.waveforms[1].iter().enumerate() {
+                    let t = (n * chunksize + i - delay) as PrcFmt;
+                    let expected = (omega * t).cos();
+                    assert!((value - expected).abs() < 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn check_phase_rotation_config() {
+        assert!(validate_phase_rotation(&params(45.0, None), 1024).is_ok());
+        assert!(validate_phase_rotation(&params(45.0, Some(2048)), 1024).is_ok());
+        assert!(validate_phase_rotation(&params(45.0, Some(1024)), 1024).is_err());
+        assert!(validate_phase_rotation(&params(45.0, Some(3000)), 1024).is_err());
+        let mut conf = params(45.0, None);
+        conf.process_channels = Some(vec![2]);
+        assert!(validate_phase_rotation(&conf, 1024).is_err());
+    }
+}
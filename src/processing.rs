@@ -1,21 +1,700 @@
 use crate::audiodevice::*;
+use crate::biquadcombo::BiquadCombo;
 use crate::config;
 use crate::filters;
+use crate::filters::Filter;
+use crate::CalibrationNoiseRequest;
+use crate::CaptureStatus;
+use crate::PlaybackStatus;
+use crate::PrcFmt;
 use crate::ProcessingParameters;
+use crate::ProcessingState;
+use parking_lot::RwLock;
+use rand::{rngs::SmallRng, SeedableRng};
+use rand_distr::{Distribution, Uniform};
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier};
 use std::thread;
 
+/// Fades the output to silence before the capture device pauses on silence,
+/// and back in when the signal returns.
+/// This follows the same silence detection as the capture devices,
+/// and starts the fade so that it ends just as the capture pauses.
+struct SilenceFade {
+    threshold: PrcFmt,
+    limit_nbr: usize,
+    fade_nbr: usize,
+    silent_nbr: usize,
+    gain: PrcFmt,
+    step: PrcFmt,
+    ramp: Vec<PrcFmt>,
+}
+
+impl SilenceFade {
+    fn from_config(conf: &config::Devices) -> Option<Self> {
+        // Count the silent chunks in the same way as the SilenceCounter of the capture device,
+        // which uses the capture sample rate.
+        let limit_nbr = (conf.silence_timeout() * conf.capture_samplerate() as PrcFmt
+            / conf.chunksize as PrcFmt)
+            .round() as usize;
+        // The fade is applied to the processed chunks, at the pipeline sample rate.
+        let fade_nbr = (conf.silence_fade_ms() as PrcFmt * conf.samplerate as PrcFmt
+            / (1000.0 * conf.chunksize as PrcFmt))
+            .ceil() as usize;
+        let fade_nbr = fade_nbr.min(limit_nbr);
+        if limit_nbr == 0 || fade_nbr == 0 {
+            return None;
+        }
+        debug!("Fading output over {} chunks on silence", fade_nbr);
+        Some(SilenceFade {
+            threshold: (10.0 as PrcFmt).powf(conf.silence_threshold() / 20.0),
+            limit_nbr,
+            fade_nbr,
+            silent_nbr: 0,
+            gain: 1.0,
+            step: 1.0 / (fade_nbr * conf.chunksize) as PrcFmt,
+            ramp: vec![1.0; conf.chunksize],
+        })
+    }
+
+    /// Update the silence count from the input chunk, before it is processed.
+    fn update(&mut self, chunk: &AudioChunk) {
+        if chunk.maxval - chunk.minval > self.threshold {
+            self.silent_nbr = 0;
+        } else {
+            self.silent_nbr += 1;
+        }
+    }
+
+    /// Apply the fade to the processed chunk.
+    fn apply(&mut self, chunk: &mut AudioChunk) {
+        let target = if self.silent_nbr + self.fade_nbr > self.limit_nbr {
+            0.0
+        } else {
+            1.0
+        };
+        if self.gain == target && target == 1.0 {
+            return;
+        }
+        self.ramp.resize(chunk.frames, 1.0);
+        for value in self.ramp.iter_mut() {
+            self.gain = if target > self.gain {
+                (self.gain + self.step).min(target)
+            } else {
+                (self.gain - self.step).max(target)
+            };
+            *value = self.gain;
+        }
+        for waveform in chunk.waveforms.iter_mut() {
+            for (sample, gain) in waveform.iter_mut().zip(self.ramp.iter()) {
+                *sample *= gain;
+            }
+        }
+    }
+}
+
+/// Replaces the captured audio with silence for a fixed time after processing starts.
+/// The captured samples are dropped rather than queued, so no latency is added once it has passed.
+struct CapturePreroll {
+    frames_left: usize,
+}
+
+impl CapturePreroll {
+    fn from_config(conf: &config::Devices) -> Option<Self> {
+        let frames_left =
+            (conf.capture_preroll_ms() / 1000.0 * conf.samplerate as f32).round() as usize;
+        if frames_left == 0 {
+            return None;
+        }
+        debug!("Starting with {} frames of silence", frames_left);
+        Some(CapturePreroll { frames_left })
+    }
+
+    /// Silence the start of the captured chunk while the preroll is running.
+    fn apply(&mut self, chunk: &mut AudioChunk) {
+        if self.frames_left == 0 {
+            return;
+        }
+        let nbr_frames = self.frames_left.min(chunk.frames);
+        for waveform in chunk.waveforms.iter_mut() {
+            let nbr_samples = nbr_frames.min(waveform.len());
+            waveform[..nbr_samples]
+                .iter_mut()
+                .for_each(|sample| *sample = 0.0);
+        }
+        if nbr_frames == chunk.frames {
+            chunk.maxval = 0.0;
+            chunk.minval = 0.0;
+        }
+        self.frames_left -= nbr_frames;
+        if self.frames_left == 0 {
+            debug!("Capture preroll done");
+        }
+    }
+}
+
+/// Keeps the main fader muted until the capture device reaches the running state.
+/// This uses the start mute of the main fader, which is separate from the mute set by the user,
+/// and it is cleared early if the main fader is unmuted with `SetMute`.
+struct StartMute;
+
+impl StartMute {
+    fn from_config(conf: &config::Devices, params: &ProcessingParameters) -> Option<Self> {
+        // Always set the start mute, since a previous run may have stopped before it was cleared.
+        params.set_start_mute(conf.start_muted());
+        if !conf.start_muted() {
+            return None;
+        }
+        debug!("Starting with the main fader muted");
+        Some(StartMute)
+    }
+
+    /// Clear the start mute if the capture device is now running.
+    /// Returns false when the start mute is done, or has already been cleared by someone else.
+    fn update(&self, params: &ProcessingParameters, capture_state: ProcessingState) -> bool {
+        if !params.is_start_muted() {
+            debug!("Start mute was cleared before the capture device was running");
+            return false;
+        }
+        if capture_state == ProcessingState::Running {
+            debug!("Capture device is running, clearing start mute");
+            params.set_start_mute(false);
+            return false;
+        }
+        true
+    }
+}
+
+/// Forces the output to digital zero when the input has been silent for longer than the hold time.
+/// Unlike the pause on silence, the chunks keep flowing so the playback device keeps running.
+struct OutputGate {
+    threshold: PrcFmt,
+    hold_nbr: usize,
+    silent_nbr: usize,
+    closed: bool,
+}
+
+impl OutputGate {
+    fn from_config(conf: &config::Devices) -> Option<Self> {
+        let gate = conf.output_gate.as_ref()?;
+        let chunks_per_second = conf.samplerate as PrcFmt / conf.chunksize as PrcFmt;
+        let hold_nbr = (gate.hold() * chunks_per_second).round() as usize;
+        debug!(
+            "Output gate enabled, threshold: {} dB, hold: {} chunks",
+            gate.threshold(),
+            hold_nbr
+        );
+        Some(OutputGate {
+            threshold: (10.0 as PrcFmt).powf(gate.threshold() / 20.0),
+            hold_nbr,
+            silent_nbr: 0,
+            closed: false,
+        })
+    }
+
+    /// Update the silence count from the input chunk, before it is processed.
+    fn update(&mut self, chunk: &AudioChunk) {
+        if chunk.maxval - chunk.minval > self.threshold {
+            self.silent_nbr = 0;
+        } else {
+            self.silent_nbr += 1;
+        }
+        let closed = self.silent_nbr > self.hold_nbr;
+        if closed != self.closed {
+            if closed {
+                debug!("Input is silent, closing output gate");
+            } else {
+                debug!("Signal detected, opening output gate");
+            }
+            self.closed = closed;
+        }
+    }
+
+    /// Replace the processed chunk with silence while the gate is closed.
+    fn apply(&self, chunk: &mut AudioChunk) {
+        if !self.closed {
+            return;
+        }
+        for waveform in chunk.waveforms.iter_mut() {
+            waveform.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+        chunk.maxval = 0.0;
+        chunk.minval = 0.0;
+    }
+}
+
+/// Slowly adjusts the output level so that the RMS level of the processed signal approaches a target.
+/// The level is measured before the normalization gain is applied, and the measurement is paused
+/// while the signal is silent, so that the gain doesn't rise during pauses between tracks.
+struct RmsNormalize {
+    target: PrcFmt,
+    range: PrcFmt,
+    max_step: PrcFmt,
+    smoothing: PrcFmt,
+    mean_square: Option<PrcFmt>,
+    gain_db: PrcFmt,
+    gain: PrcFmt,
+}
+
+impl RmsNormalize {
+    // Time constant in seconds for the level measurement.
+    const TIME_CONSTANT: PrcFmt = 3.0;
+    // Chunks with an RMS level below this, in dB, are not included in the measurement.
+    const SILENCE_LEVEL: PrcFmt = -70.0;
+
+    fn from_config(conf: &config::Devices) -> Option<Self> {
+        let normalize = conf.rms_normalize.as_ref()?;
+        let chunk_time = conf.chunksize as PrcFmt / conf.samplerate as PrcFmt;
+        debug!(
+            "RMS normalization enabled, target: {} dB, rate: {} dB/s, range: +-{} dB",
+            normalize.target(),
+            normalize.rate(),
+            normalize.range()
+        );
+        Some(RmsNormalize {
+            target: normalize.target(),
+            range: normalize.range(),
+            max_step: normalize.rate() * chunk_time,
+            smoothing: (-chunk_time / Self::TIME_CONSTANT).exp(),
+            mean_square: None,
+            gain_db: 0.0,
+            gain: 1.0,
+        })
+    }
+
+    /// Measure the level of the processed chunk and apply the gain, ramping from the previous value.
+    fn apply(&mut self, chunk: &mut AudioChunk) {
+        let (sum, count) = chunk.waveforms.iter().filter(|wf| !wf.is_empty()).fold(
+            (0.0, 0),
+            |(sum, count), wf| {
+                (
+                    sum + wf[..chunk.valid_frames.min(wf.len())]
+                        .iter()
+                        .map(|v| v * v)
+                        .sum::<PrcFmt>(),
+                    count + chunk.valid_frames.min(wf.len()),
+                )
+            },
+        );
+        if count > 0 {
+            let chunk_ms = sum / count as PrcFmt;
+            if 10.0 * chunk_ms.log10() > Self::SILENCE_LEVEL {
+                let mean_square = match self.mean_square {
+                    Some(prev) => self.smoothing * prev + (1.0 - self.smoothing) * chunk_ms,
+                    None => chunk_ms,
+                };
+                self.mean_square = Some(mean_square);
+                let wanted =
+                    (self.target - 10.0 * mean_square.log10()).clamp(-self.range, self.range);
+                self.gain_db += (wanted - self.gain_db).clamp(-self.max_step, self.max_step);
+            }
+        }
+        let new_gain = (10.0 as PrcFmt).powf(self.gain_db / 20.0);
+        let step = (new_gain - self.gain) / chunk.frames.max(1) as PrcFmt;
+        for waveform in chunk.waveforms.iter_mut() {
+            let mut gain = self.gain;
+            for sample in waveform.iter_mut() {
+                gain += step;
+                *sample *= gain;
+            }
+        }
+        chunk.maxval *= new_gain.max(self.gain);
+        chunk.minval *= new_gain.max(self.gain);
+        self.gain = new_gain;
+    }
+}
+
+/// Band limited pink noise for level matching of the playback channels.
+/// The noise replaces the processed output on one channel, and all other channels are silenced.
+/// It is created by the websocket server and handed over to the processing thread,
+/// so that the filters are settled and the buffer is allocated before it starts playing.
+#[derive(Debug)]
+pub struct CalibrationNoise {
+    channel: usize,
+    frames_left: usize,
+    gain: PrcFmt,
+    rng: SmallRng,
+    distribution: Uniform<PrcFmt>,
+    pink_state: [PrcFmt; 7],
+    highpass: BiquadCombo,
+    lowpass: BiquadCombo,
+    buffer: Vec<PrcFmt>,
+}
+
+impl CalibrationNoise {
+    const FREQ_LOW: PrcFmt = 500.0;
+    const FREQ_HIGH: PrcFmt = 2000.0;
+    const FILTER_ORDER: usize = 4;
+
+    pub fn new(
+        request: CalibrationNoiseRequest,
+        samplerate: usize,
+        chunksize: usize,
+    ) -> Option<Self> {
+        let frames_left = (request.duration * samplerate as f32).round() as usize;
+        if frames_left == 0 {
+            return None;
+        }
+        let highpass = BiquadCombo::from_config(
+            "calibration_highpass",
+            samplerate,
+            config::BiquadComboParameters::ButterworthHighpass {
+                freq: Self::FREQ_LOW,
+                order: Self::FILTER_ORDER,
+            },
+        );
+        let lowpass = BiquadCombo::from_config(
+            "calibration_lowpass",
+            samplerate,
+            config::BiquadComboParameters::ButterworthLowpass {
+                freq: Self::FREQ_HIGH,
+                order: Self::FILTER_ORDER,
+            },
+        );
+        let mut noise = CalibrationNoise {
+            channel: request.channel,
+            frames_left,
+            gain: 1.0,
+            rng: SmallRng::from_entropy(),
+            distribution: Uniform::new_inclusive(-1.0, 1.0),
+            pink_state: [0.0; 7],
+            highpass,
+            lowpass,
+            buffer: vec![0.0; samplerate],
+        };
+        // Run the generator for one second to let the filters settle,
+        // and use the rms of that to scale the noise to the requested level.
+        noise.generate(samplerate);
+        let rms = (noise.buffer.iter().map(|v| v * v).sum::<PrcFmt>() / samplerate as PrcFmt)
+            .sqrt()
+            .max(1.0e-9);
+        noise.gain = (10.0 as PrcFmt).powf(request.level as PrcFmt / 20.0) / rms;
+        noise.buffer.truncate(chunksize);
+        noise.buffer.shrink_to_fit();
+        debug!(
+            "Playing calibration noise on channel {} at {} dB for {} s",
+            request.channel, request.level, request.duration
+        );
+        Some(noise)
+    }
+
+    /// Fill the start of the buffer with the given number of frames of band limited pink noise.
+    /// Pink noise is made from white noise with the filter by Paul Kellet.
+    fn generate(&mut self, frames: usize) {
+        self.buffer.resize(frames.max(self.buffer.len()), 0.0);
+        let b = &mut self.pink_state;
+        for value in self.buffer[..frames].iter_mut() {
+            let white = self.distribution.sample(&mut self.rng);
+            b[0] = 0.99886 * b[0] + white * 0.0555179;
+            b[1] = 0.99332 * b[1] + white * 0.0750759;
+            b[2] = 0.96900 * b[2] + white * 0.1538520;
+            b[3] = 0.86650 * b[3] + white * 0.3104856;
+            b[4] = 0.55000 * b[4] + white * 0.5329522;
+            b[5] = -0.7616 * b[5] - white * 0.0168980;
+            *value = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+            b[6] = white * 0.115926;
+        }
+        // The biquads never return errors
+        self.highpass
+            .process_waveform(&mut self.buffer[..frames])
+            .unwrap_or(());
+        self.lowpass
+            .process_waveform(&mut self.buffer[..frames])
+            .unwrap_or(());
+    }
+
+    /// Replace the processed chunk with the noise, returns false when the noise has ended.
+    fn apply(&mut self, chunk: &mut AudioChunk) -> bool {
+        let nbr_frames = self.frames_left.min(chunk.frames);
+        self.generate(nbr_frames);
+        for (channel, waveform) in chunk.waveforms.iter_mut().enumerate() {
+            let nbr_samples = nbr_frames.min(waveform.len());
+            if channel == self.channel {
+                for (sample, noise) in waveform[..nbr_samples].iter_mut().zip(self.buffer.iter()) {
+                    *sample = self.gain * noise;
+                    chunk.maxval = chunk.maxval.max(*sample);
+                    chunk.minval = chunk.minval.min(*sample);
+                }
+            } else {
+                waveform[..nbr_samples]
+                    .iter_mut()
+                    .for_each(|sample| *sample = 0.0);
+            }
+        }
+        self.frames_left -= nbr_frames;
+        if self.frames_left == 0 {
+            debug!("Calibration noise done");
+            return false;
+        }
+        true
+    }
+}
+
+/// Lowers the main volume in steps when the playback device reports clipping,
+/// and slowly raises it again by the same amount when the clipping has stopped.
+/// The reduction is applied on top of the volume set by the user, so volume changes during recovery are kept.
+struct ClipAgc {
+    step: f32,
+    recovery_step: f32,
+    floor: f32,
+    check_nbr: usize,
+    chunk_nbr: usize,
+    prev_clipped: Option<usize>,
+    reduction: f32,
+}
+
+impl ClipAgc {
+    // Interval in seconds for checking the clipped samples counter.
+    const CHECK_INTERVAL: f32 = 0.1;
+
+    fn from_config(conf: &config::Devices, params: &ProcessingParameters) -> Option<Self> {
+        params.set_agc_reduction(0.0);
+        let agc = conf.clip_agc.as_ref()?;
+        let chunks_per_second = conf.samplerate as f32 / conf.chunksize as f32;
+        let check_nbr = ((Self::CHECK_INTERVAL * chunks_per_second).round() as usize).max(1);
+        let interval = check_nbr as f32 / chunks_per_second;
+        debug!(
+            "Clip AGC enabled, step: {} dB, recovery rate: {} dB/s, floor: {} dB",
+            agc.step(),
+            agc.recovery_rate(),
+            agc.floor()
+        );
+        Some(ClipAgc {
+            step: agc.step(),
+            recovery_step: agc.recovery_rate() * interval,
+            floor: agc.floor(),
+            check_nbr,
+            chunk_nbr: 0,
+            prev_clipped: None,
+            reduction: 0.0,
+        })
+    }
+
+    /// Count a processed chunk, and check the clipped samples counter when it is time for that.
+    /// The counter is read with the given function, so that the playback status is only locked for the checks.
+    fn update<F: FnOnce() -> usize>(&mut self, clipped: F, params: &ProcessingParameters) {
+        self.chunk_nbr += 1;
+        if self.chunk_nbr < self.check_nbr {
+            return;
+        }
+        self.chunk_nbr = 0;
+        let clipped = clipped();
+        // The first check only reads the counter, it may contain clipping from before the start.
+        // The counter may also have been reset since the last check.
+        let new_clipped = match self.prev_clipped {
+            Some(prev) => clipped.checked_sub(prev).unwrap_or(clipped),
+            None => 0,
+        };
+        self.prev_clipped = Some(clipped);
+        let volume = params.output_volume(0);
+        if new_clipped > 0 {
+            let new_volume = (volume - self.step).max(self.floor);
+            if new_volume < volume {
+                self.reduction += volume - new_volume;
+                params.set_agc_reduction(self.reduction);
+                info!(
+                    "Clipping detected, lowering main volume to {:.1} dB, total reduction {:.1} dB",
+                    new_volume, self.reduction
+                );
+            }
+        } else if self.reduction > 0.0 {
+            let increase = self.recovery_step.min(self.reduction);
+            self.reduction -= increase;
+            params.set_agc_reduction(self.reduction);
+            if self.reduction > 0.0 {
+                info!(
+                    "Raising main volume to {:.1} dB, remaining reduction {:.1} dB",
+                    volume + increase,
+                    self.reduction
+                );
+            } else {
+                info!(
+                    "Main volume fully restored to {:.1} dB after clipping",
+                    volume + increase
+                );
+            }
+        }
+    }
+}
+
+/// Put the channels of a processed chunk in the order of the playback device.
+struct ChannelOrder {
+    order: Vec<usize>,
+    buffers: Vec<Vec<PrcFmt>>,
+}
+
+impl ChannelOrder {
+    fn new(order: Vec<usize>) -> Self {
+        let buffers = vec![Vec::new(); order.len()];
+        ChannelOrder { order, buffers }
+    }
+
+    /// Copy the channels into the buffers in the new order, and swap the buffers with the waveforms of the chunk.
+    /// The replaced waveforms are kept as buffers for the next chunk, so nothing is allocated once they have grown to the chunk size.
+    fn apply(&mut self, chunk: &mut AudioChunk) {
+        for (buffer, channel) in self.buffers.iter_mut().zip(self.order.iter()) {
+            buffer.clear();
+            buffer.extend_from_slice(&chunk.waveforms[*channel]);
+        }
+        std::mem::swap(&mut chunk.waveforms, &mut self.buffers);
+    }
+}
+
+/// The processing for one playback device, shared by the main output and the additional outputs.
+/// This is the pipeline, followed by the channel order and the silence fade of the device.
+struct OutputPath {
+    pipeline: filters::Pipeline,
+    channel_order: Option<ChannelOrder>,
+    silence_fade: Option<SilenceFade>,
+    can_bypass: bool,
+}
+
+impl OutputPath {
+    fn from_config(
+        conf: config::Configuration,
+        processing_params: Arc<ProcessingParameters>,
+    ) -> Self {
+        let can_bypass = conf.devices.capture.channels() == conf.devices.playback.channels();
+        let silence_fade = SilenceFade::from_config(&conf.devices);
+        let channel_order = conf
+            .devices
+            .playback
+            .channel_order()
+            .cloned()
+            .map(ChannelOrder::new);
+        let pipeline = filters::Pipeline::from_config(conf, processing_params);
+        OutputPath {
+            pipeline,
+            channel_order,
+            silence_fade,
+            can_bypass,
+        }
+    }
+
+    /// Run a captured chunk through the pipeline, and put the channels in the order of the playback device.
+    fn process(
+        &mut self,
+        mut chunk: AudioChunk,
+        processing_params: &ProcessingParameters,
+    ) -> AudioChunk {
+        if let Some(fade) = &mut self.silence_fade {
+            fade.update(&chunk);
+        }
+        if !(self.can_bypass && processing_params.bypass_all()) {
+            chunk = self.pipeline.process_chunk(chunk);
+        }
+        if let Some(order) = &mut self.channel_order {
+            order.apply(&mut chunk);
+        }
+        chunk
+    }
+
+    /// Apply the silence fade and the output gate to a processed chunk.
+    fn finish(&mut self, chunk: &mut AudioChunk, output_gate: &Option<OutputGate>) {
+        if let Some(fade) = &mut self.silence_fade {
+            fade.apply(chunk);
+        }
+        if let Some(gate) = output_gate {
+            gate.apply(chunk);
+        }
+    }
+}
+
+/// An additional output, with its own pipeline and playback device.
+/// It is fed with a copy of each captured chunk.
+/// Chunks are dropped when the playback device doesn't keep up, so that it can't stall the main output.
+struct OutputChain {
+    path: OutputPath,
+    tx: mpsc::SyncSender<AudioMessage>,
+    dropped_chunks: usize,
+}
+
+impl OutputChain {
+    fn from_config(
+        conf: config::Configuration,
+        processing_params: Arc<ProcessingParameters>,
+        tx: mpsc::SyncSender<AudioMessage>,
+    ) -> Self {
+        OutputChain {
+            path: OutputPath::from_config(conf, processing_params),
+            tx,
+            dropped_chunks: 0,
+        }
+    }
+
+    fn process_and_send(
+        &mut self,
+        input: &AudioChunk,
+        output_gate: &Option<OutputGate>,
+        processing_params: &ProcessingParameters,
+    ) {
+        let chunk = AudioChunk::from(input, input.waveforms.clone());
+        let mut chunk = self.path.process(chunk, processing_params);
+        self.path.finish(&mut chunk, output_gate);
+        self.try_send(AudioMessage::Audio(chunk));
+    }
+
+    /// Send a message without waiting, and drop it if the queue of the playback thread is full.
+    fn try_send(&mut self, msg: AudioMessage) {
+        match self.tx.try_send(msg) {
+            Ok(()) => {
+                if self.dropped_chunks > 0 {
+                    info!(
+                        "Additional output is keeping up again, {} chunks were dropped",
+                        self.dropped_chunks
+                    );
+                    self.dropped_chunks = 0;
+                }
+            }
+            Err(mpsc::TrySendError::Full(_)) => {
+                if self.dropped_chunks == 0 {
+                    warn!(
+                        "Playback device of additional output is not keeping up, dropping chunks"
+                    );
+                }
+                self.dropped_chunks += 1;
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                trace!("Playback thread of additional output has already stopped.");
+            }
+        }
+    }
+
+    /// Send the end of stream, waiting for room in the queue if needed.
+    /// This must not be dropped, since the playback thread only stops when it gets it.
+    fn send_end_of_stream(&self) {
+        if self.tx.send(AudioMessage::EndOfStream).is_err() {
+            debug!("Playback thread of additional output has already stopped.");
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn run_processing(
     conf_proc: config::Configuration,
     barrier_proc: Arc<Barrier>,
     tx_pb: mpsc::SyncSender<AudioMessage>,
     rx_cap: mpsc::Receiver<AudioMessage>,
+    tx_outputs: Vec<mpsc::SyncSender<AudioMessage>>,
     rx_pipeconf: mpsc::Receiver<(config::ConfigChange, config::Configuration)>,
     processing_params: Arc<ProcessingParameters>,
+    capture_status: Arc<RwLock<CaptureStatus>>,
+    playback_status: Arc<RwLock<PlaybackStatus>>,
 ) -> thread::JoinHandle<()> {
     thread::spawn(move || {
-        let mut pipeline = filters::Pipeline::from_config(conf_proc, processing_params.clone());
+        let mut outputs: Vec<OutputChain> = conf_proc
+            .output_configs()
+            .into_iter()
+            .zip(tx_outputs)
+            .map(|(conf, tx)| OutputChain::from_config(conf, processing_params.clone(), tx))
+            .collect();
+        let mut preroll = CapturePreroll::from_config(&conf_proc.devices);
+        let mut output_gate = OutputGate::from_config(&conf_proc.devices);
+        let mut clip_agc = ClipAgc::from_config(&conf_proc.devices, &processing_params);
+        let mut rms_normalize = RmsNormalize::from_config(&conf_proc.devices);
+        let mut start_mute = StartMute::from_config(&conf_proc.devices, &processing_params);
+        let mut calibration_noise: Option<CalibrationNoise> = None;
+        let mut main_output = OutputPath::from_config(conf_proc, processing_params.clone());
         debug!("build filters, waiting to start processing loop");
         barrier_proc.wait();
         debug!("Processing loop starts now!");
@@ -23,7 +702,41 @@ pub fn run_processing(
             match rx_cap.recv() {
                 Ok(AudioMessage::Audio(mut chunk)) => {
                     //trace!("AudioMessage::Audio received");
-                    chunk = pipeline.process_chunk(chunk);
+                    if let Some(mute) = &start_mute {
+                        let capture_state = capture_status.read().state;
+                        if !mute.update(&processing_params, capture_state) {
+                            start_mute = None;
+                        }
+                    }
+                    if let Some(pre) = &mut preroll {
+                        pre.apply(&mut chunk);
+                    }
+                    if let Some(gate) = &mut output_gate {
+                        gate.update(&chunk);
+                    }
+                    for output in outputs.iter_mut() {
+                        output.process_and_send(&chunk, &output_gate, &processing_params);
+                    }
+                    chunk = main_output.process(chunk, &processing_params);
+                    if let Some(normalize) = &mut rms_normalize {
+                        normalize.apply(&mut chunk);
+                    }
+                    main_output.finish(&mut chunk, &output_gate);
+                    if let Some(noise) = processing_params.take_calibration_noise() {
+                        calibration_noise = Some(noise);
+                    }
+                    if let Some(noise) = &mut calibration_noise {
+                        if !noise.apply(&mut chunk) {
+                            calibration_noise = None;
+                        }
+                    }
+                    if let Some(agc) = &mut clip_agc {
+                        agc.update(
+                            || playback_status.read().clipped_samples,
+                            &processing_params,
+                        );
+                    }
+                    processing_params.record_output_snapshot(&chunk);
                     let msg = AudioMessage::Audio(chunk);
                     if tx_pb.send(msg).is_err() {
                         info!("Playback thread has already stopped.");
@@ -36,10 +749,16 @@ pub fn run_processing(
                     if tx_pb.send(msg).is_err() {
                         info!("Playback thread has already stopped.");
                     }
+                    for output in outputs.iter() {
+                        output.send_end_of_stream();
+                    }
                     break;
                 }
                 Ok(AudioMessage::Pause) => {
                     trace!("AudioMessage::Pause received");
+                    for output in outputs.iter_mut() {
+                        output.try_send(AudioMessage::Pause);
+                    }
                     let msg = AudioMessage::Pause;
                     if tx_pb.send(msg).is_err() {
                         info!("Playback thread has already stopped.");
@@ -52,6 +771,9 @@ pub fn run_processing(
                     if tx_pb.send(msg).is_err() {
                         info!("Playback thread has already stopped.");
                     }
+                    for output in outputs.iter() {
+                        output.send_end_of_stream();
+                    }
                     break;
                 }
             }
@@ -60,10 +782,25 @@ pub fn run_processing(
                 match diff {
                     config::ConfigChange::Pipeline | config::ConfigChange::MixerParameters => {
                         debug!("Rebuilding pipeline.");
-                        let new_pipeline =
+                        for (output, output_config) in
+                            outputs.iter_mut().zip(new_config.output_configs())
+                        {
+                            output.path.pipeline = filters::Pipeline::from_config(
+                                output_config,
+                                processing_params.clone(),
+                            );
+                        }
+                        main_output.pipeline =
                             filters::Pipeline::from_config(new_config, processing_params.clone());
-                        pipeline = new_pipeline;
                     }
+                    config::ConfigChange::PipelineBypass
+                        if !main_output.pipeline.update_bypass(&new_config) =>
+                    {
+                        debug!("Rebuilding pipeline to include steps that were bypassed.");
+                        main_output.pipeline =
+                            filters::Pipeline::from_config(new_config, processing_params.clone());
+                    }
+                    config::ConfigChange::PipelineBypass => {}
                     config::ConfigChange::FilterParameters {
                         filters,
                         mixers,
@@ -73,11 +810,29 @@ pub fn run_processing(
                             "Updating parameters of filters: {:?}, mixers: {:?}.",
                             filters, mixers
                         );
-                        pipeline.update_parameters(new_config, &filters, &mixers, &processors);
+                        for (output, output_config) in
+                            outputs.iter_mut().zip(new_config.output_configs())
+                        {
+                            output.path.pipeline.update_parameters(
+                                output_config,
+                                &filters,
+                                &mixers,
+                                &processors,
+                            );
+                        }
+                        main_output.pipeline.update_parameters(
+                            new_config,
+                            &filters,
+                            &mixers,
+                            &processors,
+                        );
                     }
                     config::ConfigChange::Devices => {
                         let msg = AudioMessage::EndOfStream;
                         tx_pb.send(msg).unwrap();
+                        for output in outputs.iter() {
+                            output.send_end_of_stream();
+                        }
                         break;
                     }
                     _ => {}
@@ -87,3 +842,354 @@ pub fn run_processing(
         processing_params.set_processing_load(0.0);
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::{AudioChunk, AudioMessage};
+    use crate::config;
+    use crate::processing::{
+        CalibrationNoise, ChannelOrder, ClipAgc, OutputChain, OutputGate, RmsNormalize,
+        SilenceFade, StartMute,
+    };
+    use crate::CalibrationNoiseRequest;
+    use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use crate::ProcessingState;
+    use realfft::RealFftPlanner;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+
+    fn test_config(extra_devices: &str) -> config::Configuration {
+        let conf_yaml = format!(
+            r#"
+devices:
+  samplerate: 48000
+  chunksize: 4
+{extra_devices}
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+    channel_order: [1, 0]
+"#
+        );
+        let mut conf: config::Configuration = serde_yaml::from_str(&conf_yaml).unwrap();
+        config::validate_config(&mut conf, None).unwrap();
+        conf
+    }
+
+    /// Feed silent chunks to a silence fade, and get the gain it applies to each sample.
+    fn silence_fade_gains(fade: &mut SilenceFade, nbr_chunks: usize) -> Vec<PrcFmt> {
+        let silent = AudioChunk::new(vec![vec![0.0; 4], vec![0.0; 4]], 0.0, 0.0, 4, 4);
+        let mut gains = Vec::new();
+        for _ in 0..nbr_chunks {
+            fade.update(&silent);
+            let mut chunk = AudioChunk::new(vec![vec![1.0; 4], vec![1.0; 4]], 1.0, 1.0, 4, 4);
+            fade.apply(&mut chunk);
+            gains.extend_from_slice(&chunk.waveforms[0]);
+        }
+        gains
+    }
+
+    #[test]
+    fn silence_fade_shape_and_length() {
+        // 10 ms timeout is 120 chunks of 4 frames, the 1 ms fade is 12 chunks or 48 frames.
+        let conf = test_config(
+            "  silence_threshold: -60\n  silence_timeout: 0.01\n  silence_fade_ms: 1.0",
+        );
+        let mut fade = SilenceFade::from_config(&conf.devices).unwrap();
+        let gains = silence_fade_gains(&mut fade, 130);
+        // Unity gain until the fade starts, so that it ends as the capture pauses
+        let fade_start = 108 * 4;
+        assert!(gains[..fade_start].iter().all(|gain| *gain == 1.0));
+        // A linear ramp down to silence
+        for (n, gain) in gains[fade_start..fade_start + 48].iter().enumerate() {
+            let expected = 1.0 - (n + 1) as PrcFmt / 48.0;
+            assert!((gain - expected).abs() < 1e-5, "{} {}", n, gain);
+        }
+        assert!(gains[fade_start + 48..].iter().all(|gain| *gain == 0.0));
+
+        // The fade comes back in at the same rate when the signal returns
+        let loud = AudioChunk::new(vec![vec![1.0; 4], vec![1.0; 4]], 1.0, -1.0, 4, 4);
+        fade.update(&loud);
+        let mut chunk = AudioChunk::new(vec![vec![1.0; 4], vec![1.0; 4]], 1.0, -1.0, 4, 4);
+        fade.apply(&mut chunk);
+        assert!((chunk.waveforms[0][3] - 4.0 / 48.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn silence_fade_follows_capture_samplerate() {
+        // The capture counts chunks at the capture samplerate,
+        // so that the 10 ms timeout is 240 chunks at 96 kHz.
+        let mut conf = test_config(
+            "  silence_threshold: -60\n  silence_timeout: 0.01\n  silence_fade_ms: 1.0",
+        );
+        conf.devices.capture_samplerate = Some(96000);
+        let mut fade = SilenceFade::from_config(&conf.devices).unwrap();
+        let gains = silence_fade_gains(&mut fade, 250);
+        let fade_start = 228 * 4;
+        assert!(gains[..fade_start].iter().all(|gain| *gain == 1.0));
+        assert!(gains[fade_start] < 1.0);
+        assert!(gains[fade_start + 48..].iter().all(|gain| *gain == 0.0));
+    }
+
+    /// Update the gate with one input chunk, and apply it to a loud processed chunk.
+    /// Returns true if the processed chunk was let through.
+    fn output_gate_passes(gate: &mut OutputGate, input: &AudioChunk) -> bool {
+        gate.update(input);
+        let mut chunk = AudioChunk::new(vec![vec![0.5; 4], vec![-0.5; 4]], 0.5, -0.5, 4, 4);
+        gate.apply(&mut chunk);
+        let passed = chunk.waveforms == vec![vec![0.5; 4], vec![-0.5; 4]];
+        if !passed {
+            assert_eq!(chunk.waveforms, vec![vec![0.0; 4], vec![0.0; 4]]);
+            assert_eq!((chunk.maxval, chunk.minval), (0.0, 0.0));
+        }
+        passed
+    }
+
+    #[test]
+    fn output_gate_open_and_close() {
+        // A 1 ms hold is 12 chunks of 4 frames.
+        let conf = test_config("  output_gate:\n    threshold: -60\n    hold: 0.001");
+        let mut gate = OutputGate::from_config(&conf.devices).unwrap();
+        let silent = AudioChunk::new(vec![vec![0.0; 4], vec![0.0; 4]], 0.0, 0.0, 4, 4);
+        // A signal below the threshold counts as silence
+        let quiet = AudioChunk::new(vec![vec![1.0e-4; 4], vec![0.0; 4]], 1.0e-4, 0.0, 4, 4);
+        let loud = AudioChunk::new(vec![vec![0.1; 4], vec![-0.1; 4]], 0.1, -0.1, 4, 4);
+
+        // The gate stays open for the hold time
+        assert!(output_gate_passes(&mut gate, &loud));
+        for _ in 0..6 {
+            assert!(output_gate_passes(&mut gate, &silent));
+            assert!(output_gate_passes(&mut gate, &quiet));
+        }
+        // and closes when the input has been silent for longer
+        assert!(!output_gate_passes(&mut gate, &silent));
+        assert!(!output_gate_passes(&mut gate, &quiet));
+
+        // The gate opens on the first chunk with signal
+        assert!(output_gate_passes(&mut gate, &loud));
+        // and the hold time starts over
+        for _ in 0..12 {
+            assert!(output_gate_passes(&mut gate, &silent));
+        }
+        assert!(!output_gate_passes(&mut gate, &silent));
+    }
+
+    #[test]
+    fn output_gate_disabled_by_default() {
+        assert!(OutputGate::from_config(&test_config("").devices).is_none());
+    }
+
+    /// Normalize a number of chunks with a constant level, and get the final gain in dB.
+    fn rms_normalize_gain(
+        normalize: &mut RmsNormalize,
+        level: PrcFmt,
+        nbr_chunks: usize,
+    ) -> PrcFmt {
+        let mut gain = 0.0;
+        for _ in 0..nbr_chunks {
+            let mut chunk =
+                AudioChunk::new(vec![vec![level; 4], vec![-level; 4]], level, -level, 4, 4);
+            normalize.apply(&mut chunk);
+            gain = 20.0 * (chunk.waveforms[0][3] / level).log10();
+        }
+        gain
+    }
+
+    #[test]
+    fn rms_normalize_rate_and_range() {
+        // At 120 dB/s, each chunk of 4 frames changes the gain by 0.01 dB.
+        let conf = test_config("  rms_normalize:\n    target: -10\n    rate: 120\n    range: 6");
+        // The signal at -20 dB wants +10 dB, the gain rises slowly
+        let mut normalize = RmsNormalize::from_config(&conf.devices).unwrap();
+        let gain = rms_normalize_gain(&mut normalize, 0.1, 1);
+        assert!((gain - 0.01).abs() < 1e-3, "{}", gain);
+        let gain = rms_normalize_gain(&mut normalize, 0.1, 99);
+        assert!((gain - 1.0).abs() < 1e-3, "{}", gain);
+        // and stops at the range limit
+        let gain = rms_normalize_gain(&mut normalize, 0.1, 1000);
+        assert!((gain - 6.0).abs() < 1e-3, "{}", gain);
+        // Silence doesn't change the gain
+        let gain = rms_normalize_gain(&mut normalize, 1.0e-5, 100);
+        assert!((gain - 6.0).abs() < 1e-3, "{}", gain);
+
+        // The signal at 0 dB wants -10 dB, the cut is limited in the same way
+        let mut normalize = RmsNormalize::from_config(&conf.devices).unwrap();
+        let gain = rms_normalize_gain(&mut normalize, 1.0, 100);
+        assert!((gain + 1.0).abs() < 1e-3, "{}", gain);
+        let gain = rms_normalize_gain(&mut normalize, 1.0, 1000);
+        assert!((gain + 6.0).abs() < 1e-3, "{}", gain);
+
+        // A signal within the range settles at the target
+        let mut normalize = RmsNormalize::from_config(&conf.devices).unwrap();
+        let gain = rms_normalize_gain(&mut normalize, 0.5, 1000);
+        assert!(
+            (gain - (-10.0 - 20.0 * (0.5 as PrcFmt).log10())).abs() < 1e-3,
+            "{}",
+            gain
+        );
+    }
+
+    #[test]
+    fn rms_normalize_ramps_within_chunk() {
+        let conf = test_config("  rms_normalize:\n    target: -10\n    rate: 120\n    range: 6");
+        let mut normalize = RmsNormalize::from_config(&conf.devices).unwrap();
+        let mut chunk = AudioChunk::new(vec![vec![0.1; 4], vec![-0.1; 4]], 0.1, -0.1, 4, 4);
+        normalize.apply(&mut chunk);
+        let end_gain = (10.0 as PrcFmt).powf(0.01 / 20.0);
+        // The gain steps linearly from unity to the new value over the chunk
+        for (n, value) in chunk.waveforms[0].iter().enumerate() {
+            let expected = 0.1 * (1.0 + (end_gain - 1.0) * (n + 1) as PrcFmt / 4.0);
+            assert!((value - expected).abs() < 1e-6, "{} {}", n, value);
+        }
+        assert!((chunk.waveforms[1][3] + 0.1 * end_gain).abs() < 1e-6);
+    }
+
+    #[test]
+    fn output_chain_drops_chunks_when_full() {
+        let params = Arc::new(ProcessingParameters::default());
+        let (tx, rx) = mpsc::sync_channel(1);
+        let mut output = OutputChain::from_config(test_config(""), params.clone(), tx);
+        let chunk = AudioChunk::new(vec![vec![1.0; 4], vec![2.0; 4]], 2.0, 1.0, 4, 4);
+        for _ in 0..3 {
+            output.process_and_send(&chunk, &None, &params);
+        }
+        assert_eq!(output.dropped_chunks, 2);
+        match rx.try_recv() {
+            Ok(AudioMessage::Audio(received)) => {
+                assert_eq!(received.waveforms, vec![vec![2.0; 4], vec![1.0; 4]]);
+            }
+            _ => panic!("expected an audio chunk"),
+        }
+        output.process_and_send(&chunk, &None, &params);
+        assert_eq!(output.dropped_chunks, 0);
+        rx.try_recv().unwrap();
+        output.send_end_of_stream();
+        assert!(matches!(rx.try_recv(), Ok(AudioMessage::EndOfStream)));
+    }
+
+    #[test]
+    fn start_mute_over_two_runs() {
+        let params = ProcessingParameters::default();
+        let conf = test_config("  start_muted: true");
+        // The first run stops before the capture device is running
+        let mute = StartMute::from_config(&conf.devices, &params).unwrap();
+        assert!(mute.update(&params, ProcessingState::Starting));
+        assert!(params.is_output_muted(0));
+        assert!(!params.is_mute(0));
+
+        // The second run clears the start mute, and the user mute is unchanged
+        let mute = StartMute::from_config(&conf.devices, &params).unwrap();
+        assert!(params.is_output_muted(0));
+        assert!(!mute.update(&params, ProcessingState::Running));
+        assert!(!params.is_output_muted(0));
+        assert!(!params.is_mute(0));
+
+        // A run without start mute clears a start mute left from before
+        params.set_start_mute(true);
+        assert!(StartMute::from_config(&test_config("").devices, &params).is_none());
+        assert!(!params.is_output_muted(0));
+    }
+
+    #[test]
+    fn calibration_noise_level_and_spectrum() {
+        let request = CalibrationNoiseRequest {
+            channel: 1,
+            level: -20.0,
+            duration: 1.0,
+        };
+        let mut noise = CalibrationNoise::new(request, 48000, 48000).unwrap();
+        let mut chunk = AudioChunk::new(vec![vec![1.0; 48000]; 2], 1.0, 1.0, 48000, 48000);
+        assert!(!noise.apply(&mut chunk));
+        assert!(chunk.waveforms[0].iter().all(|v| *v == 0.0));
+
+        let mut wave = chunk.waveforms[1].clone();
+        let rms = (wave.iter().map(|v| v * v).sum::<PrcFmt>() / 48000.0).sqrt();
+        let level = 20.0 * rms.log10();
+        assert!((level + 20.0).abs() < 1.0, "level is {} dB", level);
+
+        // One second of noise gives a spectrum with 1 Hz per bin
+        let fft = RealFftPlanner::<PrcFmt>::new().plan_fft_forward(48000);
+        let mut spectrum = fft.make_output_vec();
+        fft.process(&mut wave, &mut spectrum).unwrap();
+        let band_power = |low: usize, high: usize| {
+            spectrum[low..high]
+                .iter()
+                .map(|c| c.norm_sqr())
+                .sum::<PrcFmt>()
+                / (high - low) as PrcFmt
+        };
+        let passband = band_power(700, 1400);
+        assert!(passband > 1000.0 * band_power(20, 100));
+        assert!(passband > 1000.0 * band_power(8000, 16000));
+    }
+
+    #[test]
+    fn channel_order_reuses_buffers() {
+        let mut order = ChannelOrder::new(vec![2, 0, 0]);
+        for _ in 0..2 {
+            let mut chunk = AudioChunk::new(
+                vec![vec![1.0; 4], vec![2.0; 4], vec![3.0; 4]],
+                3.0,
+                1.0,
+                4,
+                4,
+            );
+            order.apply(&mut chunk);
+            assert_eq!(
+                chunk.waveforms,
+                vec![vec![3.0; 4], vec![1.0; 4], vec![1.0; 4]]
+            );
+        }
+        // The buffers now hold the replaced waveforms, ready for the next chunk
+        assert!(order.buffers.iter().all(|buffer| buffer.capacity() >= 4));
+    }
+
+    #[test]
+    fn clip_agc_attack_and_recovery() {
+        // 12000 chunks per second, the counter is checked every 1200 chunks
+        let conf =
+            test_config("  clip_agc:\n    step: 3.0\n    recovery_rate: 1.0\n    floor: -10.0");
+        let params = ProcessingParameters::default();
+        params.set_agc_reduction(5.0);
+        let mut agc = ClipAgc::from_config(&conf.devices, &params).unwrap();
+        assert_eq!(params.agc_reduction(), 0.0);
+        let mut run_checks = |clipped: &mut usize, nbr_checks: usize, clipping: bool| {
+            for _ in 0..nbr_checks {
+                if clipping {
+                    *clipped += 10;
+                }
+                for _ in 0..1200 {
+                    agc.update(|| *clipped, &params);
+                }
+            }
+        };
+        let mut clipped = 100;
+        // The first check only reads the counter
+        run_checks(&mut clipped, 1, false);
+        assert_eq!(params.agc_reduction(), 0.0);
+        // Attack in steps, down to the floor
+        run_checks(&mut clipped, 1, true);
+        assert_eq!(params.agc_reduction(), 3.0);
+        run_checks(&mut clipped, 2, true);
+        assert_eq!(params.agc_reduction(), 9.0);
+        run_checks(&mut clipped, 2, true);
+        assert_eq!(params.agc_reduction(), 10.0);
+        assert_eq!(params.output_volume(0), -10.0);
+        assert_eq!(params.target_volume(0), 0.0);
+        // Recovery at 0.1 dB per check, on top of a user volume change
+        run_checks(&mut clipped, 20, false);
+        assert!((params.agc_reduction() - 8.0).abs() < 1.0e-4);
+        params.set_target_volume(0, -6.0);
+        run_checks(&mut clipped, 80, false);
+        assert!(params.agc_reduction().abs() < 1.0e-4);
+        run_checks(&mut clipped, 10, false);
+        assert_eq!(params.agc_reduction(), 0.0);
+        assert_eq!(params.target_volume(0), -6.0);
+    }
+}
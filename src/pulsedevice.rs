@@ -49,6 +49,12 @@ impl PulseError {
         let desc = format!("PulseAudio error: {}, code: {}", msg, pa_error.0);
         PulseError { desc }
     }
+
+    pub fn from_message(desc: &str) -> Self {
+        PulseError {
+            desc: desc.to_string(),
+        }
+    }
 }
 
 pub struct PulsePlaybackDevice {
@@ -71,6 +77,23 @@ pub struct PulseCaptureDevice {
     pub silence_timeout: PrcFmt,
 }
 
+/// Get the PulseAudio sample format matching a CamillaDSP sample format.
+/// Note that the naming differs, the packed 24-bit format is called S24LE by PulseAudio,
+/// while 24 bits stored in 32 is called S24_32LE.
+fn pulse_sample_format(sample_format: &SampleFormat) -> Res<sample::Format> {
+    match sample_format {
+        SampleFormat::S16LE => Ok(sample::Format::S16le),
+        SampleFormat::S24LE => Ok(sample::Format::S24_32le),
+        SampleFormat::S24LE3 => Ok(sample::Format::S24le),
+        SampleFormat::S32LE => Ok(sample::Format::S32le),
+        SampleFormat::FLOAT32LE => Ok(sample::Format::F32le),
+        SampleFormat::FLOAT64LE => Err(PulseError::from_message(
+            "PulseAudio does not support the FLOAT64LE sample format",
+        )
+        .into()),
+    }
+}
+
 /// Open a PulseAudio device
 fn open_pulse(
     devname: String,
@@ -86,14 +109,7 @@ fn open_pulse(
         Direction::Playback
     };
 
-    let pulse_format = match sample_format {
-        SampleFormat::S16LE => sample::Format::S16le,
-        SampleFormat::S24LE => sample::Format::S24_32le,
-        SampleFormat::S24LE3 => sample::Format::S24le,
-        SampleFormat::S32LE => sample::Format::S32le,
-        SampleFormat::FLOAT32LE => sample::Format::F32le,
-        _ => panic!("invalid format"),
-    };
+    let pulse_format = pulse_sample_format(sample_format)?;
 
     let bytes_per_sample = sample_format.bytes_per_sample();
 
@@ -102,7 +118,13 @@ fn open_pulse(
         channels,
         rate: samplerate,
     };
-    //assert!(spec.is_valid());
+    if !spec.is_valid() {
+        let msg = format!(
+            "Invalid PulseAudio sample spec, format: {}, channels: {}, samplerate: {}",
+            sample_format, channels, samplerate
+        );
+        return Err(PulseError::from_message(&msg).into());
+    }
     let attr = pulse::def::BufferAttr {
         maxlength: std::u32::MAX,
         tlength: std::u32::MAX,
@@ -122,6 +144,14 @@ fn open_pulse(
         Some(&attr),    // Use default buffering attributes
     );
     match pulsedev_res {
+        Err(err) if *sample_format == SampleFormat::S24LE3 => {
+            let msg = format!(
+                "{}. The device '{}' may not accept the packed 24-bit format S24LE3, try S24LE or S32LE instead",
+                PulseError::new(&err),
+                devname
+            );
+            Err(PulseError::from_message(&msg).into())
+        }
         Err(err) => Err(PulseError::new(&err).into()),
         Ok(pulsedev) => Ok(pulsedev),
     }
@@ -162,6 +192,9 @@ impl PlaybackDevice for PulsePlaybackDevice {
                             rms: vec![0.0; channels],
                             peak: vec![0.0; channels],
                         };
+                        let mut level_meter =
+                            LevelMeter::new(playback_status.read().meter_ballistics, samplerate);
+                        let mut true_peak_meter = countertimer::TruePeakMeter::default();
                         let bytes_per_frame = channels * store_bytes_per_sample;
                         barrier.wait();
                         let mut last_instant = Instant::now();
@@ -195,7 +228,8 @@ impl PlaybackDevice for PulsePlaybackDevice {
                                                 .unwrap();
                                         }
                                     };
-                                    chunk.update_stats(&mut chunk_stats);
+                                    level_meter.update(&chunk, &mut chunk_stats);
+                                    true_peak_meter.update(&chunk);
                                     {
                                         let mut playback_status = playback_status.write();
                                         if conversion_result.1 > 0 {
@@ -207,6 +241,9 @@ impl PlaybackDevice for PulsePlaybackDevice {
                                         playback_status
                                             .signal_peak
                                             .add_record(chunk_stats.peak_linear());
+                                        playback_status.signal_minmax.update(&chunk);
+                                        playback_status.true_peak.add_record(&mut true_peak_meter);
+                                        playback_status.frames_played += chunk.valid_frames as u64;
                                     }
                                     //trace!(
                                     //    "Playback signal RMS: {:?}, peak: {:?}",
@@ -326,6 +363,8 @@ impl CaptureDevice for PulseCaptureDevice {
                         let mut rate_adjust = 0.0;
                         let mut state = ProcessingState::Running;
                         let mut chunk_stats = ChunkStats{rms: vec![0.0; channels], peak: vec![0.0; channels]};
+                        let mut level_meter = LevelMeter::new(capture_status.read().meter_ballistics, capture_samplerate);
+                        let mut true_peak_meter = countertimer::TruePeakMeter::default();
                         let bytes_per_frame = channels * store_bytes_per_sample;
                         let mut channel_mask = vec![true; channels];
                         let mut last_instant = Instant::now();
@@ -397,11 +436,17 @@ impl CaptureDevice for PulseCaptureDevice {
                                 }
                             };
                             let mut chunk = buffer_to_chunk_rawbytes(&buf[0..capture_bytes],channels, &sample_format, capture_bytes, &capture_status.read().used_channels);
-                            chunk.update_stats(&mut chunk_stats);
+                            level_meter.update(&chunk, &mut chunk_stats);
+                            true_peak_meter.update(&chunk);
                             {
                                 let mut capture_status = capture_status.write();
                                 capture_status.signal_rms.add_record_squared(chunk_stats.rms_linear());
                                 capture_status.signal_peak.add_record(chunk_stats.peak_linear());
+                                capture_status.signal_minmax.update(&chunk);
+                                capture_status.true_peak.add_record(&mut true_peak_meter);
+                                capture_status.recent_samples.update(&chunk);
+                                capture_status.signal_duty.update(silence_counter.is_signal(chunk.maxval - chunk.minval), chunk.valid_frames);
+                                capture_status.frames_captured += chunk.valid_frames as u64;
                             }
                             //trace!("Capture signal rms {:?}, peak {:?}", chunk_stats.rms_db(), chunk_stats.peak_db());
                             value_range = chunk.maxval - chunk.minval;
@@ -4,29 +4,55 @@ use crossbeam_channel::TrySendError;
 use native_tls::{Identity, TlsAcceptor, TlsStream};
 use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
 #[cfg(feature = "secure-websocket")]
 use std::fs::File;
-#[cfg(feature = "secure-websocket")]
-use std::io::Read;
+use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 use tungstenite::accept;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
 use tungstenite::Message;
 use tungstenite::WebSocket;
 
+use crate::audiodevice;
+use crate::biquad;
+use crate::filters;
 use crate::helpers::linear_to_db;
+use crate::mixer;
+use crate::processing::CalibrationNoise;
+use crate::CalibrationNoiseRequest;
+use crate::PrcFmt;
 use crate::ProcessingState;
 use crate::Res;
 use crate::{config, ControllerMessage};
 use crate::{
-    list_available_devices, list_supported_devices, CaptureStatus, PlaybackStatus,
+    list_available_devices, list_supported_devices, CaptureStatus, ErrorEvent, PlaybackStatus,
     ProcessingParameters, ProcessingStatus, StopReason,
 };
 
+// Longest allowed window for the noise floor measurement, in seconds.
+const MAX_NOISE_FLOOR_WINDOW: f32 = 60.0;
+const NOISE_FLOOR_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_CALIBRATION_NOISE_DURATION: f32 = 300.0;
+// Longest allowed output snapshot, in seconds, to limit the memory used for the recording.
+const MAX_OUTPUT_SNAPSHOT_DURATION: f32 = 10.0;
+// Extra time to wait for an output snapshot before giving up, for example if processing has stopped.
+const OUTPUT_SNAPSHOT_TIMEOUT_MARGIN: Duration = Duration::from_secs(2);
+// Limits for the gain in dB and the frequency, as a fraction of the sample rate, of nudged filters.
+const MAX_NUDGE_GAIN: PrcFmt = 40.0;
+const MIN_NUDGE_FREQ: PrcFmt = 1.0;
+const MAX_NUDGE_FREQ_FRACTION: PrcFmt = 0.49;
+// How often the subscribed values are checked for changes, while waiting for the next command.
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug, Clone)]
 pub struct SharedData {
     pub active_config: Arc<Mutex<Option<config::Configuration>>>,
@@ -39,7 +65,60 @@ pub struct SharedData {
     pub processing_status: Arc<RwLock<ProcessingStatus>>,
     pub state_change_notify: mpsc::SyncSender<()>,
     pub state_file_path: Option<String>,
+    pub config_dir: Option<String>,
     pub unsaved_state_change: Arc<AtomicBool>,
+    pub connected_clients: Arc<AtomicUsize>,
+    pub pipeline_solo: Arc<Mutex<Option<PipelineSolo>>>,
+    pub pending_configs: Arc<Mutex<PendingConfigs>>,
+    pub pipeline_latency: Arc<Mutex<Option<(config::Configuration, PrcFmt)>>>,
+    pub spectrum_analyzer: Arc<Mutex<audiodevice::SpectrumAnalyzer>>,
+}
+
+/// The pipeline before and after soloing a step, used for restoring the original bypass settings.
+#[derive(Debug, Clone)]
+pub struct PipelineSolo {
+    original: Vec<config::PipelineStep>,
+    soloed: Vec<config::PipelineStep>,
+}
+
+/// The configs sent to the controller by incremental changes, that may not have been applied yet.
+/// The controller updates the active config only after applying a change,
+/// and drops changes that are queued behind newer ones.
+/// Incremental changes are therefore made on top of the last sent config,
+/// as long as the active config is still one of the configs in this chain.
+#[derive(Debug, Clone, Default)]
+pub struct PendingConfigs {
+    configs: Vec<config::Configuration>,
+}
+
+impl PendingConfigs {
+    /// Get the config that the next incremental change should be made to.
+    /// If the active config was replaced by other means, the chain starts over from the active config.
+    fn base(&mut self, active: Option<config::Configuration>) -> Option<config::Configuration> {
+        let active = match active {
+            Some(conf) => conf,
+            None => {
+                self.configs.clear();
+                return None;
+            }
+        };
+        match self.configs.iter().position(|conf| *conf == active) {
+            Some(applied) => {
+                self.configs.drain(..applied);
+            }
+            None => self.configs = vec![active],
+        }
+        self.configs.last().cloned()
+    }
+
+    /// Send an incrementally changed config to the controller, and keep it as the base for the next change.
+    fn send(&mut self, shared_data: &SharedData, conf: config::Configuration) -> WsResult {
+        let result = send_config(shared_data, conf.clone());
+        if result == WsResult::Ok {
+            self.configs.push(conf);
+        }
+        result
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -48,12 +127,14 @@ pub struct LocalData {
     pub last_cap_peak_time: Instant,
     pub last_pb_rms_time: Instant,
     pub last_pb_peak_time: Instant,
+    pub subscriptions: Vec<(String, WsEvent)>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ServerParameters<'a> {
     pub address: &'a str,
     pub port: usize,
+    pub max_connections: Option<usize>,
     #[cfg(feature = "secure-websocket")]
     pub cert_file: Option<&'a str>,
     #[cfg(feature = "secure-websocket")]
@@ -65,36 +146,50 @@ enum WsCommand {
     SetConfigFilePath(String),
     SetConfig(String),
     SetConfigJson(String),
+    #[cfg(feature = "toml-config")]
+    SetConfigToml(String),
     Reload,
+    ReloadMerge(Vec<String>),
+    ReloadFilter(String),
     GetConfig,
     GetConfigTitle,
     GetConfigDescription,
+    GetConvolutionEngines,
     GetPreviousConfig,
     ReadConfig(String),
     ReadConfigFile(String),
     ValidateConfig(String),
     GetConfigJson,
     GetConfigFilePath,
+    GetConfigFileRaw,
+    GetConfigFilesInDir(String),
+    SwitchToConfigFile(String),
     GetStateFilePath,
     GetStateFileUpdated,
     GetSignalRange,
+    GetSignalDutyCycle,
+    ResetSignalDutyCycle,
+    GetUsedCaptureChannels,
     GetCaptureSignalRms,
     GetCaptureSignalRmsSince(f32),
     GetCaptureSignalRmsSinceLast,
     GetCaptureSignalPeak,
     GetCaptureSignalPeakSince(f32),
     GetCaptureSignalPeakSinceLast,
+    GetCaptureSignalTruePeak,
     GetPlaybackSignalRms,
     GetPlaybackSignalRmsSince(f32),
     GetPlaybackSignalRmsSinceLast,
     GetPlaybackSignalPeak,
     GetPlaybackSignalPeakSince(f32),
     GetPlaybackSignalPeakSinceLast,
+    GetPlaybackSignalTruePeak,
     GetSignalLevels,
     GetSignalLevelsSince(f32),
     GetSignalLevelsSinceLast,
     GetSignalPeaksSinceStart,
     ResetSignalPeaksSinceStart,
+    SetPeakDecayRate(f32),
     GetCaptureRate,
     GetUpdateInterval,
     SetUpdateInterval(usize),
@@ -111,17 +206,52 @@ enum WsCommand {
     GetFaderMute(usize),
     SetFaderMute(usize, bool),
     ToggleFaderMute(usize),
+    GetAllFaderVolumes,
+    GetAllFaderMutes,
+    SaveFaderPreset(String),
+    RecallFaderPreset(String),
     GetVersion,
+    GetConfigVersion,
     GetState,
     GetStopReason,
+    GetRecentErrors,
     GetRateAdjust,
     GetClippedSamples,
     ResetClippedSamples,
+    GetCaptureMinMax,
+    GetPlaybackMinMax,
+    ResetMinMax,
+    GetCaptureSpectrum(usize),
+    GetCaptureDominantFrequency,
+    MeasureNoiseFloor(f32),
+    PlayCalibrationNoise(usize, f32, f32),
+    CaptureOutputSnapshot(f32),
     GetBufferLevel,
     GetSupportedDeviceTypes,
     GetAvailableCaptureDevices(String),
     GetAvailablePlaybackDevices(String),
     GetProcessingLoad,
+    GetConnectedClients,
+    GetFilterPhaseAt(String, f32),
+    GetCrossoverSum(String, String, Vec<f32>),
+    GetFilterResponse(String, Vec<f32>),
+    SetBiquadCoefficients(String, [PrcFmt; 5]),
+    SetBiquadPeakingGain(String, PrcFmt),
+    NudgeFilterGain(String, PrcFmt),
+    NudgeFilterFreq(String, PrcFmt),
+    SetMixerSourcePosition(String, usize, usize, PrcFmt),
+    GetMixerGain(String, usize, usize),
+    SetMixerGain(String, usize, usize, PrcFmt),
+    GetTotalLatency,
+    GetPipelineLatency,
+    GetDeviceParameters,
+    GetPipelineChannelFlow,
+    GetStats,
+    GetBypassAll,
+    SetBypassAll(bool),
+    SoloPipelineStep(Option<usize>),
+    SetPipelineStepBypass(usize, bool),
+    Subscribe(Vec<String>),
     Exit,
     Stop,
     None,
@@ -133,6 +263,21 @@ enum WsResult {
     Error,
 }
 
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct FilterResponse {
+    magnitude: Vec<f32>,
+    phase: Vec<f32>,
+}
+
+/// The device parameters of the running config, after any overrides have been applied.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct DeviceParameters {
+    samplerate: usize,
+    capture_samplerate: usize,
+    chunksize: usize,
+    resampler: Option<config::Resampler>,
+}
+
 #[derive(Debug, PartialEq, Serialize)]
 struct AllLevels {
     playback_rms: Vec<f32>,
@@ -147,6 +292,22 @@ struct PbCapLevels {
     capture: Vec<f32>,
 }
 
+#[derive(Debug, PartialEq, Serialize)]
+struct OutputSnapshotData {
+    samplerate: usize,
+    channels: usize,
+    frames: usize,
+    data: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct EngineStats {
+    frames_captured: u64,
+    frames_played: u64,
+    elapsed: f32,
+    state: ProcessingState,
+}
+
 #[derive(Debug, PartialEq, Serialize)]
 enum WsReply {
     SetConfigFilePath {
@@ -158,9 +319,19 @@ enum WsReply {
     SetConfigJson {
         result: WsResult,
     },
+    #[cfg(feature = "toml-config")]
+    SetConfigToml {
+        result: WsResult,
+    },
     Reload {
         result: WsResult,
     },
+    ReloadMerge {
+        result: WsResult,
+    },
+    ReloadFilter {
+        result: WsResult,
+    },
     GetConfig {
         result: WsResult,
         value: String,
@@ -173,6 +344,10 @@ enum WsReply {
         result: WsResult,
         value: String,
     },
+    GetConvolutionEngines {
+        result: WsResult,
+        value: HashMap<String, config::ConvEngine>,
+    },
     GetPreviousConfig {
         result: WsResult,
         value: String,
@@ -197,6 +372,17 @@ enum WsReply {
         result: WsResult,
         value: Option<String>,
     },
+    GetConfigFileRaw {
+        result: WsResult,
+        value: String,
+    },
+    GetConfigFilesInDir {
+        result: WsResult,
+        value: Vec<String>,
+    },
+    SwitchToConfigFile {
+        result: WsResult,
+    },
     GetStateFilePath {
         result: WsResult,
         value: Option<String>,
@@ -205,10 +391,21 @@ enum WsReply {
         result: WsResult,
         value: bool,
     },
+    GetUsedCaptureChannels {
+        result: WsResult,
+        value: Vec<bool>,
+    },
     GetSignalRange {
         result: WsResult,
         value: f32,
     },
+    GetSignalDutyCycle {
+        result: WsResult,
+        value: f32,
+    },
+    ResetSignalDutyCycle {
+        result: WsResult,
+    },
     GetPlaybackSignalRms {
         result: WsResult,
         value: Vec<f32>,
@@ -233,6 +430,10 @@ enum WsReply {
         result: WsResult,
         value: Vec<f32>,
     },
+    GetPlaybackSignalTruePeak {
+        result: WsResult,
+        value: Vec<f32>,
+    },
     GetCaptureSignalRms {
         result: WsResult,
         value: Vec<f32>,
@@ -257,6 +458,10 @@ enum WsReply {
         result: WsResult,
         value: Vec<f32>,
     },
+    GetCaptureSignalTruePeak {
+        result: WsResult,
+        value: Vec<f32>,
+    },
     GetSignalLevels {
         result: WsResult,
         value: AllLevels,
@@ -276,6 +481,9 @@ enum WsReply {
     ResetSignalPeaksSinceStart {
         result: WsResult,
     },
+    SetPeakDecayRate {
+        result: WsResult,
+    },
     GetCaptureRate {
         result: WsResult,
         value: usize,
@@ -334,10 +542,28 @@ enum WsReply {
         result: WsResult,
         value: (usize, bool),
     },
+    GetAllFaderVolumes {
+        result: WsResult,
+        value: [f32; ProcessingParameters::NUM_FADERS],
+    },
+    GetAllFaderMutes {
+        result: WsResult,
+        value: [bool; ProcessingParameters::NUM_FADERS],
+    },
+    SaveFaderPreset {
+        result: WsResult,
+    },
+    RecallFaderPreset {
+        result: WsResult,
+    },
     GetVersion {
         result: WsResult,
         value: String,
     },
+    GetConfigVersion {
+        result: WsResult,
+        value: usize,
+    },
     GetState {
         result: WsResult,
         value: ProcessingState,
@@ -346,6 +572,10 @@ enum WsReply {
         result: WsResult,
         value: StopReason,
     },
+    GetRecentErrors {
+        result: WsResult,
+        value: Vec<ErrorEvent>,
+    },
     GetRateAdjust {
         result: WsResult,
         value: f32,
@@ -361,6 +591,36 @@ enum WsReply {
     ResetClippedSamples {
         result: WsResult,
     },
+    GetCaptureMinMax {
+        result: WsResult,
+        value: (Vec<f32>, Vec<f32>),
+    },
+    GetPlaybackMinMax {
+        result: WsResult,
+        value: (Vec<f32>, Vec<f32>),
+    },
+    ResetMinMax {
+        result: WsResult,
+    },
+    GetCaptureSpectrum {
+        result: WsResult,
+        value: (Vec<f32>, Vec<f32>),
+    },
+    GetCaptureDominantFrequency {
+        result: WsResult,
+        value: Vec<Option<f32>>,
+    },
+    MeasureNoiseFloor {
+        result: WsResult,
+        value: Vec<f32>,
+    },
+    PlayCalibrationNoise {
+        result: WsResult,
+    },
+    CaptureOutputSnapshot {
+        result: WsResult,
+        value: Option<OutputSnapshotData>,
+    },
     GetSupportedDeviceTypes {
         result: WsResult,
         value: (Vec<String>, Vec<String>),
@@ -377,6 +637,80 @@ enum WsReply {
         result: WsResult,
         value: f32,
     },
+    GetConnectedClients {
+        result: WsResult,
+        value: usize,
+    },
+    GetFilterPhaseAt {
+        result: WsResult,
+        value: f32,
+    },
+    GetCrossoverSum {
+        result: WsResult,
+        value: Vec<f32>,
+    },
+    GetFilterResponse {
+        result: WsResult,
+        value: FilterResponse,
+    },
+    SetMixerSourcePosition {
+        result: WsResult,
+    },
+    GetMixerGain {
+        result: WsResult,
+        value: PrcFmt,
+    },
+    SetMixerGain {
+        result: WsResult,
+    },
+    SetBiquadCoefficients {
+        result: WsResult,
+    },
+    SetBiquadPeakingGain {
+        result: WsResult,
+    },
+    NudgeFilterGain {
+        result: WsResult,
+    },
+    NudgeFilterFreq {
+        result: WsResult,
+    },
+    GetTotalLatency {
+        result: WsResult,
+        value: f32,
+    },
+    GetPipelineLatency {
+        result: WsResult,
+        value: (usize, f32),
+    },
+    GetDeviceParameters {
+        result: WsResult,
+        value: DeviceParameters,
+    },
+    GetPipelineChannelFlow {
+        result: WsResult,
+        value: Vec<(usize, usize)>,
+    },
+    GetStats {
+        result: WsResult,
+        value: EngineStats,
+    },
+    GetBypassAll {
+        result: WsResult,
+        value: bool,
+    },
+    SetBypassAll {
+        result: WsResult,
+    },
+    SoloPipelineStep {
+        result: WsResult,
+    },
+    SetPipelineStepBypass {
+        result: WsResult,
+    },
+    Subscribe {
+        result: WsResult,
+    },
     Exit {
         result: WsResult,
     },
@@ -386,6 +720,49 @@ enum WsReply {
     Invalid {
         error: String,
     },
+    Event(WsEvent),
+}
+
+/// A change of a value that a client has subscribed to, pushed without a command.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum WsEvent {
+    State(ProcessingState),
+    StopReason(StopReason),
+    Volume(f32),
+    Mute(bool),
+    ClippedSamples(usize),
+}
+
+/// Read the current value of a subscribable event, returns None for unknown names.
+fn current_event(name: &str, shared_data: &SharedData) -> Option<WsEvent> {
+    match name {
+        "State" => Some(WsEvent::State(shared_data.capture_status.read().state)),
+        "StopReason" => Some(WsEvent::StopReason(
+            shared_data.processing_status.read().stop_reason.clone(),
+        )),
+        "Volume" => Some(WsEvent::Volume(
+            shared_data.processing_params.target_volume(0),
+        )),
+        "Mute" => Some(WsEvent::Mute(shared_data.processing_params.is_mute(0))),
+        "ClippedSamples" => Some(WsEvent::ClippedSamples(
+            shared_data.playback_status.read().clipped_samples,
+        )),
+        _ => None,
+    }
+}
+
+/// Get the subscribed events whose values have changed since they were last sent.
+fn changed_events(shared_data: &SharedData, local_data: &mut LocalData) -> Vec<WsEvent> {
+    let mut changed = Vec::new();
+    for (name, last) in local_data.subscriptions.iter_mut() {
+        if let Some(event) = current_event(name, shared_data) {
+            if event != *last {
+                *last = event.clone();
+                changed.push(event);
+            }
+        }
+    }
+    changed
 }
 
 fn parse_command(cmd: Message) -> Res<WsCommand> {
@@ -429,6 +806,7 @@ fn make_acceptor(cert_file: &Option<&str>, cert_key: &Option<&str>) -> Option<Ar
 pub fn start_server(parameters: ServerParameters, shared_data: SharedData) {
     let address = parameters.address.to_string();
     let port = parameters.port;
+    let max_connections = parameters.max_connections;
     debug!("Start websocket server on {}:{}", address, parameters.port);
     #[cfg(feature = "secure-websocket")]
     let acceptor = make_acceptor(&parameters.cert_file, &parameters.cert_pass);
@@ -444,25 +822,55 @@ pub fn start_server(parameters: ServerParameters, shared_data: SharedData) {
                     last_cap_rms_time: now,
                     last_pb_peak_time: now,
                     last_pb_rms_time: now,
+                    subscriptions: Vec::new(),
                 };
                 #[cfg(feature = "secure-websocket")]
                 let acceptor_inst = acceptor.clone();
 
                 #[cfg(feature = "secure-websocket")]
-                thread::spawn(move || match acceptor_inst {
-                    None => {
-                        let websocket_res = accept_plain_stream(stream);
-                        handle_tcp(websocket_res, &shared_data_inst, local_data);
-                    }
-                    Some(acc) => {
-                        let websocket_res = accept_secure_stream(acc, stream);
-                        handle_tls(websocket_res, &shared_data_inst, local_data);
+                thread::spawn(move || {
+                    let clients = shared_data_inst
+                        .connected_clients
+                        .fetch_add(1, Ordering::Relaxed)
+                        + 1;
+                    let rejected = is_over_limit(clients, max_connections);
+                    match acceptor_inst {
+                        None => {
+                            let websocket_res = accept_plain_stream(stream);
+                            if rejected {
+                                reject_connection(websocket_res);
+                            } else {
+                                handle_tcp(websocket_res, &shared_data_inst, local_data);
+                            }
+                        }
+                        Some(acc) => {
+                            let websocket_res = accept_secure_stream(acc, stream);
+                            if rejected {
+                                reject_connection(websocket_res);
+                            } else {
+                                handle_tls(websocket_res, &shared_data_inst, local_data);
+                            }
+                        }
                     }
+                    shared_data_inst
+                        .connected_clients
+                        .fetch_sub(1, Ordering::Relaxed);
                 });
                 #[cfg(not(feature = "secure-websocket"))]
                 thread::spawn(move || {
+                    let clients = shared_data_inst
+                        .connected_clients
+                        .fetch_add(1, Ordering::Relaxed)
+                        + 1;
                     let websocket_res = accept_plain_stream(stream);
-                    handle_tcp(websocket_res, &shared_data_inst, local_data);
+                    if is_over_limit(clients, max_connections) {
+                        reject_connection(websocket_res);
+                    } else {
+                        handle_tcp(websocket_res, &shared_data_inst, local_data);
+                    }
+                    shared_data_inst
+                        .connected_clients
+                        .fetch_sub(1, Ordering::Relaxed);
                 });
             }
         } else if let Err(err) = ws_result {
@@ -471,6 +879,28 @@ pub fn start_server(parameters: ServerParameters, shared_data: SharedData) {
     });
 }
 
+fn is_over_limit(clients: usize, max_connections: Option<usize>) -> bool {
+    max_connections.map_or(false, |max| clients > max)
+}
+
+/// Close a new connection right away since there are already too many clients connected.
+fn reject_connection<S: Read + Write>(websocket_res: Res<WebSocket<S>>) {
+    match websocket_res {
+        Ok(mut websocket) => {
+            warn!("Rejecting websocket connection, too many clients are connected");
+            let frame = CloseFrame {
+                code: CloseCode::Again,
+                reason: "Too many connections".into(),
+            };
+            let close_result = websocket.close(Some(frame));
+            if let Err(err) = close_result.and(websocket.flush()) {
+                debug!("Failed to close rejected connection: {}", err);
+            }
+        }
+        Err(err) => warn!("Connection failed: {}", err),
+    }
+}
+
 macro_rules! make_handler {
     ($t:ty, $n:ident) => {
         fn $n(
@@ -481,7 +911,7 @@ macro_rules! make_handler {
             match websocket_res {
                 Ok(mut websocket) => loop {
                     let msg_res = websocket.read();
-                    match msg_res {
+                    let replies: Vec<WsReply> = match msg_res {
                         Ok(msg) => {
                             trace!("received: {:?}", msg);
                             let command = parse_command(msg);
@@ -492,16 +922,21 @@ macro_rules! make_handler {
                                     error: err.to_string(),
                                 }),
                             };
-                            if let Some(rep) = reply {
-                                let write_result = websocket
-                                    .send(Message::text(serde_json::to_string(&rep).unwrap()));
-                                if let Err(err) = write_result {
-                                    warn!("Failed to write: {}", err);
-                                    break;
-                                }
-                            } else {
+                            if reply.is_none() {
                                 debug!("Sending no reply");
                             }
+                            reply.into_iter().collect()
+                        }
+                        Err(tungstenite::error::Error::Io(err))
+                            if matches!(
+                                err.kind(),
+                                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            changed_events(&shared_data_inst, &mut local_data)
+                                .into_iter()
+                                .map(WsReply::Event)
+                                .collect()
                         }
                         Err(tungstenite::error::Error::ConnectionClosed) => {
                             debug!("Connection was closed");
@@ -511,6 +946,19 @@ macro_rules! make_handler {
                             warn!("Lost connection: {}", err);
                             break;
                         }
+                    };
+                    let mut write_failed = false;
+                    for rep in replies.iter() {
+                        let write_result =
+                            websocket.send(Message::text(serde_json::to_string(rep).unwrap()));
+                        if let Err(err) = write_result {
+                            warn!("Failed to write: {}", err);
+                            write_failed = true;
+                            break;
+                        }
+                    }
+                    if write_failed {
+                        break;
                     }
                 },
                 Err(err) => warn!("Connection failed: {}", err),
@@ -529,6 +977,9 @@ fn accept_secure_stream(
     stream: Result<TcpStream, std::io::Error>,
 ) -> Res<tungstenite::WebSocket<TlsStream<TcpStream>>> {
     let ws = accept(acceptor.accept(stream?)?)?;
+    ws.get_ref()
+        .get_ref()
+        .set_read_timeout(Some(EVENT_POLL_INTERVAL))?;
     Ok(ws)
 }
 
@@ -536,6 +987,8 @@ fn accept_plain_stream(
     stream: Result<TcpStream, std::io::Error>,
 ) -> Res<tungstenite::WebSocket<TcpStream>> {
     let ws = accept(stream?)?;
+    // Wake up regularly while waiting for commands, to push events to subscribers.
+    ws.get_ref().set_read_timeout(Some(EVENT_POLL_INTERVAL))?;
     Ok(ws)
 }
 
@@ -599,6 +1052,53 @@ fn handle_command(
                 }
             }
         }
+        WsCommand::ReloadMerge(names) => {
+            let path = match shared_data_inst.active_config_path.lock().clone() {
+                Some(path) => path,
+                None => {
+                    warn!("Config path not given, cannot reload");
+                    return Some(WsReply::ReloadMerge {
+                        result: WsResult::Error,
+                    });
+                }
+            };
+            let mut conf = match config::load_config(path.as_str()) {
+                Ok(conf) => conf,
+                Err(err) => {
+                    error!("Config file error:");
+                    error!("{}", err);
+                    return Some(WsReply::ReloadMerge {
+                        result: WsResult::Error,
+                    });
+                }
+            };
+            if let Some(active) = shared_data_inst.active_config.lock().as_ref() {
+                if let Err(err) = keep_active_definitions(&mut conf, active, &names) {
+                    error!("{}", err);
+                    return Some(WsReply::ReloadMerge {
+                        result: WsResult::Error,
+                    });
+                }
+            }
+            match config::validate_config(&mut conf, Some(path.as_str())) {
+                Ok(()) => {
+                    debug!("WS: Config file merged successfully, send to controller");
+                    Some(WsReply::ReloadMerge {
+                        result: send_config(shared_data_inst, conf),
+                    })
+                }
+                Err(err) => {
+                    error!("Invalid merged config!");
+                    error!("{}", err);
+                    Some(WsReply::ReloadMerge {
+                        result: WsResult::Error,
+                    })
+                }
+            }
+        }
+        WsCommand::ReloadFilter(name) => Some(WsReply::ReloadFilter {
+            result: reload_filter(shared_data_inst, &name),
+        }),
         WsCommand::GetCaptureRate => {
             let capstat = shared_data_inst.capture_status.read();
             Some(WsReply::GetCaptureRate {
@@ -606,6 +1106,13 @@ fn handle_command(
                 value: capstat.measured_samplerate,
             })
         }
+        WsCommand::GetUsedCaptureChannels => {
+            let capstat = shared_data_inst.capture_status.read();
+            Some(WsReply::GetUsedCaptureChannels {
+                result: WsResult::Ok,
+                value: capstat.used_channels.clone(),
+            })
+        }
         WsCommand::GetSignalRange => {
             let capstat = shared_data_inst.capture_status.read();
             Some(WsReply::GetSignalRange {
@@ -613,6 +1120,19 @@ fn handle_command(
                 value: capstat.signal_range,
             })
         }
+        WsCommand::GetSignalDutyCycle => {
+            let capstat = shared_data_inst.capture_status.read();
+            Some(WsReply::GetSignalDutyCycle {
+                result: WsResult::Ok,
+                value: capstat.signal_duty.fraction(),
+            })
+        }
+        WsCommand::ResetSignalDutyCycle => {
+            shared_data_inst.capture_status.write().signal_duty.reset();
+            Some(WsReply::ResetSignalDutyCycle {
+                result: WsResult::Ok,
+            })
+        }
         WsCommand::GetCaptureSignalRms => {
             let values = capture_signal_rms(shared_data_inst);
             Some(WsReply::GetCaptureSignalRms {
@@ -655,6 +1175,24 @@ fn handle_command(
                 value: values,
             })
         }
+        WsCommand::GetCaptureSignalTruePeak => {
+            let mut capstat = shared_data_inst.capture_status.write();
+            let mut values = capstat.true_peak.take_peak_linear();
+            linear_to_db(&mut values);
+            Some(WsReply::GetCaptureSignalTruePeak {
+                result: WsResult::Ok,
+                value: values,
+            })
+        }
+        WsCommand::GetPlaybackSignalTruePeak => {
+            let mut pbstat = shared_data_inst.playback_status.write();
+            let mut values = pbstat.true_peak.take_peak_linear();
+            linear_to_db(&mut values);
+            Some(WsReply::GetPlaybackSignalTruePeak {
+                result: WsResult::Ok,
+                value: values,
+            })
+        }
         WsCommand::GetCaptureSignalPeak => {
             let values = capture_signal_peak(shared_data_inst);
             Some(WsReply::GetCaptureSignalPeak {
@@ -755,10 +1293,35 @@ fn handle_command(
             };
             Some(result)
         }
+        WsCommand::SetPeakDecayRate(rate) => {
+            if !rate.is_finite() || rate < 0.0 {
+                error!("Invalid peak decay rate {}, must be zero or positive", rate);
+                return Some(WsReply::SetPeakDecayRate {
+                    result: WsResult::Error,
+                });
+            }
+            shared_data_inst
+                .capture_status
+                .write()
+                .signal_peak
+                .set_decay_rate(rate);
+            shared_data_inst
+                .playback_status
+                .write()
+                .signal_peak
+                .set_decay_rate(rate);
+            Some(WsReply::SetPeakDecayRate {
+                result: WsResult::Ok,
+            })
+        }
         WsCommand::GetVersion => Some(WsReply::GetVersion {
             result: WsResult::Ok,
             value: crate_version!().to_string(),
         }),
+        WsCommand::GetConfigVersion => Some(WsReply::GetConfigVersion {
+            result: WsResult::Ok,
+            value: config::CONFIG_VERSION,
+        }),
         WsCommand::GetState => {
             let capstat = shared_data_inst.capture_status.read();
             Some(WsReply::GetState {
@@ -774,6 +1337,13 @@ fn handle_command(
                 value,
             })
         }
+        WsCommand::GetRecentErrors => {
+            let stat = shared_data_inst.processing_status.read();
+            Some(WsReply::GetRecentErrors {
+                result: WsResult::Ok,
+                value: stat.recent_errors.events(),
+            })
+        }
         WsCommand::GetRateAdjust => {
             let capstat = shared_data_inst.capture_status.read();
             Some(WsReply::GetRateAdjust {
@@ -795,46 +1365,233 @@ fn handle_command(
                 result: WsResult::Ok,
             })
         }
-        WsCommand::GetBufferLevel => {
-            let pbstat = shared_data_inst.playback_status.read();
-            Some(WsReply::GetBufferLevel {
+        WsCommand::GetCaptureMinMax => {
+            let minmax = &shared_data_inst.capture_status.read().signal_minmax;
+            Some(WsReply::GetCaptureMinMax {
                 result: WsResult::Ok,
-                value: pbstat.buffer_level,
+                value: (minmax.min_linear(), minmax.max_linear()),
             })
         }
-        WsCommand::GetUpdateInterval => {
-            let capstat = shared_data_inst.capture_status.read();
-            Some(WsReply::GetUpdateInterval {
+        WsCommand::GetPlaybackMinMax => {
+            let minmax = &shared_data_inst.playback_status.read().signal_minmax;
+            Some(WsReply::GetPlaybackMinMax {
                 result: WsResult::Ok,
-                value: capstat.update_interval,
+                value: (minmax.min_linear(), minmax.max_linear()),
             })
         }
-        WsCommand::SetUpdateInterval(nbr) => {
-            {
-                let mut captstat = shared_data_inst.capture_status.write();
-                let mut playstat = shared_data_inst.playback_status.write();
-                captstat.update_interval = nbr;
-                playstat.update_interval = nbr;
-            }
-            Some(WsReply::SetUpdateInterval {
+        WsCommand::ResetMinMax => {
+            shared_data_inst
+                .capture_status
+                .write()
+                .signal_minmax
+                .reset();
+            shared_data_inst
+                .playback_status
+                .write()
+                .signal_minmax
+                .reset();
+            Some(WsReply::ResetMinMax {
                 result: WsResult::Ok,
             })
         }
-        WsCommand::GetVolume => Some(WsReply::GetVolume {
-            result: WsResult::Ok,
-            value: shared_data_inst.processing_params.target_volume(0),
-        }),
-        WsCommand::SetVolume(nbr) => {
-            let new_vol = clamped_volume(nbr);
-            shared_data_inst
-                .processing_params
-                .set_target_volume(0, new_vol);
-            shared_data_inst
-                .unsaved_state_change
-                .store(true, Ordering::Relaxed);
-            shared_data_inst
-                .state_change_notify
-                .try_send(())
+        WsCommand::GetCaptureSpectrum(fft_size) => {
+            let samplerate = shared_data_inst
+                .active_config
+                .lock()
+                .as_ref()
+                .map(|config| config.devices.capture_samplerate());
+            let spectrum = samplerate.map(|fs| {
+                let samples = shared_data_inst
+                    .capture_status
+                    .read()
+                    .recent_samples
+                    .spectrum_samples(fft_size)?;
+                shared_data_inst
+                    .spectrum_analyzer
+                    .lock()
+                    .spectrum(samples, fs)
+            });
+            match spectrum {
+                Some(Ok(value)) => Some(WsReply::GetCaptureSpectrum {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                Some(Err(err)) => {
+                    warn!("Unable to calculate capture spectrum: {}", err);
+                    Some(WsReply::GetCaptureSpectrum {
+                        result: WsResult::Error,
+                        value: (Vec::new(), Vec::new()),
+                    })
+                }
+                None => Some(WsReply::GetCaptureSpectrum {
+                    result: WsResult::Error,
+                    value: (Vec::new(), Vec::new()),
+                }),
+            }
+        }
+        WsCommand::GetCaptureDominantFrequency => {
+            let samplerate = shared_data_inst
+                .active_config
+                .lock()
+                .as_ref()
+                .map(|config| config.devices.capture_samplerate());
+            let frequencies = samplerate.map(|fs| {
+                let samples = shared_data_inst
+                    .capture_status
+                    .read()
+                    .recent_samples
+                    .dominant_frequency_samples();
+                shared_data_inst
+                    .spectrum_analyzer
+                    .lock()
+                    .dominant_frequencies(samples, fs)
+            });
+            match frequencies {
+                Some(Ok(value)) => Some(WsReply::GetCaptureDominantFrequency {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                Some(Err(err)) => {
+                    warn!("Unable to calculate dominant frequencies: {}", err);
+                    Some(WsReply::GetCaptureDominantFrequency {
+                        result: WsResult::Error,
+                        value: Vec::new(),
+                    })
+                }
+                None => Some(WsReply::GetCaptureDominantFrequency {
+                    result: WsResult::Error,
+                    value: Vec::new(),
+                }),
+            }
+        }
+        WsCommand::MeasureNoiseFloor(secs) => {
+            if !(secs > 0.0 && secs <= MAX_NOISE_FLOOR_WINDOW) {
+                warn!(
+                    "Invalid noise floor measurement window: {} s, must be between 0 and {} s",
+                    secs, MAX_NOISE_FLOOR_WINDOW
+                );
+                return Some(WsReply::MeasureNoiseFloor {
+                    result: WsResult::Error,
+                    value: Vec::new(),
+                });
+            }
+            match measure_capture_noise_floor(shared_data_inst, secs) {
+                Some(value) => Some(WsReply::MeasureNoiseFloor {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                None => Some(WsReply::MeasureNoiseFloor {
+                    result: WsResult::Error,
+                    value: Vec::new(),
+                }),
+            }
+        }
+        WsCommand::PlayCalibrationNoise(channel, level, duration) => {
+            let devices = shared_data_inst.active_config.lock().as_ref().map(|conf| {
+                (
+                    conf.devices.playback.channels(),
+                    conf.devices.samplerate,
+                    conf.devices.chunksize,
+                )
+            });
+            let (samplerate, chunksize) = match devices {
+                Some((channels, samplerate, chunksize))
+                    if channel < channels
+                        && level <= 0.0
+                        && (0.0..=MAX_CALIBRATION_NOISE_DURATION).contains(&duration) =>
+                {
+                    (samplerate, chunksize)
+                }
+                _ => {
+                    warn!(
+                        "Invalid calibration noise request, channel: {}, level: {} dB, duration: {} s",
+                        channel, level, duration
+                    );
+                    return Some(WsReply::PlayCalibrationNoise {
+                        result: WsResult::Error,
+                    });
+                }
+            };
+            // Build the noise here, to keep the filter settling and allocations out of the processing thread
+            let request = CalibrationNoiseRequest {
+                channel,
+                level,
+                duration,
+            };
+            if let Some(noise) = CalibrationNoise::new(request, samplerate, chunksize) {
+                shared_data_inst
+                    .processing_params
+                    .request_calibration_noise(noise);
+            }
+            Some(WsReply::PlayCalibrationNoise {
+                result: WsResult::Ok,
+            })
+        }
+        WsCommand::CaptureOutputSnapshot(duration) => {
+            let samplerate = shared_data_inst
+                .active_config
+                .lock()
+                .as_ref()
+                .map(|conf| conf.devices.samplerate);
+            let value = match samplerate {
+                Some(samplerate) if duration > 0.0 && duration <= MAX_OUTPUT_SNAPSHOT_DURATION => {
+                    record_output_snapshot(shared_data_inst, duration, samplerate)
+                }
+                _ => {
+                    warn!(
+                        "Invalid output snapshot duration: {} s, must be between 0 and {} s",
+                        duration, MAX_OUTPUT_SNAPSHOT_DURATION
+                    );
+                    None
+                }
+            };
+            let result = if value.is_some() {
+                WsResult::Ok
+            } else {
+                WsResult::Error
+            };
+            Some(WsReply::CaptureOutputSnapshot { result, value })
+        }
+        WsCommand::GetBufferLevel => {
+            let pbstat = shared_data_inst.playback_status.read();
+            Some(WsReply::GetBufferLevel {
+                result: WsResult::Ok,
+                value: pbstat.buffer_level,
+            })
+        }
+        WsCommand::GetUpdateInterval => {
+            let capstat = shared_data_inst.capture_status.read();
+            Some(WsReply::GetUpdateInterval {
+                result: WsResult::Ok,
+                value: capstat.update_interval,
+            })
+        }
+        WsCommand::SetUpdateInterval(nbr) => {
+            {
+                let mut captstat = shared_data_inst.capture_status.write();
+                let mut playstat = shared_data_inst.playback_status.write();
+                captstat.update_interval = nbr;
+                playstat.update_interval = nbr;
+            }
+            Some(WsReply::SetUpdateInterval {
+                result: WsResult::Ok,
+            })
+        }
+        WsCommand::GetVolume => Some(WsReply::GetVolume {
+            result: WsResult::Ok,
+            value: shared_data_inst.processing_params.target_volume(0),
+        }),
+        WsCommand::SetVolume(nbr) => {
+            let new_vol = clamped_volume(nbr);
+            shared_data_inst
+                .processing_params
+                .set_target_volume(0, new_vol);
+            shared_data_inst
+                .unsaved_state_change
+                .store(true, Ordering::Relaxed);
+            shared_data_inst
+                .state_change_notify
+                .try_send(())
                 .unwrap_or(());
             Some(WsReply::SetVolume {
                 result: WsResult::Ok,
@@ -1022,6 +1779,47 @@ fn handle_command(
                 value: (ctrl, !tempmute),
             })
         }
+        WsCommand::GetAllFaderVolumes => Some(WsReply::GetAllFaderVolumes {
+            result: WsResult::Ok,
+            value: shared_data_inst.processing_params.volumes(),
+        }),
+        WsCommand::GetAllFaderMutes => Some(WsReply::GetAllFaderMutes {
+            result: WsResult::Ok,
+            value: shared_data_inst.processing_params.mutes(),
+        }),
+        WsCommand::SaveFaderPreset(name) => {
+            shared_data_inst.processing_params.save_fader_preset(&name);
+            shared_data_inst
+                .unsaved_state_change
+                .store(true, Ordering::Relaxed);
+            shared_data_inst
+                .state_change_notify
+                .try_send(())
+                .unwrap_or(());
+            Some(WsReply::SaveFaderPreset {
+                result: WsResult::Ok,
+            })
+        }
+        WsCommand::RecallFaderPreset(name) => {
+            if !shared_data_inst
+                .processing_params
+                .recall_fader_preset(&name)
+            {
+                return Some(WsReply::RecallFaderPreset {
+                    result: WsResult::Error,
+                });
+            }
+            shared_data_inst
+                .unsaved_state_change
+                .store(true, Ordering::Relaxed);
+            shared_data_inst
+                .state_change_notify
+                .try_send(())
+                .unwrap_or(());
+            Some(WsReply::RecallFaderPreset {
+                result: WsResult::Ok,
+            })
+        }
         WsCommand::GetConfig => Some(WsReply::GetConfig {
             result: WsResult::Ok,
             value: serde_yaml::to_string(&*shared_data_inst.active_config.lock()).unwrap(),
@@ -1050,6 +1848,28 @@ fn handle_command(
                 value,
             })
         }
+        WsCommand::GetConvolutionEngines => {
+            let optional_config = shared_data_inst.active_config.lock();
+            let value = optional_config
+                .as_ref()
+                .and_then(|config| config.filters.as_ref())
+                .map(|filters| {
+                    filters
+                        .iter()
+                        .filter_map(|(name, filter)| match filter {
+                            config::Filter::Conv { parameters, .. } => {
+                                Some((name.clone(), parameters.engine()))
+                            }
+                            _ => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(WsReply::GetConvolutionEngines {
+                result: WsResult::Ok,
+                value,
+            })
+        }
         WsCommand::GetPreviousConfig => Some(WsReply::GetPreviousConfig {
             result: WsResult::Ok,
             value: serde_yaml::to_string(&*shared_data_inst.previous_config.lock()).unwrap(),
@@ -1066,6 +1886,26 @@ fn handle_command(
                 .as_ref()
                 .map(|s| s.to_string()),
         }),
+        WsCommand::GetConfigFileRaw => {
+            let cfg_path = shared_data_inst.active_config_path.lock().clone();
+            match cfg_path.map(std::fs::read_to_string) {
+                Some(Ok(contents)) => Some(WsReply::GetConfigFileRaw {
+                    result: WsResult::Ok,
+                    value: contents,
+                }),
+                Some(Err(error)) => {
+                    error!("Error reading config file: {}", error);
+                    Some(WsReply::GetConfigFileRaw {
+                        result: WsResult::Error,
+                        value: error.to_string(),
+                    })
+                }
+                None => Some(WsReply::GetConfigFileRaw {
+                    result: WsResult::Error,
+                    value: "No config file path is set".to_string(),
+                }),
+            }
+        }
         WsCommand::GetStateFilePath => Some(WsReply::GetStateFilePath {
             result: WsResult::Ok,
             value: shared_data_inst.state_file_path.clone(),
@@ -1097,85 +1937,91 @@ fn handle_command(
                 })
             }
         },
-        WsCommand::SetConfig(config_yml) => {
-            match serde_yaml::from_str::<config::Configuration>(&config_yml) {
-                Ok(mut conf) => match config::validate_config(&mut conf, None) {
-                    Ok(()) => {
-                        match shared_data_inst
-                            .command_sender
-                            .try_send(ControllerMessage::ConfigChanged(Box::new(conf)))
-                        {
-                            Ok(()) => Some(WsReply::SetConfig {
-                                result: WsResult::Ok,
-                            }),
-                            Err(TrySendError::Full(_)) => {
-                                error!("Error sending new config, too many requests");
-                                Some(WsReply::SetConfig {
-                                    result: WsResult::Error,
-                                })
-                            }
-                            Err(TrySendError::Disconnected(_)) => {
-                                error!("Error sending new config, channel was disconnected");
-                                Some(WsReply::SetConfig {
-                                    result: WsResult::Error,
-                                })
-                            }
-                        }
-                    }
-                    Err(error) => {
-                        error!("Error validating config: {}", error);
-                        Some(WsReply::SetConfig {
-                            result: WsResult::Error,
-                        })
-                    }
-                },
+        WsCommand::GetConfigFilesInDir(dir) => {
+            let config_dir = match &shared_data_inst.config_dir {
+                Some(config_dir) => config_dir,
+                None => {
+                    warn!("No config directory given, cannot list config files");
+                    return Some(WsReply::GetConfigFilesInDir {
+                        result: WsResult::Error,
+                        value: Vec::new(),
+                    });
+                }
+            };
+            match config_files_in_dir(config_dir, &dir) {
+                Ok(files) => Some(WsReply::GetConfigFilesInDir {
+                    result: WsResult::Ok,
+                    value: files,
+                }),
                 Err(error) => {
-                    error!("Error parsing yaml: {}", error);
-                    Some(WsReply::SetConfig {
+                    error!("Error listing config files in '{}': {}", dir, error);
+                    Some(WsReply::GetConfigFilesInDir {
                         result: WsResult::Error,
+                        value: Vec::new(),
                     })
                 }
             }
         }
-        WsCommand::SetConfigJson(config_json) => {
-            match serde_json::from_str::<config::Configuration>(&config_json) {
-                Ok(mut conf) => match config::validate_config(&mut conf, None) {
-                    Ok(()) => {
-                        match shared_data_inst
-                            .command_sender
-                            .try_send(ControllerMessage::ConfigChanged(Box::new(conf)))
-                        {
-                            Ok(()) => Some(WsReply::SetConfigJson {
-                                result: WsResult::Ok,
-                            }),
-                            Err(TrySendError::Full(_)) => {
-                                error!("Error sending new config, too many requests");
-                                Some(WsReply::SetConfigJson {
-                                    result: WsResult::Error,
-                                })
-                            }
-                            Err(TrySendError::Disconnected(_)) => {
-                                error!("Error sending new config, channel was disconnected");
-                                Some(WsReply::SetConfigJson {
-                                    result: WsResult::Error,
-                                })
-                            }
-                        }
-                    }
-                    Err(error) => {
-                        error!("Error validating config: {}", error);
-                        Some(WsReply::SetConfigJson {
-                            result: WsResult::Error,
-                        })
-                    }
-                },
+        WsCommand::SwitchToConfigFile(name) => {
+            let config_dir = match &shared_data_inst.config_dir {
+                Some(config_dir) => config_dir,
+                None => {
+                    warn!("No config directory given, cannot switch config file");
+                    return Some(WsReply::SwitchToConfigFile {
+                        result: WsResult::Error,
+                    });
+                }
+            };
+            let path = match path_in_config_dir(config_dir, &name) {
+                Ok(path) => path.to_string_lossy().to_string(),
                 Err(error) => {
-                    error!("Error parsing json: {}", error);
-                    Some(WsReply::SetConfigJson {
+                    error!("Invalid config file name '{}': {}", name, error);
+                    return Some(WsReply::SwitchToConfigFile {
                         result: WsResult::Error,
-                    })
+                    });
                 }
+            };
+            let conf = match config::load_validate_config(&path) {
+                Ok(conf) => conf,
+                Err(error) => {
+                    error!("Error loading config file '{}': {}", path, error);
+                    return Some(WsReply::SwitchToConfigFile {
+                        result: WsResult::Error,
+                    });
+                }
+            };
+            let result = send_config(shared_data_inst, conf);
+            if result == WsResult::Ok {
+                debug!("WS: Switching to config file {}", path);
+                *shared_data_inst.active_config_path.lock() = Some(path);
+                shared_data_inst
+                    .unsaved_state_change
+                    .store(true, Ordering::Relaxed);
+                shared_data_inst
+                    .state_change_notify
+                    .try_send(())
+                    .unwrap_or(());
             }
+            Some(WsReply::SwitchToConfigFile { result })
+        }
+        WsCommand::SetConfig(config_yml) => {
+            let parsed = serde_yaml::from_str(&config_yml).map_err(|err| err.to_string());
+            Some(WsReply::SetConfig {
+                result: validate_and_send_config(shared_data_inst, parsed, "yaml"),
+            })
+        }
+        WsCommand::SetConfigJson(config_json) => {
+            let parsed = serde_json::from_str(&config_json).map_err(|err| err.to_string());
+            Some(WsReply::SetConfigJson {
+                result: validate_and_send_config(shared_data_inst, parsed, "json"),
+            })
+        }
+        #[cfg(feature = "toml-config")]
+        WsCommand::SetConfigToml(config_toml) => {
+            let parsed = toml::from_str(&config_toml).map_err(|err| err.to_string());
+            Some(WsReply::SetConfigToml {
+                result: validate_and_send_config(shared_data_inst, parsed, "toml"),
+            })
         }
         WsCommand::ReadConfig(config_yml) => {
             match serde_yaml::from_str::<config::Configuration>(&config_yml) {
@@ -1301,10 +2147,792 @@ fn handle_command(
                 value: load,
             })
         }
+        WsCommand::GetConnectedClients => Some(WsReply::GetConnectedClients {
+            result: WsResult::Ok,
+            value: shared_data_inst.connected_clients.load(Ordering::Relaxed),
+        }),
+        WsCommand::GetFilterPhaseAt(name, freq) => {
+            let optional_config = shared_data_inst.active_config.lock();
+            let phase = optional_config.as_ref().and_then(|config| {
+                let filter = config.filters.as_ref()?.get(&name)?;
+                filters::complex_gain(config.devices.samplerate, filter, freq as PrcFmt).ok()
+            });
+            match phase {
+                Some(gain) => Some(WsReply::GetFilterPhaseAt {
+                    result: WsResult::Ok,
+                    value: gain.arg().to_degrees() as f32,
+                }),
+                None => Some(WsReply::GetFilterPhaseAt {
+                    result: WsResult::Error,
+                    value: 0.0,
+                }),
+            }
+        }
+        WsCommand::GetCrossoverSum(first, second, freqs) => {
+            let optional_config = shared_data_inst.active_config.lock();
+            let magnitudes = optional_config.as_ref().and_then(|config| {
+                let filters = config.filters.as_ref()?;
+                let first = filters.get(&first)?;
+                let second = filters.get(&second)?;
+                let fs = config.devices.samplerate;
+                freqs
+                    .iter()
+                    .map(|freq| {
+                        let sum = filters::complex_gain(fs, first, *freq as PrcFmt).ok()?
+                            + filters::complex_gain(fs, second, *freq as PrcFmt).ok()?;
+                        Some(20.0 * sum.norm().log10() as f32)
+                    })
+                    .collect::<Option<Vec<f32>>>()
+            });
+            match magnitudes {
+                Some(value) => Some(WsReply::GetCrossoverSum {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                None => Some(WsReply::GetCrossoverSum {
+                    result: WsResult::Error,
+                    value: Vec::new(),
+                }),
+            }
+        }
+        WsCommand::GetFilterResponse(name, freqs) => {
+            // Copy the filter config, to avoid holding the lock while reading coefficient files
+            let filter_and_fs = shared_data_inst
+                .active_config
+                .lock()
+                .as_ref()
+                .and_then(|config| {
+                    let filter = config.filters.as_ref()?.get(&name)?.clone();
+                    Some((filter, config.devices.samplerate))
+                });
+            let freqs: Vec<PrcFmt> = freqs.iter().map(|freq| *freq as PrcFmt).collect();
+            let response = match filter_and_fs {
+                Some((filter, fs)) => match filters::frequency_response(fs, &filter, &freqs) {
+                    Ok(gains) => Some(FilterResponse {
+                        magnitude: gains
+                            .iter()
+                            .map(|gain| 20.0 * gain.norm().log10() as f32)
+                            .collect(),
+                        phase: gains
+                            .iter()
+                            .map(|gain| gain.arg().to_degrees() as f32)
+                            .collect(),
+                    }),
+                    Err(err) => {
+                        error!(
+                            "Unable to calculate the response of filter '{}': {}",
+                            name, err
+                        );
+                        None
+                    }
+                },
+                None => {
+                    error!("No filter named '{}' in the active config", name);
+                    None
+                }
+            };
+            match response {
+                Some(value) => Some(WsReply::GetFilterResponse {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                None => Some(WsReply::GetFilterResponse {
+                    result: WsResult::Error,
+                    value: FilterResponse::default(),
+                }),
+            }
+        }
+        WsCommand::SetBiquadCoefficients(name, [a1, a2, b0, b1, b2]) => {
+            let mut new_config = shared_data_inst.active_config.lock().clone();
+            let updated = new_config.as_mut().and_then(|conf| {
+                let fs = conf.devices.samplerate;
+                match conf.filters.as_mut()?.get_mut(&name)? {
+                    config::Filter::Biquad { parameters, .. } => {
+                        *parameters = config::BiquadParameters::Free { a1, a2, b0, b1, b2 };
+                        Some(biquad::validate_config(fs, parameters))
+                    }
+                    _ => None,
+                }
+            });
+            let result = match (updated, new_config) {
+                (Some(Ok(())), Some(conf)) => send_config(shared_data_inst, conf),
+                (Some(Err(err)), _) => {
+                    error!("Invalid coefficients for filter '{}': {}", name, err);
+                    WsResult::Error
+                }
+                _ => {
+                    error!("No Biquad filter named '{}' in the active config", name);
+                    WsResult::Error
+                }
+            };
+            Some(WsReply::SetBiquadCoefficients { result })
+        }
+        WsCommand::SetBiquadPeakingGain(name, new_gain) => {
+            if !new_gain.is_finite() {
+                error!("Invalid gain {}", new_gain);
+                return Some(WsReply::SetBiquadPeakingGain {
+                    result: WsResult::Error,
+                });
+            }
+            let mut pending = shared_data_inst.pending_configs.lock();
+            let mut new_config = pending.base(shared_data_inst.active_config.lock().clone());
+            let updated = new_config.as_mut().and_then(|conf| {
+                let fs = conf.devices.samplerate;
+                match conf.filters.as_mut()?.get_mut(&name)? {
+                    config::Filter::Biquad { parameters, .. } => {
+                        match parameters {
+                            config::BiquadParameters::Peaking(config::PeakingWidth::Q {
+                                gain,
+                                ..
+                            })
+                            | config::BiquadParameters::Peaking(
+                                config::PeakingWidth::Bandwidth { gain, .. },
+                            )
+                            | config::BiquadParameters::Peaking(
+                                config::PeakingWidth::ProportionalQ { gain, .. },
+                            ) => *gain = new_gain,
+                            _ => return None,
+                        }
+                        Some(biquad::validate_config(fs, parameters))
+                    }
+                    _ => None,
+                }
+            });
+            let result = match (updated, new_config) {
+                (Some(Ok(())), Some(conf)) => {
+                    debug!("Setting gain of filter '{}' to {} dB", name, new_gain);
+                    pending.send(shared_data_inst, conf)
+                }
+                (Some(Err(err)), _) => {
+                    error!("Invalid gain for filter '{}': {}", name, err);
+                    WsResult::Error
+                }
+                _ => {
+                    error!(
+                        "No Peaking Biquad filter named '{}' in the active config",
+                        name
+                    );
+                    WsResult::Error
+                }
+            };
+            Some(WsReply::SetBiquadPeakingGain { result })
+        }
+        WsCommand::NudgeFilterGain(name, delta_db) => {
+            let result = if delta_db.is_finite() {
+                nudge_biquad(shared_data_inst, &name, delta_db, 1.0)
+            } else {
+                error!("Invalid gain change {}", delta_db);
+                WsResult::Error
+            };
+            Some(WsReply::NudgeFilterGain { result })
+        }
+        WsCommand::NudgeFilterFreq(name, ratio) => {
+            let result = if ratio.is_finite() && ratio > 0.0 {
+                nudge_biquad(shared_data_inst, &name, 0.0, ratio)
+            } else {
+                error!(
+                    "Invalid frequency ratio {}, must be larger than zero",
+                    ratio
+                );
+                WsResult::Error
+            };
+            Some(WsReply::NudgeFilterFreq { result })
+        }
+        WsCommand::SetMixerSourcePosition(name, dest, channel, position) => {
+            if !(0.0..=1.0).contains(&position) {
+                error!(
+                    "Invalid fader position {}, must be in the range 0.0 to 1.0",
+                    position
+                );
+                return Some(WsReply::SetMixerSourcePosition {
+                    result: WsResult::Error,
+                });
+            }
+            let mut new_config = shared_data_inst.active_config.lock().clone();
+            let updated = new_config.as_mut().and_then(|conf| {
+                let source = conf
+                    .mixers
+                    .as_mut()?
+                    .get_mut(&name)?
+                    .mapping
+                    .iter_mut()
+                    .find(|mapping| mapping.dest == dest)?
+                    .sources
+                    .iter_mut()
+                    .find(|source| source.channel == channel)?;
+                let gain = mixer::taper_gain(source.taper.as_ref()?, position);
+                source.gain = Some(gain);
+                source.scale = Some(config::GainScale::Decibel);
+                Some(())
+            });
+            let result = match (updated, new_config) {
+                (Some(()), Some(conf)) => send_config(shared_data_inst, conf),
+                _ => {
+                    error!(
+                        "No source {} with a taper for destination {} in mixer '{}'",
+                        channel, dest, name
+                    );
+                    WsResult::Error
+                }
+            };
+            Some(WsReply::SetMixerSourcePosition { result })
+        }
+        WsCommand::GetMixerGain(name, dest, source) => {
+            let mut config = shared_data_inst.active_config.lock().clone();
+            let gain = config
+                .as_mut()
+                .and_then(|conf| mixer_source_mut(conf, &name, dest, source))
+                .map(|source| {
+                    let gain = source.gain();
+                    match source.scale() {
+                        config::GainScale::Decibel => gain,
+                        config::GainScale::Linear => 20.0 * gain.abs().log10(),
+                    }
+                });
+            match gain {
+                Some(value) => Some(WsReply::GetMixerGain {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                None => {
+                    error!(
+                        "No source {} for destination {} in mixer '{}'",
+                        source, dest, name
+                    );
+                    Some(WsReply::GetMixerGain {
+                        result: WsResult::Error,
+                        value: 0.0,
+                    })
+                }
+            }
+        }
+        WsCommand::SetMixerGain(name, dest, source, gain) => {
+            if !gain.is_finite() {
+                error!("Invalid mixer gain {}, must be a finite number", gain);
+                return Some(WsReply::SetMixerGain {
+                    result: WsResult::Error,
+                });
+            }
+            let mut pending = shared_data_inst.pending_configs.lock();
+            let mut new_config = pending.base(shared_data_inst.active_config.lock().clone());
+            let updated = new_config.as_mut().and_then(|conf| {
+                let mixer_source = mixer_source_mut(conf, &name, dest, source)?;
+                mixer_source.gain = Some(gain);
+                mixer_source.scale = Some(config::GainScale::Decibel);
+                Some(())
+            });
+            let result = match (updated, new_config) {
+                (Some(()), Some(conf)) => pending.send(shared_data_inst, conf),
+                _ => {
+                    error!(
+                        "No source {} for destination {} in mixer '{}'",
+                        source, dest, name
+                    );
+                    WsResult::Error
+                }
+            };
+            Some(WsReply::SetMixerGain { result })
+        }
+        WsCommand::GetTotalLatency => {
+            let buffer_level = shared_data_inst.playback_status.read().buffer_level;
+            match cached_pipeline_latency(shared_data_inst) {
+                Some(Ok((frames, devices))) => Some(WsReply::GetTotalLatency {
+                    result: WsResult::Ok,
+                    value: audiodevice::estimate_total_latency(&devices, frames, buffer_level),
+                }),
+                _ => Some(WsReply::GetTotalLatency {
+                    result: WsResult::Error,
+                    value: 0.0,
+                }),
+            }
+        }
+        WsCommand::GetPipelineLatency => match cached_pipeline_latency(shared_data_inst) {
+            Some(Ok((frames, devices))) => Some(WsReply::GetPipelineLatency {
+                result: WsResult::Ok,
+                value: (
+                    frames.round() as usize,
+                    (1000.0 * frames / devices.samplerate as PrcFmt) as f32,
+                ),
+            }),
+            _ => Some(WsReply::GetPipelineLatency {
+                result: WsResult::Error,
+                value: (0, 0.0),
+            }),
+        },
+        WsCommand::GetDeviceParameters => {
+            let params = shared_data_inst
+                .active_config
+                .lock()
+                .as_ref()
+                .map(|config| DeviceParameters {
+                    samplerate: config.devices.samplerate,
+                    capture_samplerate: config.devices.capture_samplerate(),
+                    chunksize: config.devices.chunksize,
+                    resampler: config.devices.resampler,
+                });
+            match params {
+                Some(value) => Some(WsReply::GetDeviceParameters {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                None => Some(WsReply::GetDeviceParameters {
+                    result: WsResult::Error,
+                    value: DeviceParameters::default(),
+                }),
+            }
+        }
+        WsCommand::GetPipelineChannelFlow => {
+            let flow = shared_data_inst
+                .active_config
+                .lock()
+                .as_ref()
+                .map(config::pipeline_channel_flow);
+            match flow {
+                Some(Ok(value)) => Some(WsReply::GetPipelineChannelFlow {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                _ => Some(WsReply::GetPipelineChannelFlow {
+                    result: WsResult::Error,
+                    value: Vec::new(),
+                }),
+            }
+        }
+        WsCommand::GetStats => {
+            let (frames_captured, state) = {
+                let capstat = shared_data_inst.capture_status.read();
+                (capstat.frames_captured, capstat.state)
+            };
+            let frames_played = shared_data_inst.playback_status.read().frames_played;
+            let elapsed = shared_data_inst
+                .processing_status
+                .read()
+                .started
+                .map(|started| started.elapsed().as_secs_f32())
+                .unwrap_or(0.0);
+            Some(WsReply::GetStats {
+                result: WsResult::Ok,
+                value: EngineStats {
+                    frames_captured,
+                    frames_played,
+                    elapsed,
+                    state,
+                },
+            })
+        }
+        WsCommand::GetBypassAll => Some(WsReply::GetBypassAll {
+            result: WsResult::Ok,
+            value: shared_data_inst.processing_params.bypass_all(),
+        }),
+        WsCommand::SetBypassAll(bypass) => {
+            let channels_match = shared_data_inst
+                .active_config
+                .lock()
+                .as_ref()
+                .map(|config| {
+                    config.devices.capture.channels() == config.devices.playback.channels()
+                })
+                .unwrap_or(false);
+            if bypass && !channels_match {
+                warn!("Can't bypass processing, capture and playback channel counts differ");
+                Some(WsReply::SetBypassAll {
+                    result: WsResult::Error,
+                })
+            } else {
+                shared_data_inst.processing_params.set_bypass_all(bypass);
+                Some(WsReply::SetBypassAll {
+                    result: WsResult::Ok,
+                })
+            }
+        }
+        WsCommand::SoloPipelineStep(index) => Some(WsReply::SoloPipelineStep {
+            result: solo_pipeline_step(shared_data_inst, index),
+        }),
+        WsCommand::Subscribe(names) => {
+            let events: Option<Vec<WsEvent>> = names
+                .iter()
+                .map(|name| current_event(name, shared_data_inst))
+                .collect();
+            match events {
+                Some(events) => {
+                    debug!("Subscribing to events: {:?}", names);
+                    local_data.subscriptions = names.into_iter().zip(events).collect();
+                    Some(WsReply::Subscribe {
+                        result: WsResult::Ok,
+                    })
+                }
+                None => {
+                    error!("Unknown event name in {:?}", names);
+                    Some(WsReply::Subscribe {
+                        result: WsResult::Error,
+                    })
+                }
+            }
+        }
+        WsCommand::SetPipelineStepBypass(index, bypass) => Some(WsReply::SetPipelineStepBypass {
+            result: set_pipeline_step_bypass(shared_data_inst, index, bypass),
+        }),
         WsCommand::None => None,
     }
 }
 
+/// Look up a mixer source by mixer name, destination channel and index in the list of sources.
+fn mixer_source_mut<'a>(
+    conf: &'a mut config::Configuration,
+    name: &str,
+    dest: usize,
+    source: usize,
+) -> Option<&'a mut config::MixerSource> {
+    conf.mixers
+        .as_mut()?
+        .get_mut(name)?
+        .mapping
+        .iter_mut()
+        .find(|mapping| mapping.dest == dest)?
+        .sources
+        .get_mut(source)
+}
+
+/// Resolve a path relative to the config directory.
+/// Paths leading outside of the directory, via ".." or symlinks or by being absolute, are rejected.
+fn path_in_config_dir(config_dir: &str, name: &str) -> Res<PathBuf> {
+    let base = fs::canonicalize(config_dir)?;
+    let path = fs::canonicalize(base.join(name))?;
+    if !path.starts_with(&base) {
+        let msg = format!("'{}' is outside the config directory", name);
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    Ok(path)
+}
+
+/// List the config files in a directory, given relative to the config directory.
+/// The returned paths are relative to the config directory, sorted by name.
+fn config_files_in_dir(config_dir: &str, dir: &str) -> Res<Vec<String>> {
+    let base = fs::canonicalize(config_dir)?;
+    let dir = path_in_config_dir(config_dir, dir)?;
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_yaml = path
+            .extension()
+            .map(|ext| ext == "yml" || ext == "yaml")
+            .unwrap_or(false);
+        if is_yaml && path.is_file() {
+            if let Ok(relative) = path.strip_prefix(&base) {
+                files.push(relative.to_string_lossy().to_string());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Get the pipeline latency in frames of the active config, together with its devices.
+/// Calculating the latency reads the coefficient files,
+/// so it is done once for each new active config and then cached.
+fn cached_pipeline_latency(shared_data: &SharedData) -> Option<Res<(PrcFmt, config::Devices)>> {
+    let mut cache = shared_data.pipeline_latency.lock();
+    let config = {
+        let active = shared_data.active_config.lock();
+        let active = active.as_ref()?;
+        if let Some((cached_config, latency)) = cache.as_ref() {
+            if cached_config == active {
+                return Some(Ok((*latency, active.devices.clone())));
+            }
+        }
+        active.clone()
+    };
+    let latency = match filters::pipeline_latency(&config) {
+        Ok(latency) => latency,
+        Err(err) => return Some(Err(err)),
+    };
+    let devices = config.devices.clone();
+    *cache = Some((config, latency));
+    Some(Ok((latency, devices)))
+}
+
+/// Send a modified config to the controller, to be applied without validating it again.
+fn send_config(shared_data: &SharedData, conf: config::Configuration) -> WsResult {
+    match shared_data
+        .command_sender
+        .try_send(ControllerMessage::ConfigChanged(Box::new(conf)))
+    {
+        Ok(()) => WsResult::Ok,
+        Err(TrySendError::Full(_)) => {
+            error!("Error sending new config, too many requests");
+            WsResult::Error
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            error!("Error sending new config, channel was disconnected");
+            WsResult::Error
+        }
+    }
+}
+
+/// Validate a config given to one of the config setters, and send it to the controller.
+/// The format name is only used in the error message when the config couldn't be parsed.
+fn validate_and_send_config(
+    shared_data: &SharedData,
+    parsed: Result<config::Configuration, String>,
+    format: &str,
+) -> WsResult {
+    match parsed {
+        Ok(mut conf) => match config::validate_config(&mut conf, None) {
+            Ok(()) => send_config(shared_data, conf),
+            Err(error) => {
+                error!("Error validating config: {}", error);
+                WsResult::Error
+            }
+        },
+        Err(error) => {
+            error!("Error parsing {}: {}", format, error);
+            WsResult::Error
+        }
+    }
+}
+
+/// Ask the controller to re-read the coefficients of a Conv filter of the active config.
+/// The coefficients are checked here first, since a failed read in the processing thread would panic.
+fn reload_filter(shared_data: &SharedData, name: &str) -> WsResult {
+    let filter_ok = match shared_data.active_config.lock().as_ref() {
+        Some(conf) => match conf.filters.as_ref().and_then(|filters| filters.get(name)) {
+            Some(filter @ config::Filter::Conv { .. }) => {
+                match filters::validate_filter(conf.devices.samplerate, filter) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        error!("Unable to reload filter '{}': {}", name, err);
+                        false
+                    }
+                }
+            }
+            Some(_) => {
+                error!("Filter '{}' is not a Conv filter, can't reload it", name);
+                false
+            }
+            None => {
+                error!("No filter named '{}' in the active config", name);
+                false
+            }
+        },
+        None => {
+            error!("No active config, can't reload filter '{}'", name);
+            false
+        }
+    };
+    if !filter_ok {
+        return WsResult::Error;
+    }
+    match shared_data
+        .command_sender
+        .try_send(ControllerMessage::ReloadFilter(name.to_string()))
+    {
+        Ok(()) => WsResult::Ok,
+        Err(TrySendError::Full(_)) => {
+            error!("Error sending filter reload, too many requests");
+            WsResult::Error
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            error!("Error sending filter reload, channel was disconnected");
+            WsResult::Error
+        }
+    }
+}
+
+/// Get the gain and frequency of the Biquad types that can be nudged, which are the peaking and shelving filters.
+fn adjustable_biquad(
+    parameters: &mut config::BiquadParameters,
+) -> Option<(&mut PrcFmt, &mut PrcFmt)> {
+    match parameters {
+        config::BiquadParameters::Peaking(config::PeakingWidth::Q { gain, freq, .. })
+        | config::BiquadParameters::Peaking(config::PeakingWidth::Bandwidth {
+            gain, freq, ..
+        })
+        | config::BiquadParameters::Peaking(config::PeakingWidth::ProportionalQ {
+            gain,
+            freq,
+            ..
+        })
+        | config::BiquadParameters::Highshelf(config::ShelfSteepness::Q { gain, freq, .. })
+        | config::BiquadParameters::Highshelf(config::ShelfSteepness::Slope {
+            gain, freq, ..
+        })
+        | config::BiquadParameters::Lowshelf(config::ShelfSteepness::Q { gain, freq, .. })
+        | config::BiquadParameters::Lowshelf(config::ShelfSteepness::Slope {
+            gain, freq, ..
+        })
+        | config::BiquadParameters::HighshelfFO { gain, freq }
+        | config::BiquadParameters::LowshelfFO { gain, freq } => Some((gain, freq)),
+        _ => None,
+    }
+}
+
+/// Change the gain of a peaking or shelving Biquad by a number of dB, and multiply its frequency by a ratio.
+/// Only the nudged values are changed, and they are clamped to the allowed ranges.
+/// The nudge is applied on top of earlier nudges that the controller has not applied yet.
+fn nudge_biquad(shared_data: &SharedData, name: &str, delta_db: PrcFmt, ratio: PrcFmt) -> WsResult {
+    let mut pending = shared_data.pending_configs.lock();
+    let mut new_config = pending.base(shared_data.active_config.lock().clone());
+    let updated = new_config.as_mut().and_then(|conf| {
+        let maxfreq = MAX_NUDGE_FREQ_FRACTION * conf.devices.samplerate as PrcFmt;
+        match conf.filters.as_mut()?.get_mut(name)? {
+            config::Filter::Biquad { parameters, .. } => {
+                let (gain, freq) = adjustable_biquad(parameters)?;
+                if delta_db != 0.0 {
+                    *gain = (*gain + delta_db).clamp(-MAX_NUDGE_GAIN, MAX_NUDGE_GAIN);
+                }
+                if ratio != 1.0 {
+                    *freq = (*freq * ratio).clamp(MIN_NUDGE_FREQ, maxfreq);
+                }
+                debug!(
+                    "Nudged filter '{}' to gain {} dB, freq {} Hz",
+                    name, gain, freq
+                );
+                Some(())
+            }
+            _ => None,
+        }
+    });
+    match (updated, new_config) {
+        (Some(()), Some(conf)) => pending.send(shared_data, conf),
+        _ => {
+            error!(
+                "No peaking or shelving Biquad filter named '{}' in the active config",
+                name
+            );
+            WsResult::Error
+        }
+    }
+}
+
+/// Change the bypass setting of one step of the active pipeline.
+/// Bypassing filter and processor steps is applied without rebuilding the pipeline when possible.
+fn set_pipeline_step_bypass(shared_data: &SharedData, index: usize, bypass: bool) -> WsResult {
+    let mut conf = match shared_data.active_config.lock().clone() {
+        Some(conf) => conf,
+        None => {
+            error!("No active config, can't bypass a pipeline step");
+            return WsResult::Error;
+        }
+    };
+    let nbr_steps = conf.pipeline.as_ref().map_or(0, |pipeline| pipeline.len());
+    match conf
+        .pipeline
+        .as_mut()
+        .and_then(|pipeline| pipeline.get_mut(index))
+    {
+        Some(step) => step.set_bypassed(bypass),
+        None => {
+            error!(
+                "Invalid pipeline step {}, the pipeline has {} steps",
+                index, nbr_steps
+            );
+            return WsResult::Error;
+        }
+    }
+    // Bypassed steps aren't validated, check that the changed pipeline is valid.
+    // The pipeline variants are removed first, to validate the pipeline as it is.
+    let mut check_conf = conf.clone();
+    check_conf.pipeline_by_channels = None;
+    if let Err(err) = config::validate_config(&mut check_conf, None) {
+        error!(
+            "Unable to change bypass of pipeline step {}: {}",
+            index, err
+        );
+        return WsResult::Error;
+    }
+    debug!("Setting bypass of pipeline step {} to {}", index, bypass);
+    send_config(shared_data, conf)
+}
+
+/// Bypass all filter and processor steps of the active pipeline except the one at the given index.
+/// Mixer steps are kept, since they determine the number of channels.
+/// With no index, the bypass settings from before the solo are restored,
+/// unless the pipeline has been changed in the meantime.
+fn solo_pipeline_step(shared_data: &SharedData, index: Option<usize>) -> WsResult {
+    let mut solo = shared_data.pipeline_solo.lock();
+    let mut conf = match shared_data.active_config.lock().clone() {
+        Some(conf) => conf,
+        None => {
+            error!("No active config, can't solo a pipeline step");
+            return WsResult::Error;
+        }
+    };
+    let pipeline = conf.pipeline.clone().unwrap_or_default();
+    match index {
+        Some(index) => {
+            if index >= pipeline.len() {
+                error!(
+                    "Invalid pipeline step {}, the pipeline has {} steps",
+                    index,
+                    pipeline.len()
+                );
+                return WsResult::Error;
+            }
+            // When changing the soloed step, start over from the pipeline before the first solo.
+            let original = match solo.as_ref() {
+                Some(state) if state.soloed == pipeline => state.original.clone(),
+                _ => pipeline,
+            };
+            let mut soloed = original.clone();
+            for (n, step) in soloed.iter_mut().enumerate() {
+                match step {
+                    config::PipelineStep::Filter(step) => step.bypassed = Some(n != index),
+                    config::PipelineStep::Processor(step) => step.bypassed = Some(n != index),
+                    config::PipelineStep::Mixer(_) => {}
+                }
+            }
+            conf.pipeline = Some(soloed.clone());
+            let result = send_config(shared_data, conf);
+            if result == WsResult::Ok {
+                debug!("Soloing pipeline step {}", index);
+                *solo = Some(PipelineSolo { original, soloed });
+            }
+            result
+        }
+        None => match solo.take() {
+            Some(state) if state.soloed == pipeline => {
+                debug!("Clearing pipeline step solo");
+                conf.pipeline = Some(state.original);
+                send_config(shared_data, conf)
+            }
+            Some(_) => {
+                warn!("The pipeline was changed while a step was soloed, not restoring the previous bypass settings");
+                WsResult::Error
+            }
+            None => WsResult::Ok,
+        },
+    }
+}
+
+/// Copy the named filters, mixers and processors from the active config into a freshly loaded one,
+/// so that definitions edited at runtime survive a reload from file.
+fn keep_active_definitions(
+    conf: &mut config::Configuration,
+    active: &config::Configuration,
+    names: &[String],
+) -> Result<(), String> {
+    for name in names {
+        if let Some(filter) = active.filters.as_ref().and_then(|f| f.get(name)) {
+            conf.filters
+                .get_or_insert_with(HashMap::new)
+                .insert(name.clone(), filter.clone());
+        } else if let Some(mixer) = active.mixers.as_ref().and_then(|m| m.get(name)) {
+            conf.mixers
+                .get_or_insert_with(HashMap::new)
+                .insert(name.clone(), mixer.clone());
+        } else if let Some(processor) = active.processors.as_ref().and_then(|p| p.get(name)) {
+            conf.processors
+                .get_or_insert_with(HashMap::new)
+                .insert(name.clone(), processor.clone());
+        } else {
+            return Err(format!(
+                "No filter, mixer or processor named '{name}' in the active config"
+            ));
+        }
+    }
+    Ok(())
+}
+
 fn clamped_volume(vol: f32) -> f32 {
     let mut new_vol = vol;
     // Clamp to -150 .. 50 dB, probably larger than needed..
@@ -1378,6 +3006,82 @@ fn capture_signal_peak_since(shared_data: &SharedData, time: f32) -> Vec<f32> {
     }
 }
 
+// Integrate the capture RMS over the given number of seconds.
+// The history is polled regularly, so that the window may be longer than the stored history.
+fn measure_capture_noise_floor(shared_data: &SharedData, seconds: f32) -> Option<Vec<f32>> {
+    let end = Instant::now() + Duration::from_secs_f32(seconds);
+    let mut last = Instant::now();
+    let mut sum: Vec<f32> = Vec::new();
+    let mut nbr_records = 0;
+    loop {
+        let now = Instant::now();
+        let done = now >= end;
+        if !done {
+            thread::sleep((end - now).min(NOISE_FLOOR_POLL_INTERVAL));
+        }
+        let res = shared_data.capture_status.read().signal_rms.sum_since(last);
+        if let Some((nbr, record)) = res {
+            if record.values.len() != sum.len() {
+                sum = vec![0.0; record.values.len()];
+                nbr_records = 0;
+            }
+            sum.iter_mut()
+                .zip(record.values.iter())
+                .for_each(|(acc, val)| *acc += *val);
+            nbr_records += nbr;
+            last = record.time;
+        }
+        if done {
+            break;
+        }
+    }
+    if nbr_records == 0 {
+        return None;
+    }
+    sum.iter_mut()
+        .for_each(|val| *val = (*val / nbr_records as f32).sqrt());
+    linear_to_db(&mut sum);
+    Some(sum)
+}
+
+/// Record the processed output for the given number of seconds,
+/// and return it as base64 encoded interleaved FLOAT32LE samples.
+fn record_output_snapshot(
+    shared_data: &SharedData,
+    seconds: f32,
+    samplerate: usize,
+) -> Option<OutputSnapshotData> {
+    let frames = (seconds * samplerate as f32).round() as usize;
+    let params = &shared_data.processing_params;
+    if !params.start_output_snapshot(frames) {
+        warn!("An output snapshot is already being recorded");
+        return None;
+    }
+    let deadline =
+        Instant::now() + Duration::from_secs_f32(seconds) + OUTPUT_SNAPSHOT_TIMEOUT_MARGIN;
+    loop {
+        if let Some(snapshot) = params.take_output_snapshot() {
+            let bytes: Vec<u8> = snapshot
+                .samples
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            return Some(OutputSnapshotData {
+                samplerate,
+                channels: snapshot.channels,
+                frames: snapshot.frames,
+                data: data_encoding::BASE64.encode(&bytes),
+            });
+        }
+        if Instant::now() >= deadline {
+            warn!("Timed out while recording output snapshot");
+            params.cancel_output_snapshot();
+            return None;
+        }
+        thread::sleep(NOISE_FLOOR_POLL_INTERVAL);
+    }
+}
+
 fn capture_signal_rms_since(shared_data: &SharedData, time: f32) -> Vec<f32> {
     let time_instant = get_subtracted_instant(time);
     let res = shared_data
@@ -1468,7 +3172,11 @@ fn capture_signal_rms_since_last(shared_data: &SharedData, local_data: &mut Loca
 }
 
 fn playback_signal_peak(shared_data: &SharedData) -> Vec<f32> {
-    let res = shared_data.playback_status.read().signal_peak.last();
+    let res = shared_data
+        .playback_status
+        .write()
+        .signal_peak
+        .decaying_peak();
     match res {
         Some(mut record) => {
             linear_to_db(&mut record.values);
@@ -1502,7 +3210,11 @@ fn playback_signal_rms(shared_data: &SharedData) -> Vec<f32> {
 }
 
 fn capture_signal_peak(shared_data: &SharedData) -> Vec<f32> {
-    let res = shared_data.capture_status.read().signal_peak.last();
+    let res = shared_data
+        .capture_status
+        .write()
+        .signal_peak
+        .decaying_peak();
     match res {
         Some(mut record) => {
             linear_to_db(&mut record.values);
@@ -1537,7 +3249,11 @@ fn capture_signal_rms(shared_data: &SharedData) -> Vec<f32> {
 
 #[cfg(test)]
 mod tests {
-    use crate::socketserver::{parse_command, WsCommand};
+    use crate::config;
+    use crate::socketserver::{
+        adjustable_biquad, config_files_in_dir, parse_command, path_in_config_dir, PendingConfigs,
+        WsCommand,
+    };
     use tungstenite::Message;
 
     #[test]
@@ -1554,5 +3270,153 @@ mod tests {
         let cmd = Message::text("{\"SetConfigFilePath\": \"somefile\"}");
         let res = parse_command(cmd).unwrap();
         assert_eq!(res, WsCommand::SetConfigFilePath("somefile".to_string()));
+        let cmd = Message::text("{\"ReloadMerge\": [\"eq\", \"mixer\"]}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(
+            res,
+            WsCommand::ReloadMerge(vec!["eq".to_string(), "mixer".to_string()])
+        );
+        let cmd = Message::text("{\"ReloadFilter\": \"fir\"}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::ReloadFilter("fir".to_string()));
+        let cmd = Message::text("{\"GetMixerGain\": [\"surface\", 0, 1]}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::GetMixerGain("surface".to_string(), 0, 1));
+        let cmd = Message::text("{\"GetFilterResponse\": [\"fir\", [100.0, 1000.0]]}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(
+            res,
+            WsCommand::GetFilterResponse("fir".to_string(), vec![100.0, 1000.0])
+        );
+        let cmd = Message::text("{\"SetBiquadPeakingGain\": [\"presence\", -3.5]}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(
+            res,
+            WsCommand::SetBiquadPeakingGain("presence".to_string(), -3.5)
+        );
+        let cmd = Message::text("{\"Subscribe\": [\"State\", \"Volume\"]}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(
+            res,
+            WsCommand::Subscribe(vec!["State".to_string(), "Volume".to_string()])
+        );
+        let cmd = Message::text("\"GetDeviceParameters\"");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::GetDeviceParameters);
+        let cmd = Message::text("{\"SwitchToConfigFile\": \"preset.yml\"}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::SwitchToConfigFile("preset.yml".to_string()));
+        let cmd = Message::text("{\"RecallFaderPreset\": \"night\"}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::RecallFaderPreset("night".to_string()));
+        let cmd = Message::text("{\"SetPipelineStepBypass\": [2, true]}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::SetPipelineStepBypass(2, true));
+        let cmd = Message::text("{\"CaptureOutputSnapshot\": 2.5}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::CaptureOutputSnapshot(2.5));
+        let cmd = Message::text("{\"SetPeakDecayRate\": 20.0}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::SetPeakDecayRate(20.0));
+        let cmd = Message::text("{\"SetMixerGain\": [\"surface\", 0, 1, -3.0]}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(
+            res,
+            WsCommand::SetMixerGain("surface".to_string(), 0, 1, -3.0)
+        );
+    }
+
+    #[test]
+    fn nudge_adjustable_biquads() {
+        let mut peaking = config::BiquadParameters::Peaking(config::PeakingWidth::Q {
+            freq: 100.0,
+            q: 1.0,
+            gain: -3.0,
+        });
+        let (gain, freq) = adjustable_biquad(&mut peaking).unwrap();
+        assert_eq!((*gain, *freq), (-3.0, 100.0));
+        *gain += 1.0;
+        assert_eq!(
+            peaking,
+            config::BiquadParameters::Peaking(config::PeakingWidth::Q {
+                freq: 100.0,
+                q: 1.0,
+                gain: -2.0,
+            })
+        );
+        let mut shelf = config::BiquadParameters::LowshelfFO {
+            freq: 200.0,
+            gain: 6.0,
+        };
+        assert!(adjustable_biquad(&mut shelf).is_some());
+        let mut highpass = config::BiquadParameters::Highpass {
+            freq: 100.0,
+            q: 0.7,
+        };
+        assert!(adjustable_biquad(&mut highpass).is_none());
+    }
+
+    #[test]
+    fn list_config_files() {
+        let files = config_files_in_dir("exampleconfigs", "").unwrap();
+        assert!(files.contains(&"simpleconfig.yml".to_string()));
+        assert!(files
+            .iter()
+            .all(|f| f.ends_with(".yml") || f.ends_with(".yaml")));
+        let mut sorted = files.clone();
+        sorted.sort();
+        assert_eq!(files, sorted);
+        assert!(config_files_in_dir("exampleconfigs", "..").is_err());
+    }
+
+    #[test]
+    fn reject_paths_outside_config_dir() {
+        assert!(path_in_config_dir("exampleconfigs", "simpleconfig.yml").is_ok());
+        assert!(path_in_config_dir("exampleconfigs", "../Cargo.toml").is_err());
+        assert!(path_in_config_dir("exampleconfigs", "../exampleconfigs/simpleconfig.yml").is_ok());
+        let absolute = std::fs::canonicalize("Cargo.toml").unwrap();
+        assert!(path_in_config_dir("exampleconfigs", absolute.to_str().unwrap()).is_err());
+        assert!(path_in_config_dir("exampleconfigs", "nonexistent.yml").is_err());
+    }
+
+    #[test]
+    fn pending_configs_build_on_unapplied_changes() {
+        let active: config::Configuration = serde_yaml::from_str(
+            r#"
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+"#,
+        )
+        .unwrap();
+        let mut pending = PendingConfigs::default();
+        assert_eq!(pending.base(Some(active.clone())), Some(active.clone()));
+
+        let mut first = active.clone();
+        first.title = Some("first".to_string());
+        pending.configs.push(first.clone());
+        // The first change is not applied yet, the next one builds on it
+        assert_eq!(pending.base(Some(active.clone())), Some(first.clone()));
+
+        let mut second = first.clone();
+        second.description = Some("second".to_string());
+        pending.configs.push(second.clone());
+        // Only the first change is applied, the next one still builds on the second
+        assert_eq!(pending.base(Some(first)), Some(second));
+
+        // The active config was replaced by another one, start over from that
+        let mut other = active;
+        other.title = Some("other".to_string());
+        assert_eq!(pending.base(Some(other.clone())), Some(other));
+        assert_eq!(pending.base(None), None);
+        assert!(pending.configs.is_empty());
     }
 }
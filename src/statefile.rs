@@ -1,6 +1,7 @@
 //use crate::config::Configuration;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::Read;
@@ -15,6 +16,16 @@ pub struct State {
     pub config_path: Option<String>,
     pub mute: [bool; 5],
     pub volume: [f32; 5],
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fader_presets: BTreeMap<String, FaderPreset>,
+}
+
+/// A named snapshot of the volume and mute settings of all faders.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FaderPreset {
+    pub mute: [bool; 5],
+    pub volume: [f32; 5],
 }
 
 pub fn load_state(filename: &str) -> Option<State> {
@@ -54,6 +65,7 @@ pub fn save_state(
         config_path: config_path.lock().as_ref().map(|s| s.to_string()),
         volume: params.volumes(),
         mute: params.mutes(),
+        fader_presets: params.fader_presets(),
     };
     if save_state_to_file(filename, &state) {
         unsaved_changes.store(false, Ordering::Relaxed);
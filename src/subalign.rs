@@ -0,0 +1,134 @@
+use crate::audiodevice::AudioChunk;
+use crate::basicfilters;
+use crate::basicfilters::Delay;
+use crate::biquad;
+use crate::biquad::{Biquad, BiquadCoefficients};
+use crate::config;
+use crate::filters::Filter;
+use crate::filters::Processor;
+use crate::PrcFmt;
+use crate::Res;
+
+pub struct SubAlign {
+    pub name: String,
+    pub channel: usize,
+    pub samplerate: usize,
+    delay: Delay,
+    allpass: Biquad,
+}
+
+fn delay_config(config: &config::SubAlignParameters) -> config::DelayParameters {
+    config::DelayParameters {
+        delay: config.delay_ms,
+        unit: Some(config::TimeUnit::Milliseconds),
+        subsample: Some(false),
+    }
+}
+
+/// Get the delay of the sub align in samples.
+pub fn delay_samples(samplerate: usize, config: &config::SubAlignParameters) -> PrcFmt {
+    basicfilters::delay_in_samples(samplerate, &delay_config(config))
+}
+
+fn allpass_config(config: &config::SubAlignParameters) -> config::BiquadParameters {
+    config::BiquadParameters::Allpass(config::NotchWidth::Q {
+        freq: config.freq,
+        q: config.q(),
+    })
+}
+
+impl SubAlign {
+    /// Creates a SubAlign from a config struct
+    pub fn from_config(name: &str, config: config::SubAlignParameters, samplerate: usize) -> Self {
+        let name = name.to_string();
+        debug!(
+            "Creating sub align '{}', channel: {}, freq: {}, q: {}, delay: {} ms",
+            name,
+            config.channel,
+            config.freq,
+            config.q(),
+            config.delay_ms
+        );
+        let delay = Delay::from_config(&name, samplerate, delay_config(&config));
+        let allpass = Biquad::new(
+            &name,
+            samplerate,
+            BiquadCoefficients::from_config(samplerate, allpass_config(&config)),
+        );
+        SubAlign {
+            name,
+            channel: config.channel,
+            samplerate,
+            delay,
+            allpass,
+        }
+    }
+}
+
+impl Processor for SubAlign {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply the delay and allpass filter to the selected channel, modifying it in-place.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        let waveform = &mut input.waveforms[self.channel];
+        if !waveform.is_empty() {
+            self.delay.process_waveform(waveform)?;
+            self.allpass.process_waveform(waveform)?;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::SubAlign {
+            parameters: config, ..
+        } = config
+        {
+            *self = SubAlign::from_config(&self.name, config, self.samplerate);
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the sub align config, to give a helpful message intead of a panic.
+pub fn validate_sub_align(samplerate: usize, config: &config::SubAlignParameters) -> Res<()> {
+    if config.channel >= config.channels {
+        let msg = format!(
+            "Invalid channel: {}, max is: {}.",
+            config.channel,
+            config.channels - 1
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    basicfilters::validate_delay_config(&delay_config(config))?;
+    biquad::validate_config(samplerate, &allpass_config(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SubAlignParameters;
+    use crate::subalign::validate_sub_align;
+
+    #[test]
+    fn check_sub_align_config() {
+        let mut conf = SubAlignParameters {
+            channels: 3,
+            channel: 2,
+            freq: 80.0,
+            q: None,
+            delay_ms: 2.5,
+        };
+        assert!(validate_sub_align(44100, &conf).is_ok());
+        conf.channel = 3;
+        assert!(validate_sub_align(44100, &conf).is_err());
+        conf.channel = 2;
+        conf.freq = 30000.0;
+        assert!(validate_sub_align(44100, &conf).is_err());
+        conf.freq = 80.0;
+        conf.delay_ms = -1.0;
+        assert!(validate_sub_align(44100, &conf).is_err());
+    }
+}
@@ -41,6 +41,7 @@ pub struct WasapiPlaybackDevice {
     pub target_level: usize,
     pub adjust_period: f32,
     pub enable_rate_adjust: bool,
+    pub open_retry: OpenRetry,
 }
 
 #[derive(Clone, Debug)]
@@ -58,6 +59,7 @@ pub struct WasapiCaptureDevice {
     pub silence_timeout: PrcFmt,
     pub stop_on_rate_change: bool,
     pub rate_measure_interval: f32,
+    pub open_retry: OpenRetry,
 }
 
 #[derive(Clone, Debug)]
@@ -603,6 +605,7 @@ impl PlaybackDevice for WasapiPlaybackDevice {
         playback_status: Arc<RwLock<PlaybackStatus>>,
     ) -> Res<Box<thread::JoinHandle<()>>> {
         let devname = self.devname.clone();
+        let open_retry = self.open_retry;
         let exclusive = self.exclusive;
         let samplerate = self.samplerate;
         let chunksize = self.chunksize;
@@ -636,6 +639,8 @@ impl PlaybackDevice for WasapiPlaybackDevice {
                     rms: vec![0.0; channels],
                     peak: vec![0.0; channels],
                 };
+                let mut level_meter = LevelMeter::new(playback_status.read().meter_ballistics, samplerate);
+                let mut true_peak_meter = countertimer::TruePeakMeter::default();
 
                 trace!("Build output stream");
                 let mut conversion_result;
@@ -645,12 +650,18 @@ impl PlaybackDevice for WasapiPlaybackDevice {
                     .name("WasapiPlaybackInner".to_string())
                     .spawn(move || {
                         let (_device, audio_client, render_client, handle, wave_format) =
-                            match open_playback(
-                                &devname,
-                                samplerate,
-                                channels,
-                                &sample_format_dev,
-                                exclusive,
+                            match open_with_retry(
+                                open_retry,
+                                devname.as_deref().unwrap_or("default"),
+                                || {
+                                    open_playback(
+                                        &devname,
+                                        samplerate,
+                                        channels,
+                                        &sample_format_dev,
+                                        exclusive,
+                                    )
+                                },
                             ) {
                                 Ok((_device, audio_client, render_client, handle, wave_format)) => {
                                     tx_state_dev.send(DeviceState::Ok).unwrap_or(());
@@ -762,7 +773,8 @@ impl PlaybackDevice for WasapiPlaybackDevice {
                                 }
                                 conversion_result =
                                     chunk_to_buffer_rawbytes(&chunk, &mut buf, &sample_format);
-                                chunk.update_stats(&mut chunk_stats);
+                                level_meter.update(&chunk, &mut chunk_stats);
+                                true_peak_meter.update(&chunk);
                                 {
                                     let mut playback_status = playback_status.write();
                                     if conversion_result.1 > 0 {
@@ -775,6 +787,9 @@ impl PlaybackDevice for WasapiPlaybackDevice {
                                     playback_status
                                         .signal_peak
                                         .add_record(chunk_stats.peak_linear());
+                                    playback_status.signal_minmax.update(&chunk);
+                                    playback_status.true_peak.add_record(&mut true_peak_meter);
+                                    playback_status.frames_played += chunk.valid_frames as u64;
                                 }
                             }
                             match tx_dev.send(PlaybackDeviceMessage::Data(buf)) {
@@ -896,6 +911,7 @@ impl CaptureDevice for WasapiCaptureDevice {
         let exclusive = self.exclusive;
         let loopback = self.loopback;
         let devname = self.devname.clone();
+        let open_retry = self.open_retry;
         let samplerate = self.samplerate;
         let capture_samplerate = self.capture_samplerate;
         let chunksize = self.chunksize;
@@ -939,7 +955,7 @@ impl CaptureDevice for WasapiCaptureDevice {
                     .name("WasapiCaptureInner".to_string())
                     .spawn(move || {
                         let (_device, audio_client, capture_client, handle, wave_format) =
-                        match open_capture(&devname, capture_samplerate, channels, &sample_format_dev, exclusive, loopback) {
+                        match open_with_retry(open_retry, devname.as_deref().unwrap_or("default"), || open_capture(&devname, capture_samplerate, channels, &sample_format_dev, exclusive, loopback)) {
                             Ok((_device, audio_client, capture_client, handle, wave_format)) => {
                                 tx_state_dev.send(DeviceState::Ok).unwrap_or(());
                                 (_device, audio_client, capture_client, handle, wave_format)
@@ -985,6 +1001,8 @@ impl CaptureDevice for WasapiCaptureDevice {
                 let mut valuewatcher = countertimer::ValueWatcher::new(capture_samplerate as f32, RATE_CHANGE_THRESHOLD_VALUE, RATE_CHANGE_THRESHOLD_COUNT);
                 let mut value_range = 0.0;
                 let mut chunk_stats = ChunkStats{rms: vec![0.0; channels], peak: vec![0.0; channels]};
+                let mut level_meter = LevelMeter::new(capture_status.read().meter_ballistics, capture_samplerate);
+                let mut true_peak_meter = countertimer::TruePeakMeter::default();
                 let mut rate_adjust = 0.0;
                 let mut silence_counter = countertimer::SilenceCounter::new(silence_threshold, silence_timeout, capture_samplerate, chunksize);
                 let mut state = ProcessingState::Running;
@@ -1138,12 +1156,18 @@ impl CaptureDevice for WasapiCaptureDevice {
                             capture_bytes,
                             &capture_status.read().used_channels,
                         );
-                        chunk.update_stats(&mut chunk_stats);
+                        level_meter.update(&chunk, &mut chunk_stats);
+                        true_peak_meter.update(&chunk);
                         //trace!("Capture rms {:?}, peak {:?}", chunk_stats.rms_db(), chunk_stats.peak_db());
                         {
                             let mut capture_status = capture_status.write();
                             capture_status.signal_rms.add_record_squared(chunk_stats.rms_linear());
                             capture_status.signal_peak.add_record(chunk_stats.peak_linear());
+                            capture_status.signal_minmax.update(&chunk);
+                            capture_status.true_peak.add_record(&mut true_peak_meter);
+                            capture_status.recent_samples.update(&chunk);
+                            capture_status.signal_duty.update(silence_counter.is_signal(chunk.maxval - chunk.minval), chunk.valid_frames);
+                            capture_status.frames_captured += chunk.valid_frames as u64;
                         }
                         value_range = chunk.maxval - chunk.minval;
                         state = silence_counter.update(value_range);
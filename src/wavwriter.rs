@@ -0,0 +1,256 @@
+use crate::config::{SampleFormat, WavHeaderFormat};
+use std::io::{Seek, SeekFrom, Write};
+
+// Size value used in the 32-bit size fields when the real size is unknown or stored elsewhere.
+const UNKNOWN_SIZE_32: u32 = 0xFFFF_FFFF;
+const UNKNOWN_SIZE_64: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Build the "fmt " chunk describing the sample format.
+fn fmt_chunk(sample_format: &SampleFormat, channels: usize, samplerate: usize) -> Vec<u8> {
+    let format_tag = match sample_format {
+        SampleFormat::FLOAT32LE | SampleFormat::FLOAT64LE => WAVE_FORMAT_IEEE_FLOAT,
+        _ => WAVE_FORMAT_PCM,
+    };
+    let block_align = channels * sample_format.bytes_per_sample();
+    let mut chunk = Vec::with_capacity(24);
+    chunk.extend_from_slice(b"fmt ");
+    chunk.extend_from_slice(&16u32.to_le_bytes());
+    chunk.extend_from_slice(&format_tag.to_le_bytes());
+    chunk.extend_from_slice(&(channels as u16).to_le_bytes());
+    chunk.extend_from_slice(&(samplerate as u32).to_le_bytes());
+    chunk.extend_from_slice(&((samplerate * block_align) as u32).to_le_bytes());
+    chunk.extend_from_slice(&(block_align as u16).to_le_bytes());
+    chunk.extend_from_slice(&(sample_format.bits_per_sample() as u16).to_le_bytes());
+    chunk
+}
+
+/// Build a WAV or RF64 header for a stream with the given format.
+/// When the length of the data isn't known, the size fields are set to the maximum value.
+/// The header has the same length regardless of the data length,
+/// so that it can be overwritten with the final sizes when the stream ends.
+pub fn wav_header(
+    header_format: WavHeaderFormat,
+    sample_format: &SampleFormat,
+    channels: usize,
+    samplerate: usize,
+    data_bytes: Option<u64>,
+) -> Vec<u8> {
+    let fmt = fmt_chunk(sample_format, channels, samplerate);
+    let mut header = Vec::new();
+    match header_format {
+        WavHeaderFormat::None => {}
+        WavHeaderFormat::Wav => {
+            let riff_size = data_bytes.map_or(UNKNOWN_SIZE_32, |bytes| {
+                (bytes + 4 + fmt.len() as u64 + 8).min(UNKNOWN_SIZE_32 as u64) as u32
+            });
+            let data_size = data_bytes.map_or(UNKNOWN_SIZE_32, |bytes| {
+                bytes.min(UNKNOWN_SIZE_32 as u64) as u32
+            });
+            header.extend_from_slice(b"RIFF");
+            header.extend_from_slice(&riff_size.to_le_bytes());
+            header.extend_from_slice(b"WAVE");
+            header.extend_from_slice(&fmt);
+            header.extend_from_slice(b"data");
+            header.extend_from_slice(&data_size.to_le_bytes());
+        }
+        WavHeaderFormat::Rf64 => {
+            // The real sizes are stored in the ds64 chunk
+            let riff_size = data_bytes.map_or(UNKNOWN_SIZE_64, |bytes| {
+                bytes + 4 + 36 + fmt.len() as u64 + 8
+            });
+            let data_size = data_bytes.unwrap_or(UNKNOWN_SIZE_64);
+            let frames = data_bytes.map_or(UNKNOWN_SIZE_64, |bytes| {
+                bytes / (channels * sample_format.bytes_per_sample()) as u64
+            });
+            header.extend_from_slice(b"RF64");
+            header.extend_from_slice(&UNKNOWN_SIZE_32.to_le_bytes());
+            header.extend_from_slice(b"WAVE");
+            header.extend_from_slice(b"ds64");
+            header.extend_from_slice(&28u32.to_le_bytes());
+            header.extend_from_slice(&riff_size.to_le_bytes());
+            header.extend_from_slice(&data_size.to_le_bytes());
+            header.extend_from_slice(&frames.to_le_bytes());
+            // No table of other chunk sizes
+            header.extend_from_slice(&0u32.to_le_bytes());
+            header.extend_from_slice(&fmt);
+            header.extend_from_slice(b"data");
+            header.extend_from_slice(&UNKNOWN_SIZE_32.to_le_bytes());
+        }
+    }
+    header
+}
+
+/// Overwrite the header at the start of a file with one containing the final data size.
+pub fn update_wav_header<W: Write + Seek>(
+    file: &mut W,
+    header_format: WavHeaderFormat,
+    sample_format: &SampleFormat,
+    channels: usize,
+    samplerate: usize,
+    data_bytes: u64,
+) -> std::io::Result<()> {
+    if header_format == WavHeaderFormat::Wav && data_bytes > UNKNOWN_SIZE_32 as u64 - 36 {
+        warn!(
+            "The data is too large for a WAV header, {} bytes. Use the Rf64 header format for large files.",
+            data_bytes
+        );
+    }
+    let header = wav_header(
+        header_format,
+        sample_format,
+        channels,
+        samplerate,
+        Some(data_bytes),
+    );
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    file.flush()
+}
+
+/// Keeps count of the data written after a WAV or RF64 header,
+/// and updates the header with the final size when dropped.
+/// This way the header gets updated however the writing ends.
+pub struct WavHeaderGuard<W: Write + Seek> {
+    file: W,
+    header_format: WavHeaderFormat,
+    sample_format: SampleFormat,
+    channels: usize,
+    samplerate: usize,
+    data_bytes: u64,
+}
+
+impl<W: Write + Seek> WavHeaderGuard<W> {
+    pub fn new(
+        file: W,
+        header_format: WavHeaderFormat,
+        sample_format: SampleFormat,
+        channels: usize,
+        samplerate: usize,
+    ) -> Self {
+        WavHeaderGuard {
+            file,
+            header_format,
+            sample_format,
+            channels,
+            samplerate,
+            data_bytes: 0,
+        }
+    }
+
+    pub fn add_data_bytes(&mut self, bytes: usize) {
+        self.data_bytes += bytes as u64;
+    }
+}
+
+impl<W: Write + Seek> Drop for WavHeaderGuard<W> {
+    fn drop(&mut self) {
+        debug!("Updating header, data size is {} bytes", self.data_bytes);
+        if let Err(err) = update_wav_header(
+            &mut self.file,
+            self.header_format,
+            &self.sample_format,
+            self.channels,
+            self.samplerate,
+            self.data_bytes,
+        ) {
+            warn!("Unable to update the header, error: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{SampleFormat, WavHeaderFormat};
+    use crate::wavwriter::{update_wav_header, wav_header, WavHeaderGuard};
+    use std::io::Cursor;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn write_wav_header() {
+        let header = wav_header(
+            WavHeaderFormat::Wav,
+            &SampleFormat::S16LE,
+            2,
+            44100,
+            Some(1000),
+        );
+        assert_eq!(header.len(), 44);
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(read_u32(&header, 4), 1036);
+        assert_eq!(&header[8..16], b"WAVEfmt ");
+        // PCM, 2 channels, 44100 Hz, 176400 bytes/s, 4 bytes per frame, 16 bits
+        assert_eq!(read_u32(&header, 20), 0x0002_0001);
+        assert_eq!(read_u32(&header, 24), 44100);
+        assert_eq!(read_u32(&header, 28), 176400);
+        assert_eq!(read_u32(&header, 32), 0x0010_0004);
+        assert_eq!(&header[36..40], b"data");
+        assert_eq!(read_u32(&header, 40), 1000);
+    }
+
+    #[test]
+    fn write_rf64_header() {
+        let header = wav_header(
+            WavHeaderFormat::Rf64,
+            &SampleFormat::FLOAT32LE,
+            2,
+            48000,
+            None,
+        );
+        assert_eq!(header.len(), 80);
+        assert_eq!(&header[0..4], b"RF64");
+        assert_eq!(&header[12..16], b"ds64");
+        assert_eq!(read_u64(&header, 28), u64::MAX);
+        let mut file = Cursor::new(header);
+        let data_bytes = 5_000_000_000;
+        update_wav_header(
+            &mut file,
+            WavHeaderFormat::Rf64,
+            &SampleFormat::FLOAT32LE,
+            2,
+            48000,
+            data_bytes,
+        )
+        .unwrap();
+        let header = file.into_inner();
+        assert_eq!(header.len(), 80);
+        assert_eq!(read_u32(&header, 4), u32::MAX);
+        assert_eq!(read_u64(&header, 20), data_bytes + 72);
+        assert_eq!(read_u64(&header, 28), data_bytes);
+        assert_eq!(read_u64(&header, 36), data_bytes / 8);
+        // IEEE float format tag
+        assert_eq!(read_u32(&header, 56), 0x0002_0003);
+        assert_eq!(&header[72..76], b"data");
+        assert_eq!(read_u32(&header, 76), u32::MAX);
+    }
+
+    #[test]
+    fn header_updated_when_guard_dropped() {
+        let header = wav_header(WavHeaderFormat::Wav, &SampleFormat::S16LE, 2, 44100, None);
+        assert_eq!(read_u32(&header, 40), u32::MAX);
+        let mut file = Cursor::new(header);
+        {
+            let mut guard = WavHeaderGuard::new(
+                &mut file,
+                WavHeaderFormat::Wav,
+                SampleFormat::S16LE,
+                2,
+                44100,
+            );
+            guard.add_data_bytes(400);
+            guard.add_data_bytes(600);
+        }
+        let header = file.into_inner();
+        assert_eq!(read_u32(&header, 4), 1036);
+        assert_eq!(read_u32(&header, 40), 1000);
+    }
+}